@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS faction__technologies(
+    id UUID PRIMARY KEY,
+    game_id UUID NOT NULL REFERENCES game__games(id) ON DELETE CASCADE,
+    faction_id INT NOT NULL REFERENCES faction__factions(id) ON DELETE CASCADE,
+    player_id UUID NOT NULL REFERENCES player__players(id) ON DELETE CASCADE,
+    kind VARCHAR(30) NOT NULL,
+    status VARCHAR(25) NOT NULL,
+    started_at TIMESTAMPTZ NOT NULL,
+    unlocked_at TIMESTAMPTZ NOT NULL,
+    UNIQUE (game_id, faction_id, kind)
+);
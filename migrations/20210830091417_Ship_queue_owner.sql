@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE system__ship_queues ADD COLUMN player_id UUID REFERENCES player__players(id) ON DELETE CASCADE;
+UPDATE system__ship_queues q SET player_id = s.player_id FROM map__systems s WHERE s.id = q.system_id;
+DELETE FROM system__ship_queues WHERE player_id IS NULL;
+ALTER TABLE system__ship_queues ALTER COLUMN player_id SET NOT NULL;
@@ -0,0 +1,5 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS map__system_minefields(
+    system_id UUID PRIMARY KEY REFERENCES map__systems(id) ON DELETE CASCADE,
+    quantity INT NOT NULL
+);
@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS player__notifications(
+    id UUID PRIMARY KEY,
+    player_id UUID NOT NULL REFERENCES player__players(id) ON DELETE CASCADE,
+    game_id UUID NOT NULL REFERENCES game__games(id) ON DELETE CASCADE,
+    kind VARCHAR(25) NOT NULL,
+    data JSONB NOT NULL,
+    is_read BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX player_notifications_idx ON player__notifications (player_id, game_id, created_at);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE fleet__fleets ADD COLUMN departure_date TIMESTAMPTZ;
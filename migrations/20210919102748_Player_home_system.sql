@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE player__players ADD COLUMN home_system_id UUID REFERENCES map__systems(id) ON DELETE SET NULL;
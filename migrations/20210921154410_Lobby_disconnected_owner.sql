@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE lobby__lobbies ADD COLUMN disconnected_owner_id UUID REFERENCES player__players(id) ON DELETE SET NULL;
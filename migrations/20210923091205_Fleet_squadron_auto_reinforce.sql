@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE fleet__squadrons ADD COLUMN max_quantity INT NOT NULL DEFAULT 0;
+ALTER TABLE fleet__squadrons ADD COLUMN auto_reinforce BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE fleet__squadrons SET max_quantity = quantity;
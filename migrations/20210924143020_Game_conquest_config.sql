@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE game__games ADD COLUMN conquest_duration_max DOUBLE PRECISION NOT NULL DEFAULT 60000;
+ALTER TABLE game__games ADD COLUMN conquest_duration_min DOUBLE PRECISION NOT NULL DEFAULT 5000;
+ALTER TABLE game__games ADD COLUMN conquest_strength_coeff DOUBLE PRECISION NOT NULL DEFAULT 100;
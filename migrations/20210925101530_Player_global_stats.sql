@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS player__global_stats(
+    player_id UUID PRIMARY KEY REFERENCES player__players(id) ON DELETE CASCADE,
+    wins INT NOT NULL DEFAULT 0,
+    destroyed_ships INT NOT NULL DEFAULT 0,
+    conquests INT NOT NULL DEFAULT 0
+);
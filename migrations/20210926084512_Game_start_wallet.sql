@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE game__games ADD COLUMN start_wallet INT NOT NULL DEFAULT 200;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE player__players ADD COLUMN is_eliminated BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,6 @@
+-- Add migration script here
+ALTER TABLE fleet__fleets ADD COLUMN destroyed_at TIMESTAMPTZ DEFAULT NULL;
+UPDATE fleet__fleets SET destroyed_at = NOW() WHERE is_destroyed = TRUE;
+CREATE INDEX fleet_destroyed_at_idx ON fleet__fleets (destroyed_at) WHERE destroyed_at IS NOT NULL;
+-- Battles keep a snapshot of their fleets, they must outlive the removal of their attacker
+ALTER TABLE fleet__combat__battles DROP CONSTRAINT attacker_fkey;
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE lobby__lobbies ADD COLUMN victory_mode VARCHAR(15) NOT NULL DEFAULT 'victory_points';
+ALTER TABLE game__games ADD COLUMN victory_mode VARCHAR(15) NOT NULL DEFAULT 'victory_points';
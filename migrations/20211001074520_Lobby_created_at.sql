@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE lobby__lobbies ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE lobby__lobbies ADD COLUMN nb_factions INT DEFAULT NULL;
+ALTER TABLE game__games ADD COLUMN nb_factions INT DEFAULT NULL;
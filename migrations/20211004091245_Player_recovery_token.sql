@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE player__players ADD COLUMN recovery_token_hash VARCHAR(64) DEFAULT NULL;
+CREATE UNIQUE INDEX player_recovery_token_hash_idx ON player__players (recovery_token_hash);
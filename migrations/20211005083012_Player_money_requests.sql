@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS player__money_requests(
+    id UUID PRIMARY KEY,
+    game_id UUID NOT NULL REFERENCES game__games(id) ON DELETE CASCADE,
+    requester_id UUID NOT NULL REFERENCES player__players(id) ON DELETE CASCADE,
+    target_id UUID NOT NULL REFERENCES player__players(id) ON DELETE CASCADE,
+    amount INT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX player_money_requests_idx ON player__money_requests (target_id, game_id, created_at);
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE game__games ADD COLUMN victory_points_per_minute INT NOT NULL DEFAULT 10;
+ALTER TABLE game__games ADD COLUMN victory_points_per_system INT NOT NULL DEFAULT 100;
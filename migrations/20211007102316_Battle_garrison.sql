@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE fleet__combat__battles ADD COLUMN garrison_id UUID DEFAULT NULL;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE game__games ADD COLUMN started_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
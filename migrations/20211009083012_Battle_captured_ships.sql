@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE fleet__combat__battles ADD COLUMN are_ships_captured BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE fleet__combat__battles ADD COLUMN initial_fleets JSONB DEFAULT NULL;
@@ -1 +1,3 @@
-pub mod chat;
\ No newline at end of file
+pub mod chat;
+pub mod money_request;
+pub mod notification;
\ No newline at end of file
@@ -0,0 +1,248 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+use serde::{Serialize, Deserialize};
+use sqlx::{PgPool, postgres::{PgRow, PgQueryAs}, FromRow, Executor, Error, Postgres};
+use sqlx_core::row::Row;
+use uuid::Uuid;
+use crate::{
+    lib::{
+        Result,
+        auth::Claims,
+        error::{ServerError, InternalError},
+        time::Time,
+    },
+    game::{
+        communication::notification::{Notification, NotificationKind},
+        faction::FactionID,
+        game::{game::GameID, server::GameNotifyPlayerMessage},
+        player::{Player, PlayerID, send_money},
+    },
+    ws::protocol,
+    AppState,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub struct MoneyRequestID(pub Uuid);
+
+/// A player asking a faction-mate for money. It is kept until the target answers it,
+/// so that it can be accepted after a reconnection.
+#[derive(Serialize, Clone)]
+pub struct MoneyRequest {
+    pub id: MoneyRequestID,
+    pub game: GameID,
+    pub requester: PlayerID,
+    pub target: PlayerID,
+    pub amount: usize,
+    pub created_at: Time,
+}
+
+#[derive(Deserialize)]
+pub struct MoneyRequestData {
+    pub amount: usize,
+}
+
+impl From<MoneyRequestID> for Uuid {
+    fn from(mrid: MoneyRequestID) -> Self { mrid.0 }
+}
+
+impl<'a> FromRow<'a, PgRow<'a>> for MoneyRequest {
+    fn from_row(row: &PgRow) -> std::result::Result<Self, Error> {
+        Ok(MoneyRequest {
+            id: row.try_get("id").map(MoneyRequestID)?,
+            game: row.try_get("game_id").map(GameID)?,
+            requester: row.try_get("requester_id").map(PlayerID)?,
+            target: row.try_get("target_id").map(PlayerID)?,
+            amount: row.try_get::<i32, _>("amount")? as usize,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
+
+impl MoneyRequest {
+    pub fn new(game: GameID, requester: PlayerID, target: PlayerID, amount: usize) -> Self {
+        MoneyRequest {
+            id: MoneyRequestID(Uuid::new_v4()),
+            game,
+            requester,
+            target,
+            amount,
+            created_at: Time::now(),
+        }
+    }
+
+    pub async fn find(mrid: MoneyRequestID, db_pool: &PgPool) -> Result<Self> {
+        sqlx::query_as("SELECT * FROM player__money_requests WHERE id = $1")
+            .bind(Uuid::from(mrid))
+            .fetch_one(db_pool).await.map_err(ServerError::if_row_not_found(InternalError::NotFound))
+    }
+
+    pub async fn find_by_target(pid: PlayerID, gid: GameID, db_pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as("SELECT * FROM player__money_requests WHERE target_id = $1 AND game_id = $2 ORDER BY created_at DESC")
+            .bind(Uuid::from(pid))
+            .bind(Uuid::from(gid))
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub async fn insert<E>(&self, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("INSERT INTO player__money_requests(id, game_id, requester_id, target_id, amount, created_at) VALUES($1, $2, $3, $4, $5, $6)")
+            .bind(Uuid::from(self.id))
+            .bind(Uuid::from(self.game))
+            .bind(Uuid::from(self.requester))
+            .bind(Uuid::from(self.target))
+            .bind(self.amount as i32)
+            .bind(self.created_at)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
+    /// Deletes the request and returns it, unless it was already answered meanwhile
+    pub async fn claim<E>(mrid: MoneyRequestID, exec: &mut E) -> Result<Option<Self>>
+        where E: Executor<Database = Postgres> {
+        sqlx::query_as("DELETE FROM player__money_requests WHERE id = $1 RETURNING *")
+            .bind(Uuid::from(mrid))
+            .fetch_optional(&mut *exec).await.map_err(ServerError::from)
+    }
+
+    pub async fn remove<E>(&self, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("DELETE FROM player__money_requests WHERE id = $1")
+            .bind(Uuid::from(self.id))
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+}
+
+/// Both players must still belong to the faction of the request, in the same game
+fn check_faction_mates(gid: GameID, fid: FactionID, requester: &Player, target: &Player) -> Result<()> {
+    for player in &[requester, target] {
+        if player.game != Some(gid) || player.faction != Some(fid) {
+            return Err(InternalError::AccessDenied.into());
+        }
+    }
+    if requester.id == target.id {
+        return Err(InternalError::Conflict.into());
+    }
+    Ok(())
+}
+
+#[post("/players/{player_id}/request-money/")]
+pub async fn request_money(state: web::Data<AppState>, info: web::Path<(GameID, FactionID, PlayerID)>, data: web::Json<MoneyRequestData>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    let (r, t) = futures::join!(
+        Player::find(claims.pid, &state.db_pool),
+        Player::find(info.2, &state.db_pool),
+    );
+    let requester = r?;
+    let target = t?;
+
+    check_faction_mates(info.0, info.1, &requester, &target)?;
+    if data.amount == 0 {
+        return Err(InternalError::Conflict.into());
+    }
+    // The amount is stored as a signed integer
+    if data.amount > i32::MAX as usize {
+        return Err(InternalError::ValidationFailed{ field: "amount" }.into());
+    }
+
+    let money_request = MoneyRequest::new(info.0, requester.id, target.id, data.amount);
+    let mut tx = state.db_pool.begin().await?;
+    money_request.insert(&mut tx).await?;
+    Notification::new(
+        target.id,
+        info.0,
+        NotificationKind::MoneyRequested,
+        money_request.clone(),
+    ).insert(&mut tx).await?;
+    tx.commit().await?;
+
+    let game_server = state.games().get(&info.0).cloned().ok_or(InternalError::GameUnknown)?;
+    game_server.do_send(GameNotifyPlayerMessage(
+        target.id,
+        protocol::Message::new(
+            protocol::Action::MoneyRequested,
+            money_request.clone(),
+            None,
+        )
+    ));
+
+    Ok(HttpResponse::Created().json(money_request))
+}
+
+#[get("/money-requests/")]
+pub async fn get_money_requests(state: web::Data<AppState>, info: web::Path<(GameID, FactionID)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    Ok(HttpResponse::Ok().json(MoneyRequest::find_by_target(claims.pid, info.0, &state.db_pool).await?))
+}
+
+/// The target of the request sends the money, with the same rules as a spontaneous transfer
+#[post("/money-requests/{request_id}/accept/")]
+pub async fn accept_money_request(state: web::Data<AppState>, info: web::Path<(GameID, FactionID, MoneyRequestID)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    let money_request = MoneyRequest::find(info.2, &state.db_pool).await?;
+    if money_request.target != claims.pid || money_request.game != info.0 {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let (r, t) = futures::join!(
+        Player::find(money_request.requester, &state.db_pool),
+        Player::find(money_request.target, &state.db_pool),
+    );
+    let requester = r?;
+    let target = t?;
+
+    check_faction_mates(info.0, info.1, &requester, &target)?;
+
+    // The request is deleted in the transfer transaction, so accepting it twice concurrently pays it once
+    let mut tx = state.db_pool.begin().await?;
+    let money_request = MoneyRequest::claim(money_request.id, &mut tx).await?.ok_or(InternalError::NotFound)?;
+    send_money(info.0, &target, &requester, money_request.amount, tx, &state).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[delete("/money-requests/{request_id}/")]
+pub async fn decline_money_request(state: web::Data<AppState>, info: web::Path<(GameID, FactionID, MoneyRequestID)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    let money_request = MoneyRequest::find(info.2, &state.db_pool).await?;
+    if money_request.target != claims.pid || money_request.game != info.0 {
+        return Err(InternalError::AccessDenied.into());
+    }
+    money_request.remove(&mut &state.db_pool).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_faction_mates() {
+        let gid = GameID(Uuid::new_v4());
+        let requester = get_player_mock(gid, FactionID(1));
+        let target = get_player_mock(gid, FactionID(1));
+
+        assert!(check_faction_mates(gid, FactionID(1), &requester, &target).is_ok());
+        // The players must belong to the faction of the request
+        assert!(check_faction_mates(gid, FactionID(2), &requester, &target).is_err());
+        assert!(check_faction_mates(gid, FactionID(1), &requester, &get_player_mock(gid, FactionID(2))).is_err());
+        assert!(check_faction_mates(gid, FactionID(1), &requester, &get_player_mock(GameID(Uuid::new_v4()), FactionID(1))).is_err());
+        assert!(check_faction_mates(gid, FactionID(1), &requester, &requester).is_err());
+    }
+
+    fn get_player_mock(gid: GameID, fid: FactionID) -> Player {
+        Player{
+            id: PlayerID(Uuid::new_v4()),
+            username: String::from("Galadruin"),
+            game: Some(gid),
+            lobby: None,
+            faction: Some(fid),
+            ready: true,
+            wallet: 200,
+            is_connected: true,
+            home_system: None,
+            is_eliminated: false,
+        }
+    }
+}
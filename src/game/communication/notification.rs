@@ -0,0 +1,176 @@
+use actix_web::{get, patch, web, HttpResponse};
+use serde::{Serialize, Deserialize};
+use sqlx::{PgPool, postgres::{PgRow, PgQueryAs}, FromRow, Executor, Error, Postgres, types::Json};
+use sqlx_core::row::Row;
+use uuid::Uuid;
+use crate::{
+    lib::{
+        Result,
+        auth::Claims,
+        error::{ServerError, InternalError},
+        pagination::{Paginator, new_paginated_response},
+        time::Time,
+    },
+    game::{
+        game::game::GameID,
+        player::PlayerID,
+    },
+    AppState,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub struct NotificationID(pub Uuid);
+
+/// Important events which are kept in database, so that players can read them even if they missed
+/// the associated websocket message.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, sqlx::Type)]
+#[sqlx(rename = "VARCHAR")]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all(serialize = "snake_case", deserialize = "snake_case"))]
+pub enum NotificationKind {
+    BattleReport,
+    MoneyReceived,
+    MoneyRequested,
+    SystemLost,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Notification {
+    pub id: NotificationID,
+    pub player: PlayerID,
+    pub game: GameID,
+    pub kind: NotificationKind,
+    pub data: serde_json::Value,
+    pub is_read: bool,
+    pub created_at: Time,
+}
+
+impl From<NotificationID> for Uuid {
+    fn from(nid: NotificationID) -> Self { nid.0 }
+}
+
+impl<'a> FromRow<'a, PgRow<'a>> for Notification {
+    fn from_row(row: &PgRow) -> std::result::Result<Self, Error> {
+        Ok(Notification {
+            id: row.try_get("id").map(NotificationID)?,
+            player: row.try_get("player_id").map(PlayerID)?,
+            game: row.try_get("game_id").map(GameID)?,
+            kind: row.try_get("kind")?,
+            data: (&*row.try_get::<Json<serde_json::Value>, _>("data")?).clone(),
+            is_read: row.try_get("is_read")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
+
+impl Notification {
+    pub fn new<T: Serialize>(player: PlayerID, game: GameID, kind: NotificationKind, data: T) -> Self {
+        Notification {
+            id: NotificationID(Uuid::new_v4()),
+            player,
+            game,
+            kind,
+            data: serde_json::value::to_value(data).unwrap(),
+            is_read: false,
+            created_at: Time::now(),
+        }
+    }
+
+    pub async fn find(nid: NotificationID, db_pool: &PgPool) -> Result<Self> {
+        sqlx::query_as("SELECT * FROM player__notifications WHERE id = $1")
+            .bind(Uuid::from(nid))
+            .fetch_one(db_pool).await.map_err(ServerError::if_row_not_found(InternalError::NotFound))
+    }
+
+    pub async fn find_by_player(pid: PlayerID, gid: GameID, limit: i64, offset: i64, db_pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as("SELECT * FROM player__notifications WHERE player_id = $1 AND game_id = $2 ORDER BY created_at DESC LIMIT $3 OFFSET $4")
+            .bind(Uuid::from(pid))
+            .bind(Uuid::from(gid))
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub async fn count_by_player(pid: PlayerID, gid: GameID, db_pool: &PgPool) -> Result<i64> {
+        sqlx::query_as("SELECT COUNT(*) FROM player__notifications WHERE player_id = $1 AND game_id = $2")
+            .bind(Uuid::from(pid))
+            .bind(Uuid::from(gid))
+            .fetch_one(db_pool).await
+            .map(|count: (i64,)| count.0)
+            .map_err(ServerError::from)
+    }
+
+    pub async fn insert<E>(&self, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("INSERT INTO player__notifications(id, player_id, game_id, kind, data, is_read, created_at) VALUES($1, $2, $3, $4, $5, $6, $7)")
+            .bind(Uuid::from(self.id))
+            .bind(Uuid::from(self.player))
+            .bind(Uuid::from(self.game))
+            .bind(self.kind)
+            .bind(Json(&self.data))
+            .bind(self.is_read)
+            .bind(self.created_at)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
+    pub async fn update<E>(&self, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE player__notifications SET is_read = $1 WHERE id = $2")
+            .bind(self.is_read)
+            .bind(Uuid::from(self.id))
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+}
+
+#[get("/")]
+pub async fn get_notifications(state: web::Data<AppState>, info: web::Path<(GameID,)>, pagination: web::Query<Paginator>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    let (count, notifications) = futures::join!(
+        Notification::count_by_player(claims.pid, info.0, &state.db_pool),
+        Notification::find_by_player(claims.pid, info.0, pagination.limit, (pagination.page - 1) * pagination.limit, &state.db_pool),
+    );
+    Ok(new_paginated_response(
+        pagination.limit,
+        pagination.page,
+        count?,
+        notifications?,
+    ))
+}
+
+#[patch("/{notification_id}/")]
+pub async fn read_notification(state: web::Data<AppState>, info: web::Path<(GameID, NotificationID)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    let mut notification = Notification::find(info.1, &state.db_pool).await?;
+
+    if notification.player != claims.pid || notification.game != info.0 {
+        return Err(InternalError::AccessDenied.into());
+    }
+    notification.is_read = true;
+    notification.update(&mut &state.db_pool).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_notification() {
+        #[derive(Serialize)]
+        struct MoneyData {
+            amount: usize,
+        }
+        let notification = Notification::new(
+            PlayerID(Uuid::new_v4()),
+            GameID(Uuid::new_v4()),
+            NotificationKind::MoneyReceived,
+            MoneyData{ amount: 150 },
+        );
+
+        assert!(!notification.is_read);
+        assert_eq!(150, notification.data["amount"]);
+    }
+}
@@ -4,13 +4,16 @@ use crate::{
     AppState,
     game::{
         game::game::GameID,
+        player::Player,
     },
-    lib::{Result, error::{ServerError, InternalError}},
+    lib::{Result, auth::Claims, error::{ServerError, InternalError}},
 };
 use uuid::Uuid;
 use sqlx::{PgPool, postgres::{PgRow, PgQueryAs}, FromRow, Executor, Error, Postgres};
 use sqlx_core::row::Row;
 
+pub const MIN_NB_FACTIONS: usize = 2;
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Faction{
     pub id: FactionID,
@@ -45,6 +48,14 @@ impl<'a> FromRow<'a, PgRow<'a>> for GameFaction {
     }
 }
 
+/// Metadata of a faction taking part in a game, along with its score
+#[derive(Serialize, Clone)]
+pub struct GameFactionDetails{
+    #[serde(flatten)]
+    pub faction: Faction,
+    pub victory_points: i32,
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone, Hash, PartialEq, Eq, Debug)]
 pub struct FactionID(pub u8);
 #[derive(Serialize, Deserialize, Copy, Clone)]
@@ -123,12 +134,33 @@ pub async fn get_factions(state: web::Data<AppState>) -> Result<HttpResponse> {
     Ok(HttpResponse::Ok().json(Faction::find_all(&state.db_pool).await?))
 }
 
-pub async fn generate_game_factions(gid: GameID, db_pool: &PgPool) -> Result<()> {
-    let factions = Faction::find_all(db_pool).await?.into_iter().map(|f| GameFaction{
-        faction: f.id,
-        game: gid.clone(),
-        victory_points: 0,
-    });
+#[get("/full/")]
+pub async fn get_game_factions(state: web::Data<AppState>, claims: Claims, info: web::Path<(GameID,)>) -> Result<HttpResponse> {
+    let player = Player::find(claims.pid, &state.db_pool).await?;
+    if player.game != Some(info.0) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let (f, gf) = futures::join!(
+        Faction::find_all(&state.db_pool),
+        GameFaction::find_all(info.0, &state.db_pool),
+    );
+    Ok(HttpResponse::Ok().json(merge_game_factions(f?, gf?)))
+}
+
+fn merge_game_factions(factions: Vec<Faction>, game_factions: Vec<GameFaction>) -> Vec<GameFactionDetails> {
+    factions.into_iter()
+        .filter_map(|faction| game_factions.iter()
+            .find(|gf| gf.faction == faction.id)
+            .map(|gf| GameFactionDetails{
+                faction,
+                victory_points: gf.victory_points,
+            })
+        )
+        .collect()
+}
+
+pub async fn generate_game_factions(gid: GameID, nb_factions: Option<u8>, db_pool: &PgPool) -> Result<()> {
+    let factions = select_game_factions(gid, Faction::find_all(db_pool).await?, nb_factions)?;
 
     let mut tx = db_pool.begin().await?;
     for faction in factions {
@@ -137,3 +169,58 @@ pub async fn generate_game_factions(gid: GameID, db_pool: &PgPool) -> Result<()>
     tx.commit().await?;
     Ok(())
 }
+
+/// The game factions are the first ones by ID, or all of them when their number is not restricted
+pub fn select_game_factions(gid: GameID, factions: Vec<Faction>, nb_factions: Option<u8>) -> Result<Vec<GameFaction>> {
+    let nb_factions = nb_factions.map_or(factions.len(), usize::from);
+    if nb_factions < MIN_NB_FACTIONS || nb_factions > factions.len() {
+        return Err(InternalError::Conflict.into());
+    }
+    Ok(factions.into_iter().take(nb_factions).map(|f| GameFaction{
+        faction: f.id,
+        game: gid,
+        victory_points: 0,
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_game_factions() {
+        let gid = GameID(Uuid::new_v4());
+        let factions = vec![
+            Faction{ id: FactionID(1), name: String::from("Kalankar"), color: FactionColor(255, 0, 0, 255) },
+            Faction{ id: FactionID(2), name: String::from("Valkar"), color: FactionColor(0, 0, 255, 255) },
+        ];
+        let game_factions = vec![
+            GameFaction{ faction: FactionID(2), game: gid, victory_points: 120 },
+        ];
+
+        let details = merge_game_factions(factions, game_factions);
+
+        assert_eq!(1, details.len());
+        assert_eq!(FactionID(2), details[0].faction.id);
+        assert_eq!("Valkar", details[0].faction.name);
+        assert_eq!(120, details[0].victory_points);
+    }
+
+    #[test]
+    fn test_select_game_factions() {
+        let gid = GameID(Uuid::new_v4());
+        let factions = vec![
+            Faction{ id: FactionID(1), name: String::from("Kalankar"), color: FactionColor(255, 0, 0, 255) },
+            Faction{ id: FactionID(2), name: String::from("Valkar"), color: FactionColor(0, 0, 255, 255) },
+            Faction{ id: FactionID(3), name: String::from("Adranite"), color: FactionColor(0, 255, 0, 255) },
+        ];
+
+        let game_factions = select_game_factions(gid, factions.clone(), Some(2)).unwrap();
+        assert_eq!(vec![FactionID(1), FactionID(2)], game_factions.iter().map(|gf| gf.faction).collect::<Vec<FactionID>>());
+        assert!(game_factions.iter().all(|gf| gf.game == gid && gf.victory_points == 0));
+
+        assert_eq!(3, select_game_factions(gid, factions.clone(), None).unwrap().len());
+        assert!(select_game_factions(gid, factions.clone(), Some(1)).is_err());
+        assert!(select_game_factions(gid, factions, Some(4)).is_err());
+    }
+}
@@ -1,27 +1,39 @@
 use std::collections::{HashSet, HashMap};
+use actix_web::{get, web, http::header, HttpResponse};
 use crate::{
     task,
     lib::{
+        auth::Claims,
         error::{ServerError, InternalError},
         time::Time,
         log::{log, Loggable},
         Result
     },
     game::{
+        communication::notification::{Notification, NotificationKind},
         faction::FactionID,
-        game::server::GameServer,
+        game::{
+            game::GameID,
+            server::GameServer,
+        },
         fleet::{
             combat::{
                 conquest::Conquest,
-                round::Round,
+                round::{BattleLosses, Round, SquadronAction, sum_losses},
             },
-            squadron::FleetSquadron,
-            fleet::{Fleet, FleetID, get_fleet_player_ids},
+            formation::FleetFormation,
+            squadron::{FleetSquadron, FleetSquadronID},
+            fleet::{Fleet, FleetID, get_fleet_player_ids, has_other_fleets_than},
+        },
+        ship::{
+            model::ShipModelCategory,
+            squadron::Squadron,
         },
         system::system::{System, SystemID},
-        player::{PlayerID, Player},
+        player::{PlayerID, Player, check_player_elimination},
     },
     ws::protocol,
+    AppState,
 };
 use serde::{Deserialize, Serialize};
 use sqlx::{PgPool, PgConnection, pool::PoolConnection, postgres::{PgRow, PgQueryAs}, FromRow, Executor, Transaction, Postgres, Error, types::Json};
@@ -39,13 +51,52 @@ pub struct Battle{
     pub system: SystemID,
     pub attacker: FleetID,
     pub fleets: HashMap<FactionID, HashMap<FleetID, Fleet>>,
+    /// Fleets as they entered the battle, only served in its replay. Missing for the battles recorded before it was stored
+    #[serde(skip)]
+    pub initial_fleets: Option<HashMap<FactionID, HashMap<FleetID, Fleet>>>,
     pub rounds: Vec<Round>,
     pub defender_faction: Option<FactionID>,
+    /// Fleet formed by the ships stationed in the attacked system, given back to the system after the battle
+    pub garrison: Option<FleetID>,
     pub victor: Option<FactionID>,
     pub begun_at: Time,
     pub ended_at: Option<Time>,
 }
 
+pub const GARRISON_FLEET_NAME: &str = "Garrison";
+/// The victor must destroy this many times the ships it lost for its victory to be decisive
+const DECISIVE_VICTORY_RATIO: u32 = 3;
+
+/// Public view of an ongoing battle. The fleets are only detailed to the factions taking part in it.
+#[derive(Serialize, Clone)]
+pub struct BattleSummary {
+    pub id: BattleID,
+    pub system: SystemID,
+    pub factions: Vec<FactionID>,
+    pub defender_faction: Option<FactionID>,
+    pub round_number: u16,
+    pub begun_at: Time,
+    pub fleets: Option<HashMap<FactionID, HashMap<FleetID, Fleet>>>,
+}
+
+/// Entry of the combat history of a system, covering both ended and ongoing battles
+#[derive(Serialize, Clone)]
+pub struct SystemBattle {
+    #[serde(flatten)]
+    pub summary: BattleSummary,
+    pub victor: Option<FactionID>,
+    pub ended_at: Option<Time>,
+}
+
+/// Outcome sent to the participants when a battle ends. The rounds can be fetched afterwards.
+#[derive(Serialize, Clone)]
+pub struct BattleOutcome {
+    pub id: BattleID,
+    pub system: SystemID,
+    pub victor: Option<FactionID>,
+    pub losses: HashMap<FactionID, BattleLosses>,
+}
+
 #[derive(Serialize, Clone)]
 pub struct Report {
     pub player: PlayerID,
@@ -63,7 +114,9 @@ impl<'a> FromRow<'a, PgRow<'a>> for Battle {
             system: row.try_get("system_id").map(SystemID)?,
             attacker: row.try_get("attacker_id").map(FleetID)?,
             defender_faction: row.try_get("defender_faction_id").map(|id: i32| FactionID(id as u8)).ok(),
+            garrison: row.try_get("garrison_id").map(FleetID).ok(),
             fleets: (&*row.try_get::<Json<HashMap<FactionID, HashMap<FleetID, Fleet>>>, _>("fleets")?).clone(),
+            initial_fleets: row.try_get::<Json<HashMap<FactionID, HashMap<FleetID, Fleet>>>, _>("initial_fleets").map(|fleets| fleets.0).ok(),
             rounds: (&*row.try_get::<Json<Vec<Round>>, _>("rounds")?).clone(),
             victor: row.try_get("victor_id").map(|id: i32| FactionID(id as u8)).ok(),
             begun_at: row.try_get("begun_at")?,
@@ -82,13 +135,15 @@ impl Battle {
     pub async fn insert<E>(&self, exec: &mut E) -> Result<u64>
     where
         E: Executor<Database = Postgres> {
-        sqlx::query("INSERT INTO fleet__combat__battles(id, attacker_id, system_id, fleets, rounds, defender_faction_id, begun_at, ended_at) VALUES($1, $2, $3, $4, $5, $6, $7, $8)")
+        sqlx::query("INSERT INTO fleet__combat__battles(id, attacker_id, system_id, fleets, initial_fleets, rounds, defender_faction_id, garrison_id, begun_at, ended_at) VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)")
             .bind(Uuid::from(self.id))
             .bind(Uuid::from(self.attacker))
             .bind(Uuid::from(self.system))
             .bind(Json(&self.fleets))
+            .bind(self.initial_fleets.as_ref().map(Json))
             .bind(Json(&self.rounds))
             .bind(self.defender_faction.map(i32::from))
+            .bind(self.garrison.map(Uuid::from))
             .bind(self.begun_at)
             .bind(self.ended_at)
             .execute(&mut *exec).await.map_err(ServerError::from)
@@ -121,6 +176,42 @@ impl Battle {
         Ok(result)
     }
 
+    pub async fn find_current_by_game(gid: GameID, db_pool: &PgPool) -> Result<Vec<Battle>> {
+        sqlx::query_as("SELECT b.* FROM fleet__combat__battles b
+            INNER JOIN map__systems s ON s.id = b.system_id
+            WHERE s.game_id = $1 AND b.ended_at IS NULL
+            ORDER BY b.begun_at")
+            .bind(Uuid::from(gid))
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub async fn find_current_by_systems(ids: Vec<SystemID>, db_pool: &PgPool) -> Result<Vec<Battle>> {
+        sqlx::query_as("SELECT * FROM fleet__combat__battles WHERE system_id = ANY($1) AND ended_at IS NULL ORDER BY begun_at")
+            .bind(ids.into_iter().map(Uuid::from).collect::<Vec<Uuid>>())
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub async fn find_by_system(sid: SystemID, db_pool: &PgPool) -> Result<Vec<Battle>> {
+        sqlx::query_as("SELECT * FROM fleet__combat__battles WHERE system_id = $1 ORDER BY begun_at")
+            .bind(Uuid::from(sid))
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub async fn find_last_ended_by_system(sid: SystemID, db_pool: &PgPool) -> Result<Option<Battle>> {
+        sqlx::query_as("SELECT * FROM fleet__combat__battles WHERE system_id = $1 AND ended_at IS NOT NULL ORDER BY ended_at DESC LIMIT 1")
+            .bind(Uuid::from(sid))
+            .fetch_optional(db_pool).await.map_err(ServerError::from)
+    }
+
+    /// The defeated ships can only be captured once, even if the system changes hands again afterwards
+    pub async fn claim_captured_ships<E>(&self, exec: &mut E) -> Result<u64>
+    where
+        E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE fleet__combat__battles SET are_ships_captured = TRUE WHERE id = $1 AND are_ships_captured = FALSE")
+            .bind(Uuid::from(self.id))
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
     pub async fn count_current_by_system(sid: &SystemID, db_pool: &PgPool) -> Result<i16> {
         sqlx::query_as("SELECT COUNT(*) FROM fleet__combat__battles WHERE system_id = $1 AND ended_at IS NULL")
             .bind(Uuid::from(*sid))
@@ -159,7 +250,7 @@ impl Battle {
                     continue;
                 }
                 for squadron in &fleet.squadrons {
-                    if squadron.quantity > 0 {
+                    if squadron.can_fight() {
                         let initiative = (f64::from(squadron.category.to_data().combat_speed) * rng.gen_range(0.5, 1.5)).round() as i32;
 
                         squadrons.entry(initiative)
@@ -172,6 +263,46 @@ impl Battle {
         squadrons.values().flatten().cloned().collect()
     }
 
+    /// Fleets as they entered the battle. For the battles recorded before they were stored,
+    /// they are rebuilt by giving back the ships lost in each round to the remaining fleets
+    pub fn get_initial_fleets(&self) -> HashMap<FactionID, HashMap<FleetID, Fleet>> {
+        if let Some(initial_fleets) = &self.initial_fleets {
+            return initial_fleets.clone();
+        }
+        let mut losses: HashMap<FleetSquadronID, u16> = HashMap::new();
+        for action in self.rounds.iter().flat_map(|r| r.squadron_actions.iter()) {
+            let (target, loss) = action.get_target_loss();
+            *losses.entry(target).or_insert(0) += loss;
+        }
+        let mut fleets = self.fleets.clone();
+        for squadron in fleets.values_mut().flat_map(HashMap::values_mut).flat_map(|fleet| fleet.squadrons.iter_mut()) {
+            squadron.quantity = squadron.quantity.saturating_add(losses.get(&squadron.id).copied().unwrap_or(0));
+        }
+        fleets
+    }
+
+    /// Part of the ships destroyed by the victor which it seizes, after a decisive victory only
+    pub fn get_captured_ships(&self, capture_rate: f64) -> HashMap<ShipModelCategory, u16> {
+        let victor = match self.victor {
+            Some(victor) if capture_rate > 0.0 => victor,
+            _ => return HashMap::new(),
+        };
+        let losses = sum_losses(&self.rounds).remove(&victor).unwrap_or_default();
+        if losses.ships_destroyed == 0 || losses.ships_lost * DECISIVE_VICTORY_RATIO > losses.ships_destroyed {
+            return HashMap::new();
+        }
+        let mut destroyed_ships: HashMap<ShipModelCategory, u32> = HashMap::new();
+        for (target_faction, category, loss) in self.rounds.iter().flat_map(|r| r.squadron_actions.iter()).filter_map(SquadronAction::get_category_loss) {
+            if target_faction != victor {
+                *destroyed_ships.entry(category).or_insert(0) += loss as u32;
+            }
+        }
+        destroyed_ships.into_iter()
+            .map(|(category, quantity)| (category, (f64::from(quantity) * capture_rate).floor() as u16))
+            .filter(|(_, quantity)| *quantity > 0)
+            .collect()
+    }
+
     pub fn get_fleet_ids(&self) -> Vec<FleetID> {
         self.fleets
             .iter()
@@ -180,10 +311,17 @@ impl Battle {
             .collect()
     }
 
+    /// Surviving fleets of the victor, as updated by the last round
+    pub fn get_victor_fleets(&self) -> Vec<&Fleet> {
+        self.victor
+            .and_then(|fid| self.fleets.get(&fid))
+            .map_or(vec![], |fleets| fleets.values().filter(|f| !f.is_destroyed).collect())
+    }
+
     fn process_victor(&self) -> Result<FactionID> {
         for (fid, fleets) in &self.fleets {
             for fleet in fleets.values() {
-                if fleet.squadrons.iter().any(|s| s.quantity > 0) {
+                if fleet.squadrons.iter().any(FleetSquadron::can_fight) {
                     return Ok(*fid);
                 }
             }
@@ -191,13 +329,29 @@ impl Battle {
         Err(InternalError::NotFound.into())
     }
 
-    pub async fn engage(arriver: &Fleet, orbiting_fleets: &HashMap<FleetID, Fleet>, system: &System, defender_faction: Option<FactionID>, server: &GameServer) -> Result<()> {
-        Conquest::stop(&system, &server).await?;
-        
+    /// The garrison is formed from the ships stationed when the battle begins
+    pub async fn engage(
+        arriver: &Fleet,
+        orbiting_fleets: &HashMap<FleetID, Fleet>,
+        system: &System,
+        defender_faction: Option<FactionID>,
+        has_garrison: bool,
+        server: &GameServer
+    ) -> Result<()> {
+        let garrison = if has_garrison { station_garrison(system, &server.state.db_pool).await? } else { None };
         let mut fleets = orbiting_fleets.clone();
+        if let Some(g) = garrison.as_ref() {
+            fleets.insert(g.id, g.clone());
+        }
+        // The stationed ships may have left the system since the arrival of the fleet
+        if !has_other_fleets_than(&fleets, arriver) {
+            return Conquest::resume(arriver, system, None, server).await;
+        }
+        Conquest::stop(&system, &server).await?;
+
         fleets.insert(arriver.id.clone(), arriver.clone());
     
-        let battle = init_battle(arriver, system, fleets, defender_faction, &server.state.db_pool).await?;
+        let battle = init_battle(arriver, system, fleets, defender_faction, garrison.map(|g| g.id), &server.state.db_pool).await?;
     
         server.ws_broadcast(&protocol::Message::new(protocol::Action::BattleStarted, &battle, None)).await?;
     
@@ -219,6 +373,32 @@ impl Battle {
         Ok(())
     }
 
+    /// The surviving ships of the garrison are stationed in the system again
+    async fn disband_garrison(&self, db_pool: &PgPool) -> Result<()> {
+        let garrison = match self.garrison.and_then(|gid| self.fleets.values().find_map(|fleets| fleets.get(&gid))) {
+            Some(garrison) if !garrison.is_destroyed => garrison,
+            _ => return Ok(()),
+        };
+        let mut quantities: HashMap<ShipModelCategory, i32> = HashMap::new();
+        for fs in &garrison.squadrons {
+            *quantities.entry(fs.category).or_insert(0) += fs.quantity as i32;
+        }
+
+        let mut tx = db_pool.begin().await?;
+        for (category, quantity) in quantities {
+            let squadron = Squadron::find_by_system_and_category_for_update(self.system, category, &mut tx).await?;
+            let stationed_quantity = squadron.as_ref().map_or(0, |s| s.quantity as i32);
+            Squadron::assign(squadron, self.system, category, stationed_quantity + quantity, &mut tx).await?;
+        }
+        for fs in &garrison.squadrons {
+            fs.remove(&mut tx).await?;
+        }
+        garrison.remove(&mut tx).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
     // remaining fleets are grouped by faction ID. If there is less than two factions present, the fight is over
     pub fn is_over(&self) -> bool {
         2 > self.fleets.keys().len()
@@ -228,10 +408,14 @@ impl Battle {
         self.victor = Some(self.process_victor()?);
         self.ended_at = Some(Time::now());
         self.update(&mut &server.state.db_pool).await?;
-        
+        self.disband_garrison(&server.state.db_pool).await?;
+
+        // The rounds are the heaviest part of the battle and are only relevant to its participants
+        let mut ended_battle = self.clone();
+        ended_battle.rounds.clear();
         server.ws_broadcast(&protocol::Message::new(
             protocol::Action::BattleEnded,
-            self.clone(),
+            ended_battle,
             None
         )).await?;
 
@@ -250,15 +434,111 @@ impl Battle {
             &server.state.logger
         );
 
+        #[derive(Serialize, Clone)]
+        struct BattleReportData {
+            battle: BattleID,
+            system: SystemID,
+            victor: Option<FactionID>,
+        }
+        let data = BattleReportData{ battle: self.id, system: self.system, victor: self.victor };
+        let outcome = BattleOutcome::new(self);
+        for report in Report::find_by_battle(self.id, &server.state.db_pool).await? {
+            Notification::new(report.player, server.id, NotificationKind::BattleReport, data.clone())
+                .insert(&mut &server.state.db_pool).await?;
+            server.player_broadcast(&report.player, &protocol::Message::new(
+                protocol::Action::BattleSummary,
+                outcome.clone(),
+                None,
+            ));
+            check_player_elimination(report.player, server).await?;
+        }
+
         if self.victor == self.defender_faction {
             return Ok(());
         }
 
-        Conquest::resume(&fleet, &system, self.victor, &server).await
+        // The surviving fleets are taken from memory, as reading them back could race the battle transaction
+        Conquest::resume_with_fleets(&fleet, self.get_victor_fleets(), &system, self.victor, &server).await
+    }
+}
+
+impl BattleOutcome {
+    pub fn new(battle: &Battle) -> Self {
+        BattleOutcome{
+            id: battle.id,
+            system: battle.system,
+            victor: battle.victor,
+            losses: sum_losses(&battle.rounds),
+        }
+    }
+}
+
+impl BattleSummary {
+    pub fn new(battle: Battle, faction: Option<FactionID>) -> Self {
+        let mut factions: Vec<FactionID> = battle.fleets.keys().copied().collect();
+        factions.sort_by_key(|fid| fid.0);
+        let is_involved = faction.map_or(false, |fid| factions.contains(&fid));
+
+        BattleSummary {
+            id: battle.id,
+            system: battle.system,
+            factions,
+            defender_faction: battle.defender_faction,
+            round_number: battle.rounds.len() as u16,
+            begun_at: battle.begun_at,
+            fleets: if is_involved { Some(battle.fleets) } else { None },
+        }
+    }
+}
+
+impl SystemBattle {
+    pub fn new(battle: Battle, faction: Option<FactionID>) -> Self {
+        let (victor, ended_at) = (battle.victor, battle.ended_at);
+
+        SystemBattle {
+            summary: BattleSummary::new(battle, faction),
+            victor,
+            ended_at,
+        }
+    }
+}
+
+impl<'a> FromRow<'a, PgRow<'a>> for Report {
+    fn from_row(row: &PgRow) -> std::result::Result<Self, Error> {
+        Ok(Report {
+            player: row.try_get("player_id").map(PlayerID)?,
+            battle: row.try_get("battle_id").map(BattleID)?,
+        })
     }
 }
 
 impl Report {
+    pub async fn find_by_battle(bid: BattleID, db_pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as("SELECT * FROM fleet__combat__reports WHERE battle_id = $1")
+            .bind(Uuid::from(bid))
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub async fn exists(bid: BattleID, pid: PlayerID, db_pool: &PgPool) -> Result<bool> {
+        sqlx::query_as("SELECT COUNT(*) FROM fleet__combat__reports WHERE battle_id = $1 AND player_id = $2")
+            .bind(Uuid::from(bid))
+            .bind(Uuid::from(pid))
+            .fetch_one(db_pool).await
+            .map(|count: (i64,)| count.0 > 0)
+            .map_err(ServerError::from)
+    }
+
+    pub async fn find_battle_ids_by_system(sid: SystemID, pid: PlayerID, db_pool: &PgPool) -> Result<HashSet<BattleID>> {
+        sqlx::query_as("SELECT r.battle_id FROM fleet__combat__reports r
+            INNER JOIN fleet__combat__battles b ON b.id = r.battle_id
+            WHERE b.system_id = $1 AND r.player_id = $2")
+            .bind(Uuid::from(sid))
+            .bind(Uuid::from(pid))
+            .fetch_all(db_pool).await
+            .map(|ids: Vec<(Uuid,)>| ids.into_iter().map(|(id,)| BattleID(id)).collect())
+            .map_err(ServerError::from)
+    }
+
     pub async fn insert<E>(&self, exec: &mut E) -> Result<u64>
     where
         E: Executor<Database = Postgres>  {
@@ -269,6 +549,135 @@ impl Report {
     }
 }
 
+#[get("/ongoing/")]
+pub async fn get_ongoing_battles(state: web::Data<AppState>, info: web::Path<(GameID,)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    let player = Player::find(claims.pid, &state.db_pool).await?;
+    if player.game != Some(info.0) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    // Battles are announced to the whole game, so they are all visible
+    let battles: Vec<BattleSummary> = Battle::find_current_by_game(info.0, &state.db_pool).await?
+        .into_iter()
+        .map(|battle| BattleSummary::new(battle, player.faction))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(battles))
+}
+
+/// Returns the whole battle, including its rounds, to one of its participants
+#[get("/{battle_id}/")]
+pub async fn get_battle(state: web::Data<AppState>, info: web::Path<(GameID, BattleID)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    let (b, r) = futures::join!(
+        Battle::find(info.1, &state.db_pool),
+        Report::exists(info.1, claims.pid, &state.db_pool),
+    );
+    let battle = b?;
+    if !r? || System::find(battle.system, &state.db_pool).await?.game != info.0 {
+        return Err(InternalError::AccessDenied.into());
+    }
+    Ok(HttpResponse::Ok().json(battle))
+}
+
+/// Returns the combat history of a system.
+/// Its current and former owners see all its battles, the other players only the ones they were reported
+#[get("/")]
+pub async fn get_system_battles(state: web::Data<AppState>, info: web::Path<(GameID, SystemID)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    let (s, p, b, r, c) = futures::join!(
+        System::find(info.1, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+        Battle::find_by_system(info.1, &state.db_pool),
+        Report::find_battle_ids_by_system(info.1, claims.pid, &state.db_pool),
+        Conquest::find_by_system(&info.1, &state.db_pool),
+    );
+    let system = s?;
+    let player = p?;
+    if system.game != info.0 || player.game != Some(info.0) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let has_owned_system = system.player == Some(player.id)
+        || c?.iter().any(|conquest| conquest.player == player.id && conquest.is_successful);
+
+    let battles: Vec<SystemBattle> = filter_visible_battles(b?, &r?, has_owned_system)
+        .into_iter()
+        .map(|battle| SystemBattle::new(battle, player.faction))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(battles))
+}
+
+fn filter_visible_battles(battles: Vec<Battle>, reported_battles: &HashSet<BattleID>, has_owned_system: bool) -> Vec<Battle> {
+    battles.into_iter()
+        .filter(|battle| has_owned_system || reported_battles.contains(&battle.id))
+        .collect()
+}
+
+/// Sends the whole battle to one of its participants as a JSON file, along with the fleets it began with.
+/// The rounds are serialized one by one while the response is streamed
+#[get("/{battle_id}/replay/")]
+pub async fn get_battle_replay(state: web::Data<AppState>, info: web::Path<(GameID, BattleID)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    #[derive(Serialize)]
+    struct BattleReplay<'a> {
+        id: BattleID,
+        system: SystemID,
+        attacker: FleetID,
+        defender_faction: Option<FactionID>,
+        victor: Option<FactionID>,
+        begun_at: Time,
+        ended_at: Option<Time>,
+        initial_fleets: HashMap<FactionID, HashMap<FleetID, Fleet>>,
+        fleets: &'a HashMap<FactionID, HashMap<FleetID, Fleet>>,
+    }
+    let (b, r) = futures::join!(
+        Battle::find(info.1, &state.db_pool),
+        Report::exists(info.1, claims.pid, &state.db_pool),
+    );
+    let mut battle = b?;
+    if !r? || System::find(battle.system, &state.db_pool).await?.game != info.0 {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let head = serde_json::to_vec(&BattleReplay{
+        id: battle.id,
+        system: battle.system,
+        attacker: battle.attacker,
+        defender_faction: battle.defender_faction,
+        victor: battle.victor,
+        begun_at: battle.begun_at,
+        ended_at: battle.ended_at,
+        initial_fleets: battle.get_initial_fleets(),
+        fleets: &battle.fleets,
+    });
+    let rounds = std::mem::take(&mut battle.rounds);
+    let nb_rounds = rounds.len();
+
+    // The rounds are appended to the battle object, which is reopened for them
+    let chunks = std::iter::once(head.map(|mut head| {
+            head.pop();
+            head.extend_from_slice(b",\"rounds\":[");
+            head
+        }))
+        .chain(rounds.into_iter().enumerate().map(move |(i, round)| serde_json::to_vec(&round).map(|mut chunk| {
+            if i + 1 < nb_rounds {
+                chunk.push(b',');
+            }
+            chunk
+        })))
+        .chain(std::iter::once(Ok(b"]}".to_vec())))
+        .map(|chunk| chunk.map(web::Bytes::from).map_err(actix_web::Error::from));
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"battle-{}.json\"", battle.id.0))
+        .streaming(futures::stream::iter(chunks)))
+}
+
 pub async fn get_factions_fleets(fleets: HashMap<FleetID, Fleet>, db_pool: &PgPool) -> Result<HashMap<FactionID, HashMap<FleetID, Fleet>>> {
     let players: HashMap<PlayerID, Player> = Player::find_by_ids(get_fleet_player_ids(&fleets), &db_pool).await?
         .iter()
@@ -287,14 +696,89 @@ pub async fn get_factions_fleets(fleets: HashMap<FleetID, Fleet>, db_pool: &PgPo
     Ok(faction_parties)
 }
 
-async fn init_battle(attacker: &Fleet, system: &System, fleets: HashMap<FleetID, Fleet>, defender_faction: Option<FactionID>, db_pool: &PgPool) -> Result<Battle> {
+/// Ships stationed in an owned system, out of any fleet, defend it as a garrison
+pub fn form_garrison(system: &System, squadrons: Vec<Squadron>) -> Option<Fleet> {
+    let player = system.player?;
+    let fid = FleetID(Uuid::new_v4());
+    let squadrons: Vec<FleetSquadron> = squadrons.into_iter()
+        .filter(|s| s.quantity > 0)
+        .map(|s| FleetSquadron{
+            id: FleetSquadronID(Uuid::new_v4()),
+            fleet: fid,
+            formation: get_garrison_formation(s.category),
+            category: s.category,
+            quantity: s.quantity,
+            max_quantity: s.quantity,
+            auto_reinforce: false,
+        })
+        .collect();
+    if squadrons.is_empty() {
+        return None;
+    }
+    Some(Fleet{
+        id: fid,
+        name: Some(GARRISON_FLEET_NAME.to_string()),
+        system: system.id,
+        destination_system: None,
+        destination_arrival_date: None,
+        departure_date: None,
+        player,
+        squadrons,
+        is_destroyed: false,
+    })
+}
+
+/// The system squadrons are grouped by category, so each one gets its own formation
+const fn get_garrison_formation(category: ShipModelCategory) -> FleetFormation {
+    match category {
+        ShipModelCategory::Fighter => FleetFormation::Left,
+        ShipModelCategory::Corvette => FleetFormation::Right,
+        ShipModelCategory::Frigate => FleetFormation::Center,
+        ShipModelCategory::Cruiser => FleetFormation::Rear,
+    }
+}
+
+/// Moves the stationed ships into the garrison fleet for the time of the battle.
+/// The garrison is formed from the locked stock, so ships produced or assigned meanwhile are accounted for
+async fn station_garrison(system: &System, db_pool: &PgPool) -> Result<Option<Fleet>> {
+    let mut tx = db_pool.begin().await?;
+    let squadrons = Squadron::find_by_system_for_update(system.id, &mut tx).await?;
+    let garrison = match form_garrison(system, squadrons.clone()) {
+        Some(garrison) => garrison,
+        None => {
+            tx.rollback().await?;
+            return Ok(None);
+        },
+    };
+    garrison.insert(&mut tx).await?;
+    for squadron in &squadrons {
+        squadron.remove(&mut tx).await?;
+    }
+    for fs in &garrison.squadrons {
+        fs.insert(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(Some(garrison))
+}
+
+async fn init_battle(
+    attacker: &Fleet,
+    system: &System,
+    fleets: HashMap<FleetID, Fleet>,
+    defender_faction: Option<FactionID>,
+    garrison: Option<FleetID>,
+    db_pool: &PgPool
+) -> Result<Battle> {
+    let fleets = get_factions_fleets(fleets, &db_pool).await?;
     let battle = Battle{
         id: BattleID(Uuid::new_v4()),
         attacker: attacker.id,
         system: system.id.clone(),
-        fleets: get_factions_fleets(fleets, &db_pool).await?,
+        initial_fleets: Some(fleets.clone()),
+        fleets,
         rounds: vec![],
         defender_faction,
+        garrison,
         victor: None,
         begun_at: Time::now(),
         ended_at: None,
@@ -348,16 +832,16 @@ pub async fn update_fleets(battle: &Battle, server: &GameServer) -> Result<HashM
     Ok(remaining_fleets)
 }
 
-async fn update_fleet(mut fleet: Fleet, tx: &mut Transaction<PoolConnection<PgConnection>>) -> Result<bool> {
+pub async fn update_fleet(mut fleet: Fleet, tx: &mut Transaction<PoolConnection<PgConnection>>) -> Result<bool> {
     for s in &fleet.squadrons {
-        if s.quantity > 0 {
+        if s.can_fight() {
             s.update(tx).await?;
         } else {
             s.remove(tx).await?;
         }
     }
     
-    fleet.squadrons.retain(|s| s.quantity > 0);
+    fleet.squadrons.retain(FleetSquadron::can_fight);
 
     if fleet.squadrons.is_empty() {
         fleet.is_destroyed = true;
@@ -366,3 +850,162 @@ async fn update_fleet(mut fleet: Fleet, tx: &mut Transaction<PoolConnection<PgCo
 
     Ok(fleet.is_destroyed)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{
+        ship::squadron::SquadronID,
+        system::system::{Coordinates, SystemKind},
+    };
+
+    #[test]
+    fn test_battle_summary() {
+        let mut fleets = HashMap::new();
+        fleets.insert(FactionID(1), HashMap::new());
+        fleets.insert(FactionID(2), HashMap::new());
+        let battle = Battle{
+            id: BattleID(Uuid::new_v4()),
+            system: SystemID(Uuid::new_v4()),
+            attacker: FleetID(Uuid::new_v4()),
+            fleets,
+            initial_fleets: None,
+            rounds: vec![Round::new(BattleID(Uuid::new_v4()), 1)],
+            defender_faction: Some(FactionID(2)),
+            garrison: None,
+            victor: None,
+            begun_at: Time::now(),
+            ended_at: None,
+        };
+
+        let summary = BattleSummary::new(battle.clone(), Some(FactionID(1)));
+        assert_eq!(vec![FactionID(1), FactionID(2)], summary.factions);
+        assert_eq!(1, summary.round_number);
+        assert!(summary.fleets.is_some());
+
+        assert!(BattleSummary::new(battle.clone(), Some(FactionID(3))).fleets.is_none());
+        assert!(BattleSummary::new(battle, None).fleets.is_none());
+    }
+
+    #[test]
+    fn test_filter_visible_battles() {
+        let create_battle = || Battle{
+            id: BattleID(Uuid::new_v4()),
+            system: SystemID(Uuid::new_v4()),
+            attacker: FleetID(Uuid::new_v4()),
+            fleets: HashMap::new(),
+            initial_fleets: None,
+            rounds: vec![],
+            defender_faction: None,
+            garrison: None,
+            victor: None,
+            begun_at: Time::now(),
+            ended_at: None,
+        };
+        let battles = vec![create_battle(), create_battle()];
+        let mut reported_battles = HashSet::new();
+        reported_battles.insert(battles[1].id);
+
+        let visible_battles = filter_visible_battles(battles.clone(), &reported_battles, false);
+        assert_eq!(1, visible_battles.len());
+        assert!(visible_battles[0].id == battles[1].id);
+
+        assert_eq!(2, filter_visible_battles(battles.clone(), &HashSet::new(), true).len());
+        assert!(filter_visible_battles(battles, &HashSet::new(), false).is_empty());
+    }
+
+    #[test]
+    fn test_three_faction_battle() {
+        let mut fleets = HashMap::new();
+        for fid in 1..=3 {
+            let fleet = get_fleet_mock(10);
+            let mut faction_fleets = HashMap::new();
+            faction_fleets.insert(fleet.id, fleet);
+            fleets.insert(FactionID(fid), faction_fleets);
+        }
+        let mut battle = Battle{
+            id: BattleID(Uuid::new_v4()),
+            system: SystemID(Uuid::new_v4()),
+            attacker: FleetID(Uuid::new_v4()),
+            fleets,
+            initial_fleets: None,
+            rounds: vec![],
+            defender_faction: Some(FactionID(1)),
+            garrison: None,
+            victor: None,
+            begun_at: Time::now(),
+            ended_at: None,
+        };
+        assert!(!battle.is_over());
+
+        // Destroyed factions are removed from the battle by update_fleets
+        battle.fleets.remove(&FactionID(1));
+        assert!(!battle.is_over());
+
+        battle.fleets.remove(&FactionID(3));
+        assert!(battle.is_over());
+        assert_eq!(FactionID(2), battle.process_victor().unwrap());
+    }
+
+    #[test]
+    fn test_form_garrison() {
+        let owner = PlayerID(Uuid::new_v4());
+        let mut system = System{
+            id: SystemID(Uuid::new_v4()),
+            game: GameID(Uuid::new_v4()),
+            player: Some(owner),
+            kind: SystemKind::BaseSystem,
+            coordinates: Coordinates{ x: 0.0, y: 0.0 },
+            unreachable: false,
+        };
+        let get_squadrons = |system: &System| vec![
+            Squadron{ id: SquadronID(Uuid::new_v4()), system: system.id, category: ShipModelCategory::Fighter, quantity: 20 },
+            Squadron{ id: SquadronID(Uuid::new_v4()), system: system.id, category: ShipModelCategory::Cruiser, quantity: 2 },
+            Squadron{ id: SquadronID(Uuid::new_v4()), system: system.id, category: ShipModelCategory::Frigate, quantity: 0 },
+        ];
+        let attacker = get_fleet_mock(10);
+
+        // A system without any fleet still fights back with its stationed ships
+        let garrison = form_garrison(&system, get_squadrons(&system)).unwrap();
+        assert_eq!(owner, garrison.player);
+        assert_eq!(system.id, garrison.system);
+        assert_eq!(2, garrison.squadrons.len());
+        assert!(garrison.can_fight());
+        assert!(garrison.squadrons.iter().all(|s| s.fleet == garrison.id));
+        assert_eq!(FleetFormation::Left, garrison.squadrons[0].formation);
+        assert_eq!(FleetFormation::Rear, garrison.squadrons[1].formation);
+
+        let mut fleets = HashMap::new();
+        fleets.insert(attacker.id, attacker.clone());
+        assert!(!has_other_fleets_than(&fleets, &attacker));
+        fleets.insert(garrison.id, garrison);
+        assert!(has_other_fleets_than(&fleets, &attacker));
+
+        assert!(form_garrison(&system, vec![]).is_none());
+        system.player = None;
+        assert!(form_garrison(&system, get_squadrons(&system)).is_none());
+    }
+
+    fn get_fleet_mock(quantity: u16) -> Fleet {
+        let id = FleetID(Uuid::new_v4());
+        Fleet{
+            id,
+            name: None,
+            player: PlayerID(Uuid::new_v4()),
+            system: SystemID(Uuid::new_v4()),
+            destination_system: None,
+            destination_arrival_date: None,
+            departure_date: None,
+            squadrons: vec![FleetSquadron{
+                id: FleetSquadronID(Uuid::new_v4()),
+                fleet: id,
+                formation: FleetFormation::Center,
+                category: ShipModelCategory::Fighter,
+                quantity,
+                max_quantity: quantity,
+                auto_reinforce: false,
+            }],
+            is_destroyed: false,
+        }
+    }
+}
@@ -1,39 +1,45 @@
-use actix_web::web;
+use actix_web::{get, web, HttpResponse};
 use crate::{
     task,
     cancel_task,
     lib::{
         time::{ms_to_time, Time},
         log::{log, Loggable},
-        error::ServerError,
+        error::{ServerError, InternalError},
+        auth::Claims,
         Result
     },
     game::{
+        communication::notification::{Notification, NotificationKind},
         faction::FactionID,
         fleet::{
-            fleet::{FleetID, Fleet},
+            combat::battle::Battle,
+            fleet::{FleetID, Fleet, has_other_fleets_than},
         },
         game::{
-            game::{Game, GameID},
+            game::{Game, GameConfig, GameID},
             option::GameOptionSpeed,
             server::{GameServer, GameServerTask},
         },
-        player::{Player, PlayerID},
+        player::{Player, PlayerID, check_player_elimination},
+        ship::squadron::Squadron,
         system::system::{SystemID, System},
     },
     AppState,
     ws::protocol,
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use futures::executor::block_on;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use sqlx::{PgPool, PgConnection, pool::PoolConnection, postgres::{PgRow, PgQueryAs}, FromRow, Executor, Error, Transaction, Postgres, types::Json};
 use sqlx_core::row::Row;
 
-const CONQUEST_DURATION_MAX: f64 = 60000.0;
-const CONQUEST_DURATION_MIN: f64 = 5000.0;
-const CONQUEST_STRENGTH_COEFF: f64 = 100.0;
+pub const CONQUEST_DURATION_MAX: f64 = 60000.0;
+pub const CONQUEST_DURATION_MIN: f64 = 5000.0;
+/// Claiming an empty and uncontested neutral system does not need a full conquest
+pub const COLONIZATION_DURATION: f64 = 3000.0;
+pub const CONQUEST_STRENGTH_COEFF: f64 = 100.0;
 
 #[derive(Serialize, Deserialize, Clone, Hash, PartialEq, Eq, Copy)]
 pub struct ConquestID(pub Uuid);
@@ -42,6 +48,16 @@ impl From<ConquestID> for Uuid {
     fn from(cid: ConquestID) -> Self { cid.0 }
 }
 
+/// How a conquest ending is applied, depending on what happened concurrently to the conquest and its system
+#[derive(Debug, PartialEq)]
+enum ConquestResolution {
+    Applied,
+    /// The conquest was cancelled or resolved meanwhile
+    AlreadyOver,
+    /// The system changed hands meanwhile, the conquest is cancelled instead of overwriting its owner
+    OwnerChanged,
+}
+
 #[derive(Serialize, Clone)]
 pub struct Conquest {
     pub id: ConquestID,
@@ -89,6 +105,10 @@ impl GameServerTask for Conquest {
     fn get_task_end_time(&self) -> Time {
         self.ended_at
     }
+
+    fn get_task_owner(&self) -> Option<PlayerID> {
+        Some(self.player)
+    }
 }
 
 impl Conquest {
@@ -103,20 +123,61 @@ impl Conquest {
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
+    /// Delays the ongoing conquests of a paused game, the ones started meanwhile beginning once it is resumed.
+    /// Both bounds move to keep their progress unchanged
+    pub async fn postpone_deadlines<E>(gid: GameID, paused_at: Time, resumed_at: Time, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE fleet__combat__conquests SET
+            started_at = started_at + ($3 - GREATEST(started_at, $2)),
+            ended_at = ended_at + ($3 - GREATEST(started_at, $2))
+            WHERE is_over = false AND system_id IN (SELECT id FROM map__systems WHERE game_id = $1)")
+            .bind(Uuid::from(gid))
+            .bind(paused_at)
+            .bind(resumed_at)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
+    /// Marks the conquest as successful, unless it is already over. Returns its current conqueror,
+    /// which may be the heir of the player who started it
+    pub async fn succeed<E>(&self, exec: &mut E) -> Result<Option<PlayerID>>
+        where E: Executor<Database = Postgres> {
+        sqlx::query_as("UPDATE fleet__combat__conquests SET is_successful = true, is_over = true WHERE id = $1 AND is_over = false RETURNING player_id")
+            .bind(Uuid::from(self.id))
+            .fetch_optional(&mut *exec).await
+            .map(|row: Option<(Uuid,)>| row.map(|(pid,)| PlayerID(pid)))
+            .map_err(ServerError::from)
+    }
+
+    /// The ongoing conquests of a leaving player go to its heir. Without heir, they are over
+    pub async fn hand_over<E>(pid: PlayerID, heir: Option<PlayerID>, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        match heir {
+            Some(hid) => sqlx::query("UPDATE fleet__combat__conquests SET player_id = $2 WHERE player_id = $1 AND is_over = false")
+                .bind(Uuid::from(pid))
+                .bind(Uuid::from(hid))
+                .execute(&mut *exec).await.map_err(ServerError::from),
+            None => sqlx::query("UPDATE fleet__combat__conquests SET is_over = true WHERE player_id = $1 AND is_over = false")
+                .bind(Uuid::from(pid))
+                .execute(&mut *exec).await.map_err(ServerError::from),
+        }
+    }
+
+    /// Only ongoing conquests are updated, so that a conquest read before its end cannot undo its success,
+    /// which is only set by `succeed` and counted by the leaderboard
     pub async fn update<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres> {
         sqlx::query("UPDATE fleet__combat__conquests SET
             started_at = $2,
             ended_at = $3,
-            is_successful = $4,
-            is_stopped = $5,
-            is_over = $6 WHERE id = $1")
+            is_stopped = $4,
+            is_over = $5,
+            percent = $6 WHERE id = $1 AND is_over = false")
             .bind(Uuid::from(self.id))
             .bind(self.started_at)
             .bind(self.ended_at)
-            .bind(self.is_successful)
             .bind(self.is_stopped)
             .bind(self.is_over)
+            .bind(self.percent)
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
@@ -139,6 +200,23 @@ impl Conquest {
             .fetch_optional(db_pool).await.map_err(ServerError::from)
     }
 
+    pub async fn find_current_by_systems(ids: Vec<SystemID>, db_pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as("SELECT * FROM fleet__combat__conquests WHERE system_id = ANY($1) AND is_over = false")
+            .bind(ids.into_iter().map(Uuid::from).collect::<Vec<Uuid>>())
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    /// A halted conquest keeps its progress, but no longer contests the system until it is resumed
+    pub async fn find_ongoing_by_systems(ids: Vec<SystemID>, db_pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as("SELECT * FROM fleet__combat__conquests WHERE system_id = ANY($1) AND is_over = false AND is_stopped = false")
+            .bind(ids.into_iter().map(Uuid::from).collect::<Vec<Uuid>>())
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub const fn is_ongoing(&self) -> bool {
+        !self.is_over && !self.is_stopped
+    }
+
     pub async fn remove_fleet(&mut self, system: &System, fleet: &Fleet, server: &GameServer) -> Result<()> {
         let mut fleets = system.retrieve_orbiting_fleets(&server.state.db_pool).await?;
         let game = Game::find(system.game, &server.state.db_pool).await?;
@@ -148,7 +226,7 @@ impl Conquest {
             return self.cancel(&server).await;
         }
         server.state.games().get(&server.id).unwrap().do_send(cancel_task!(self));
-        self.update_time(fleets.values().collect(), game.game_speed, &server.state.db_pool).await?;
+        self.update_time(fleets.values().collect(), &game, &server.state.db_pool).await?;
 
         let mut conquest = self.clone();
         server.state.games().get(&server.id).unwrap().do_send(task!(conquest -> move |server| block_on(conquest.end(&server))));
@@ -156,17 +234,31 @@ impl Conquest {
         Ok(())
     }
 
-    pub async fn update_time(&mut self, fleets: Vec<&Fleet>, game_speed: GameOptionSpeed, mut db_pool: &PgPool) -> Result<()> {
+    pub async fn update_time(&mut self, fleets: Vec<&Fleet>, game: &Game, mut db_pool: &PgPool) -> Result<()> {
+        self.reschedule(&fleets, game.game_speed, &game.config, Utc::now());
+        self.update(&mut db_pool).await?;
+
+        Ok(())
+    }
+
+    /// Computes the new deadline of the conquest when its fleets changed.
+    ///
+    /// The progress made so far is kept, and the timer can be postponed at most once per minimal
+    /// conquest duration. Otherwise, shuttling a fleet in and out of the system would stall the conquest forever.
+    fn reschedule(&mut self, fleets: &Vec<&Fleet>, game_speed: GameOptionSpeed, config: &GameConfig, now: DateTime<Utc>) {
         // If the conquest is currently on and a new fleet joins it, we calculate the progress so the get_conquest_time method can have it
+        let is_recently_reset = !self.is_stopped
+            && (now.signed_duration_since(self.started_at.into()).num_milliseconds() as f64) < config.conquest_duration_min;
         if !self.is_stopped {
-            self.percent = self.calculate_progress();
+            self.percent = self.get_progress_at(now);
+        }
+        let mut ended_at = now + Duration::milliseconds(get_conquest_time(fleets, self.percent, game_speed, config).ceil() as i64);
+        if is_recently_reset {
+            ended_at = ended_at.min(self.ended_at.into());
         }
         self.is_stopped = false;
-        self.ended_at = ms_to_time(get_conquest_time(&fleets, self.percent, game_speed));
-        self.started_at = Time::now();
-        self.update(&mut db_pool).await?;
-
-        Ok(())
+        self.ended_at = ended_at.into();
+        self.started_at = now.into();
     }
 
     pub async fn cancel(&mut self, server: &GameServer) -> Result<()> {
@@ -218,21 +310,31 @@ impl Conquest {
     }
 
     pub fn calculate_progress(&self) -> f32 {
+        self.get_progress_at(Utc::now())
+    }
+
+    /// The current time window only covers the progress which remained when it started
+    fn get_progress_at(&self, now: DateTime<Utc>) -> f32 {
         let started_at: DateTime<Utc> = self.started_at.into();
         let ended_at: DateTime<Utc> = self.ended_at.into();
 
         let total_ms = ended_at.signed_duration_since(started_at).num_milliseconds() as f32;
-        let consumed_ms = Utc::now().signed_duration_since(started_at).num_milliseconds() as f32;
+        let consumed_ms = now.signed_duration_since(started_at).num_milliseconds() as f32;
+        let ratio = if total_ms > 0.0 { (consumed_ms / total_ms).max(0.0).min(1.0) } else { 1.0 };
 
-        self.percent + (consumed_ms / total_ms)
+        (self.percent + (1.0 - self.percent) * ratio).max(self.percent).min(1.0)
     }
 
     pub async fn resume(fleet: &Fleet, system: &System, victor_faction: Option<FactionID>, server: &GameServer) -> Result<()> {
+        let fleets_data = system.retrieve_orbiting_fleets(&server.state.db_pool).await?;
+
+        Self::resume_with_fleets(fleet, fleets_data.values().collect(), system, victor_faction, &server).await
+    }
+
+    pub async fn resume_with_fleets(fleet: &Fleet, fleets: Vec<&Fleet>, system: &System, victor_faction: Option<FactionID>, server: &GameServer) -> Result<()> {
         let c = Self::find_current_by_system(&system.id, &server.state.db_pool).await?;
         let game = Game::find(system.game, &server.state.db_pool).await?;
-        let fleets_data = system.retrieve_orbiting_fleets(&server.state.db_pool).await?;
-        let fleets = fleets_data.values().collect();
-        
+
         if let Some(mut conquest) = c {
             let conquest_player = Player::find(conquest.player, &server.state.db_pool).await?;
             let games = server.state.games();
@@ -241,7 +343,7 @@ impl Conquest {
             if victor_faction.is_some() && victor_faction != conquest_player.faction {
                 conquest.cancel(&server).await?;
 
-                return Self::new(fleet, fleets, system, game.game_speed, &server).await;
+                return Self::new(fleet, fleets, system, &game, &server).await;
             }
 
             // This case means the fleet is reinforcing a current conquest
@@ -253,7 +355,7 @@ impl Conquest {
                     None,
                 ));
             }
-            conquest.update_time(fleets, game.game_speed, &server.state.db_pool).await?;
+            conquest.update_time(fleets, &game, &server.state.db_pool).await?;
 
             game_server.do_send(protocol::Message::new(
                 protocol::Action::ConquestUpdated,
@@ -264,10 +366,31 @@ impl Conquest {
 
             return Ok(());
         }
-        Self::new(fleet, fleets, system, game.game_speed, &server).await
+        Self::new(fleet, fleets, system, &game, &server).await
+    }
+
+    /// Fast path for fleets arriving in a neutral system: if no one else is there, the system is claimed
+    /// after a short delay. Otherwise, a regular conquest is started or resumed.
+    pub async fn colonize(fleet: &Fleet, system: &System, server: &GameServer) -> Result<()> {
+        let (c, f) = futures::join!(
+            Self::find_current_by_system(&system.id, &server.state.db_pool),
+            system.retrieve_orbiting_fleets(&server.state.db_pool),
+        );
+        if c?.is_some() || has_other_fleets_than(&f?, fleet) {
+            return Self::resume(fleet, system, None, &server).await;
+        }
+        let game = Game::find(system.game, &server.state.db_pool).await?;
+
+        Self::start(fleet, vec![fleet], system, get_colonization_time(game.game_speed), &server).await
+    }
+
+    pub async fn new(fleet: &Fleet, fleets: Vec<&Fleet>, system: &System, game: &Game, server: &GameServer) -> Result<()> {
+        let duration = get_conquest_time(&fleets, 0.0, game.game_speed, &game.config);
+
+        Self::start(fleet, fleets, system, duration, &server).await
     }
 
-    pub async fn new(fleet: &Fleet, fleets: Vec<&Fleet>, system: &System, game_speed: GameOptionSpeed, server: &GameServer) -> Result<()> {
+    async fn start(fleet: &Fleet, fleets: Vec<&Fleet>, system: &System, duration: f64, server: &GameServer) -> Result<()> {
         let conquest_id = ConquestID(Uuid::new_v4());
         let mut conquest = Conquest{
             id: conquest_id,
@@ -276,7 +399,7 @@ impl Conquest {
             fleet: Some(fleet.id),
             fleets: Some(fleets.iter().map(|&f| f.clone()).collect()),
             started_at: Time::now(),
-            ended_at: ms_to_time(get_conquest_time(&fleets, 0.0, game_speed)),
+            ended_at: ms_to_time(duration),
             percent: 0.0,
             is_stopped: false,
             is_successful: false,
@@ -314,11 +437,65 @@ impl Conquest {
         let mut system = System::find(self.system.clone(), &server.state.db_pool).await?;
         let fleets = system.retrieve_orbiting_fleets(&server.state.db_pool).await?.values().cloned().collect();
 
+        let previous_owner = system.player;
+
+        // Battles and other conquests may resolve the system meanwhile, so both updates are guarded
+        let mut tx = server.state.db_pool.begin().await?;
+        let conqueror = self.succeed(&mut tx).await?;
+        let is_conquest_ongoing = conqueror.is_some();
+        if let Some(pid) = conqueror {
+            self.player = pid;
+        }
+        system.player = Some(self.player);
+        let is_owner_updated = is_conquest_ongoing && system.update_owner(previous_owner, &mut tx).await? > 0;
+
+        match get_conquest_resolution(is_conquest_ongoing, is_owner_updated) {
+            ConquestResolution::Applied => { tx.commit().await?; },
+            ConquestResolution::AlreadyOver => {
+                tx.rollback().await?;
+                return Ok(());
+            },
+            ConquestResolution::OwnerChanged => {
+                tx.rollback().await?;
+                log(
+                    gelf::Level::Warning,
+                    "Conquest outdated",
+                    &format!("System {} changed hands during its conquest", system.to_log_message()),
+                    vec![
+                        ("conquest_id", self.id.0.to_string()),
+                        ("system_id", self.system.0.to_string()),
+                    ],
+                    &server.state.logger
+                );
+                return self.cancel(&server).await;
+            },
+        }
         self.is_over = true;
-        self.update(&mut &server.state.db_pool).await?;
+        self.is_successful = true;
 
-        system.player = Some(self.player.clone());
-        system.update(&mut &server.state.db_pool).await?;
+        if let Some(previous_owner) = previous_owner.filter(|pid| *pid != self.player) {
+            #[derive(Serialize)]
+            struct SystemLostData {
+                system: SystemID,
+                conqueror: PlayerID,
+            }
+            Notification::new(
+                previous_owner,
+                server.id,
+                NotificationKind::SystemLost,
+                SystemLostData{ system: system.id, conqueror: self.player },
+            ).insert(&mut &server.state.db_pool).await?;
+
+            let loser = server.player_cache.find(previous_owner, &server.state.db_pool).await?;
+            self.capture_ships(&system, &loser, server).await?;
+            if loser.home_system == Some(system.id) {
+                server.player_broadcast(&previous_owner, &protocol::Message::new(
+                    protocol::Action::HomeSystemLost,
+                    SystemLostData{ system: system.id, conqueror: self.player },
+                    None,
+                ));
+            }
+        }
 
         log(
             gelf::Level::Informational,
@@ -339,23 +516,100 @@ impl Conquest {
             None
         )).await?;
 
+        if let Some(previous_owner) = previous_owner.filter(|pid| *pid != self.player) {
+            check_player_elimination(previous_owner, server).await?;
+        }
+        Ok(())
+    }
+
+    /// The conqueror seizes part of the ships destroyed in the battle it won against the former owner
+    async fn capture_ships(&self, system: &System, loser: &Player, server: &GameServer) -> Result<()> {
+        if server.capture_rate <= 0.0 {
+            return Ok(());
+        }
+        let battle = match Battle::find_last_ended_by_system(system.id, &server.state.db_pool).await? {
+            Some(battle) => battle,
+            None => return Ok(()),
+        };
+        let conqueror = server.player_cache.find(self.player, &server.state.db_pool).await?;
+        if conqueror.faction.is_none() || battle.victor != conqueror.faction || battle.defender_faction != loser.faction {
+            return Ok(());
+        }
+        let captured_ships = battle.get_captured_ships(server.capture_rate);
+        if captured_ships.is_empty() {
+            return Ok(());
+        }
+        let mut tx = server.state.db_pool.begin().await?;
+        // A battle already used by a former conquest of the system does not yield ships again
+        if battle.claim_captured_ships(&mut tx).await? == 0 {
+            tx.rollback().await?;
+            return Ok(());
+        }
+        for (category, quantity) in &captured_ships {
+            let squadron = Squadron::find_by_system_and_category_for_update(system.id, *category, &mut tx).await?;
+            let stationed_quantity = squadron.as_ref().map_or(0, |s| s.quantity as i32);
+            Squadron::assign(squadron, system.id, *category, stationed_quantity + *quantity as i32, &mut tx).await?;
+        }
+        tx.commit().await?;
+        log(
+            gelf::Level::Informational,
+            "Ships captured",
+            &format!("{} ships have been captured in system {}", captured_ships.values().map(|q| *q as u32).sum::<u32>(), system.to_log_message()),
+            vec![
+                ("conquest_id", self.id.0.to_string()),
+                ("battle_id", battle.id.0.to_string()),
+                ("player_id", self.player.0.to_string()),
+            ],
+            &server.state.logger
+        );
         Ok(())
     }
 }
 
-    
-fn get_conquest_time(fleets: &Vec<&Fleet>, percent: f32, game_speed: GameOptionSpeed) -> f64 {
+const fn get_conquest_resolution(is_conquest_ongoing: bool, is_owner_updated: bool) -> ConquestResolution {
+    match (is_conquest_ongoing, is_owner_updated) {
+        (false, _) => ConquestResolution::AlreadyOver,
+        (true, false) => ConquestResolution::OwnerChanged,
+        (true, true) => ConquestResolution::Applied,
+    }
+}
+
+#[get("/")]
+pub async fn get_system_conquest(state: web::Data<AppState>, info: web::Path<(GameID, SystemID)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    let (s, p) = futures::join!(
+        System::find(info.1, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    let system = s?;
+    // Conquests are announced to the whole game, so every player of the game can see them
+    if system.game != info.0 || p?.game != Some(info.0) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let mut conquest = Conquest::find_current_by_system(&system.id, &state.db_pool).await?.ok_or(InternalError::NotFound)?;
+    if !conquest.is_stopped {
+        conquest.percent = conquest.calculate_progress();
+    }
+    Ok(HttpResponse::Ok().json(conquest))
+}
+
+fn get_conquest_time(fleets: &Vec<&Fleet>, percent: f32, game_speed: GameOptionSpeed, config: &GameConfig) -> f64 {
     let mut strength = 0;
 
     for fleet in fleets {
         strength += fleet.get_strength();
     }
 
-    let mut remaining_time = CONQUEST_DURATION_MAX * game_speed.into_conquest_speed();
+    let mut remaining_time = config.conquest_duration_max * game_speed.into_conquest_speed();
     if 0.0 < percent {
         remaining_time = remaining_time - (remaining_time * (percent as f64));
     }
-    (remaining_time - CONQUEST_STRENGTH_COEFF * strength as f64).max(CONQUEST_DURATION_MIN)
+    (remaining_time - config.conquest_strength_coeff * strength as f64).max(config.conquest_duration_min)
+}
+
+fn get_colonization_time(game_speed: GameOptionSpeed) -> f64 {
+    COLONIZATION_DURATION * game_speed.into_conquest_speed()
 }
 
 #[cfg(test)]
@@ -365,13 +619,24 @@ mod tests
     use crate::game::{
         fleet::{
             fleet::Fleet,
+            combat::battle::{Battle, BattleID},
             formation::FleetFormation,
             squadron::{FleetSquadron, FleetSquadronID},
         },
         ship::model::ShipModelCategory,
     };
+    use std::collections::HashMap;
     use uuid::Uuid;
 
+    #[test]
+    fn test_get_conquest_resolution() {
+        assert_eq!(ConquestResolution::Applied, get_conquest_resolution(true, true));
+        // A battle gave the system to another player while the conquest was ending
+        assert_eq!(ConquestResolution::OwnerChanged, get_conquest_resolution(true, false));
+        // Another resolution already cancelled the conquest, its claim must not be applied
+        assert_eq!(ConquestResolution::AlreadyOver, get_conquest_resolution(false, false));
+    }
+
     #[test]
     fn test_get_conquest_time() {
         let mut fleet = get_fleet_mock();
@@ -379,7 +644,7 @@ mod tests
         let fleets = vec![&fleet];
         let game_speed = GameOptionSpeed::Medium;
 
-        assert_eq!(50000.0, get_conquest_time(&fleets, 0.0, game_speed));
+        assert_eq!(50000.0, get_conquest_time(&fleets, 0.0, game_speed, &GameConfig::default()));
     }
 
     #[test]
@@ -389,7 +654,7 @@ mod tests
         let fleets = vec![&fleet];
         let game_speed = GameOptionSpeed::Fast;
 
-        assert_eq!(38000.0, get_conquest_time(&fleets, 0.0, game_speed));
+        assert_eq!(38000.0, get_conquest_time(&fleets, 0.0, game_speed, &GameConfig::default()));
     }
 
     #[test]
@@ -399,7 +664,7 @@ mod tests
         let fleets = vec![&fleet];
         let game_speed = GameOptionSpeed::Medium;
 
-        assert_eq!(20000.0, get_conquest_time(&fleets, 0.5, game_speed));
+        assert_eq!(20000.0, get_conquest_time(&fleets, 0.5, game_speed, &GameConfig::default()));
     }
 
     #[test]
@@ -409,7 +674,18 @@ mod tests
         let fleets = vec![&fleet];
         let game_speed = GameOptionSpeed::Medium;
 
-        assert_eq!(CONQUEST_DURATION_MIN, get_conquest_time(&fleets, 0.0, game_speed));
+        assert_eq!(CONQUEST_DURATION_MIN, get_conquest_time(&fleets, 0.0, game_speed, &GameConfig::default()));
+    }
+
+    #[test]
+    fn test_get_conquest_time_with_game_config() {
+        let mut fleet = get_fleet_mock();
+        fleet.squadrons.push(get_squadron_mock(10, ShipModelCategory::Fighter));
+        let fleets = vec![&fleet];
+        let config = GameConfig::new(30000.0, 2000.0, 200.0, 200, 10, 100).unwrap();
+
+        assert_eq!(28000.0, get_conquest_time(&fleets, 0.0, GameOptionSpeed::Medium, &config));
+        assert_eq!(2000.0, get_conquest_time(&fleets, 0.99, GameOptionSpeed::Medium, &config));
     }
 
     #[test]
@@ -422,16 +698,111 @@ mod tests
         let fleets = vec![&fleet1, &fleet2];
         let game_speed = GameOptionSpeed::Medium;
 
-        assert_eq!(40000.0, get_conquest_time(&fleets, 0.0, game_speed));
+        assert_eq!(40000.0, get_conquest_time(&fleets, 0.0, game_speed, &GameConfig::default()));
+    }
+
+    #[test]
+    fn test_get_conquest_time_after_battle() {
+        let mut victor_fleet = get_fleet_mock();
+        victor_fleet.squadrons.push(get_squadron_mock(50, ShipModelCategory::Fighter));
+        victor_fleet.squadrons.push(get_squadron_mock(0, ShipModelCategory::Cruiser));
+        let mut destroyed_fleet = get_fleet_mock();
+        destroyed_fleet.is_destroyed = true;
+        destroyed_fleet.squadrons.push(get_squadron_mock(100, ShipModelCategory::Fighter));
+        let mut defender_fleet = get_fleet_mock();
+        defender_fleet.squadrons.push(get_squadron_mock(100, ShipModelCategory::Fighter));
+
+        let mut fleets = HashMap::new();
+        fleets.insert(FactionID(1), vec![victor_fleet.clone(), destroyed_fleet.clone()].into_iter().map(|f| (f.id, f)).collect());
+        fleets.insert(FactionID(2), vec![defender_fleet.clone()].into_iter().map(|f| (f.id, f)).collect());
+        let battle = Battle{
+            id: BattleID(Uuid::new_v4()),
+            system: victor_fleet.system,
+            attacker: victor_fleet.id,
+            fleets,
+            initial_fleets: None,
+            rounds: vec![],
+            defender_faction: Some(FactionID(2)),
+            garrison: None,
+            victor: Some(FactionID(1)),
+            begun_at: Time::now(),
+            ended_at: Some(Time::now()),
+        };
+
+        assert_eq!(55000.0, get_conquest_time(&battle.get_victor_fleets(), 0.0, GameOptionSpeed::Medium, &GameConfig::default()));
+    }
+
+    #[test]
+    fn test_get_progress_at() {
+        let now = Utc::now();
+        let mut conquest = get_conquest_mock(now - Duration::seconds(10), now + Duration::seconds(10));
+
+        assert_eq!(0.5, conquest.get_progress_at(now));
+
+        conquest.percent = 0.5;
+        assert_eq!(0.75, conquest.get_progress_at(now));
+        assert_eq!(0.5, conquest.get_progress_at(now - Duration::seconds(20)));
+        assert_eq!(1.0, conquest.get_progress_at(now + Duration::seconds(20)));
+    }
+
+    #[test]
+    fn test_reschedule_with_reinforcement_churn() {
+        let mut fleet = get_fleet_mock();
+        fleet.squadrons.push(get_squadron_mock(100, ShipModelCategory::Fighter));
+        let mut shuttle = get_fleet_mock();
+        shuttle.squadrons.push(get_squadron_mock(10, ShipModelCategory::Fighter));
+        let config = GameConfig::default();
+
+        let mut now = Utc::now();
+        let duration = get_conquest_time(&vec![&fleet], 0.0, GameOptionSpeed::Medium, &config);
+        let mut conquest = get_conquest_mock(now, now + Duration::milliseconds(duration as i64));
+        let deadline: DateTime<Utc> = conquest.ended_at.into();
+
+        // The shuttle joins and leaves the conquest every second
+        for i in 0..40 {
+            now = now + Duration::seconds(1);
+            let fleets = if i % 2 == 0 { vec![&fleet, &shuttle] } else { vec![&fleet] };
+            let previous_percent = conquest.percent;
+
+            conquest.reschedule(&fleets, GameOptionSpeed::Medium, &config, now);
+
+            assert!(conquest.percent > previous_percent);
+            assert!(DateTime::<Utc>::from(conquest.ended_at) <= deadline);
+        }
+        assert!(conquest.percent > 0.75);
+    }
+
+    #[test]
+    fn test_get_colonization_time() {
+        assert_eq!(COLONIZATION_DURATION, get_colonization_time(GameOptionSpeed::Medium));
+        assert!(get_colonization_time(GameOptionSpeed::Slow) < CONQUEST_DURATION_MIN);
+    }
+
+    fn get_conquest_mock(started_at: DateTime<Utc>, ended_at: DateTime<Utc>) -> Conquest {
+        Conquest{
+            id: ConquestID(Uuid::new_v4()),
+            player: PlayerID(Uuid::new_v4()),
+            system: SystemID(Uuid::new_v4()),
+            fleet: None,
+            fleets: None,
+            is_successful: false,
+            is_stopped: false,
+            is_over: false,
+            percent: 0.0,
+            started_at: started_at.into(),
+            ended_at: ended_at.into(),
+        }
     }
 
     fn get_fleet_mock() -> Fleet {
         Fleet{
             id: FleetID(Uuid::new_v4()),
+            name: None,
             player: PlayerID(Uuid::new_v4()),
             system: SystemID(Uuid::new_v4()),
             destination_system: None,
             destination_arrival_date: None,
+            departure_date: None,
             squadrons: vec![],
             is_destroyed: false,
         }
@@ -443,6 +814,8 @@ mod tests
             fleet: FleetID(Uuid::new_v4()),
             formation: FleetFormation::Center,
             quantity,
+            max_quantity: quantity,
+            auto_reinforce: false,
             category,
         }
     }
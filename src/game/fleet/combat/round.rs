@@ -8,14 +8,19 @@ use crate::{
     },
     game::{
         faction::{FactionID},
+        player::PlayerID,
         fleet::{
             combat::{
                 battle::{BattleID, Battle, get_factions_fleets, update_fleets},
             },
             fleet::{FleetID, Fleet},
+            formation::AttackOrders,
             squadron::{FleetSquadronID, FleetSquadron},
         },
-        game::server::{ GameServer, GameServerTask }
+        game::server::{ GameServer, GameServerTask },
+        ship::model::{ShipModel, ShipModelCategory},
+        system::system::{System, SystemID},
+        technology::{Technology, TechnologyKind},
     }
 };
 use futures::executor::block_on;
@@ -23,6 +28,18 @@ use chrono::{Duration, Utc};
 use serde::{Deserialize, Serialize};
 use rand::prelude::*;
 
+/// Hit points bonus, in percent, of the ships defending their owner's home system
+const HOME_SYSTEM_DEFENSE_BONUS: u16 = 20;
+
+/// Combat bonuses, in percent, of the squadrons fighting for the faction which owns the battle system.
+/// Every bonus is disabled by default.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct DefenderBonus {
+    pub precision: u16,
+    pub hit_points: u16,
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Round {
     pub battle: BattleID,
@@ -52,7 +69,23 @@ pub struct SquadronAction {
     squadron: FleetSquadronID,
     battle: BattleID,
     kind: SquadronActionKind,
-    round_number: u16
+    round_number: u16,
+    // Rounds of older battles were stored without the factions
+    #[serde(default)]
+    faction: Option<FactionID>,
+    #[serde(default)]
+    target_faction: Option<FactionID>,
+    #[serde(default)]
+    player: Option<PlayerID>,
+    #[serde(default)]
+    target_category: Option<ShipModelCategory>,
+}
+
+/// Ships lost and destroyed by a faction during a battle
+#[derive(Serialize, Clone, Copy, Default, Debug, PartialEq)]
+pub struct BattleLosses {
+    pub ships_lost: u32,
+    pub ships_destroyed: u32,
 }
 
 #[derive(Deserialize, Serialize, Clone, Copy)]
@@ -61,6 +94,37 @@ pub enum SquadronActionKind {
     Attack { target: FleetSquadronID, loss: u16 }
 }
 
+impl SquadronAction {
+    /// Returns the faction of the attacker, the faction of the target and the number of destroyed ships
+    pub fn get_loss(&self) -> Option<(FactionID, FactionID, u16)> {
+        match self.kind {
+            SquadronActionKind::Attack{ loss, .. } => Some((self.faction?, self.target_faction?, loss)),
+        }
+    }
+
+    /// Returns the faction of the target, the category of its ships and the number of destroyed ships
+    pub fn get_category_loss(&self) -> Option<(FactionID, ShipModelCategory, u16)> {
+        match self.kind {
+            SquadronActionKind::Attack{ loss, .. } => Some((self.target_faction?, self.target_category?, loss)),
+        }
+    }
+
+    /// Returns the targeted squadron and the number of ships it lost
+    pub const fn get_target_loss(&self) -> (FleetSquadronID, u16) {
+        match self.kind {
+            SquadronActionKind::Attack{ target, loss } => (target, loss),
+        }
+    }
+
+    /// Returns the owner of the attacking squadron and the number of ships it destroyed
+    #[cfg(feature = "leaderboard")]
+    pub fn get_destroyed_ships(&self) -> Option<(PlayerID, u16)> {
+        match self.kind {
+            SquadronActionKind::Attack{ loss, .. } => Some((self.player?, loss)),
+        }
+    }
+}
+
 impl GameServerTask for Round {
     fn get_task_id(&self) -> String {
         format!("{}.{}", self.battle.0.to_string(), self.number.to_string())
@@ -111,7 +175,10 @@ impl Round {
             }
         }
 
-        self.fight(&mut battle, &new_fleets, &server);
+        let technologies = Technology::find_unlocked_by_game(server.id, &server.state.db_pool).await?;
+        let home_faction = get_home_system_faction(battle.system, &server).await?;
+
+        self.fight(&mut battle, &new_fleets, &technologies, home_faction, &server);
         battle.rounds.push(self.clone());
         battle.fleets = update_fleets(&battle, &server).await?;
         battle.update(&mut &server.state.db_pool).await?;
@@ -125,7 +192,7 @@ impl Round {
         Ok(())
     }
 
-    pub fn fight(&mut self, mut battle: &mut Battle, new_fleets: &HashMap<FleetID, Fleet>, server: &GameServer) {
+    pub fn fight(&mut self, mut battle: &mut Battle, new_fleets: &HashMap<FleetID, Fleet>, technologies: &HashMap<FactionID, Vec<TechnologyKind>>, home_faction: Option<FactionID>, server: &GameServer) {
         // new fleets arrival
         for fleet in new_fleets.values() {
             log(
@@ -149,16 +216,46 @@ impl Round {
         // make each squadron fight
         for (fid, squadron) in battle.get_fighting_squadrons_by_initiative(&new_fleets) {
             // a squadron may have no ennemy to attack, this is why we wrap its action into an Option
-            if let Some(act) = attack(&mut battle, fid, &squadron, self.number, &new_fleets, &server) {
+            if let Some(act) = attack(&mut battle, fid, &squadron, self.number, &new_fleets, &technologies, home_faction, &server) {
                 self.squadron_actions.push(act);
             }
         }
     }
 }
 
-fn attack(battle: &mut Battle, fid: FactionID, attacker: &FleetSquadron, round_number: u16, excluded_fleets: &HashMap<FleetID, Fleet>, server: &GameServer) -> Option<SquadronAction> {
-    let (target_faction, target) = pick_target_squadron(&battle, fid, &attacker, &excluded_fleets)?;
-    let (remaining_ships, loss) = fire(&attacker, &target);
+impl DefenderBonus {
+    /// Parses a JSON object such as `{"precision": 10, "hit_points": 20}`.
+    /// An invalid configuration disables the bonus.
+    pub fn from_config(config: &str) -> Self {
+        serde_json::from_str(config).unwrap_or_default()
+    }
+
+    pub fn with_attack_bonus(self, mut model: ShipModel) -> ShipModel {
+        model.precision = model.precision.saturating_add(model.precision.saturating_mul(self.precision) / 100);
+        model
+    }
+
+    pub fn with_defense_bonus(self, mut model: ShipModel) -> ShipModel {
+        model.hit_points = model.hit_points.saturating_add(model.hit_points.saturating_mul(self.hit_points) / 100);
+        model
+    }
+}
+
+fn attack(battle: &mut Battle, fid: FactionID, attacker: &FleetSquadron, round_number: u16, excluded_fleets: &HashMap<FleetID, Fleet>, technologies: &HashMap<FactionID, Vec<TechnologyKind>>, home_faction: Option<FactionID>, server: &GameServer) -> Option<SquadronAction> {
+    let (target_faction, target) = pick_target_squadron(&battle, fid, &attacker, &excluded_fleets, &server.attack_orders)?;
+    let attacker_player = battle.fleets.get(&fid).and_then(|fleets| fleets.get(&attacker.fleet)).map(|fleet| fleet.player);
+    let mut attacker_model = get_faction_ship_model(attacker, fid, technologies);
+    let mut defender_model = get_faction_ship_model(&target, target_faction, technologies);
+    if battle.defender_faction == Some(fid) {
+        attacker_model = server.defender_bonus.with_attack_bonus(attacker_model);
+    }
+    if battle.defender_faction == Some(target_faction) {
+        defender_model = server.defender_bonus.with_defense_bonus(defender_model);
+    }
+    if home_faction == Some(target_faction) {
+        defender_model = with_home_system_bonus(defender_model);
+    }
+    let (remaining_ships, loss) = fire(&attacker, attacker_model, &target, defender_model);
 
     log(
         gelf::Level::Debug,
@@ -186,9 +283,23 @@ fn attack(battle: &mut Battle, fid: FactionID, attacker: &FleetSquadron, round_n
         squadron: attacker.id,
         kind: SquadronActionKind::Attack{ target: target.id, loss },
         round_number,
+        faction: Some(fid),
+        target_faction: Some(target_faction),
+        player: attacker_player,
+        target_category: Some(target.category),
     })
 }
 
+pub fn sum_losses(rounds: &[Round]) -> HashMap<FactionID, BattleLosses> {
+    let mut losses: HashMap<FactionID, BattleLosses> = HashMap::new();
+
+    for (faction, target_faction, loss) in rounds.iter().flat_map(|r| r.squadron_actions.iter()).filter_map(SquadronAction::get_loss) {
+        losses.entry(faction).or_default().ships_destroyed += loss as u32;
+        losses.entry(target_faction).or_default().ships_lost += loss as u32;
+    }
+    losses
+}
+
 /// This is an adaptation for multiple-fleet battles of Galadruin's battle idea (c.f. backlog
 /// trello card).
 ///
@@ -197,11 +308,11 @@ fn attack(battle: &mut Battle, fid: FactionID, attacker: &FleetSquadron, round_n
 ///
 /// Also, when attacking, it is not fleet vs fleet but squadron vs squadron. Because of this, each
 /// squadron of a fleet can attack a different fleet each turn.
-fn pick_target_squadron(battle: &Battle, faction_id: FactionID, attacker: &FleetSquadron, excluded_fleets: &HashMap<FleetID, Fleet>) -> Option<(FactionID, FleetSquadron)> {
+fn pick_target_squadron(battle: &Battle, faction_id: FactionID, attacker: &FleetSquadron, excluded_fleets: &HashMap<FleetID, Fleet>, attack_orders: &AttackOrders) -> Option<(FactionID, FleetSquadron)> {
     let mut potential_targets : Vec<(FactionID, &FleetSquadron)> = Vec::new();
 
     // c.f. game::fleet::formation::FleetFormation::attack_order()
-    for target_formation in attacker.formation.attack_order() {
+    for target_formation in attack_orders.get(attacker.formation) {
         potential_targets.extend(battle.fleets
             .iter()
             .filter(|(fid, _)| **fid != faction_id)
@@ -210,7 +321,7 @@ fn pick_target_squadron(battle: &Battle, faction_id: FactionID, attacker: &Fleet
                 .flat_map(|(_, fleet)| &fleet.squadrons)
                 .map(move |fs| (*fid, fs))
             )
-            .filter(|(_, squadron)| !excluded_fleets.contains_key(&squadron.fleet) && squadron.formation == *target_formation && squadron.quantity > 0)
+            .filter(|(_, squadron)| !excluded_fleets.contains_key(&squadron.fleet) && squadron.formation == *target_formation && squadron.can_fight())
         );
 
         if !potential_targets.is_empty() { break }
@@ -223,10 +334,29 @@ fn pick_target_squadron(battle: &Battle, faction_id: FactionID, attacker: &Fleet
     potential_targets.choose(&mut rng).map(|(fid, fs)| (*fid, (*fs).clone()))
 }
 
-fn fire(attacker: &FleetSquadron, defender: &FleetSquadron) -> (u16, u16) {
-    let attacker_model = attacker.category.to_data();
+fn get_faction_ship_model(squadron: &FleetSquadron, faction_id: FactionID, technologies: &HashMap<FactionID, Vec<TechnologyKind>>) -> ShipModel {
+    squadron.category.to_data().with_technologies(technologies.get(&faction_id).map_or(&[][..], Vec::as_slice))
+}
+
+/// Returns the faction of the system owner if the battle takes place in its home system
+async fn get_home_system_faction(sid: SystemID, server: &GameServer) -> Result<Option<FactionID>> {
+    let system = System::find(sid, &server.state.db_pool).await?;
+    if let Some(pid) = system.player {
+        let owner = server.player_cache.find(pid, &server.state.db_pool).await?;
+        if owner.home_system == Some(sid) {
+            return Ok(owner.faction);
+        }
+    }
+    Ok(None)
+}
+
+fn with_home_system_bonus(mut model: ShipModel) -> ShipModel {
+    model.hit_points = model.hit_points.saturating_add(model.hit_points.saturating_mul(HOME_SYSTEM_DEFENSE_BONUS) / 100);
+    model
+}
+
+fn fire(attacker: &FleetSquadron, attacker_model: ShipModel, defender: &FleetSquadron, defender_model: ShipModel) -> (u16, u16) {
     let attack_coeff = attacker.formation.attack_coeff(defender.formation);
-    let defender_model = defender.category.to_data();
 
     let mut rng = thread_rng();
     let percent = rng.gen_range(attacker_model.precision as f64 / 2.0, attacker_model.precision as f64);
@@ -262,6 +392,83 @@ mod tests {
         }
     };
 
+    #[test]
+    fn test_sum_losses() {
+        let battle_id = BattleID(Uuid::new_v4());
+        let get_action = |faction, target_faction, loss| SquadronAction{
+            squadron: FleetSquadronID(Uuid::new_v4()),
+            battle: battle_id,
+            kind: SquadronActionKind::Attack{ target: FleetSquadronID(Uuid::new_v4()), loss },
+            round_number: 1,
+            faction,
+            target_faction,
+            player: None,
+            target_category: None,
+        };
+        let mut first_round = Round::new(battle_id, 1);
+        first_round.squadron_actions = vec![
+            get_action(Some(FactionID(1)), Some(FactionID(2)), 5),
+            get_action(Some(FactionID(2)), Some(FactionID(1)), 3),
+        ];
+        let mut second_round = Round::new(battle_id, 2);
+        second_round.squadron_actions = vec![
+            get_action(Some(FactionID(1)), Some(FactionID(2)), 4),
+            get_action(Some(FactionID(3)), Some(FactionID(2)), 1),
+            get_action(None, None, 10),
+        ];
+
+        let losses = sum_losses(&[first_round, second_round]);
+
+        assert_eq!(Some(&BattleLosses{ ships_lost: 3, ships_destroyed: 9 }), losses.get(&FactionID(1)));
+        assert_eq!(Some(&BattleLosses{ ships_lost: 10, ships_destroyed: 3 }), losses.get(&FactionID(2)));
+        assert_eq!(Some(&BattleLosses{ ships_lost: 0, ships_destroyed: 1 }), losses.get(&FactionID(3)));
+    }
+
+    #[test]
+    fn test_get_initial_fleets() {
+        let mut battle = get_battle_mock();
+        let (fid, fleet) = battle.fleets[&FactionID(2)].iter().next().map(|(fid, fleet)| (*fid, fleet.clone())).unwrap();
+        let (target, untouched) = (&fleet.squadrons[0], &fleet.squadrons[1]);
+        let get_action = |loss| SquadronAction{
+            squadron: FleetSquadronID(Uuid::new_v4()),
+            battle: battle.id,
+            kind: SquadronActionKind::Attack{ target: target.id, loss },
+            round_number: 1,
+            faction: Some(FactionID(1)),
+            target_faction: Some(FactionID(2)),
+            player: None,
+            target_category: Some(target.category),
+        };
+        let mut round = Round::new(battle.id, 1);
+        round.squadron_actions = vec![get_action(4), get_action(2)];
+        battle.rounds.push(round);
+
+        let initial_fleets = battle.get_initial_fleets();
+        let initial_fleet = &initial_fleets[&FactionID(2)][&fid];
+
+        assert_eq!(target.quantity + 6, initial_fleet.squadrons[0].quantity);
+        assert_eq!(untouched.quantity, initial_fleet.squadrons[1].quantity);
+    }
+
+    #[test]
+    fn test_get_stored_initial_fleets() {
+        let mut battle = get_battle_mock();
+        let mut initial_fleets = battle.fleets.clone();
+        let destroyed_fleet = get_fleet_mock();
+        let destroyed_fid = destroyed_fleet.id;
+        initial_fleets.get_mut(&FactionID(2)).unwrap().insert(destroyed_fid, destroyed_fleet);
+        battle.initial_fleets = Some(initial_fleets);
+
+        let late_fleet = get_fleet_mock();
+        let late_fid = late_fleet.id;
+        battle.fleets.get_mut(&FactionID(1)).unwrap().insert(late_fid, late_fleet);
+
+        let fleets = battle.get_initial_fleets();
+
+        assert!(fleets[&FactionID(2)].contains_key(&destroyed_fid));
+        assert!(!fleets[&FactionID(1)].contains_key(&late_fid));
+    }
+
     #[test]
     fn test_pick_target_squadron() {
         let battle = get_battle_mock();
@@ -276,7 +483,7 @@ mod tests {
         
         for (fid, tfid, formation) in data {
             let squadron = get_squadron_mock(ShipModelCategory::Corvette, formation, 5);
-            let target = pick_target_squadron(&battle, FactionID(fid), &squadron, &excluded_fleets);
+            let target = pick_target_squadron(&battle, FactionID(fid), &squadron, &excluded_fleets, &AttackOrders::default());
 
             assert_eq!(true, target.is_some());
 
@@ -287,6 +494,102 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_pick_target_squadron_with_three_factions() {
+        let mut battle = get_battle_mock();
+        let mut faction_3_fleets = HashMap::new();
+        faction_3_fleets.insert(FleetID(Uuid::new_v4()), get_fleet_mock());
+        battle.fleets.insert(FactionID(3), faction_3_fleets);
+        let excluded_fleets = HashMap::new();
+        let squadron = get_squadron_mock(ShipModelCategory::Corvette, FleetFormation::Center, 5);
+
+        for _ in 0..20 {
+            let (target_faction, _) = pick_target_squadron(&battle, FactionID(1), &squadron, &excluded_fleets, &AttackOrders::default()).unwrap();
+            assert_ne!(FactionID(1), target_faction);
+        }
+
+        // Once the second faction is wiped out, the third one is still targeted
+        for fleet in battle.fleets.get_mut(&FactionID(2)).unwrap().values_mut() {
+            fleet.squadrons.iter_mut().for_each(|s| s.quantity = 0);
+        }
+        let (target_faction, _) = pick_target_squadron(&battle, FactionID(1), &squadron, &excluded_fleets, &AttackOrders::default()).unwrap();
+        assert_eq!(FactionID(3), target_faction);
+    }
+
+    #[test]
+    fn test_pick_target_squadron_with_custom_attack_order() {
+        let mut battle = get_battle_mock();
+        for fleet in battle.fleets.get_mut(&FactionID(2)).unwrap().values_mut() {
+            fleet.squadrons = vec![
+                get_squadron_mock(ShipModelCategory::Fighter, FleetFormation::Left, 10),
+                get_squadron_mock(ShipModelCategory::Fighter, FleetFormation::Right, 10),
+            ];
+        }
+        let squadron = get_squadron_mock(ShipModelCategory::Corvette, FleetFormation::Center, 5);
+        let excluded_fleets = HashMap::new();
+
+        let (_, target) = pick_target_squadron(&battle, FactionID(1), &squadron, &excluded_fleets, &AttackOrders::default()).unwrap();
+
+        assert_eq!(FleetFormation::Left, target.formation);
+
+        let attack_orders = AttackOrders::from_config(r#"{"center": ["right", "left", "center", "rear"]}"#);
+        let (_, target) = pick_target_squadron(&battle, FactionID(1), &squadron, &excluded_fleets, &attack_orders).unwrap();
+
+        assert_eq!(FleetFormation::Right, target.formation);
+    }
+
+    #[test]
+    fn test_with_home_system_bonus() {
+        let model = with_home_system_bonus(ShipModelCategory::Cruiser.to_data());
+
+        assert_eq!(240, model.hit_points);
+        assert_eq!(ShipModelCategory::Cruiser.to_data().damage, model.damage);
+
+        // The hit points already raised by the other bonuses can not overflow
+        let sturdy_model = ShipModel{ hit_points: 65_000, ..ShipModelCategory::Cruiser.to_data() };
+        assert_eq!(u16::MAX, with_home_system_bonus(sturdy_model).hit_points);
+    }
+
+    #[test]
+    fn test_defender_bonus_from_config() {
+        let bonus = DefenderBonus::from_config(r#"{"precision": 10}"#);
+
+        assert_eq!(10, bonus.precision);
+        assert_eq!(0, bonus.hit_points);
+        assert_eq!(DefenderBonus::default(), DefenderBonus::from_config("{}"));
+        assert_eq!(DefenderBonus::default(), DefenderBonus::from_config("invalid"));
+    }
+
+    #[test]
+    fn test_defender_bonus() {
+        let model = ShipModelCategory::Fighter.to_data();
+        let bonus = DefenderBonus{ precision: 50, hit_points: 200 };
+
+        assert_eq!(90, bonus.with_attack_bonus(model).precision);
+        assert_eq!(30, bonus.with_defense_bonus(model).hit_points);
+
+        let disabled = DefenderBonus::default();
+        assert_eq!(model.precision, disabled.with_attack_bonus(model).precision);
+        assert_eq!(model.hit_points, disabled.with_defense_bonus(model).hit_points);
+    }
+
+    #[test]
+    fn test_fire_with_defender_bonus() {
+        let bonus = DefenderBonus{ precision: 0, hit_points: 200 };
+        let model = ShipModelCategory::Fighter.to_data();
+        let attacker = get_squadron_mock(ShipModelCategory::Fighter, FleetFormation::Right, 10);
+        let defender = get_squadron_mock(ShipModelCategory::Fighter, FleetFormation::Left, 20);
+
+        for _ in 0..20 {
+            // 10 fighters deal between 45 and 90 damage, which kill at least 4 fighters without the bonus
+            let (_, nb_casualties) = fire(&attacker, model, &defender, model);
+            let (_, nb_bonus_casualties) = fire(&attacker, model, &defender, bonus.with_defense_bonus(model));
+
+            assert!(nb_casualties >= 4);
+            assert!(nb_bonus_casualties < 4);
+        }
+    }
+
     #[test]
     fn test_fire() {
         let data = vec![
@@ -300,7 +603,7 @@ mod tests {
             let attacker = get_squadron_mock(cat, FleetFormation::Right, quantity);
             let defender = get_squadron_mock(tcat, FleetFormation::Left, tquantity);
 
-            let (remaining_ships, nb_casualties) = fire(&attacker, &defender);
+            let (remaining_ships, nb_casualties) = fire(&attacker, cat.to_data(), &defender, tcat.to_data());
 
             if has_casualties {
                 assert_eq!(true, remaining_ships > 0);
@@ -313,6 +616,45 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_get_captured_ships() {
+        let mut battle = get_battle_mock();
+        let get_action = |faction: u8, target_faction: u8, category, loss| SquadronAction{
+            squadron: FleetSquadronID(Uuid::new_v4()),
+            battle: battle.id,
+            kind: SquadronActionKind::Attack{ target: FleetSquadronID(Uuid::new_v4()), loss },
+            round_number: 1,
+            faction: Some(FactionID(faction)),
+            target_faction: Some(FactionID(target_faction)),
+            player: None,
+            target_category: Some(category),
+        };
+        let mut round = Round::new(battle.id, 1);
+        round.squadron_actions = vec![
+            get_action(1, 2, ShipModelCategory::Fighter, 40),
+            get_action(1, 2, ShipModelCategory::Cruiser, 5),
+            get_action(2, 1, ShipModelCategory::Fighter, 10),
+        ];
+        battle.rounds.push(round);
+        battle.victor = Some(FactionID(1));
+
+        // The capture is disabled by default
+        assert!(battle.get_captured_ships(0.0).is_empty());
+
+        let captured = battle.get_captured_ships(0.25);
+        assert_eq!(Some(&10), captured.get(&ShipModelCategory::Fighter));
+        assert_eq!(Some(&1), captured.get(&ShipModelCategory::Cruiser));
+        // The victor's own losses are never captured
+        assert_eq!(2, captured.len());
+
+        // A costly victory is not decisive enough to capture anything
+        battle.rounds[0].squadron_actions.push(get_action(2, 1, ShipModelCategory::Fighter, 20));
+        assert!(battle.get_captured_ships(0.25).is_empty());
+
+        battle.victor = None;
+        assert!(battle.get_captured_ships(0.25).is_empty());
+    }
+
     fn get_battle_mock() -> Battle {
         let mut faction_fleets = HashMap::new();
         let mut faction_1_fleets = HashMap::new();
@@ -332,7 +674,9 @@ mod tests {
             system: SystemID(Uuid::new_v4()),
             attacker: FleetID(Uuid::new_v4()),
             defender_faction: None,
+            garrison: None,
             fleets: faction_fleets,
+            initial_fleets: None,
             rounds: vec![],
             victor: None,
             begun_at: Time::now(),
@@ -343,10 +687,12 @@ mod tests {
     fn get_fleet_mock() -> Fleet {
         Fleet{
             id: FleetID(Uuid::new_v4()),
+            name: None,
             player: PlayerID(Uuid::new_v4()),
             system: SystemID(Uuid::new_v4()),
             destination_system: None,
             destination_arrival_date: None,
+            departure_date: None,
             squadrons: vec![
                 get_squadron_mock(ShipModelCategory::Fighter, FleetFormation::Left, 10),
                 get_squadron_mock(ShipModelCategory::Fighter, FleetFormation::Rear, 20),
@@ -363,6 +709,8 @@ mod tests {
             formation,
             category,
             quantity,
+            max_quantity: quantity,
+            auto_reinforce: false,
         }
     }
 }
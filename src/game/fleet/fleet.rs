@@ -1,4 +1,4 @@
-use actix_web::{post, patch, web, HttpResponse};
+use actix_web::{delete, get, post, patch, web, HttpResponse};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use std::fmt;
@@ -13,32 +13,58 @@ use crate::{
     game::{
         game::game::GameID,
         player::{Player, PlayerID},
-        system::system::{System, SystemID},
-        fleet::squadron::{FleetSquadron},
+        system::system::{System, SystemID, Coordinates},
+        fleet::{
+            combat::battle::Battle,
+            squadron::{FleetSquadron},
+            travel::get_travel_position,
+        },
+        ship::{
+            model::ShipModelCategory,
+            queue::ShipQueue,
+            squadron::Squadron,
+        },
     },
     ws::protocol,
     AppState
 };
 use sqlx::{PgPool, postgres::{PgRow, PgQueryAs}, FromRow, Executor, Error, Postgres};
 use sqlx_core::row::Row;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use chrono::Utc;
 
+/// Fleet range on medium maps, scaled for the other map sizes
 pub const FLEET_RANGE: f64 = 20.0;
+pub const FLEET_NAME_MAX_LENGTH: usize = 30;
 
 #[derive(Serialize, Debug, Deserialize, Clone, Hash, PartialEq, Eq, Copy)]
 pub struct FleetID(pub Uuid);
 
+/// The strength of one or several fleets, along with the share of each ship category
+#[derive(Debug, Serialize, Default)]
+pub struct FleetStrength {
+    pub strength: u32,
+    pub categories: HashMap<ShipModelCategory, u32>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Fleet{
     pub id: FleetID,
+    pub name: Option<String>,
     pub system: SystemID,
     pub destination_system: Option<SystemID>,
     pub destination_arrival_date: Option<Time>,
+    pub departure_date: Option<Time>,
     pub player: PlayerID,
     pub squadrons: Vec<FleetSquadron>,
     pub is_destroyed: bool,
 }
 
+#[derive(Deserialize)]
+pub struct FleetRenameRequest{
+    pub name: Option<String>,
+}
+
 impl fmt::Display for FleetID {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -59,9 +85,11 @@ impl<'a> FromRow<'a, PgRow<'a>> for Fleet {
     fn from_row(row: &PgRow) -> std::result::Result<Self, Error> {
         Ok(Fleet {
             id: row.try_get("id").map(FleetID)?,
+            name: row.try_get("name")?,
             system: row.try_get("system_id").map(SystemID)?,
             destination_system: row.try_get("destination_id").ok().map(SystemID),
             destination_arrival_date: row.try_get("destination_arrival_date")?,
+            departure_date: row.try_get("departure_date")?,
             player: row.try_get("player_id").map(PlayerID)?,
             squadrons: vec![],
             is_destroyed: row.try_get("is_destroyed")?,
@@ -74,10 +102,21 @@ impl Fleet {
         self.system = system.id.clone();
         self.destination_system = None;
         self.destination_arrival_date = None;
+        self.departure_date = None;
     }
 
+    /// While travelling, the fleet is located between its origin system and its destination
+    pub fn get_position(&self, origin: &Coordinates, destination: &Coordinates) -> Coordinates {
+        match (self.departure_date, self.destination_arrival_date) {
+            (Some(departure), Some(arrival)) => get_travel_position(origin, destination, departure.into(), arrival.into(), Utc::now()),
+            _ => origin.clone(),
+        }
+    }
+
+    /// Squadrons emptied in combat may not be removed yet, so a fleet is only considered empty
+    /// through the quantity of its squadrons
     pub fn can_fight(&self) -> bool {
-        !self.is_destroyed && !self.squadrons.is_empty() && self.squadrons.iter().any(|s| s.quantity > 0)
+        !self.is_destroyed && self.squadrons.iter().any(FleetSquadron::can_fight)
     }
 
     pub fn is_travelling(&self) -> bool {
@@ -96,35 +135,121 @@ impl Fleet {
             .fetch_all(db_pool).await.map_err(ServerError::from)
     }
 
-    pub async fn count_stationed_by_system(sid: &SystemID, db_pool: &PgPool) -> Result<i16> {
+    pub async fn find_by_player(pid: PlayerID, db_pool: &PgPool) -> Result<Vec<Fleet>> {
+        sqlx::query_as("SELECT * FROM fleet__fleets WHERE player_id = $1 AND is_destroyed = FALSE")
+            .bind(Uuid::from(pid))
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    /// The fleets travelling towards the systems of the given player, including its own ones
+    pub async fn find_incoming_by_player(pid: PlayerID, db_pool: &PgPool) -> Result<Vec<Fleet>> {
+        sqlx::query_as("SELECT f.* FROM fleet__fleets f
+            INNER JOIN map__systems s ON s.id = f.destination_id
+            WHERE s.player_id = $1 AND f.is_destroyed = FALSE")
+            .bind(Uuid::from(pid))
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub async fn count_by_player(pid: PlayerID, db_pool: &PgPool) -> Result<i64> {
+        sqlx::query_as("SELECT COUNT(*) FROM fleet__fleets WHERE player_id = $1 AND is_destroyed = FALSE")
+            .bind(Uuid::from(pid))
+            .fetch_one(db_pool).await
+            .map(|count: (i64,)| count.0)
+            .map_err(ServerError::from)
+    }
+
+    /// Locks the fleet until the end of the transaction, returning its current owner
+    pub async fn lock<E>(fid: FleetID, exec: &mut E) -> Result<PlayerID>
+        where E: Executor<Database = Postgres> {
+        sqlx::query_as("SELECT player_id FROM fleet__fleets WHERE id = $1 FOR UPDATE")
+            .bind(Uuid::from(fid))
+            .fetch_one(&mut *exec).await
+            .map(|(pid,): (Uuid,)| PlayerID(pid))
+            .map_err(ServerError::from)
+    }
+
+    /// Locks the given fleets which are neither travelling nor fighting until the end of the transaction,
+    /// returning their ids. The fleets are locked in a consistent order
+    pub async fn lock_idle<E>(ids: Vec<FleetID>, exec: &mut E) -> Result<HashSet<FleetID>>
+        where E: Executor<Database = Postgres> {
+        sqlx::query_as("SELECT f.id FROM fleet__fleets f
+            WHERE f.id = ANY($1) AND f.destination_id IS NULL AND f.is_destroyed = FALSE
+            AND NOT EXISTS (SELECT 1 FROM fleet__combat__battles b WHERE b.system_id = f.system_id AND b.ended_at IS NULL)
+            ORDER BY f.id FOR UPDATE OF f")
+            .bind(ids.into_iter().map(Uuid::from).collect::<Vec<Uuid>>())
+            .fetch_all(&mut *exec).await
+            .map(|rows: Vec<(Uuid,)>| rows.into_iter().map(|(id,)| FleetID(id)).collect())
+            .map_err(ServerError::from)
+    }
+
+    pub async fn count_stationed_by_system<E>(sid: &SystemID, exec: &mut E) -> Result<i16>
+        where E: Executor<Database = Postgres> {
         sqlx::query_as("SELECT COUNT(*) FROM fleet__fleets WHERE system_id = $1 AND destination_id IS NULL AND is_destroyed = FALSE")
             .bind(Uuid::from(sid.clone()))
-            .fetch_one(db_pool).await
+            .fetch_one(&mut *exec).await
             .map(|count: (i64,)| count.0 as i16)
             .map_err(ServerError::from)
     }
 
     pub async fn insert<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres> {
-        sqlx::query("INSERT INTO fleet__fleets(id, system_id, player_id) VALUES($1, $2, $3)")
+        sqlx::query("INSERT INTO fleet__fleets(id, name, system_id, player_id) VALUES($1, $2, $3, $4)")
             .bind(Uuid::from(self.id))
+            .bind(self.name.clone())
             .bind(Uuid::from(self.system))
             .bind(Uuid::from(self.player))
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
+    /// Delays the arrival of the fleets travelling during the pause. The ones which left meanwhile only start once it is resumed.
+    /// The departure moves by as much, so the travel progress is kept
+    pub async fn postpone_deadlines<E>(gid: GameID, paused_at: Time, resumed_at: Time, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE fleet__fleets SET
+            departure_date = departure_date + ($3 - GREATEST(departure_date, $2)),
+            destination_arrival_date = destination_arrival_date + ($3 - GREATEST(departure_date, $2))
+            WHERE destination_arrival_date > $2 AND system_id IN (SELECT id FROM map__systems WHERE game_id = $1)")
+            .bind(Uuid::from(gid))
+            .bind(paused_at)
+            .bind(resumed_at)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
     pub async fn update<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres> {
-        sqlx::query("UPDATE fleet__fleets SET system_id=$1, destination_id=$2, destination_arrival_date=$3, player_id=$4, is_destroyed=$5 WHERE id=$6")
+        sqlx::query("UPDATE fleet__fleets SET name=$1, system_id=$2, destination_id=$3, destination_arrival_date=$4, departure_date=$5, player_id=$6, is_destroyed=$7,
+            destroyed_at = CASE WHEN $7 THEN COALESCE(destroyed_at, NOW()) ELSE NULL END WHERE id=$8")
+            .bind(self.name.clone())
             .bind(Uuid::from(self.system))
             .bind(self.destination_system.map(Uuid::from))
             .bind(self.destination_arrival_date)
+            .bind(self.departure_date)
             .bind(Uuid::from(self.player))
             .bind(self.is_destroyed)
             .bind(Uuid::from(self.id))
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
+    /// Only renames a fleet still owned by the player, the other columns may have changed since it was read
+    pub async fn update_name<E>(&self, pid: PlayerID, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE fleet__fleets SET name = $1 WHERE id = $2 AND player_id = $3 AND is_destroyed = FALSE")
+            .bind(self.name.clone())
+            .bind(Uuid::from(self.id))
+            .bind(Uuid::from(pid))
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
+    /// Returns zero when the fleet is no longer stationed and owned by the player
+    pub async fn scuttle<E>(fid: FleetID, pid: PlayerID, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE fleet__fleets SET is_destroyed = TRUE, destroyed_at = NOW()
+            WHERE id = $1 AND player_id = $2 AND destination_id IS NULL AND is_destroyed = FALSE")
+            .bind(Uuid::from(fid))
+            .bind(Uuid::from(pid))
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
     pub async fn remove<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres> {
         sqlx::query("DELETE FROM fleet__fleets WHERE id = $1")
@@ -132,6 +257,29 @@ impl Fleet {
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
+    /// Removes the fleets of the game destroyed for longer than the retention delay, with their squadrons.
+    /// Fleets of systems with an ongoing battle are kept until the battle reports are written.
+    pub async fn remove_destroyed<E>(gid: GameID, retention: f64, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("DELETE FROM fleet__fleets f
+            WHERE f.is_destroyed = TRUE AND f.destroyed_at < NOW() - make_interval(secs => $2)
+            AND f.system_id IN (SELECT id FROM map__systems WHERE game_id = $1)
+            AND NOT EXISTS (SELECT 1 FROM fleet__combat__battles b WHERE b.system_id = f.system_id AND b.ended_at IS NULL)")
+            .bind(Uuid::from(gid))
+            .bind(retention)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
+    pub fn rename(&mut self, name: Option<String>) -> Result<()> {
+        let name = name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+
+        if name.as_ref().map_or(false, |n| n.chars().count() > FLEET_NAME_MAX_LENGTH) {
+            return Err(InternalError::ValidationFailed{ field: "name" }.into());
+        }
+        self.name = name;
+        Ok(())
+    }
+
     pub fn get_strength(&self) -> u32 {
         let mut strength = 0;
         for squadron in &self.squadrons {
@@ -150,10 +298,12 @@ pub async fn create_fleet(state: web::Data<AppState>, info: web::Path<(GameID,Sy
     }
     let fleet = Fleet{
         id: FleetID(Uuid::new_v4()),
+        name: None,
         player: claims.pid.clone(),
         system: system.id.clone(),
         destination_system: None,
         destination_arrival_date: None,
+        departure_date: None,
         squadrons: vec![],
         is_destroyed: false,
     };
@@ -171,6 +321,15 @@ pub async fn create_fleet(state: web::Data<AppState>, info: web::Path<(GameID,Sy
     Ok(HttpResponse::Created().json(fleet))
 }
 
+/// A travelling fleet has a pending arrival, and a fighting one belongs to the current battle of its system,
+/// so neither can change hands
+fn check_donation(fleet: &Fleet, nb_current_battles: i16) -> Result<()> {
+    if fleet.is_travelling() || nb_current_battles > 0 {
+        return Err(InternalError::Conflict.into());
+    }
+    Ok(())
+}
+
 #[patch("/donate/")]
 pub async fn donate(
     state: web::Data<AppState>,
@@ -191,6 +350,10 @@ pub async fn donate(
     if fleet.player != player.id || system.player.is_none() || fleet.system != system.id {
         return Err(InternalError::Conflict.into());
     }
+    if !fleet.can_fight() {
+        return Err(InternalError::FleetEmpty.into());
+    }
+    check_donation(&fleet, Battle::count_current_by_system(&system.id, &state.db_pool).await?)?;
 
     let other_player = Player::find(system.player.unwrap(), &state.db_pool).await?;
 
@@ -233,6 +396,306 @@ pub async fn donate(
     Ok(HttpResponse::NoContent().finish())
 }
 
+#[get("/")]
+pub async fn get_fleet(
+    state: web::Data<AppState>,
+    info: web::Path<(GameID,SystemID,FleetID,)>,
+    claims: Claims
+) -> Result<HttpResponse> {
+    #[derive(Serialize)]
+    pub struct FleetData {
+        #[serde(flatten)]
+        fleet: Fleet,
+        position: Coordinates,
+    }
+    let (f, sg, p) = futures::join!(
+        Fleet::find(&info.2, &state.db_pool),
+        FleetSquadron::find_by_fleet(info.2, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    let mut fleet = f?;
+    fleet.squadrons = sg?;
+    let (s, o) = futures::join!(
+        System::find(fleet.system, &state.db_pool),
+        Player::find(fleet.player, &state.db_pool),
+    );
+    let system = s?;
+
+    // The squadrons are only disclosed like the strength of the fleet, to its owner and their faction mates
+    if system.game != info.0 || fleet.system != info.1 || !can_see_strength(info.0, &p?, &o?) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let position = match fleet.destination_system {
+        Some(destination_id) => fleet.get_position(&system.coordinates, &System::find(destination_id, &state.db_pool).await?.coordinates),
+        None => system.coordinates,
+    };
+
+    Ok(HttpResponse::Ok().json(FleetData{ fleet, position }))
+}
+
+#[patch("/")]
+pub async fn rename(
+    state: web::Data<AppState>,
+    info: web::Path<(GameID,SystemID,FleetID,)>,
+    json_data: web::Json<FleetRenameRequest>,
+    claims: Claims
+) -> Result<HttpResponse> {
+    let (s, f) = futures::join!(
+        System::find(info.1, &state.db_pool),
+        Fleet::find(&info.2, &state.db_pool),
+    );
+    let system = s?;
+    let mut fleet = f?;
+
+    if system.game != info.0 || fleet.system != info.1 || fleet.player != claims.pid {
+        return Err(InternalError::AccessDenied.into());
+    }
+    if fleet.is_destroyed {
+        return Err(InternalError::Conflict.into());
+    }
+    fleet.rename(json_data.name.clone())?;
+    if fleet.update_name(claims.pid, &mut &state.db_pool).await? == 0 {
+        return Err(InternalError::Conflict.into());
+    }
+    fleet.squadrons = FleetSquadron::find_by_fleet(fleet.id, &state.db_pool).await?;
+
+    Ok(HttpResponse::Ok().json(fleet))
+}
+
+#[delete("/")]
+pub async fn disband(
+    state: web::Data<AppState>,
+    info: web::Path<(GameID,SystemID,FleetID,)>,
+    claims: Claims
+) -> Result<HttpResponse> {
+    let (s, f) = futures::join!(
+        System::find(info.1, &state.db_pool),
+        Fleet::find(&info.2, &state.db_pool),
+    );
+    let system = s?;
+    let mut fleet = f?;
+
+    if system.game != info.0 || fleet.player != claims.pid || system.player != Some(claims.pid) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    if fleet.system != system.id || fleet.is_destroyed {
+        return Err(InternalError::Conflict.into());
+    }
+    if fleet.is_travelling() {
+        return Err(InternalError::FleetAlreadyTravelling.into());
+    }
+    if Battle::count_current_by_system(&system.id, &state.db_pool).await? > 0 {
+        return Err(InternalError::Conflict.into());
+    }
+
+    // The ships are read with their rows locked, in the same order as the assignments and reinforcements,
+    // so that ships produced or assigned meanwhile are neither lost nor duplicated
+    let mut tx = state.db_pool.begin().await?;
+    let system_owner = System::lock(system.id, &mut tx).await?;
+    let fleet_owner = Fleet::lock(fleet.id, &mut tx).await?;
+    if system_owner != Some(claims.pid) || fleet_owner != claims.pid {
+        tx.rollback().await?;
+        return Err(InternalError::AccessDenied.into());
+    }
+    let mut squadrons: HashMap<ShipModelCategory, Squadron> = Squadron::find_by_system_for_update(system.id, &mut tx).await?
+        .into_iter()
+        .map(|s| (s.category, s))
+        .collect();
+    fleet.squadrons = FleetSquadron::find_by_fleet_for_update(fleet.id, &mut tx).await?;
+
+    // Several fleet squadrons may share the same category on different formations
+    let mut quantities: HashMap<ShipModelCategory, i32> = HashMap::new();
+    for fs in &fleet.squadrons {
+        *quantities.entry(fs.category).or_insert(0) += fs.quantity as i32;
+    }
+
+    for (category, quantity) in quantities {
+        let squadron = squadrons.remove(&category);
+        let stationed_quantity = squadron.as_ref().map_or(0, |s| s.quantity as i32);
+        Squadron::assign(squadron, system.id, category, stationed_quantity + quantity, &mut tx).await?;
+    }
+    for fs in &fleet.squadrons {
+        fs.remove(&mut tx).await?;
+    }
+    // The ships still produced for the fleet are stored in the system instead
+    ShipQueue::unassign_fleet(fleet.id, &mut tx).await?;
+    fleet.remove(&mut tx).await?;
+    tx.commit().await?;
+
+    log(
+        gelf::Level::Informational,
+        "Fleet disbanded",
+        &format!("Fleet {} has been disbanded on system {}", fleet.to_log_message(), system.to_log_message()),
+        vec![
+            ("fleet_id", fleet.id.to_string()),
+            ("system_id", system.id.0.to_string()),
+        ],
+        &state.logger
+    );
+
+    let games = state.games();
+    let game = games.get(&info.0).cloned().ok_or(InternalError::GameUnknown)?;
+    game.do_send(protocol::Message::new(
+        protocol::Action::FleetDisbanded,
+        fleet,
+        None,
+    ));
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Destroys a fleet of the player, denying it to an incoming enemy. No one is credited for the destroyed ships
+#[post("/scuttle/")]
+pub async fn scuttle(
+    state: web::Data<AppState>,
+    info: web::Path<(GameID,SystemID,FleetID,)>,
+    claims: Claims
+) -> Result<HttpResponse> {
+    let (s, f) = futures::join!(
+        System::find(info.1, &state.db_pool),
+        Fleet::find(&info.2, &state.db_pool),
+    );
+    let system = s?;
+    let mut fleet = f?;
+
+    if system.game != info.0 || fleet.player != claims.pid {
+        return Err(InternalError::AccessDenied.into());
+    }
+    if fleet.system != system.id || fleet.is_destroyed {
+        return Err(InternalError::Conflict.into());
+    }
+    if fleet.is_travelling() {
+        return Err(InternalError::FleetAlreadyTravelling.into());
+    }
+    // Fleets in a battle are resolved by the combat
+    if Battle::count_current_by_system(&system.id, &state.db_pool).await? > 0 {
+        return Err(InternalError::FleetInBattle.into());
+    }
+
+    // The rows are locked in the same order as the assignments and reinforcements,
+    // so that no ship is assigned to the fleet while it is destroyed
+    let mut tx = state.db_pool.begin().await?;
+    System::lock(system.id, &mut tx).await?;
+    if Fleet::lock(fleet.id, &mut tx).await? != claims.pid {
+        tx.rollback().await?;
+        return Err(InternalError::AccessDenied.into());
+    }
+    for fs in FleetSquadron::find_by_fleet_for_update(fleet.id, &mut tx).await? {
+        fs.remove(&mut tx).await?;
+    }
+    // The ships still produced for the fleet are stored in the system instead
+    ShipQueue::unassign_fleet(fleet.id, &mut tx).await?;
+    if Fleet::scuttle(fleet.id, claims.pid, &mut tx).await? == 0 {
+        tx.rollback().await?;
+        return Err(InternalError::Conflict.into());
+    }
+    tx.commit().await?;
+    fleet.squadrons = vec![];
+    fleet.is_destroyed = true;
+
+    log(
+        gelf::Level::Informational,
+        "Fleet scuttled",
+        &format!("Fleet {} has been scuttled on system {}", fleet.to_log_message(), system.to_log_message()),
+        vec![
+            ("fleet_id", fleet.id.to_string()),
+            ("system_id", system.id.0.to_string()),
+        ],
+        &state.logger
+    );
+
+    let games = state.games();
+    let game = games.get(&info.0).cloned().ok_or(InternalError::GameUnknown)?;
+    game.do_send(protocol::Message::new(
+        protocol::Action::FleetScuttled,
+        fleet,
+        None,
+    ));
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn get_fleets_strength<'a>(fleets: impl Iterator<Item = &'a Fleet>) -> FleetStrength {
+    let mut fleet_strength = FleetStrength::default();
+
+    for fleet in fleets {
+        fleet_strength.strength += fleet.get_strength();
+        for squadron in &fleet.squadrons {
+            *fleet_strength.categories.entry(squadron.category).or_default() += squadron.category.to_data().strength as u32 * squadron.quantity as u32;
+        }
+    }
+    fleet_strength
+}
+
+/// The forces of a player are disclosed to itself and to its faction members only.
+/// Faction IDs are shared by all games, so both players must belong to the requested game
+fn can_see_strength(gid: GameID, player: &Player, owner: &Player) -> bool {
+    if player.game != Some(gid) || owner.game != Some(gid) {
+        return false;
+    }
+    player.id == owner.id || (player.faction.is_some() && player.faction == owner.faction)
+}
+
+#[get("/strength/")]
+pub async fn get_fleet_strength(
+    state: web::Data<AppState>,
+    info: web::Path<(GameID,SystemID,FleetID,)>,
+    claims: Claims
+) -> Result<HttpResponse> {
+    let (f, sg, p) = futures::join!(
+        Fleet::find(&info.2, &state.db_pool),
+        FleetSquadron::find_by_fleet(info.2, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    let mut fleet = f?;
+    fleet.squadrons = sg?;
+    let owner = Player::find(fleet.player, &state.db_pool).await?;
+
+    if fleet.system != info.1 || !can_see_strength(info.0, &p?, &owner) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    Ok(HttpResponse::Ok().json(get_fleets_strength(std::iter::once(&fleet))))
+}
+
+/// Sums the strength of the fleets stationed in the system by its owner and the owner's faction-mates.
+/// The enemy fleets waiting in the system are not part of its garrison
+#[get("/strength/")]
+pub async fn get_system_fleets_strength(
+    state: web::Data<AppState>,
+    info: web::Path<(GameID,SystemID,)>,
+    claims: Claims
+) -> Result<HttpResponse> {
+    let (s, p) = futures::join!(
+        System::find(info.1, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    let system = s?;
+    if system.game != info.0 {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let owner = Player::find(system.player.ok_or(InternalError::AccessDenied)?, &state.db_pool).await?;
+
+    if !can_see_strength(info.0, &p?, &owner) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let mut fleets: HashMap<FleetID, Fleet> = Fleet::find_stationed_by_system(&system.id, &state.db_pool).await?
+        .into_iter()
+        .map(|fleet| (fleet.id, fleet))
+        .collect();
+    let allies: HashSet<PlayerID> = Player::find_by_ids(get_fleet_player_ids(&fleets), &state.db_pool).await?
+        .into_iter()
+        .filter(|player| can_see_strength(info.0, player, &owner))
+        .map(|player| player.id)
+        .collect();
+    fleets.retain(|_, fleet| allies.contains(&fleet.player));
+    for squadron in FleetSquadron::find_by_fleets(fleets.keys().copied().collect(), &state.db_pool).await? {
+        if let Some(fleet) = fleets.get_mut(&squadron.fleet) {
+            fleet.squadrons.push(squadron);
+        }
+    }
+    Ok(HttpResponse::Ok().json(get_fleets_strength(fleets.values())))
+}
+
 pub fn get_fleet_player_ids(fleets: &HashMap<FleetID, Fleet>) -> Vec<PlayerID> {
     fleets.iter().map(|(_, f)| f.player).collect()
 }
@@ -254,7 +717,8 @@ mod tests {
             },
             ship::model::ShipModelCategory,
             system::system::{System, SystemID, SystemKind,  Coordinates},
-            player::{PlayerID}
+            player::{PlayerID},
+            faction::FactionID,
         }
     };
 
@@ -278,6 +742,71 @@ mod tests {
         assert!(!fleet.can_fight());
     }
 
+    #[test]
+    fn test_can_fight_with_emptied_squadrons() {
+        let mut fleet = get_fleet_mock();
+        let mut squadron = fleet.squadrons[0].clone();
+        squadron.formation = FleetFormation::Left;
+        fleet.squadrons.push(squadron);
+        fleet.squadrons.iter_mut().for_each(|s| s.quantity = 0);
+
+        assert!(fleet.squadrons.iter().all(|s| !s.can_fight()));
+        assert!(!fleet.can_fight());
+
+        fleet.squadrons[1].quantity = 3;
+
+        assert!(fleet.can_fight());
+    }
+
+    #[test]
+    fn test_get_fleets_strength() {
+        let mut fleet = get_fleet_mock();
+        fleet.squadrons[0].quantity = 10;
+        let mut other_fleet = get_fleet_mock();
+        let mut squadron = other_fleet.squadrons[0].clone();
+        squadron.category = ShipModelCategory::Cruiser;
+        squadron.quantity = 2;
+        other_fleet.squadrons.push(squadron);
+
+        let fleet_strength = get_fleets_strength(vec![&fleet, &other_fleet].into_iter());
+
+        assert_eq!(fleet.get_strength() + other_fleet.get_strength(), fleet_strength.strength);
+        assert_eq!(Some(&11), fleet_strength.categories.get(&ShipModelCategory::Fighter));
+        assert_eq!(Some(&150), fleet_strength.categories.get(&ShipModelCategory::Cruiser));
+        assert_eq!(None, fleet_strength.categories.get(&ShipModelCategory::Frigate));
+    }
+
+    #[test]
+    fn test_can_see_strength() {
+        let gid = GameID(Uuid::new_v4());
+        let get_game_player_mock = |faction| Player{ game: Some(gid), ..get_player_mock(faction) };
+        let owner = get_game_player_mock(Some(FactionID(1)));
+        let ally = get_game_player_mock(Some(FactionID(1)));
+        let enemy = get_game_player_mock(Some(FactionID(2)));
+        let lone_owner = get_game_player_mock(None);
+
+        assert!(can_see_strength(gid, &owner, &owner));
+        assert!(can_see_strength(gid, &ally, &owner));
+        assert!(!can_see_strength(gid, &enemy, &owner));
+        assert!(!can_see_strength(gid, &get_game_player_mock(None), &lone_owner));
+        // The same faction in another game is not an ally
+        let other_game_player = Player{ game: Some(GameID(Uuid::new_v4())), ..get_player_mock(Some(FactionID(1))) };
+        assert!(!can_see_strength(gid, &other_game_player, &owner));
+        // Neither are the players and fleets of another game than the requested one
+        assert!(!can_see_strength(GameID(Uuid::new_v4()), &ally, &owner));
+    }
+
+    #[test]
+    fn test_check_donation() {
+        let mut fleet = get_fleet_mock();
+
+        assert!(check_donation(&fleet, 0).is_ok());
+        assert!(check_donation(&fleet, 1).is_err());
+
+        fleet.destination_system = Some(SystemID(Uuid::new_v4()));
+        assert!(check_donation(&fleet, 0).is_err());
+    }
+
     #[test]
     fn test_is_travelling() {
         let mut fleet = get_fleet_mock();
@@ -301,6 +830,21 @@ mod tests {
         assert_eq!(fleet.system, system.id);
         assert_eq!(fleet.destination_system, None);
         assert_eq!(fleet.destination_arrival_date, None);
+        assert_eq!(fleet.departure_date, None);
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut fleet = get_fleet_mock();
+
+        assert!(fleet.rename(Some(String::from("  Home Guard "))).is_ok());
+        assert_eq!(fleet.name, Some(String::from("Home Guard")));
+
+        assert!(fleet.rename(Some("a".repeat(FLEET_NAME_MAX_LENGTH + 1))).is_err());
+        assert_eq!(fleet.name, Some(String::from("Home Guard")));
+
+        assert!(fleet.rename(Some(String::from("   "))).is_ok());
+        assert_eq!(fleet.name, None);
     }
 
     #[test]
@@ -324,10 +868,12 @@ mod tests {
     fn get_fleet_mock() -> Fleet {
         Fleet{
             id: FleetID(Uuid::new_v4()),
+            name: None,
             player: PlayerID(Uuid::new_v4()),
             system: SystemID(Uuid::new_v4()),
             destination_system: None,
             destination_arrival_date: None,
+            departure_date: None,
             squadrons: vec![
                 FleetSquadron{
                     id: FleetSquadronID(Uuid::new_v4()),
@@ -335,12 +881,29 @@ mod tests {
                     formation: FleetFormation::Center,
                     category: ShipModelCategory::Fighter,
                     quantity: 1,
+                    max_quantity: 1,
+                    auto_reinforce: false,
                 }
             ],
             is_destroyed: false,
         }
     }
 
+    fn get_player_mock(faction: Option<FactionID>) -> Player {
+        Player{
+            id: PlayerID(Uuid::new_v4()),
+            username: String::from("Galadruin"),
+            game: None,
+            lobby: None,
+            faction,
+            ready: true,
+            wallet: 0,
+            is_connected: true,
+            home_system: None,
+            is_eliminated: false,
+        }
+    }
+
     fn get_system_mock() -> System {
         System {
             id: SystemID(Uuid::new_v4()),
@@ -2,6 +2,7 @@ use serde::{Serialize, Deserialize};
 use crate::lib::error::InternalError;
 use std::str::FromStr;
 use std::fmt;
+use std::collections::HashMap;
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone, Hash, Eq, PartialEq, sqlx::Type)]
 #[sqlx(rename = "VARCHAR")]
@@ -14,7 +15,18 @@ pub enum FleetFormation {
     Rear,
 }
 
+/// Targeting rules of a game, indexed by the attacking formation. The hardcoded
+/// [FleetFormation::attack_order] is used unless a valid custom order is configured.
+#[derive(Debug, Clone)]
+pub struct AttackOrders(HashMap<FleetFormation, Vec<FleetFormation>>);
+
 impl FleetFormation {
+    pub const ALL: [FleetFormation; 4] = [
+        FleetFormation::Left,
+        FleetFormation::Center,
+        FleetFormation::Right,
+        FleetFormation::Rear,
+    ];
 
     /// When attacking, a squadron will have an advantage or disadvantage because of
     /// their relative position with its opponent. This function represent this "position factor"
@@ -48,6 +60,34 @@ impl FleetFormation {
     }
 }
 
+impl Default for AttackOrders {
+    fn default() -> Self {
+        AttackOrders(FleetFormation::ALL.iter().map(|f| (*f, f.attack_order().to_vec())).collect())
+    }
+}
+
+impl AttackOrders {
+    /// Parses a JSON object such as `{"rear": ["center", "left", "right", "rear"]}`.
+    /// Formations which are not configured keep their default order.
+    pub fn from_config(config: &str) -> Self {
+        let mut attack_orders = Self::default();
+
+        if let Ok(custom_orders) = serde_json::from_str::<HashMap<FleetFormation, Vec<FleetFormation>>>(config) {
+            for (formation, order) in custom_orders {
+                // Same as the default orders, every formation must be attackable to prevent battles to get stuck
+                if FleetFormation::ALL.iter().all(|f| order.contains(f)) {
+                    attack_orders.0.insert(formation, order);
+                }
+            }
+        }
+        attack_orders
+    }
+
+    pub fn get(&self, formation: FleetFormation) -> &[FleetFormation] {
+        self.0.get(&formation).map_or(formation.attack_order(), Vec::as_slice)
+    }
+}
+
 impl FromStr for FleetFormation {
     type Err = InternalError;
 
@@ -93,4 +133,21 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_attack_orders_from_config() {
+        let attack_orders = AttackOrders::from_config(r#"{
+            "center": ["right", "left", "center", "rear"],
+            "rear": ["left", "right"]
+        }"#);
+
+        assert_eq!(&[FleetFormation::Right, FleetFormation::Left, FleetFormation::Center, FleetFormation::Rear], attack_orders.get(FleetFormation::Center));
+        // Incomplete orders are ignored
+        assert_eq!(FleetFormation::Rear.attack_order(), attack_orders.get(FleetFormation::Rear));
+        assert_eq!(FleetFormation::Left.attack_order(), attack_orders.get(FleetFormation::Left));
+
+        let attack_orders = AttackOrders::from_config("invalid");
+
+        assert_eq!(FleetFormation::Center.attack_order(), attack_orders.get(FleetFormation::Center));
+    }
 }
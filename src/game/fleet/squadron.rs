@@ -1,4 +1,4 @@
-use actix_web::{post , web, HttpResponse};
+use actix_web::{patch, post, web, HttpResponse};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 use crate::{
@@ -24,14 +24,16 @@ use crate::{
             squadron::Squadron,
             model::ShipModelCategory,
         },
-        player::Player,
+        player::{Player, PlayerID},
     },
+    ws::protocol,
     AppState
 };
 use futures::executor::block_on;
 use sqlx::{PgPool, postgres::{PgRow, PgQueryAs}, FromRow, Executor, Error, Postgres};
 use sqlx_core::row::Row;
 use futures::join;
+use std::collections::HashMap;
 
 #[derive(Debug, Deserialize, Serialize, Clone, Hash, PartialEq, Eq, Copy)]
 pub struct FleetSquadronID(pub Uuid);
@@ -47,6 +49,9 @@ pub struct FleetSquadron {
     pub formation: FleetFormation,
     pub category: ShipModelCategory,
     pub quantity: u16,
+    /// Size given by the player at the last assignment, which auto-reinforcement refills toward
+    pub max_quantity: u16,
+    pub auto_reinforce: bool,
 }
 
 #[derive(Deserialize)]
@@ -56,6 +61,11 @@ pub struct SquadronAssignmentData {
     pub quantity: usize
 }
 
+#[derive(Deserialize)]
+pub struct SquadronUpdateData {
+    pub auto_reinforce: bool,
+}
+
 impl Loggable for FleetSquadron {
     fn to_log_message(&self) -> String {
         self.id.0.to_string()
@@ -70,6 +80,25 @@ impl<'a> FromRow<'a, PgRow<'a>> for FleetSquadron {
             formation: row.try_get("formation")?,
             category: row.try_get("category")?,
             quantity: row.try_get::<i32, _>("quantity")? as u16,
+            max_quantity: row.try_get::<i32, _>("max_quantity")? as u16,
+            auto_reinforce: row.try_get("auto_reinforce")?,
+        })
+    }
+}
+
+/// Fleet squadron along with the location and owner of its fleet
+pub struct StationedSquadron {
+    pub system: SystemID,
+    pub player: PlayerID,
+    pub squadron: FleetSquadron,
+}
+
+impl<'a> FromRow<'a, PgRow<'a>> for StationedSquadron {
+    fn from_row(row: &PgRow) -> std::result::Result<Self, Error> {
+        Ok(StationedSquadron {
+            system: row.try_get("fleet_system_id").map(SystemID)?,
+            player: row.try_get("fleet_player_id").map(PlayerID)?,
+            squadron: FleetSquadron::from_row(row)?,
         })
     }
 }
@@ -91,6 +120,14 @@ impl FleetSquadron {
             .fetch_all(db_pool).await.map_err(ServerError::from)
     }
     
+    /// Locks the squadrons of the fleet until the end of the transaction
+    pub async fn find_by_fleet_for_update<E>(fid: FleetID, exec: &mut E) -> Result<Vec<Self>>
+        where E: Executor<Database = Postgres> {
+        sqlx::query_as("SELECT * FROM fleet__squadrons WHERE fleet_id = $1 FOR UPDATE")
+            .bind(Uuid::from(fid))
+            .fetch_all(&mut *exec).await.map_err(ServerError::from)
+    }
+    
     pub async fn find_by_fleet_and_category(fid: FleetID, category: ShipModelCategory, db_pool: &PgPool) -> Result<Option<Self>> {
         sqlx::query_as("SELECT * FROM fleet__squadrons WHERE fleet_id = $1 AND category = $2")
             .bind(Uuid::from(fid))
@@ -104,31 +141,78 @@ impl FleetSquadron {
             .bind(formation)
             .fetch_optional(db_pool).await.map_err(ServerError::from)
     }
-    
+
+    pub async fn find_by_fleet_and_formation_for_update<E>(fid: FleetID, formation: FleetFormation, exec: &mut E) -> Result<Option<Self>>
+        where E: Executor<Database = Postgres> {
+        sqlx::query_as("SELECT * FROM fleet__squadrons WHERE fleet_id = $1 AND formation = $2 FOR UPDATE")
+            .bind(Uuid::from(fid))
+            .bind(formation)
+            .fetch_optional(&mut *exec).await.map_err(ServerError::from)
+    }
+
+    /// Returns the squadrons to refill, belonging to fleets stationed in a system of their owner,
+    /// which are neither travelling nor fighting
+    pub async fn find_reinforceable(gid: GameID, db_pool: &PgPool) -> Result<Vec<StationedSquadron>> {
+        sqlx::query_as("SELECT fs.*, f.system_id AS fleet_system_id, f.player_id AS fleet_player_id FROM fleet__squadrons fs
+            INNER JOIN fleet__fleets f ON f.id = fs.fleet_id
+            INNER JOIN map__systems s ON s.id = f.system_id
+            WHERE s.game_id = $1 AND s.player_id = f.player_id
+            AND fs.auto_reinforce = TRUE AND fs.quantity < fs.max_quantity
+            AND f.is_destroyed = FALSE AND f.destination_id IS NULL
+            AND NOT EXISTS (SELECT 1 FROM fleet__combat__battles b WHERE b.system_id = f.system_id AND b.ended_at IS NULL)")
+            .bind(Uuid::from(gid))
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    /// Returns the total strength of the fleets of each player of the game
+    pub async fn get_strength_by_players(gid: GameID, db_pool: &PgPool) -> Result<HashMap<PlayerID, u32>> {
+        let quantities: Vec<(Uuid, ShipModelCategory, i64)> = sqlx::query_as("SELECT f.player_id, fs.category, SUM(fs.quantity) FROM fleet__squadrons fs
+            INNER JOIN fleet__fleets f ON f.id = fs.fleet_id
+            INNER JOIN player__players p ON p.id = f.player_id
+            WHERE p.game_id = $1 AND f.is_destroyed = FALSE
+            GROUP BY f.player_id, fs.category")
+            .bind(Uuid::from(gid))
+            .fetch_all(db_pool).await.map_err(ServerError::from)?;
+
+        Ok(sum_strength_by_players(quantities))
+    }
+
     pub async fn insert<E>(&self, exec: &mut E) -> Result<u64>
     where
         E: Executor<Database = Postgres> {
-        sqlx::query("INSERT INTO fleet__squadrons (id, fleet_id, category, formation, quantity) VALUES($1, $2, $3, $4, $5)")
+        sqlx::query("INSERT INTO fleet__squadrons (id, fleet_id, category, formation, quantity, max_quantity, auto_reinforce) VALUES($1, $2, $3, $4, $5, $6, $7)")
             .bind(Uuid::from(self.id))
             .bind(Uuid::from(self.fleet))
             .bind(self.category)
             .bind(self.formation)
             .bind(self.quantity as i32)
+            .bind(self.max_quantity as i32)
+            .bind(self.auto_reinforce)
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
     pub async fn update<E>(&self, exec: &mut E) -> Result<u64>
     where
         E: Executor<Database = Postgres> {
-        sqlx::query("UPDATE fleet__squadrons SET fleet_id = $2, category = $3, formation = $4, quantity = $5 WHERE id = $1")
+        sqlx::query("UPDATE fleet__squadrons SET fleet_id = $2, category = $3, formation = $4, quantity = $5, max_quantity = $6, auto_reinforce = $7 WHERE id = $1")
             .bind(Uuid::from(self.id))
             .bind(Uuid::from(self.fleet))
             .bind(self.category)
             .bind(self.formation)
             .bind(self.quantity as i32)
+            .bind(self.max_quantity as i32)
+            .bind(self.auto_reinforce)
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
     
+    pub async fn update_auto_reinforce<E>(fsid: FleetSquadronID, auto_reinforce: bool, exec: &mut E) -> Result<Self>
+        where E: Executor<Database = Postgres> {
+        sqlx::query_as("UPDATE fleet__squadrons SET auto_reinforce = $1 WHERE id = $2 RETURNING *")
+            .bind(auto_reinforce)
+            .bind(Uuid::from(fsid))
+            .fetch_one(&mut *exec).await.map_err(ServerError::if_row_not_found(InternalError::NotFound))
+    }
+
     pub async fn remove<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres> {
         sqlx::query("DELETE FROM fleet__squadrons WHERE id = $1")
@@ -151,7 +235,9 @@ impl FleetSquadron {
                 fleet: fid.clone(),
                 formation: formation.clone(),
                 quantity: quantity as u16,
+                max_quantity: quantity as u16,
                 category: category.clone(),
+                auto_reinforce: false,
             };
             fs.insert(&mut *exec).await?;
         } else if fleet_squadron.is_some() && quantity > 0 {
@@ -160,6 +246,7 @@ impl FleetSquadron {
                 return Err(InternalError::Conflict.into());
             }
             fs.quantity = quantity;
+            fs.max_quantity = quantity;
             fs.update(&mut *exec).await?;
         } else if fleet_squadron.is_some() {
             fleet_squadron.unwrap().remove(&mut *exec).await?;
@@ -187,48 +274,42 @@ pub async fn assign_ships(
     json_data: web::Json<SquadronAssignmentData>,
     claims: Claims
 ) -> Result<HttpResponse> {
-    let (g, s, f, p, sq, fs) = join!(
+    let (g, s, f, p) = join!(
         Game::find(info.0, &state.db_pool),
         System::find(info.1, &state.db_pool),
         Fleet::find(&info.2, &state.db_pool),
         Player::find(claims.pid.clone(), &state.db_pool),
-        Squadron::find_by_system_and_category(
-            info.1,
-            json_data.category,
-            &state.db_pool
-        ),
-        FleetSquadron::find_by_fleet_and_formation(
-            info.2,
-            json_data.formation,
-            &state.db_pool
-        )
     );
     let game = g?;
     let system = s?;
     let fleet = f?;
     let mut player = p?;
-    let squadron = sq?;
-    let fleet_squadron = fs?;
 
-    if system.player != Some(claims.pid.clone()) || fleet.player != claims.pid {
-        return Err(InternalError::AccessDenied.into());
-    }
+    check_assignment_owners(system.player, fleet.player, claims.pid)?;
+
+    // The quantities are read in the transaction writing them, with their rows locked,
+    // so concurrent assignments on the same fleet or the same stock wait for each other.
+    // The owners are checked again, the system may have been conquered meanwhile
+    let mut tx = state.db_pool.begin().await?;
+    let system_owner = System::lock(system.id, &mut tx).await?;
+    let fleet_owner = Fleet::lock(fleet.id, &mut tx).await?;
+    check_assignment_owners(system_owner, fleet_owner, claims.pid)?;
+    let squadron = Squadron::find_by_system_and_category_for_update(system.id, json_data.category, &mut tx).await?;
+    let fleet_squadron = FleetSquadron::find_by_fleet_and_formation_for_update(fleet.id, json_data.formation, &mut tx).await?;
 
     let available_quantity = get_available_ship_quantity(&squadron, &fleet_squadron);
-    let required_quantity = json_data.quantity.clone() as u16;
-    let mut assigned_quantity = required_quantity;
-    let remaining_quantity: u16;
+    let required_quantity = check_assignment_quantity(json_data.quantity, &fleet_squadron)?;
+    let (assigned_quantity, remaining_quantity) = split_assignment(available_quantity, required_quantity);
     let mut ship_queue: Option<ShipQueue> = None;
 
     if required_quantity > available_quantity {
-        assigned_quantity = available_quantity as u16;
-        remaining_quantity = 0;
         let assigned_fleet = format!("{}:{}", fleet.id, json_data.formation.to_string());
         let producing_ships = ShipQueue::count_assigned_ships(&assigned_fleet, json_data.category, &state.db_pool).await?;
         let needed_quantity = get_needed_quantity(required_quantity as i32, available_quantity as i32, producing_ships as i32);
 
+        // The missing ships are ordered in the assignment transaction, so they are not paid if it fails
         if needed_quantity > 0 {
-            ship_queue = ShipQueue::schedule(
+            ship_queue = ShipQueue::schedule_in(
                 &mut player,
                 system.id,
                 json_data.category,
@@ -236,15 +317,12 @@ pub async fn assign_ships(
                 true,
                 Some(assigned_fleet),
                 game.game_speed,
-                &state.db_pool
+                &state,
+                &mut tx
             ).await?;
         }
-    } else {
-        remaining_quantity = available_quantity - required_quantity;
     }
 
-    let mut tx = state.db_pool.begin().await?;
-    
     FleetSquadron::assign(
         fleet_squadron,
         fleet.id,
@@ -272,6 +350,117 @@ pub async fn assign_ships(
     Ok(HttpResponse::NoContent().finish())
 }
 
+/// Only the owner of both the system and the fleet can move ships between them
+fn check_assignment_owners(system_owner: Option<PlayerID>, fleet_owner: PlayerID, pid: PlayerID) -> Result<()> {
+    if system_owner != Some(pid) || fleet_owner != pid {
+        return Err(InternalError::AccessDenied.into());
+    }
+    Ok(())
+}
+
+#[patch("/{formation}/")]
+pub async fn update_squadron(
+    state: web::Data<AppState>,
+    info: web::Path<(GameID, SystemID, FleetID, FleetFormation)>,
+    json_data: web::Json<SquadronUpdateData>,
+    claims: Claims
+) -> Result<HttpResponse> {
+    let (s, f) = join!(
+        System::find(info.1, &state.db_pool),
+        Fleet::find(&info.2, &state.db_pool),
+    );
+    let system = s?;
+    let fleet = f?;
+    if system.game != info.0 || fleet.system != system.id || fleet.player != claims.pid {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let fleet_squadron = FleetSquadron::find_by_fleet_and_formation(fleet.id, info.3, &state.db_pool).await?
+        .ok_or(InternalError::NotFound)?;
+
+    // Only the flag is written, the quantity may be changed meanwhile by a battle or a reinforcement
+    let fleet_squadron = FleetSquadron::update_auto_reinforce(fleet_squadron.id, json_data.auto_reinforce, &mut &state.db_pool).await?;
+
+    Ok(HttpResponse::Ok().json(fleet_squadron))
+}
+
+/// Refills the squadrons with auto-reinforcement from the ships stored in their system
+pub async fn reinforce_squadrons(server: &GameServer) -> Result<()> {
+    let squadrons = FleetSquadron::find_reinforceable(server.id, &server.state.db_pool).await?;
+    let mut stocks: HashMap<(SystemID, ShipModelCategory), Option<Squadron>> = HashMap::new();
+    let mut reinforced = vec![];
+
+    // The quantities are read again with their rows locked, in the same order as the assignments,
+    // so that ships assigned or produced meanwhile are neither lost nor duplicated.
+    // The fleets which left or entered a battle since are skipped
+    let mut tx = server.state.db_pool.begin().await?;
+    let idle_fleets = Fleet::lock_idle(squadrons.iter().map(|s| s.squadron.fleet).collect(), &mut tx).await?;
+    for StationedSquadron{ system: sid, player: pid, squadron } in squadrons {
+        if !idle_fleets.contains(&squadron.fleet) {
+            continue;
+        }
+        if !stocks.contains_key(&(sid, squadron.category)) {
+            let stock = Squadron::find_by_system_and_category_for_update(sid, squadron.category, &mut tx).await?;
+            stocks.insert((sid, squadron.category), stock);
+        }
+        let stock = match stocks.get_mut(&(sid, squadron.category)).and_then(Option::as_mut) {
+            Some(stock) => stock,
+            None => continue,
+        };
+        let mut fs = match FleetSquadron::find_by_fleet_and_formation_for_update(squadron.fleet, squadron.formation, &mut tx).await? {
+            Some(fs) if fs.auto_reinforce && fs.category == squadron.category => fs,
+            _ => continue,
+        };
+        let quantity = get_reinforcement_quantity(&fs, stock.quantity);
+        if quantity == 0 {
+            continue;
+        }
+        fs.quantity += quantity;
+        stock.quantity -= quantity;
+        reinforced.push((pid, fs));
+    }
+    if reinforced.is_empty() {
+        tx.rollback().await?;
+        return Ok(());
+    }
+
+    for (_, fs) in reinforced.iter() {
+        fs.update(&mut tx).await?;
+    }
+    for squadron in stocks.into_iter().filter_map(|(_, s)| s) {
+        if squadron.quantity > 0 {
+            squadron.update(&mut tx).await?;
+        } else {
+            squadron.remove(&mut tx).await?;
+        }
+    }
+    tx.commit().await?;
+
+    for (pid, fs) in reinforced {
+        server.player_broadcast(&pid, &protocol::Message::new(
+            protocol::Action::FleetSquadronReinforced,
+            fs,
+            None,
+        ));
+    }
+    Ok(())
+}
+
+const fn get_reinforcement_quantity(fleet_squadron: &FleetSquadron, available_quantity: u16) -> u16 {
+    let missing_quantity = fleet_squadron.max_quantity.saturating_sub(fleet_squadron.quantity);
+    if missing_quantity < available_quantity {
+        return missing_quantity;
+    }
+    available_quantity
+}
+
+/// Returns the ships assigned to the fleet squadron and the ones left in the system
+const fn split_assignment(available_quantity: u16, required_quantity: u16) -> (u16, u16) {
+    if required_quantity > available_quantity {
+        return (available_quantity, 0);
+    }
+    (required_quantity, available_quantity - required_quantity)
+}
+
 const fn get_available_ship_quantity(squadron: &Option<Squadron>, fleet_squadron: &Option<FleetSquadron>) -> u16 {
     let mut available_quantity: u16 = 0;
     if let Some(sg) = squadron {
@@ -283,6 +472,18 @@ const fn get_available_ship_quantity(squadron: &Option<Squadron>, fleet_squadron
     available_quantity
 }
 
+/// A zero quantity means the player wants to unassign the ships of an existing fleet squadron.
+/// If there is no such squadron, there is nothing to unassign and the request is rejected.
+fn check_assignment_quantity(quantity: usize, fleet_squadron: &Option<FleetSquadron>) -> Result<u16> {
+    if quantity == 0 && fleet_squadron.is_none() {
+        return Err(InternalError::Conflict.into());
+    }
+    if quantity > u16::MAX as usize {
+        return Err(InternalError::ValidationFailed{ field: "quantity" }.into());
+    }
+    Ok(quantity as u16)
+}
+
 const fn get_needed_quantity(required_quantity: i32, available_quantity: i32, producing_ships: i32) -> u16 {
     let future_quantity = available_quantity + producing_ships;
     if future_quantity <= required_quantity {
@@ -291,6 +492,14 @@ const fn get_needed_quantity(required_quantity: i32, available_quantity: i32, pr
     0
 }
 
+fn sum_strength_by_players(quantities: Vec<(Uuid, ShipModelCategory, i64)>) -> HashMap<PlayerID, u32> {
+    let mut strengths = HashMap::new();
+    for (pid, category, quantity) in quantities {
+        *strengths.entry(PlayerID(pid)).or_insert(0) += category.to_data().strength as u32 * quantity as u32;
+    }
+    strengths
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -302,6 +511,67 @@ mod tests {
         }
     };
 
+    #[test]
+    fn test_check_assignment_owners() {
+        let pid = PlayerID(Uuid::new_v4());
+        let conqueror = PlayerID(Uuid::new_v4());
+
+        assert!(check_assignment_owners(Some(pid), pid, pid).is_ok());
+        // The system was conquered between the request checks and the locked reads
+        assert!(check_assignment_owners(Some(conqueror), pid, pid).is_err());
+        assert!(check_assignment_owners(None, pid, pid).is_err());
+        assert!(check_assignment_owners(Some(pid), conqueror, pid).is_err());
+    }
+
+    #[test]
+    fn test_split_assignment() {
+        assert_eq!((4, 6), split_assignment(10, 4));
+        assert_eq!((10, 0), split_assignment(10, 15));
+    }
+
+    /// The row locks themselves can only be exercised against Postgres, which the test suite does not run.
+    /// Once they serialize two assignments on the same stock, each one reads the rows the previous one wrote:
+    /// this replays the read, compute and write steps of `assign_ships` on these rows, in that order
+    #[test]
+    fn test_serialized_assignments() {
+        let mut stock = Some(Squadron{
+            id: SquadronID(Uuid::new_v4()),
+            system: SystemID(Uuid::new_v4()),
+            category: ShipModelCategory::Corvette,
+            quantity: 10,
+        });
+        let get_fleet_squadron = |quantity| Some(FleetSquadron{
+            id: FleetSquadronID(Uuid::new_v4()),
+            fleet: FleetID(Uuid::new_v4()),
+            formation: FleetFormation::Center,
+            category: ShipModelCategory::Corvette,
+            quantity,
+            max_quantity: quantity,
+            auto_reinforce: false,
+        });
+        let mut assign = |fleet_squadron: &mut Option<FleetSquadron>, quantity| -> Result<()> {
+            let available_quantity = get_available_ship_quantity(&stock, fleet_squadron);
+            let required_quantity = check_assignment_quantity(quantity, fleet_squadron)?;
+            let (assigned_quantity, remaining_quantity) = split_assignment(available_quantity, required_quantity);
+            if let Some(fs) = fleet_squadron {
+                fs.quantity = assigned_quantity;
+            }
+            if let Some(sq) = &mut stock {
+                sq.quantity = remaining_quantity;
+            }
+            Ok(())
+        };
+        let mut first_fleet_squadron = get_fleet_squadron(2);
+        let mut second_fleet_squadron = get_fleet_squadron(0);
+
+        assert!(assign(&mut first_fleet_squadron, 7).is_ok());
+        assert!(assign(&mut second_fleet_squadron, 7).is_ok());
+
+        assert_eq!(7, first_fleet_squadron.unwrap().quantity);
+        assert_eq!(5, second_fleet_squadron.unwrap().quantity);
+        assert_eq!(0, stock.unwrap().quantity);
+    }
+
     #[test]
     fn test_get_available_quantity() {
         let squadron = Some(Squadron{
@@ -316,6 +586,8 @@ mod tests {
             formation: FleetFormation::Center,
             category: ShipModelCategory::Corvette,
             quantity: 5,
+            max_quantity: 5,
+            auto_reinforce: false,
         });
         let none = None;
         let none_fs = None;
@@ -326,6 +598,110 @@ mod tests {
         assert_eq!(0, get_available_ship_quantity(&none, &none_fs));
     }
 
+    #[test]
+    fn test_get_reinforcement_quantity() {
+        let mut fleet_squadron = FleetSquadron{
+            id: FleetSquadronID(Uuid::new_v4()),
+            fleet: FleetID(Uuid::new_v4()),
+            formation: FleetFormation::Center,
+            category: ShipModelCategory::Corvette,
+            quantity: 4,
+            max_quantity: 10,
+            auto_reinforce: true,
+        };
+
+        assert_eq!(6, get_reinforcement_quantity(&fleet_squadron, 20));
+        assert_eq!(3, get_reinforcement_quantity(&fleet_squadron, 3));
+        assert_eq!(0, get_reinforcement_quantity(&fleet_squadron, 0));
+
+        fleet_squadron.quantity = 12;
+        assert_eq!(0, get_reinforcement_quantity(&fleet_squadron, 20));
+    }
+
+    #[test]
+    fn test_sum_strength_by_players() {
+        let (pid1, pid2) = (Uuid::new_v4(), Uuid::new_v4());
+        let strengths = sum_strength_by_players(vec![
+            (pid1, ShipModelCategory::Fighter, 15),
+            (pid1, ShipModelCategory::Corvette, 2),
+            (pid2, ShipModelCategory::Fighter, 5),
+        ]);
+
+        assert_eq!(Some(&35), strengths.get(&PlayerID(pid1)));
+        assert_eq!(Some(&5), strengths.get(&PlayerID(pid2)));
+    }
+
+    #[test]
+    fn test_check_assignment_quantity() {
+        let fleet_squadron = Some(FleetSquadron{
+            id: FleetSquadronID(Uuid::new_v4()),
+            fleet: FleetID(Uuid::new_v4()),
+            formation: FleetFormation::Center,
+            category: ShipModelCategory::Corvette,
+            quantity: 5,
+            max_quantity: 5,
+            auto_reinforce: false,
+        });
+        let none_fs = None;
+
+        assert_eq!(0, check_assignment_quantity(0, &fleet_squadron).unwrap());
+        assert!(check_assignment_quantity(0, &none_fs).is_err());
+        assert_eq!(1, check_assignment_quantity(1, &none_fs).unwrap());
+        assert_eq!(u16::MAX, check_assignment_quantity(u16::MAX as usize, &none_fs).unwrap());
+        assert!(check_assignment_quantity(u16::MAX as usize + 1, &fleet_squadron).is_err());
+    }
+
+    #[test]
+    fn test_assign_exactly_available_quantity() {
+        let squadron = Some(Squadron{
+            id: SquadronID(Uuid::new_v4()),
+            system: SystemID(Uuid::new_v4()),
+            category: ShipModelCategory::Fighter,
+            quantity: 7,
+        });
+        let fleet_squadron = Some(FleetSquadron{
+            id: FleetSquadronID(Uuid::new_v4()),
+            fleet: FleetID(Uuid::new_v4()),
+            formation: FleetFormation::Left,
+            category: ShipModelCategory::Fighter,
+            quantity: 3,
+            max_quantity: 3,
+            auto_reinforce: false,
+        });
+        let available_quantity = get_available_ship_quantity(&squadron, &fleet_squadron);
+        let required_quantity = check_assignment_quantity(10, &fleet_squadron).unwrap();
+
+        assert_eq!(available_quantity, required_quantity);
+        assert_eq!(0, get_needed_quantity(required_quantity as i32, available_quantity as i32, 0));
+    }
+
+    #[test]
+    fn test_assign_more_than_available_quantity() {
+        let squadron = Some(Squadron{
+            id: SquadronID(Uuid::new_v4()),
+            system: SystemID(Uuid::new_v4()),
+            category: ShipModelCategory::Fighter,
+            quantity: 4,
+        });
+        let fleet_squadron = Some(FleetSquadron{
+            id: FleetSquadronID(Uuid::new_v4()),
+            fleet: FleetID(Uuid::new_v4()),
+            formation: FleetFormation::Left,
+            category: ShipModelCategory::Fighter,
+            quantity: 2,
+            max_quantity: 2,
+            auto_reinforce: false,
+        });
+        let available_quantity = get_available_ship_quantity(&squadron, &fleet_squadron);
+        let required_quantity = check_assignment_quantity(15, &fleet_squadron).unwrap();
+
+        // The fleet squadron takes the whole stock and the missing ships are queued,
+        // minus the ones already produced for this fleet squadron
+        assert_eq!((6, 0), split_assignment(available_quantity, required_quantity));
+        assert_eq!(9, get_needed_quantity(required_quantity as i32, available_quantity as i32, 0));
+        assert_eq!(4, get_needed_quantity(required_quantity as i32, available_quantity as i32, 5));
+    }
+
     #[test]
     fn test_get_needed_quantity() {
         let data = vec![
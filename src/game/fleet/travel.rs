@@ -1,9 +1,11 @@
-use actix_web::{post, web, HttpResponse};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
 use serde::{Serialize, Deserialize};
 use crate::{
     lib::{
         Result,
-        error::InternalError,
+        error::{InternalError, ServerError},
+        idempotency::{get_idempotency_key, IdempotencyStatus},
+        time::Time,
         log::{log, Loggable},
         auth::Claims
     },
@@ -12,29 +14,49 @@ use crate::{
             game::{Game, GameID},
             server::{GameServer, GameFleetTravelMessage},
         },
-        player::Player,
+        player::{Player, PlayerID},
         faction::FactionID,
+        ship::squadron::Squadron,
         fleet::{
             combat::{
-                battle::Battle,
+                battle::{Battle, form_garrison},
                 conquest::Conquest,
             },
-            fleet::{Fleet, FleetID, has_other_fleets_than, FLEET_RANGE},
+            fleet::{Fleet, FleetID, has_other_fleets_than},
+        },
+        system::{
+            minefield::Minefield,
+            system::{System, SystemID, Coordinates},
         },
-        system::system::{System, SystemID, Coordinates},
         fleet::squadron::{FleetSquadron},
     },
     ws::protocol,
     AppState
 };
 use std::collections::HashMap;
-use chrono::{Duration, Utc};
+use sqlx_core::Error as SqlxError;
+use chrono::{DateTime, Duration, Utc};
 
 #[derive(Deserialize)]
 pub struct FleetTravelRequest {
     pub destination_system_id: SystemID,
 }
 
+#[derive(Serialize, Clone)]
+pub struct IncomingFleet {
+    #[serde(flatten)]
+    pub fleet: Fleet,
+    pub position: Coordinates,
+    /// Remaining travel time, in milliseconds
+    pub eta: i64,
+}
+
+#[derive(Serialize)]
+pub struct IncomingFleets {
+    pub reinforcements: Vec<IncomingFleet>,
+    pub threats: Vec<IncomingFleet>,
+}
+
 #[derive(Clone)]
 pub enum FleetArrivalOutcome {
     Arrived{
@@ -44,6 +66,7 @@ pub enum FleetArrivalOutcome {
         defender_faction: Option<FactionID>,
         fleet: Fleet,
         fleets: HashMap<FleetID, Fleet>,
+        has_garrison: bool,
         system: System,
     },
     Colonize{
@@ -91,8 +114,36 @@ pub async fn travel(
     state: web::Data<AppState>,
     info: web::Path<(GameID,SystemID,FleetID,)>,
     json_data: web::Json<FleetTravelRequest>,
-    claims: Claims
+    claims: Claims,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
+    // A retried request which has already been processed gets the original response,
+    // and is rejected while the original one is still processed
+    let idempotency_key = get_idempotency_key(&req);
+    let (pid, fid) = (claims.pid, info.2);
+    if let Some(key) = idempotency_key.as_ref() {
+        match state.idempotency_cache.reserve(pid, fid, key) {
+            IdempotencyStatus::Done(response) => return Ok(HttpResponse::Ok().json(response)),
+            IdempotencyStatus::Pending => return Err(InternalError::Conflict.into()),
+            IdempotencyStatus::New => {},
+        }
+    }
+    let result = travel_fleet(state.clone(), info, json_data, claims).await;
+    if let Some(key) = idempotency_key {
+        match result.as_ref() {
+            Ok(fleet) => state.idempotency_cache.store(pid, fid, key, fleet),
+            Err(_) => state.idempotency_cache.release(pid, fid, &key),
+        }
+    }
+    Ok(HttpResponse::Ok().json(result?))
+}
+
+async fn travel_fleet(
+    state: web::Data<AppState>,
+    info: web::Path<(GameID,SystemID,FleetID,)>,
+    json_data: web::Json<FleetTravelRequest>,
+    claims: Claims,
+) -> Result<Fleet> {
     let (ds, g, s, f, sg, p) = futures::join!(
         System::find(json_data.destination_system_id, &state.db_pool),
         Game::find(info.0, &state.db_pool),
@@ -119,11 +170,13 @@ pub async fn travel(
     if !fleet.can_fight() {
         return Err(InternalError::FleetEmpty.into());
     }
-    if Battle::count_current_by_system(&system.id, &state.db_pool).await? > 1 {
-        return Err(InternalError::Conflict.into());
+    // There is at most one battle per system, which every fleet of the system takes part in
+    if Battle::count_current_by_system(&system.id, &state.db_pool).await? > 0 {
+        return Err(InternalError::FleetInBattle.into());
     }
-    check_travel_destination(&system.coordinates, &destination_system.coordinates)?;
+    check_travel_destination(&system.coordinates, &destination_system.coordinates, game.get_fleet_range())?;
     fleet.destination_system = Some(destination_system.id.clone());
+    fleet.departure_date = Some(Time::now());
     fleet.destination_arrival_date = Some(
         (Utc::now() + get_travel_time(
             &system.coordinates,
@@ -136,7 +189,7 @@ pub async fn travel(
     let game = games.get(&info.0).cloned().ok_or(InternalError::GameUnknown)?;
 
     if let Some(mut conquest) = Conquest::find_current_by_system(&system.id, &state.db_pool).await? {
-        let count = Fleet::count_stationed_by_system(&system.id, &state.db_pool).await?;
+        let count = Fleet::count_stationed_by_system(&system.id, &mut &state.db_pool).await?;
         if 1 >= count {
             conquest.halt(&state, &game_id).await?;
         }
@@ -156,14 +209,22 @@ pub async fn travel(
 
     game.do_send(GameFleetTravelMessage{ system, fleet: fleet.clone() });
 
-    Ok(HttpResponse::Ok().json(fleet))
+    Ok(fleet)
 }
 
 pub async fn process_fleet_arrival(server: &GameServer, fleet_id: FleetID) -> Result<()> {
     let mut fleet = Fleet::find(&fleet_id, &server.state.db_pool).await?;
+    // The fleet may have been destroyed on its way, e.g. when its owner left the game
+    if fleet.is_destroyed {
+        return Ok(());
+    }
     fleet.squadrons = FleetSquadron::find_by_fleet(fleet.id.clone(), &server.state.db_pool).await?;
     let destination_system_id = fleet.destination_system.ok_or(InternalError::SystemUnknown)?;
-    let destination_system = System::find(destination_system_id, &server.state.db_pool).await?;
+    let destination_system = match System::find(destination_system_id, &server.state.db_pool).await {
+        Ok(system) => system,
+        Err(error) if is_missing_system(&error) => return cancel_fleet_arrival(fleet, error, server).await,
+        Err(error) => return Err(error),
+    };
     let player = Player::find(fleet.player, &server.state.db_pool).await?;
 
     let system_owner = {
@@ -184,86 +245,174 @@ pub async fn process_fleet_arrival(server: &GameServer, fleet_id: FleetID) -> Re
     process_arrival_outcome(&outcome, &server).await
 }
 
-async fn resolve_arrival_outcome(system: &System, server: &GameServer, fleet: Fleet, player: &Player, system_owner: Option<Player>) -> Result<FleetArrivalOutcome> {
-    // First we check if a battle rages in the destination system. No matter the opponents, the fleet joins in
-    if Battle::count_current_by_system(&system.id, &server.state.db_pool).await? > 0 {
-        log(
-            gelf::Level::Informational,
-            "Fleet joined battle",
-            &format!("Fleet {} has finished its journey to system {} and encountered an ongoing battle", fleet.to_log_message(), system.to_log_message()),
-            vec![
-                ("fleet_id", fleet.id.to_string()),
-                ("system_id", system.id.0.to_string()),
-            ],
-            &server.state.logger
-        );
+/// Other errors, such as a lost database connection, do not mean the destination is gone
+fn is_missing_system(error: &ServerError) -> bool {
+    matches!(error,
+        ServerError::InternalError(InternalError::SystemUnknown) |
+        ServerError::SqlxError(SqlxError::RowNotFound)
+    )
+}
+
+/// The destination may have been removed while the fleet was travelling.
+/// The fleet then stays in its origin system instead of travelling forever.
+async fn cancel_fleet_arrival(mut fleet: Fleet, error: ServerError, server: &GameServer) -> Result<()> {
+    log(
+        gelf::Level::Warning,
+        "Fleet arrival cancelled",
+        &format!("Fleet {} could not reach its destination", fleet.to_log_message()),
+        vec![
+            ("fleet_id", fleet.id.to_string()),
+            ("system_id", fleet.system.0.to_string()),
+        ],
+        &server.state.logger
+    );
+    fleet.destination_system = None;
+    fleet.destination_arrival_date = None;
+    fleet.departure_date = None;
+    fleet.update(&mut &server.state.db_pool).await?;
+
+    Err(error)
+}
 
-        return Ok(FleetArrivalOutcome::JoinedBattle{ fleet });
+/// What happens to a fleet reaching a system, depending on the state of the system at that time
+#[derive(Debug, PartialEq)]
+enum ArrivalKind {
+    JoinBattle,
+    Park,
+    Invade,
+    Colonize,
+    ContestColonization,
+}
+
+/// Players without faction are never allies, even of each other
+fn are_allies(faction: Option<FactionID>, other_faction: Option<FactionID>) -> bool {
+    faction.is_some() && faction == other_faction
+}
+
+/// The owner and the colonizer are given as their faction, if they exist.
+/// The state of the destination is read when the fleet arrives, as it may have changed during the journey.
+fn get_arrival_kind(
+    has_battle: bool,
+    player_faction: Option<FactionID>,
+    owner: Option<Option<FactionID>>,
+    colonizer: Option<Option<FactionID>>
+) -> ArrivalKind {
+    // No matter the opponents, the fleet joins an ongoing battle
+    if has_battle {
+        return ArrivalKind::JoinBattle;
+    }
+    match (owner, colonizer) {
+        (Some(owner_faction), _) if are_allies(player_faction, owner_faction) => ArrivalKind::Park,
+        (Some(_), _) => ArrivalKind::Invade,
+        (None, Some(colonizer_faction)) if !are_allies(player_faction, colonizer_faction) => ArrivalKind::ContestColonization,
+        (None, _) => ArrivalKind::Colonize,
     }
-    match system_owner {
-        Some(system_owner) => {
-            // Both players have the same faction, the arrived fleet just parks here
-            if system_owner.faction == player.faction {
-                log(
-                    gelf::Level::Informational,
-                    "Fleet arrived",
-                    &format!("Fleet {} has finished its journey to system {}", fleet.to_log_message(), system.to_log_message()),
-                    vec![
-                        ("fleet_id", fleet.id.to_string()),
-                        ("system_id", system.id.0.to_string()),
-                    ],
-                    &server.state.logger
-                );
+}
+
+async fn resolve_arrival_outcome(system: &System, server: &GameServer, fleet: Fleet, player: &Player, system_owner: Option<Player>) -> Result<FleetArrivalOutcome> {
+    let has_battle = Battle::count_current_by_system(&system.id, &server.state.db_pool).await? > 0;
+    // The current colonization is only relevant for neutral systems
+    let colonizer = match (&system_owner, has_battle) {
+        (None, false) => match Conquest::find_current_by_system(&system.id, &server.state.db_pool).await? {
+            Some(colonization) => Some(Player::find(colonization.player, &server.state.db_pool).await?.faction),
+            None => None,
+        },
+        _ => None,
+    };
 
+    match get_arrival_kind(has_battle, player.faction, system_owner.as_ref().map(|o| o.faction), colonizer) {
+        ArrivalKind::JoinBattle => {
+            log(
+                gelf::Level::Informational,
+                "Fleet joined battle",
+                &format!("Fleet {} has finished its journey to system {} and encountered an ongoing battle", fleet.to_log_message(), system.to_log_message()),
+                vec![
+                    ("fleet_id", fleet.id.to_string()),
+                    ("system_id", system.id.0.to_string()),
+                ],
+                &server.state.logger
+            );
+
+            Ok(FleetArrivalOutcome::JoinedBattle{ fleet })
+        },
+        // Both players have the same faction, the arrived fleet just parks here
+        ArrivalKind::Park => {
+            log(
+                gelf::Level::Informational,
+                "Fleet arrived",
+                &format!("Fleet {} has finished its journey to system {}", fleet.to_log_message(), system.to_log_message()),
+                vec![
+                    ("fleet_id", fleet.id.to_string()),
+                    ("system_id", system.id.0.to_string()),
+                ],
+                &server.state.logger
+            );
+
+            Ok(FleetArrivalOutcome::Arrived{ fleet })
+        },
+        ArrivalKind::Invade => {
+            let defender_faction = system_owner.and_then(|o| o.faction);
+            // Mines explode before any defending fleet can engage the intruder
+            let fleet = Minefield::trigger(system, fleet, player, defender_faction, server).await?;
+            if fleet.is_destroyed {
                 return Ok(FleetArrivalOutcome::Arrived{ fleet });
             }
-            // The fleet landed in an enemy system. We check if it is defended by some fleets and initiate a battle
+            // The fleet landed in an enemy system. We check if it is defended by some fleets or by its garrison and initiate a battle
             let fleets = system.retrieve_orbiting_fleets(&server.state.db_pool).await?;
-            if has_other_fleets_than(&fleets, &fleet) {
-                return Ok(FleetArrivalOutcome::Battle{ system: system.clone(), fleet, fleets, defender_faction: system_owner.faction });
+            let has_garrison = form_garrison(system, Squadron::find_by_system(system.id, &server.state.db_pool).await?).is_some();
+            if has_garrison || has_other_fleets_than(&fleets, &fleet) {
+                return Ok(FleetArrivalOutcome::Battle{ system: system.clone(), fleet, fleets, has_garrison, defender_faction });
             }
-            return Ok(FleetArrivalOutcome::Conquer{ system: system.clone(), fleet });
+            Ok(FleetArrivalOutcome::Conquer{ system: system.clone(), fleet })
         },
-        None => {
-            // The fleet landed in a neutral system. We check if it is currently being colonized by some fleets and initiate a battle
-            match Conquest::find_current_by_system(&system.id, &server.state.db_pool).await? {
-                Some(current_colonization) => {
-                    let colonizer = Player::find(current_colonization.player, &server.state.db_pool).await?;
-
-                    if colonizer.faction != player.faction {
-                        let fleets = system.retrieve_orbiting_fleets(&server.state.db_pool).await?;
-
-                        return Ok(FleetArrivalOutcome::Battle{ system: system.clone(), fleet, fleets, defender_faction: None })
-                    }
-                    // The fleet reinforces the current colonization
-                    Ok(FleetArrivalOutcome::Colonize{ system: system.clone(), fleet })
-                },
-                None => Ok(FleetArrivalOutcome::Colonize{ system: system.clone(), fleet })
-            }
+        // The fleet landed in a neutral system being colonized by an enemy
+        ArrivalKind::ContestColonization => {
+            let fleets = system.retrieve_orbiting_fleets(&server.state.db_pool).await?;
+
+            Ok(FleetArrivalOutcome::Battle{ system: system.clone(), fleet, fleets, has_garrison: false, defender_faction: None })
         },
+        // The fleet colonizes the neutral system, or reinforces the current colonization
+        ArrivalKind::Colonize => Ok(FleetArrivalOutcome::Colonize{ system: system.clone(), fleet }),
     }
 }
 
 async fn process_arrival_outcome(outcome: &FleetArrivalOutcome, server: &GameServer) -> Result<()> {
     match outcome {
-        FleetArrivalOutcome::Battle { fleet, fleets, system, defender_faction } => Battle::engage(&fleet, &fleets, &system, *defender_faction, &server).await,
-        FleetArrivalOutcome::Colonize { fleet, system } => Conquest::resume(fleet, &system, None, &server).await,
+        FleetArrivalOutcome::Battle { fleet, fleets, system, has_garrison, defender_faction } => Battle::engage(&fleet, &fleets, &system, *defender_faction, *has_garrison, &server).await,
+        FleetArrivalOutcome::Colonize { fleet, system } => Conquest::colonize(fleet, &system, &server).await,
         FleetArrivalOutcome::Conquer { fleet, system } => Conquest::resume(fleet, &system, None, &server).await,
         _ => Ok(())
     }
 }
 
-#[allow(clippy::suboptimal_flops)]
-fn check_travel_destination(origin_coords: &Coordinates, dest_coords: &Coordinates) -> Result<()> {
+fn check_travel_destination(origin_coords: &Coordinates, dest_coords: &Coordinates, fleet_range: f64) -> Result<()> {
     let distance = origin_coords.as_distance_to(dest_coords);
 
-    if distance > FLEET_RANGE.powi(2) {
+    if distance > fleet_range {
         return Err(InternalError::FleetInvalidDestination.into());
     }
 
     Ok(())
 }
 
+/// Linear interpolation of the fleet position between its origin and its destination.
+/// A fleet which has not departed yet is still on its origin, and an arrived one is on its destination.
+pub fn get_travel_position(origin: &Coordinates, destination: &Coordinates, departure: DateTime<Utc>, arrival: DateTime<Utc>, now: DateTime<Utc>) -> Coordinates {
+    let total_ms = arrival.signed_duration_since(departure).num_milliseconds();
+    if total_ms <= 0 || now >= arrival {
+        return destination.clone();
+    }
+    if now <= departure {
+        return origin.clone();
+    }
+    let progress = now.signed_duration_since(departure).num_milliseconds() as f64 / total_ms as f64;
+
+    Coordinates{
+        x: origin.x + (destination.x - origin.x) * progress,
+        y: origin.y + (destination.y - origin.y) * progress,
+    }
+}
+
 fn get_travel_time(from: &Coordinates, to: &Coordinates, time_coeff: f64) -> Duration {
     let distance = from.as_distance_to(to);
     let ms = distance / time_coeff;
@@ -271,15 +420,169 @@ fn get_travel_time(from: &Coordinates, to: &Coordinates, time_coeff: f64) -> Dur
     Duration::seconds(ms.ceil() as i64)
 }
 
+fn get_eta(fleet: &Fleet, now: DateTime<Utc>) -> i64 {
+    fleet.destination_arrival_date.map_or(0, |arrival| {
+        DateTime::<Utc>::from(arrival).signed_duration_since(now).num_milliseconds().max(0)
+    })
+}
+
+/// The fleets of the player's faction are reinforcements, every other fleet is a threat
+fn split_incoming_fleets(fleets: Vec<IncomingFleet>, faction: Option<FactionID>, factions: &HashMap<PlayerID, Option<FactionID>>) -> IncomingFleets {
+    let (reinforcements, threats) = fleets.into_iter().partition(|incoming| {
+        are_allies(faction, factions.get(&incoming.fleet.player).copied().flatten())
+    });
+    IncomingFleets{ reinforcements, threats }
+}
+
+#[get("/incoming/")]
+pub async fn get_incoming_fleets(state: web::Data<AppState>, info: web::Path<(GameID,)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    let player = Player::find(claims.pid, &state.db_pool).await?;
+    if player.game != Some(info.0) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let (f, p) = futures::join!(
+        Fleet::find_incoming_by_player(player.id, &state.db_pool),
+        Player::find_by_game(info.0, &state.db_pool),
+    );
+    let fleets = f?;
+    let factions: HashMap<PlayerID, Option<FactionID>> = p?.into_iter().map(|p| (p.id, p.faction)).collect();
+    let system_ids = fleets.iter()
+        .flat_map(|fleet| vec![Some(fleet.system), fleet.destination_system])
+        .flatten()
+        .collect();
+    let systems: HashMap<SystemID, System> = System::find_by_ids(system_ids, &state.db_pool).await?
+        .into_iter()
+        .map(|system| (system.id, system))
+        .collect();
+    let now = Utc::now();
+
+    let incoming_fleets = fleets.into_iter().filter_map(|fleet| {
+        let origin = systems.get(&fleet.system)?;
+        let destination = systems.get(&fleet.destination_system?)?;
+        Some(IncomingFleet{
+            position: fleet.get_position(&origin.coordinates, &destination.coordinates),
+            eta: get_eta(&fleet, now),
+            fleet,
+        })
+    }).collect();
+
+    Ok(HttpResponse::Ok().json(split_incoming_fleets(incoming_fleets, player.faction, &factions)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
         game::{
+            fleet::fleet::FLEET_RANGE,
+            game::option::GameOptionMapSize,
             system::system::Coordinates
         }
     };
+    use uuid::Uuid;
     
+    #[test]
+    fn test_is_missing_system() {
+        assert!(is_missing_system(&InternalError::SystemUnknown.into()));
+        assert!(is_missing_system(&SqlxError::RowNotFound.into()));
+        assert!(!is_missing_system(&SqlxError::PoolClosed.into()));
+        assert!(!is_missing_system(&InternalError::FleetUnknown.into()));
+    }
+
+    #[test]
+    fn test_get_arrival_kind() {
+        let faction = Some(FactionID(1));
+        let enemy_faction = Some(FactionID(2));
+
+        // A battle started in the destination during the journey
+        assert_eq!(ArrivalKind::JoinBattle, get_arrival_kind(true, faction, Some(enemy_faction), None));
+        assert_eq!(ArrivalKind::JoinBattle, get_arrival_kind(true, faction, None, None));
+        // The destination has been conquered by an ally during the journey
+        assert_eq!(ArrivalKind::Park, get_arrival_kind(false, faction, Some(faction), None));
+        // The destination has been conquered by an enemy during the journey
+        assert_eq!(ArrivalKind::Invade, get_arrival_kind(false, faction, Some(enemy_faction), None));
+        // The destination has been abandoned during the journey
+        assert_eq!(ArrivalKind::Colonize, get_arrival_kind(false, faction, None, None));
+        // Someone started to colonize the destination during the journey
+        assert_eq!(ArrivalKind::Colonize, get_arrival_kind(false, faction, None, Some(faction)));
+        assert_eq!(ArrivalKind::ContestColonization, get_arrival_kind(false, faction, None, Some(enemy_faction)));
+    }
+
+    #[test]
+    fn test_get_arrival_kind_without_faction() {
+        assert_eq!(ArrivalKind::Invade, get_arrival_kind(false, None, Some(None), None));
+        assert_eq!(ArrivalKind::ContestColonization, get_arrival_kind(false, None, None, Some(None)));
+        assert!(!are_allies(None, None));
+        assert!(are_allies(Some(FactionID(1)), Some(FactionID(1))));
+    }
+
+    #[test]
+    fn test_split_incoming_fleets() {
+        let ally = PlayerID(Uuid::new_v4());
+        let enemy = PlayerID(Uuid::new_v4());
+        let lone_wolf = PlayerID(Uuid::new_v4());
+        let factions: HashMap<PlayerID, Option<FactionID>> = vec![
+            (ally, Some(FactionID(1))),
+            (enemy, Some(FactionID(2))),
+            (lone_wolf, None),
+        ].into_iter().collect();
+        let fleets = vec![
+            get_incoming_fleet_mock(ally),
+            get_incoming_fleet_mock(enemy),
+            get_incoming_fleet_mock(lone_wolf),
+        ];
+
+        let incoming_fleets = split_incoming_fleets(fleets, Some(FactionID(1)), &factions);
+
+        assert_eq!(1, incoming_fleets.reinforcements.len());
+        assert_eq!(ally, incoming_fleets.reinforcements[0].fleet.player);
+        assert_eq!(2, incoming_fleets.threats.len());
+    }
+
+    #[test]
+    fn test_get_eta() {
+        let now = Utc::now();
+        let mut fleet = get_incoming_fleet_mock(PlayerID(Uuid::new_v4())).fleet;
+
+        fleet.destination_arrival_date = Some(Time::from(now + Duration::seconds(5)));
+        assert_eq!(5000, get_eta(&fleet, now));
+
+        fleet.destination_arrival_date = Some(Time::from(now - Duration::seconds(5)));
+        assert_eq!(0, get_eta(&fleet, now));
+    }
+
+    fn get_incoming_fleet_mock(player: PlayerID) -> IncomingFleet {
+        IncomingFleet{
+            fleet: Fleet{
+                id: FleetID(Uuid::new_v4()),
+                name: None,
+                player,
+                system: SystemID(Uuid::new_v4()),
+                destination_system: Some(SystemID(Uuid::new_v4())),
+                destination_arrival_date: None,
+                departure_date: None,
+                squadrons: vec![],
+                is_destroyed: false,
+            },
+            position: Coordinates{ x: 0.0, y: 0.0 },
+            eta: 0,
+        }
+    }
+
+    #[test]
+    fn test_check_travel_destination() {
+        let origin = Coordinates{ x: 0.0, y: 0.0 };
+        let fleet_range = FLEET_RANGE * GameOptionMapSize::Medium.into_fleet_range_coeff();
+
+        assert!(check_travel_destination(&origin, &Coordinates{ x: 15.0, y: 0.0 }, fleet_range).is_ok());
+        assert!(check_travel_destination(&origin, &Coordinates{ x: 12.0, y: 16.0 }, fleet_range).is_ok());
+        // The range is a distance, destinations just past it are out of reach
+        assert!(check_travel_destination(&origin, &Coordinates{ x: 25.0, y: 0.0 }, fleet_range).is_err());
+        assert!(check_travel_destination(&origin, &Coordinates{ x: 15.0, y: 15.0 }, fleet_range).is_err());
+    }
+
     #[test]
     fn test_get_travel_time() {
         let time = get_travel_time(
@@ -296,4 +599,38 @@ mod tests {
         );
         assert_eq!(19, time.num_seconds());
     }
+
+    #[test]
+    fn test_get_travel_position() {
+        let origin = Coordinates{ x: 2.0, y: 4.0 };
+        let destination = Coordinates{ x: 6.0, y: 12.0 };
+        let departure = Utc::now();
+        let arrival = departure + Duration::seconds(10);
+
+        let position = get_travel_position(&origin, &destination, departure, arrival, departure);
+        assert_eq!(2.0, position.x);
+        assert_eq!(4.0, position.y);
+
+        let position = get_travel_position(&origin, &destination, departure, arrival, departure + Duration::seconds(5));
+        assert_eq!(4.0, position.x);
+        assert_eq!(8.0, position.y);
+
+        let position = get_travel_position(&origin, &destination, departure, arrival, arrival);
+        assert_eq!(6.0, position.x);
+        assert_eq!(12.0, position.y);
+    }
+
+    #[test]
+    fn test_get_travel_position_out_of_journey() {
+        let origin = Coordinates{ x: 2.0, y: 4.0 };
+        let destination = Coordinates{ x: 6.0, y: 12.0 };
+        let departure = Utc::now();
+        let arrival = departure + Duration::seconds(10);
+
+        let position = get_travel_position(&origin, &destination, departure, arrival, departure - Duration::seconds(5));
+        assert_eq!(2.0, position.x);
+
+        let position = get_travel_position(&origin, &destination, departure, arrival, arrival + Duration::seconds(5));
+        assert_eq!(6.0, position.x);
+    }
 }
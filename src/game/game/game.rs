@@ -1,32 +1,48 @@
-use actix_web::{get, delete, web, HttpResponse};
+use actix_web::{get, post, delete, web, HttpResponse};
 use actix::prelude::*;
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
 use std::sync::{Arc, RwLock};
-use std::collections::{HashMap};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use crate::{
     lib::{
         Result,
         error::{InternalError, ServerError},
         auth::Claims,
+        pagination::{Paginator, new_paginated_response},
+        time::Time,
     },
     game::{
-        fleet::fleet::FLEET_RANGE,
+        system::minefield::{MINE_COST, MINE_DAMAGE, MINEFIELD_CAPACITY},
+        fleet::{
+            combat::{
+                conquest::{COLONIZATION_DURATION, CONQUEST_DURATION_MAX, CONQUEST_DURATION_MIN, CONQUEST_STRENGTH_COEFF},
+                round::DefenderBonus,
+            },
+            fleet::FLEET_RANGE,
+            formation::AttackOrders,
+            squadron::FleetSquadron,
+        },
         game::{
-            option::{GameOptionSpeed, GameOptionMapSize},
-            server::{GameServer, GameRemovePlayerMessage},
+            option::{GameOptionSpeed, GameOptionMapSize, GameOptionVictoryMode},
+            server::{GameServer, GamePauseVoteMessage, GamePlayerTasksMessage, GameRemovePlayerMessage, TaskCatchUp},
         },
         lobby::Lobby,
-        player::{PlayerID, Player},
+        player::{PlayerID, Player, PlayerCache, MoneyTransferLimit, hand_over_assets},
+        ship::queue::get_max_pending_queues,
+        system::system::System,
     },
-    ws::client::ClientSession,
+    ws::{client::ClientSession, protocol},
     AppState,
+    get_env,
 };
 use sqlx::{PgPool, postgres::{PgRow, PgQueryAs}, FromRow, Error, Executor, Postgres};
 use sqlx_core::row::Row;
 
 pub const GAME_START_WALLET: usize = 200;
 pub const VICTORY_POINTS_PER_MINUTE: i32 = 10;
+pub const VICTORY_POINTS_PER_SYSTEM: i32 = 100;
 
 #[derive(Serialize, Deserialize, Hash, PartialEq, Eq, Clone, Copy, Debug)]
 pub struct GameID(pub Uuid);
@@ -36,7 +52,123 @@ pub struct Game {
     pub id: GameID,
     pub victory_points: i32,
     pub game_speed: GameOptionSpeed,
-    pub map_size: GameOptionMapSize
+    pub map_size: GameOptionMapSize,
+    pub victory_mode: GameOptionVictoryMode,
+    /// All factions take part in the game when their number is not restricted
+    pub nb_factions: Option<u8>,
+    #[serde(flatten)]
+    pub config: GameConfig,
+}
+
+/// Entry of the list of running games
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct GameSummary {
+    pub id: GameID,
+    pub nb_players: u16,
+    pub game_speed: GameOptionSpeed,
+    pub map_size: GameOptionMapSize,
+    pub victory_mode: GameOptionVictoryMode,
+    pub started_at: Time,
+    /// Seconds since the beginning of the game
+    pub elapsed_time: i64,
+    /// Whether the current player takes part in this game
+    pub is_joined: bool,
+}
+
+/// Tuning of a game, set by the server operator when the game is created
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GameConfig {
+    pub conquest_duration_max: f64,
+    pub conquest_duration_min: f64,
+    pub conquest_strength_coeff: f64,
+    pub start_wallet: usize,
+    /// Points scored by the faction owning a victory system, at each victory interval
+    pub victory_points_per_minute: i32,
+    /// The points needed to win the game are given for each victory system of the map
+    pub victory_points_per_system: i32,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig{
+            conquest_duration_max: CONQUEST_DURATION_MAX,
+            conquest_duration_min: CONQUEST_DURATION_MIN,
+            conquest_strength_coeff: CONQUEST_STRENGTH_COEFF,
+            start_wallet: GAME_START_WALLET,
+            victory_points_per_minute: VICTORY_POINTS_PER_MINUTE,
+            victory_points_per_system: VICTORY_POINTS_PER_SYSTEM,
+        }
+    }
+}
+
+impl GameConfig {
+    pub fn new(
+        conquest_duration_max: f64,
+        conquest_duration_min: f64,
+        conquest_strength_coeff: f64,
+        start_wallet: usize,
+        victory_points_per_minute: i32,
+        victory_points_per_system: i32,
+    ) -> Result<Self> {
+        if conquest_duration_min < 0.0 || conquest_duration_min > conquest_duration_max || conquest_strength_coeff < 0.0 {
+            return Err(InternalError::Conflict.into());
+        }
+        // A game without points to score could never be won
+        if victory_points_per_minute <= 0 || victory_points_per_system <= 0 {
+            return Err(InternalError::Conflict.into());
+        }
+        Ok(GameConfig{
+            conquest_duration_max,
+            conquest_duration_min,
+            conquest_strength_coeff,
+            start_wallet,
+            victory_points_per_minute,
+            victory_points_per_system,
+        })
+    }
+
+    pub fn get_victory_points(&self, nb_victory_systems: u32) -> i32 {
+        nb_victory_systems as i32 * self.victory_points_per_system
+    }
+
+    /// Reads the configuration set by the server operator
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Each invalid setting is replaced by its default value, the valid ones are kept
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let default = Self::default();
+        let conquest_duration_max = read_setting(&lookup, "CONQUEST_DURATION_MAX_MS")
+            .filter(|d: &f64| *d >= 0.0)
+            .unwrap_or(default.conquest_duration_max);
+        let conquest_duration_min = read_setting(&lookup, "CONQUEST_DURATION_MIN_MS")
+            .filter(|d: &f64| *d >= 0.0 && *d <= conquest_duration_max)
+            .unwrap_or_else(|| default.conquest_duration_min.min(conquest_duration_max));
+        let conquest_strength_coeff = read_setting(&lookup, "CONQUEST_STRENGTH_COEFF")
+            .filter(|c: &f64| *c >= 0.0)
+            .unwrap_or(default.conquest_strength_coeff);
+        let victory_points_per_minute = read_setting(&lookup, "GAME_VICTORY_POINTS_PER_MINUTE")
+            .filter(|p: &i32| *p > 0)
+            .unwrap_or(default.victory_points_per_minute);
+        let victory_points_per_system = read_setting(&lookup, "GAME_VICTORY_POINTS_PER_SYSTEM")
+            .filter(|p: &i32| *p > 0)
+            .unwrap_or(default.victory_points_per_system);
+
+        // Each setting being valid, so is the whole configuration
+        Self::new(
+            conquest_duration_max,
+            conquest_duration_min,
+            conquest_strength_coeff,
+            read_setting(&lookup, "GAME_START_WALLET").unwrap_or(default.start_wallet),
+            victory_points_per_minute,
+            victory_points_per_system,
+        ).unwrap_or(default)
+    }
+}
+
+fn read_setting<T: std::str::FromStr>(lookup: &impl Fn(&str) -> Option<String>, key: &str) -> Option<T> {
+    lookup(key)?.parse().ok()
 }
 
 impl From<GameID> for Uuid {
@@ -51,12 +183,61 @@ impl<'a> FromRow<'a, PgRow<'a>> for Game {
             id: GameID(id),
             victory_points: row.try_get::<i32, _>("victory_points")?,
             game_speed: row.try_get("game_speed")?,
-            map_size: row.try_get("map_size")?
+            map_size: row.try_get("map_size")?,
+            victory_mode: row.try_get("victory_mode")?,
+            nb_factions: row.try_get::<Option<i32>, _>("nb_factions")?.map(|n| n as u8),
+            config: GameConfig{
+                conquest_duration_max: row.try_get("conquest_duration_max")?,
+                conquest_duration_min: row.try_get("conquest_duration_min")?,
+                conquest_strength_coeff: row.try_get("conquest_strength_coeff")?,
+                start_wallet: row.try_get::<i32, _>("start_wallet")? as usize,
+                victory_points_per_minute: row.try_get("victory_points_per_minute")?,
+                victory_points_per_system: row.try_get("victory_points_per_system")?,
+            },
+        })
+    }
+}
+
+impl<'a> FromRow<'a, PgRow<'a>> for GameSummary {
+    fn from_row(row: &PgRow) -> std::result::Result<Self, Error> {
+        Ok(GameSummary {
+            id: row.try_get("id").map(GameID)?,
+            nb_players: row.try_get::<i64, _>("nb_players")? as u16,
+            game_speed: row.try_get("game_speed")?,
+            map_size: row.try_get("map_size")?,
+            victory_mode: row.try_get("victory_mode")?,
+            started_at: row.try_get("started_at")?,
+            elapsed_time: 0,
+            is_joined: false,
         })
     }
 }
 
+impl GameSummary {
+    pub async fn find_all(limit: i64, offset: i64, db_pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as("SELECT g.id, g.game_speed, g.map_size, g.victory_mode, g.started_at, COUNT(p.id) AS nb_players
+            FROM game__games g
+            LEFT JOIN player__players p ON p.game_id = g.id
+            GROUP BY g.id
+            ORDER BY g.started_at DESC LIMIT $1 OFFSET $2")
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub async fn count(db_pool: &PgPool) -> Result<i64> {
+        sqlx::query_as("SELECT COUNT(*) FROM game__games")
+            .fetch_one(db_pool).await
+            .map(|count: (i64,)| count.0)
+            .map_err(ServerError::from)
+    }
+}
+
 impl Game {
+    pub fn get_fleet_range(&self) -> f64 {
+        FLEET_RANGE * self.map_size.into_fleet_range_coeff()
+    }
+
     pub async fn find(gid: GameID, db_pool: &PgPool) -> Result<Self> {
         sqlx::query_as("SELECT * FROM game__games WHERE id = $1")
             .bind(Uuid::from(gid))
@@ -65,10 +246,19 @@ impl Game {
 
     pub async fn insert<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres> {
-        sqlx::query("INSERT INTO game__games(id, game_speed, map_size) VALUES($1, $2, $3)")
+        sqlx::query("INSERT INTO game__games(id, game_speed, map_size, conquest_duration_max, conquest_duration_min, conquest_strength_coeff, start_wallet, victory_mode, nb_factions, victory_points_per_minute, victory_points_per_system)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)")
             .bind(Uuid::from(self.id))
             .bind(self.game_speed)
             .bind(self.map_size)
+            .bind(self.config.conquest_duration_max)
+            .bind(self.config.conquest_duration_min)
+            .bind(self.config.conquest_strength_coeff)
+            .bind(self.config.start_wallet as i32)
+            .bind(self.victory_mode)
+            .bind(self.nb_factions.map(i32::from))
+            .bind(self.config.victory_points_per_minute)
+            .bind(self.config.victory_points_per_system)
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
@@ -87,6 +277,11 @@ impl Game {
     }
 }
 
+/// The victory points are distributed at this interval, in proportion to the points per minute
+pub fn get_victory_interval() -> Duration {
+    Duration::from_secs(get_env("GAME_VICTORY_INTERVAL_SEC", "60").parse().ok().filter(|s| *s > 0).unwrap_or(60))
+}
+
 pub async fn create_game(lobby: &Lobby, state: web::Data<AppState>, clients: HashMap<PlayerID, actix::Addr<ClientSession>>) -> Result<(GameID, Addr<GameServer>)> {
     let id = GameID(Uuid::new_v4());
     
@@ -95,12 +290,32 @@ pub async fn create_game(lobby: &Lobby, state: web::Data<AppState>, clients: Has
         state: state.clone(),
         clients: RwLock::new(clients),
         tasks: HashMap::new(),
+        task_catch_up: TaskCatchUp::new(Duration::from_millis(get_env("GAME_TASK_CATCH_UP_INTERVAL_MS", "50").parse().unwrap_or(50))),
+        batch_window: Duration::from_millis(get_env("WS_BATCH_WINDOW_MS", "0").parse().unwrap_or(0)),
+        pending_messages: RwLock::new(HashMap::new()),
+        player_cache: PlayerCache::default(),
+        attack_orders: AttackOrders::from_config(&get_env("FLEET_ATTACK_ORDERS", "{}")),
+        defender_bonus: DefenderBonus::from_config(&get_env("FLEET_DEFENDER_BONUS", "{}")),
+        capture_rate: get_env("CONQUEST_CAPTURE_RATE", "0").parse().ok().filter(|r: &f64| (0.0..=1.0).contains(r)).unwrap_or(0.0),
+        income_interval: Duration::from_secs(get_env("GAME_INCOME_INTERVAL_SEC", "5").parse().ok().filter(|s| *s > 0).unwrap_or(5)),
+        victory_interval: get_victory_interval(),
+        victory_ticks: 0,
+        fleet_retention: Duration::from_secs(get_env("FLEET_DESTROYED_RETENTION_SEC", "300").parse().ok().filter(|s| *s > 0).unwrap_or(300)),
+        money_transfers: MoneyTransferLimit::new(get_env("PLAYER_MONEY_TRANSFER_CAP", "0").parse().ok().filter(|c| *c > 0)),
+        intervals: vec![],
+        paused_at: None,
+        pause_votes: HashMap::new(),
+        starting_factions: HashSet::new(),
+        async_map_generation: get_env("GAME_ASYNC_MAP_GENERATION", "false").parse().unwrap_or(false),
     };
     let game = Game{
         id: id.clone(),
         victory_points: 0,
         game_speed: lobby.game_speed.clone(),
         map_size: lobby.map_size.clone(),
+        victory_mode: lobby.victory_mode,
+        nb_factions: lobby.nb_factions,
+        config: GameConfig::from_env(),
     };
 
     let mut tx = state.db_pool.begin().await?;
@@ -112,11 +327,93 @@ pub async fn create_game(lobby: &Lobby, state: web::Data<AppState>, clients: Has
     Ok((id, game_server.start()))
 }
 
+#[get("/")]
+pub async fn get_games(state: web::Data<AppState>, claims: Claims, pagination: web::Query<Paginator>)
+    -> Result<HttpResponse>
+{
+    let (count, games, player) = futures::join!(
+        GameSummary::count(&state.db_pool),
+        GameSummary::find_all(pagination.limit, (pagination.page - 1) * pagination.limit, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    Ok(new_paginated_response(
+        pagination.limit,
+        pagination.page,
+        count?,
+        complete_game_summaries(games?, player?.game, Time::now()),
+    ))
+}
+
+fn complete_game_summaries(mut games: Vec<GameSummary>, player_game: Option<GameID>, now: Time) -> Vec<GameSummary> {
+    for game in games.iter_mut() {
+        game.elapsed_time = now.0.signed_duration_since(game.started_at.0).num_seconds().max(0);
+        game.is_joined = player_game == Some(game.id);
+    }
+    games
+}
+
+#[get("/{id}/")]
+pub async fn get_game(state: web::Data<AppState>, claims: Claims, info: web::Path<(GameID,)>) -> Result<HttpResponse> {
+    #[derive(Serialize)]
+    pub struct GameData {
+        #[serde(flatten)]
+        game: Game,
+        time_coeff: f64,
+        travel_speed_coeff: f64,
+        conquest_speed_coeff: f64,
+        fleet_range: f64,
+    }
+    let (g, p) = futures::join!(
+        Game::find(info.0, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    let game = g?;
+
+    if p?.game != Some(game.id) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    Ok(HttpResponse::Ok().json(GameData{
+        time_coeff: game.game_speed.into_coeff(),
+        travel_speed_coeff: game.game_speed.into_travel_speed(),
+        conquest_speed_coeff: game.game_speed.into_conquest_speed(),
+        fleet_range: game.get_fleet_range(),
+        game,
+    }))
+}
+
 #[get("/{id}/players/")]
 pub async fn get_players(state: web::Data<AppState>, info: web::Path<(GameID,)>) -> Result<HttpResponse> {
     Ok(HttpResponse::Ok().json(Player::find_by_game(info.0, &state.db_pool).await?))
 }
 
+#[get("/{id}/players/status/")]
+pub async fn get_players_status(state: web::Data<AppState>, info: web::Path<(GameID,)>, claims: Claims) -> Result<HttpResponse> {
+    #[derive(Serialize)]
+    struct PlayerStatus {
+        #[serde(flatten)]
+        player: Player,
+        nb_systems: u32,
+        fleet_strength: u32,
+    }
+    let (p, s, f) = futures::join!(
+        Player::find_by_game(info.0, &state.db_pool),
+        System::count_by_players(info.0, &state.db_pool),
+        FleetSquadron::get_strength_by_players(info.0, &state.db_pool),
+    );
+    let players = p?;
+    if !players.iter().any(|p| p.id == claims.pid) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let nb_systems = s?;
+    let fleet_strengths = f?;
+
+    Ok(HttpResponse::Ok().json(players.into_iter().map(|player| PlayerStatus{
+        nb_systems: nb_systems.get(&player.id).copied().unwrap_or(0),
+        fleet_strength: fleet_strengths.get(&player.id).copied().unwrap_or(0),
+        player,
+    }).collect::<Vec<PlayerStatus>>()))
+}
+
 #[delete("/{id}/players/")]
 pub async fn leave_game(state:web::Data<AppState>, claims: Claims, info: web::Path<(GameID,)>)
     -> Result<HttpResponse>
@@ -127,10 +424,20 @@ pub async fn leave_game(state:web::Data<AppState>, claims: Claims, info: web::Pa
     if player.game != Some(game.id) {
         return Err(InternalError::NotInLobby.into());
     }
+    let assets_transfer = hand_over_assets(&mut player, &state.db_pool).await?;
     player.reset(&state.db_pool).await?;
 
     let games = state.games();
     let game_server = games.get(&game.id).expect("Game exists in DB but not in HashMap");
+    game_server.do_send(GamePlayerTasksMessage{
+        pid: player.id,
+        heir: assets_transfer.heir,
+    });
+    game_server.do_send(protocol::Message::new(
+        protocol::Action::PlayerAssetsTransferred,
+        assets_transfer,
+        None,
+    ));
     let (client, is_empty) = Arc::try_unwrap(game_server.send(GameRemovePlayerMessage(player.id.clone())).await?).ok().unwrap();
     if let Some(c) = client {
         state.add_client(&player.id, c);
@@ -142,15 +449,229 @@ pub async fn leave_game(state:web::Data<AppState>, claims: Claims, info: web::Pa
     Ok(HttpResponse::NoContent().finish())
 }
 
+#[post("/{id}/pause/")]
+pub async fn pause_game(state: web::Data<AppState>, claims: Claims, info: web::Path<(GameID,)>)
+    -> Result<HttpResponse>
+{
+    vote_pause(state, claims, info.0, true).await
+}
+
+#[post("/{id}/resume/")]
+pub async fn resume_game(state: web::Data<AppState>, claims: Claims, info: web::Path<(GameID,)>)
+    -> Result<HttpResponse>
+{
+    vote_pause(state, claims, info.0, false).await
+}
+
+/// The game is paused or resumed once a majority of its players voted for it
+async fn vote_pause(state: web::Data<AppState>, claims: Claims, gid: GameID, pause: bool) -> Result<HttpResponse> {
+    let player = Player::find(claims.pid, &state.db_pool).await?;
+    if player.game != Some(gid) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let game_server = state.games().get(&gid).cloned().ok_or(InternalError::GameUnknown)?;
+    if game_server.send(GamePauseVoteMessage{ pid: player.id, pause }).await?? {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct GameConstants {
+    fleet_range: f64,
+    victory_points_per_minute: i32,
+    victory_points_per_system: i32,
+    victory_interval_sec: u64,
+    mine_cost: usize,
+    mine_damage: u32,
+    minefield_capacity: u16,
+    start_wallet: usize,
+    ship_queue_max_pending: Option<usize>,
+}
+
+/// Values in effect for a given game, once its options and configuration are applied
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct GameEffectiveConstants {
+    #[serde(flatten)]
+    constants: GameConstants,
+    time_coeff: f64,
+    travel_speed_coeff: f64,
+    conquest_speed_coeff: f64,
+    conquest_duration_max: f64,
+    conquest_duration_min: f64,
+    conquest_strength_coeff: f64,
+    colonization_duration: f64,
+    victory_points: i32,
+}
+
+impl GameConstants {
+    fn new(config: &GameConfig, fleet_range: f64) -> Self {
+        GameConstants{
+            fleet_range,
+            victory_points_per_minute: config.victory_points_per_minute,
+            victory_points_per_system: config.victory_points_per_system,
+            victory_interval_sec: get_victory_interval().as_secs(),
+            mine_cost: MINE_COST,
+            mine_damage: MINE_DAMAGE,
+            minefield_capacity: MINEFIELD_CAPACITY,
+            start_wallet: config.start_wallet,
+            ship_queue_max_pending: get_max_pending_queues(),
+        }
+    }
+}
+
+impl From<&Game> for GameEffectiveConstants {
+    fn from(game: &Game) -> Self {
+        let conquest_speed_coeff = game.game_speed.into_conquest_speed();
+
+        GameEffectiveConstants{
+            constants: GameConstants::new(&game.config, game.get_fleet_range()),
+            time_coeff: game.game_speed.into_coeff(),
+            travel_speed_coeff: game.game_speed.into_travel_speed(),
+            conquest_speed_coeff,
+            conquest_duration_max: game.config.conquest_duration_max * conquest_speed_coeff,
+            conquest_duration_min: game.config.conquest_duration_min,
+            conquest_strength_coeff: game.config.conquest_strength_coeff,
+            colonization_duration: COLONIZATION_DURATION * conquest_speed_coeff,
+            victory_points: game.victory_points,
+        }
+    }
+}
+
+/// Reference values for the games to come. The running games may use other ones, given by [get_game_effective_constants]
 #[get("/constants/")]
 pub async fn get_game_constants() -> Result<HttpResponse> {
-    #[derive(Serialize, Clone)]
-    pub struct GameConstants {
-        fleet_range: f64,
-        victory_points_per_minute: i32,
+    // Games created from now on use the configured settings
+    Ok(HttpResponse::Ok().json(GameConstants::new(&GameConfig::from_env(), FLEET_RANGE)))
+}
+
+#[get("/{id}/constants/")]
+pub async fn get_game_effective_constants(state: web::Data<AppState>, claims: Claims, info: web::Path<(GameID,)>) -> Result<HttpResponse> {
+    let (g, p) = futures::join!(
+        Game::find(info.0, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    let game = g?;
+
+    if p?.game != Some(game.id) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    Ok(HttpResponse::Ok().json(GameEffectiveConstants::from(&game)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_game_config_validation() {
+        assert!(GameConfig::new(60000.0, 5000.0, 100.0, 200, 10, 100).is_ok());
+        assert!(GameConfig::new(5000.0, 5000.0, 0.0, 0, 10, 100).is_ok());
+        assert!(GameConfig::new(5000.0, 60000.0, 100.0, 200, 10, 100).is_err());
+        assert!(GameConfig::new(60000.0, -1.0, 100.0, 200, 10, 100).is_err());
+        assert!(GameConfig::new(60000.0, 5000.0, -100.0, 200, 10, 100).is_err());
+        assert!(GameConfig::new(60000.0, 5000.0, 100.0, 200, 0, 100).is_err());
+        assert!(GameConfig::new(60000.0, 5000.0, 100.0, 200, 10, -100).is_err());
+    }
+
+    fn get_lookup(settings: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+        move |key| settings.iter().find(|(k, _)| *k == key).map(|(_, v)| v.to_string())
+    }
+
+    #[test]
+    fn test_game_config_start_wallet_from_env() {
+        assert_eq!(1000, GameConfig::from_lookup(get_lookup(&[("GAME_START_WALLET", "1000")])).start_wallet);
+        assert_eq!(GAME_START_WALLET, GameConfig::from_lookup(get_lookup(&[])).start_wallet);
+        assert_eq!(GAME_START_WALLET, GameConfig::from_lookup(get_lookup(&[("GAME_START_WALLET", "-5")])).start_wallet);
+    }
+
+    #[test]
+    fn test_game_config_from_env() {
+        assert_eq!(GameConfig::default(), GameConfig::from_lookup(get_lookup(&[])));
+
+        // An invalid setting falls back to its default value without discarding the other ones
+        let config = GameConfig::from_lookup(get_lookup(&[
+            ("CONQUEST_DURATION_MIN_MS", "90000"),
+            ("CONQUEST_STRENGTH_COEFF", "50"),
+            ("GAME_START_WALLET", "1000"),
+        ]));
+        assert_eq!(CONQUEST_DURATION_MIN, config.conquest_duration_min);
+        assert_eq!(CONQUEST_DURATION_MAX, config.conquest_duration_max);
+        assert_eq!(50.0, config.conquest_strength_coeff);
+        assert_eq!(1000, config.start_wallet);
+
+        // The victory points are validated one by one too
+        let config = GameConfig::from_lookup(get_lookup(&[
+            ("GAME_VICTORY_POINTS_PER_MINUTE", "25"),
+            ("GAME_VICTORY_POINTS_PER_SYSTEM", "-50"),
+        ]));
+        assert_eq!(25, config.victory_points_per_minute);
+        assert_eq!(VICTORY_POINTS_PER_SYSTEM, config.victory_points_per_system);
+
+        // The default minimum duration never exceeds a shorter maximum
+        let config = GameConfig::from_lookup(get_lookup(&[("CONQUEST_DURATION_MAX_MS", "3000")]));
+        assert_eq!(3000.0, config.conquest_duration_max);
+        assert_eq!(3000.0, config.conquest_duration_min);
+        assert!(GameConfig::new(config.conquest_duration_max, config.conquest_duration_min, config.conquest_strength_coeff, 0, 10, 100).is_ok());
+    }
+
+    #[test]
+    fn test_complete_game_summaries() {
+        let now = Time::now();
+        let get_summary = |started_at: Time| GameSummary{
+            id: GameID(Uuid::new_v4()),
+            nb_players: 4,
+            game_speed: GameOptionSpeed::Medium,
+            map_size: GameOptionMapSize::Medium,
+            victory_mode: GameOptionVictoryMode::VictoryPoints,
+            started_at,
+            elapsed_time: 0,
+            is_joined: false,
+        };
+        let player_game = get_summary((now.0 - chrono::Duration::minutes(10)).into());
+        let other_game = get_summary(now);
+
+        let games = complete_game_summaries(vec![player_game.clone(), other_game.clone()], Some(player_game.id), now);
+        assert_eq!(600, games[0].elapsed_time);
+        assert!(games[0].is_joined);
+        assert_eq!(0, games[1].elapsed_time);
+        assert!(!games[1].is_joined);
+
+        assert!(complete_game_summaries(vec![player_game], None, now).iter().all(|g| !g.is_joined));
+    }
+
+    #[test]
+    fn test_game_effective_constants() {
+        let get_game = |game_speed, map_size, config: GameConfig| Game{
+            id: GameID(Uuid::new_v4()),
+            victory_points: config.get_victory_points(3),
+            game_speed,
+            map_size,
+            victory_mode: GameOptionVictoryMode::VictoryPoints,
+            nb_factions: None,
+            config,
+        };
+        let default_game = get_game(GameOptionSpeed::Medium, GameOptionMapSize::Medium, GameConfig::default());
+        let constants = GameEffectiveConstants::from(&default_game);
+        // A default game uses the same values as the global endpoint
+        assert_eq!(GameConstants::new(&GameConfig::default(), FLEET_RANGE), constants.constants);
+        assert_eq!(CONQUEST_DURATION_MAX, constants.conquest_duration_max);
+        assert_eq!(COLONIZATION_DURATION, constants.colonization_duration);
+
+        let config = GameConfig::new(90000.0, 10000.0, 50.0, 500, 20, 300).unwrap();
+        let game = get_game(GameOptionSpeed::VeryFast, GameOptionMapSize::Large, config);
+        let constants = GameEffectiveConstants::from(&game);
+        assert_eq!(FLEET_RANGE * 1.2, constants.constants.fleet_range);
+        assert_eq!(500, constants.constants.start_wallet);
+        assert_eq!(20, constants.constants.victory_points_per_minute);
+        assert_eq!(300, constants.constants.victory_points_per_system);
+        assert_eq!(get_victory_interval().as_secs(), constants.constants.victory_interval_sec);
+        assert_eq!(900, constants.victory_points);
+        assert_eq!(0.6, constants.time_coeff);
+        assert_eq!(0.85, constants.travel_speed_coeff);
+        assert_eq!(90000.0 * 0.65, constants.conquest_duration_max);
+        assert_eq!(10000.0, constants.conquest_duration_min);
+        assert_eq!(50.0, constants.conquest_strength_coeff);
+        assert_eq!(COLONIZATION_DURATION * 0.65, constants.colonization_duration);
     }
-    Ok(HttpResponse::Ok().json(GameConstants{
-        fleet_range: FLEET_RANGE,
-        victory_points_per_minute: VICTORY_POINTS_PER_MINUTE,
-    }))
 }
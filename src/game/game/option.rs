@@ -1,5 +1,7 @@
+use actix_web::{get, HttpResponse};
 use serde::{Serialize, Deserialize};
 use galaxy_rs::GalaxyBuilder;
+use crate::lib::Result;
 
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, sqlx::Type)]
@@ -7,9 +9,11 @@ use galaxy_rs::GalaxyBuilder;
 #[sqlx(rename_all = "snake_case")]
 #[serde(rename_all(serialize = "snake_case", deserialize = "snake_case"))]
 pub enum GameOptionSpeed {
+    VerySlow,
     Slow,
     Medium,
     Fast,
+    VeryFast,
 }
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, sqlx::Type)]
@@ -25,33 +29,85 @@ pub enum GameOptionMapSize {
     VeryLarge,
 }
 
+/// How a faction wins the game: by holding the victory systems long enough, or by being the last one standing
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, sqlx::Type)]
+#[sqlx(rename = "VARCHAR")]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all(serialize = "snake_case", deserialize = "snake_case"))]
+pub enum GameOptionVictoryMode {
+    VictoryPoints,
+    Elimination,
+}
+
+impl Default for GameOptionVictoryMode {
+    fn default() -> Self {
+        GameOptionVictoryMode::VictoryPoints
+    }
+}
+
+impl GameOptionVictoryMode {
+    pub const ALL: [GameOptionVictoryMode; 2] = [
+        GameOptionVictoryMode::VictoryPoints,
+        GameOptionVictoryMode::Elimination,
+    ];
+
+    pub const fn has_victory_systems(self) -> bool {
+        matches!(self, GameOptionVictoryMode::VictoryPoints)
+    }
+}
+
 impl GameOptionSpeed {
+    pub const ALL: [GameOptionSpeed; 5] = [
+        GameOptionSpeed::VerySlow,
+        GameOptionSpeed::Slow,
+        GameOptionSpeed::Medium,
+        GameOptionSpeed::Fast,
+        GameOptionSpeed::VeryFast,
+    ];
+
     pub const fn into_coeff(self) -> f64 {
         match self {
+            GameOptionSpeed::VerySlow => 1.5,
             GameOptionSpeed::Slow => 1.2,
             GameOptionSpeed::Medium => 1.0,
             GameOptionSpeed::Fast => 0.8,
+            GameOptionSpeed::VeryFast => 0.6,
         }
     }
 
     pub const fn into_travel_speed(self) -> f64 {
         match self  {
+            GameOptionSpeed::VerySlow => 0.3,
             GameOptionSpeed::Slow => 0.4,
             GameOptionSpeed::Medium => 0.55,
             GameOptionSpeed::Fast => 0.7,
+            GameOptionSpeed::VeryFast => 0.85,
         }
     }
 
     pub const fn into_conquest_speed(self) -> f64 {
         match self {
+            GameOptionSpeed::VerySlow => 1.25,
             GameOptionSpeed::Slow => 1.1,
             GameOptionSpeed::Medium => 1.0,
             GameOptionSpeed::Fast => 0.8,
+            GameOptionSpeed::VeryFast => 0.65,
         }
     }
 }
 
 impl GameOptionMapSize {
+    pub const fn into_fleet_range_coeff(self) -> f64 {
+        match self {
+            GameOptionMapSize::Mini => 0.6,
+            GameOptionMapSize::VerySmall => 0.7,
+            GameOptionMapSize::Small => 0.85,
+            GameOptionMapSize::Medium => 1.0,
+            GameOptionMapSize::Large => 1.2,
+            GameOptionMapSize::VeryLarge => 1.4,
+        }
+    }
+
     pub fn to_galaxy_builder(self) -> GalaxyBuilder {
         match self {
             GameOptionMapSize::Mini => GalaxyBuilder::default()
@@ -106,21 +162,76 @@ impl GameOptionMapSize {
     }
 }
 
+#[get("/game-speeds/")]
+pub async fn get_game_speeds() -> Result<HttpResponse> {
+    #[derive(Serialize)]
+    struct GameSpeedData {
+        speed: GameOptionSpeed,
+        time_coeff: f64,
+        travel_speed_coeff: f64,
+        conquest_speed_coeff: f64,
+    }
+    Ok(HttpResponse::Ok().json(GameOptionSpeed::ALL.iter().map(|&speed| GameSpeedData{
+        speed,
+        time_coeff: speed.into_coeff(),
+        travel_speed_coeff: speed.into_travel_speed(),
+        conquest_speed_coeff: speed.into_conquest_speed(),
+    }).collect::<Vec<GameSpeedData>>()))
+}
+
+#[get("/victory-modes/")]
+pub async fn get_victory_modes() -> Result<HttpResponse> {
+    #[derive(Serialize)]
+    struct VictoryModeData {
+        mode: GameOptionVictoryMode,
+        has_victory_systems: bool,
+    }
+    Ok(HttpResponse::Ok().json(GameOptionVictoryMode::ALL.iter().map(|&mode| VictoryModeData{
+        mode,
+        has_victory_systems: mode.has_victory_systems(),
+    }).collect::<Vec<VictoryModeData>>()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_get_construction_time_coeff() {
+        assert_eq!(1.5, GameOptionSpeed::VerySlow.into_coeff());
         assert_eq!(1.2, GameOptionSpeed::Slow.into_coeff());
         assert_eq!(1.0, GameOptionSpeed::Medium.into_coeff());
         assert_eq!(0.8, GameOptionSpeed::Fast.into_coeff());
+        assert_eq!(0.6, GameOptionSpeed::VeryFast.into_coeff());
     }
 
     #[test]
     fn test_get_travel_speed() {
+        assert_eq!(0.3, GameOptionSpeed::VerySlow.into_travel_speed());
         assert_eq!(0.4, GameOptionSpeed::Slow.into_travel_speed());
         assert_eq!(0.55, GameOptionSpeed::Medium.into_travel_speed());
         assert_eq!(0.7, GameOptionSpeed::Fast.into_travel_speed());
+        assert_eq!(0.85, GameOptionSpeed::VeryFast.into_travel_speed());
+    }
+
+    #[test]
+    fn test_get_conquest_speed() {
+        assert_eq!(1.25, GameOptionSpeed::VerySlow.into_conquest_speed());
+        assert_eq!(1.0, GameOptionSpeed::Medium.into_conquest_speed());
+        assert_eq!(0.65, GameOptionSpeed::VeryFast.into_conquest_speed());
+    }
+
+    #[test]
+    fn test_victory_mode_has_victory_systems() {
+        assert!(GameOptionVictoryMode::VictoryPoints.has_victory_systems());
+        assert!(!GameOptionVictoryMode::Elimination.has_victory_systems());
+        assert_eq!(GameOptionVictoryMode::VictoryPoints, GameOptionVictoryMode::default());
+    }
+
+    #[test]
+    fn test_get_fleet_range_coeff() {
+        assert_eq!(0.6, GameOptionMapSize::Mini.into_fleet_range_coeff());
+        assert_eq!(1.0, GameOptionMapSize::Medium.into_fleet_range_coeff());
+        assert_eq!(1.4, GameOptionMapSize::VeryLarge.into_fleet_range_coeff());
     }
 }
@@ -2,8 +2,8 @@ use actix_web::web;
 use actix::prelude::*;
 use serde::{Serialize};
 use std::sync::{Arc, RwLock};
-use std::collections::{HashMap};
-use std::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 use chrono::{DateTime, Utc};
 use futures::{
     executor::block_on,
@@ -12,21 +12,26 @@ use crate::{
     lib::{
         Result,
         log::log,
-        error::ServerError,
+        error::{ServerError, InternalError},
+        request_id::{current_request_id, in_request_scope},
         time::Time
     },
     game::{
         faction::{FactionID, GameFaction, generate_game_factions},
         fleet::{
-            combat::conquest::Conquest,
+            combat::{conquest::Conquest, round::DefenderBonus},
             fleet::Fleet,
+            formation::AttackOrders,
+            squadron::reinforce_squadrons,
             travel::process_fleet_arrival,
         },
-        game::game::{Game, GameID, VICTORY_POINTS_PER_MINUTE},
-        player::{PlayerID, Player, init_player_wallets},
+        game::game::{Game, GameID},
+        player::{PlayerID, Player, PlayerCache, MoneyTransferLimit, init_player_wallets},
+        ship::queue::ShipQueue,
+        technology::Technology,
         system::{
             building::{Building, BuildingStatus, BuildingKind},
-            system::{System, SystemID, assign_systems, generate_systems, init_player_systems}
+            system::{System, SystemID, assign_systems, generate_systems, generate_systems_with_rng, init_player_systems}
         },
     },
     ws::{ client::ClientSession, protocol},
@@ -37,7 +42,71 @@ pub struct GameServer {
     pub id: GameID,
     pub state: web::Data<AppState>,
     pub clients: RwLock<HashMap<PlayerID, actix::Addr<ClientSession>>>,
-    pub tasks: HashMap<String, actix::SpawnHandle>,
+    pub tasks: HashMap<String, ScheduledTask>,
+    pub task_catch_up: TaskCatchUp,
+    /// Non-critical messages waiting for the next flush. A zero `batch_window` disables batching.
+    pub batch_window: Duration,
+    pub pending_messages: RwLock<HashMap<PlayerID, Vec<protocol::Message>>>,
+    pub player_cache: PlayerCache,
+    pub attack_orders: AttackOrders,
+    pub defender_bonus: DefenderBonus,
+    /// Part of the defeated ships seized by the conqueror of a system after a decisive battle
+    pub capture_rate: f64,
+    pub income_interval: Duration,
+    pub victory_interval: Duration,
+    /// Number of victory intervals elapsed, the points per minute being spread over them
+    pub victory_ticks: u32,
+    /// Delay during which destroyed fleets are kept, so clients can still display their loss
+    pub fleet_retention: Duration,
+    /// Money sent by each player to its faction-mates during the current income interval
+    pub money_transfers: MoneyTransferLimit,
+    pub intervals: Vec<actix::SpawnHandle>,
+    pub paused_at: Option<Time>,
+    /// Players who asked to pause the game, or to resume it when it is paused, with the time of their vote
+    pub pause_votes: HashMap<PlayerID, Instant>,
+    /// Factions which had players when the game began, players leaving the game forfeit for their faction
+    pub starting_factions: HashSet<FactionID>,
+    /// Generates the galaxy on the blocking thread pool, keeping the server responsive meanwhile
+    pub async_map_generation: bool,
+}
+
+/// Overdue tasks, such as the ones rescheduled after a restart, are performed one after another
+/// at a steady pace instead of all at once. A zero interval disables the throttling.
+pub struct TaskCatchUp {
+    interval: Duration,
+    next_slot: Option<Instant>,
+}
+
+impl TaskCatchUp {
+    pub const fn new(interval: Duration) -> Self {
+        TaskCatchUp{
+            interval,
+            next_slot: None,
+        }
+    }
+
+    /// Delay before performing the next overdue task
+    pub fn get_delay(&mut self, now: Instant) -> Duration {
+        if self.interval.as_millis() == 0 {
+            return Duration::new(0, 0);
+        }
+        let slot = self.next_slot.filter(|slot| *slot > now).unwrap_or(now);
+        self.next_slot = Some(slot + self.interval);
+        slot.duration_since(now)
+    }
+}
+
+type TaskCallback = Box<dyn FnOnce(&mut GameServer, &<GameServer as Actor>::Context) -> Result<()>>;
+
+/// A timer of the game server. Its callback is kept aside to be rescheduled when the game is resumed.
+pub struct ScheduledTask {
+    handle: Option<actix::SpawnHandle>,
+    end_time: Instant,
+    /// Time left before the task is performed, set while the game is paused
+    remaining_time: Option<Duration>,
+    /// Player whose data the task acts upon, its tasks are handed over or cancelled when it leaves
+    owner: Option<PlayerID>,
+    callback: Option<TaskCallback>,
 }
 
 /// The trait of every type that can represent a task. A task is launched by message-passing to the
@@ -51,6 +120,10 @@ pub trait GameServerTask{
 
     fn get_task_end_time(&self) -> Time;
 
+    fn get_task_owner(&self) -> Option<PlayerID> {
+        None
+    }
+
     fn get_task_duration(&self) -> Option<Duration> {
         let datetime: DateTime<Utc> = self.get_task_end_time().into();
         datetime.signed_duration_since(Utc::now()).to_std().ok()
@@ -75,33 +148,87 @@ impl Actor for GameServer {
             None,
         )));
         
-        self.add_task(ctx, "init".to_string(), Duration::new(1, 0), |this, _| block_on(this.init()));
-        self.add_task(ctx, "begin".to_string(), Duration::new(4, 0), |this, _| block_on(this.begin()));
-        run_interval(ctx, Duration::new(5, 0), move |this, _| {
-            block_on(this.produce_income())
-        });
-        run_interval(ctx, Duration::new(60, 0), move |this, _| {
-            block_on(this.distribute_victory_points())
-        });
+        if self.async_map_generation {
+            self.add_task(ctx, "init".to_string(), Duration::new(1, 0), |this, ctx| this.init_async(ctx.address()));
+        } else {
+            self.add_task(ctx, "init".to_string(), Duration::new(1, 0), |this, _| block_on(this.init()));
+            self.add_task(ctx, "begin".to_string(), Duration::new(4, 0), |this, _| block_on(this.begin()));
+        }
+        self.start_intervals(ctx);
+        if self.batch_window.as_millis() > 0 {
+            run_interval(ctx, self.batch_window, move |this, _| {
+                this.flush_pending_messages();
+                Ok(())
+            });
+        }
     }
 }
 
 impl GameServer {
+    fn start_intervals(&mut self, ctx: &mut <Self as Actor>::Context) {
+        self.intervals = vec![
+            run_interval(ctx, self.income_interval, move |this, _| {
+                block_on(this.produce_income())
+            }),
+            run_interval(ctx, self.victory_interval, move |this, _| {
+                block_on(this.distribute_victory_points())
+            }),
+            run_interval(ctx, self.fleet_retention, move |this, _| {
+                block_on(this.remove_destroyed_fleets())
+            }),
+        ];
+    }
+
     async fn init(&mut self) -> Result<()> {
-        generate_game_factions(self.id.clone(), &self.state.db_pool).await?;
+        let game = self.prepare_map().await?;
+
+        let (systems, nb_victory_systems) = generate_systems(self.id.clone(), game.map_size, game.victory_mode).await?;
+
+        self.populate_map(game, systems, nb_victory_systems).await
+    }
+
+    /// The galaxy is drawn off the actor thread, the map is populated once it is received back
+    fn init_async(&mut self, addr: Addr<GameServer>) -> Result<()> {
+        let game = block_on(self.prepare_map())?;
+
+        block_on(self.ws_broadcast(&protocol::Message::new(
+            protocol::Action::MapGenerating,
+            (),
+            None
+        )))?;
+        generate_map(addr, game);
+        Ok(())
+    }
 
-        let mut game = Game::find(self.id.clone(), &self.state.db_pool).await?;
+    /// The players are not left waiting for a map which will never come
+    async fn abort_map_generation(&self, game: &Game) -> Result<()> {
+        self.ws_broadcast(&protocol::Message::new(
+            protocol::Action::MapGenerationFailed,
+            (),
+            None
+        )).await?;
+        self.state.clear_game(game).await
+    }
 
-        let (mut systems, nb_victory_systems) = generate_systems(self.id.clone(), game.map_size).await?;
+    async fn prepare_map(&self) -> Result<Game> {
+        let game = Game::find(self.id.clone(), &self.state.db_pool).await?;
 
-        game.victory_points = nb_victory_systems as i32 * 100;
+        generate_game_factions(self.id.clone(), game.nb_factions, &self.state.db_pool).await?;
+
+        Ok(game)
+    }
+
+    async fn populate_map(&mut self, mut game: Game, mut systems: Vec<System>, nb_victory_systems: u32) -> Result<()> {
+        game.victory_points = game.config.get_victory_points(nb_victory_systems);
 
         Game::update(game.clone(), &self.state.db_pool).await?;
 
         let mut players = Player::find_by_game(self.id, &self.state.db_pool).await?;
-        assign_systems(&players, &mut systems).await?;
-        init_player_wallets(&mut players, &self.state.db_pool).await?;
+        self.starting_factions = players.iter().filter_map(|p| p.faction).collect();
+        assign_systems(&mut players, &mut systems).await?;
         System::insert_all(systems.iter(), &self.state.db_pool).await?;
+        // The players home systems are persisted along with their wallets
+        init_player_wallets(&mut players, game.config.start_wallet, &self.state.db_pool).await?;
         init_player_systems(&systems, game.game_speed, &self.state.db_pool).await?;
         
         self.ws_broadcast(&protocol::Message::new(
@@ -132,7 +259,7 @@ impl GameServer {
 
     pub async fn ws_broadcast(&self, message: &protocol::Message) -> Result<()> {
         let clients = self.clients();
-        for pid in Player::find_ids_by_game(self.id, &self.state.db_pool).await? {
+        for pid in self.player_cache.find_ids_by_game(self.id, &self.state.db_pool).await? {
             self.ws_send(&clients, &pid, message);
         }
         Ok(())
@@ -140,7 +267,7 @@ impl GameServer {
 
     pub async fn faction_broadcast(&self, fid: FactionID, message: protocol::Message) -> Result<()> {
         let clients = self.clients();
-        for pid in Player::find_ids_by_game_and_faction(self.id, fid, &self.state.db_pool).await? {
+        for pid in self.player_cache.find_ids_by_game_and_faction(self.id, fid, &self.state.db_pool).await? {
             self.ws_send(&clients, &pid, &message);
         }
         Ok(())
@@ -151,58 +278,118 @@ impl GameServer {
         self.ws_send(&clients, pid, message);
     }
 
+    /// Sends a message only relevant at the time, such as a periodic update.
+    /// It is not kept for the disconnected players, who get the up-to-date data on their next resync.
+    pub fn connected_player_send(&self, pid: &PlayerID, message: &protocol::Message) {
+        let clients = self.clients();
+        if clients.contains_key(pid) {
+            self.ws_send(&clients, pid, message);
+        }
+    }
+
     pub fn ws_send(&self, clients: &std::sync::RwLockReadGuard<HashMap<PlayerID, actix::Addr<ClientSession>>>, pid: &PlayerID, message: &protocol::Message) {
+        if self.batch_window.as_millis() > 0 {
+            if message.action.is_batchable() {
+                if message.skip_id != Some(*pid) {
+                    self.pending_messages_mut().entry(*pid).or_default().push(message.clone());
+                }
+                return;
+            }
+            // Critical messages must not overtake the ones already waiting for this player
+            if let Some(pending) = self.pending_messages_mut().remove(pid) {
+                self.deliver(clients, pid, batch_messages(pending));
+            }
+        }
+        self.deliver(clients, pid, message.clone());
+    }
+
+    fn deliver(&self, clients: &std::sync::RwLockReadGuard<HashMap<PlayerID, actix::Addr<ClientSession>>>, pid: &PlayerID, message: protocol::Message) {
         let mut missing_messages = self.state.missing_messages_mut();
 
         if let Some(client) = clients.get(pid) {
-            client.do_send(message.clone());
+            client.do_send(message);
         } else {
             missing_messages.entry(*pid)
                 .or_default()
-                .push(message.clone());
+                .push(message);
+        }
+    }
+
+    fn pending_messages_mut(&self) -> std::sync::RwLockWriteGuard<HashMap<PlayerID, Vec<protocol::Message>>> {
+        self.pending_messages.write().expect("Poisoned lock on game pending messages")
+    }
+
+    /// Sends every buffered message, as a single frame per player.
+    pub fn flush_pending_messages(&self) {
+        let pending: HashMap<PlayerID, Vec<protocol::Message>> = self.pending_messages_mut().drain().collect();
+        if pending.is_empty() {
+            return;
+        }
+        let nb_messages: usize = pending.values().map(Vec::len).sum();
+        let clients = self.clients();
+        let nb_frames = pending.len();
+
+        for (pid, messages) in pending {
+            self.deliver(&clients, &pid, batch_messages(messages));
         }
+
+        log(
+            gelf::Level::Debug,
+            "Messages batched",
+            &format!("{} messages have been sent in {} frames", nb_messages, nb_frames),
+            vec![
+                ("game_id", self.id.0.to_string()),
+                ("messages_number", nb_messages.to_string()),
+                ("frames_number", nb_frames.to_string()),
+            ],
+            &self.state.logger
+        );
+    }
+
+    async fn remove_destroyed_fleets(&mut self) -> Result<()> {
+        let nb_fleets = Fleet::remove_destroyed(self.id, self.fleet_retention.as_secs_f64(), &mut &self.state.db_pool).await?;
+
+        if nb_fleets > 0 {
+            log(
+                gelf::Level::Debug,
+                "Destroyed fleets removed",
+                &format!("{} destroyed fleets have been removed", nb_fleets),
+                vec![
+                    ("game_id", self.id.0.to_string()),
+                ],
+                &self.state.logger
+            );
+        }
+        Ok(())
     }
 
     async fn produce_income(&mut self) -> Result<()> {
-        let mut players: HashMap<PlayerID, Player> = Player::find_by_game(self.id.clone(), &self.state.db_pool).await?
+        self.money_transfers.reset();
+        // Wallets may have been updated by players since the last tick
+        self.player_cache.clear();
+        let mut players: HashMap<PlayerID, Player> = self.player_cache.find_by_game(self.id.clone(), &self.state.db_pool).await?
             .into_iter()
             .map(|p| (p.id.clone(), p))
             .collect();
-        let mut players_income = HashMap::new();
-        let mines: Vec<SystemID> = Building::find_by_kind(BuildingKind::Mine, &self.state.db_pool).await?
+        // A mine finished during the tick is only operational once its construction task ran
+        let now = Time::now();
+        let mines: HashSet<SystemID> = Building::find_by_kind(BuildingKind::Mine, &self.state.db_pool).await?
             .into_iter()
-            .filter(|b| b.status == BuildingStatus::Operational)
+            .filter(|b| b.status == BuildingStatus::Operational && b.built_at.0 <= now.0)
             .map(|b| b.system)
             .collect();
-
-        // Add money to each player based on the number of
-        // currently, the income is `some_player.income = some_player.number_of_systems_owned * 15`
-        System::find_possessed(self.id.clone(), &self.state.db_pool).await?
-            .into_iter()
-            .for_each(|system| {
-                let mut income = 10;
-                if mines.contains(&system.id) {
-                    income = 40;
-                }
-                *players_income.entry(system.player).or_insert(0) += income;
-            }); // update the player's income
+        let systems = System::find_possessed(self.id.clone(), &self.state.db_pool).await?;
+        let contested_systems = get_contested_systems(Conquest::find_ongoing_by_systems(systems.iter().map(|s| s.id).collect(), &self.state.db_pool).await?);
 
         // Notify the player for wallet update
-        #[derive(Serialize, Clone)]
-        struct PlayerIncome {
-            income: usize
-        }
-        let clients = self.clients.read().expect("Poisoned lock on game clients");
-        for (pid, income) in players_income {
-            if let Some(p) = players.get_mut(&pid.unwrap()) {
-                p.wallet += income;
-                if let Some(c) = clients.get(&pid.unwrap()){
-                    c.do_send(protocol::Message::new(
-                        protocol::Action::PlayerIncome,
-                        PlayerIncome{ income },
-                        None,
-                    ));
-                }
+        for (pid, income) in get_players_income(&systems, &mines, &contested_systems) {
+            if let Some(p) = players.get_mut(&pid) {
+                p.wallet += income.income;
+                self.connected_player_send(&p.id, &protocol::Message::new(
+                    protocol::Action::PlayerIncome,
+                    income,
+                    None,
+                ));
             }
         }
         let mut tx = self.state.db_pool.begin().await?;
@@ -210,7 +397,10 @@ impl GameServer {
             p.update(&mut tx).await?;
         }
         tx.commit().await?;
-        Ok(())
+        for p in players.into_iter().map(|(_, p)| p) {
+            self.player_cache.store(p);
+        }
+        reinforce_squadrons(self).await
     }
 
     async fn distribute_victory_points(&mut self) -> Result<()> {
@@ -220,20 +410,26 @@ impl GameServer {
             .into_iter()
             .map(|gf| (gf.faction.clone(), gf))
             .collect::<HashMap<FactionID, GameFaction>>();
-        let mut players = Player::find_by_ids(victory_systems.clone().into_iter().map(|s| s.player.clone().unwrap()).collect(), &self.state.db_pool).await?
+        self.player_cache.clear();
+        let players = self.player_cache.find_by_game(self.id, &self.state.db_pool).await?
             .into_iter()
             .map(|p| (p.id.clone(), p))
             .collect::<HashMap<PlayerID, Player>>();
 
-        for system in victory_systems.iter() {
-            factions.get_mut(
-                &players.get_mut(&system.player.unwrap())
-                    .unwrap()
-                    .faction
-                    .unwrap()
-            ).unwrap().victory_points += VICTORY_POINTS_PER_MINUTE;
+        if !game.victory_mode.has_victory_systems() {
+            let last_faction = get_last_faction_standing(&self.starting_factions, players.values());
+            if let Some(f) = last_faction.and_then(|fid| factions.get(&fid)) {
+                self.process_victory(f, factions.values().cloned().collect::<Vec<GameFaction>>()).await?;
+            }
+            return Ok(());
         }
 
+        // The owner may have left the game, in which case the system does not score anymore
+        let owner_factions = victory_systems.iter().map(|s| players.get(&s.player.unwrap()).and_then(|p| p.faction));
+        self.victory_ticks += 1;
+        let points = get_victory_points_per_tick(game.config.victory_points_per_minute, self.victory_interval, self.victory_ticks);
+        score_victory_systems(&mut factions, owner_factions, points);
+
         let mut victorious_faction: Option<&GameFaction> = None;
         let mut tx = self.state.db_pool.begin().await?;
         for f in factions.values() {
@@ -272,12 +468,32 @@ impl GameServer {
             None,
         )).await?;
 
+        #[cfg(feature = "leaderboard")]
+        self.record_game_stats(victorious_faction.faction).await;
+
         let game = Game::find(self.id, &self.state.db_pool).await?;
         self.state.clear_game(&game).await?;
         Ok(())
     }
 
+    /// The game ends even if its statistics can not be recorded
+    #[cfg(feature = "leaderboard")]
+    async fn record_game_stats(&self, victorious_faction: FactionID) {
+        if let Err(e) = crate::game::leaderboard::record_game_stats(self.id, victorious_faction, &self.state.db_pool).await {
+            log(
+                gelf::Level::Error,
+                "Game statistics recording failed",
+                &format!("{:?}", e),
+                vec![
+                    ("game_id", self.id.0.to_string()),
+                ],
+                &self.state.logger
+            );
+        }
+    }
+
     pub async fn remove_player(&self, pid: PlayerID) -> Result<Option<actix::Addr<ClientSession>>> {
+        self.player_cache.invalidate(&pid);
         let mut player = Player::find(pid, &self.state.db_pool).await?;
         player.is_connected = false;
         self.ws_broadcast(&protocol::Message::new(
@@ -298,32 +514,156 @@ impl GameServer {
     )
         where F: 'static + FnOnce(&mut Self, & <Self as Actor>::Context) -> Result<()>,
     {
-        self.tasks.insert(task_name.clone(), ctx.run_later(
-            duration,
-            move |this, ctx| {
-                let result = closure(this, ctx).map_err(ServerError::from);
-                this.remove_task(&task_name);
-                if result.is_err() {
-                    log(
-                        gelf::Level::Error,
-                        &format!("Task {} failed", task_name),
-                        &format!("{:?}", result.err()),
-                        vec![],
-                        &this.state.logger
-                    );
-                }
+        let mut task = ScheduledTask{
+            handle: None,
+            end_time: Instant::now() + duration,
+            remaining_time: None,
+            owner: None,
+            callback: Some(Box::new(closure)),
+        };
+        // Tasks scheduled during a pause wait for the game to be resumed
+        if self.paused_at.is_some() {
+            task.remaining_time = Some(duration);
+        } else {
+            task.handle = Some(schedule_task(ctx, task_name.clone(), duration));
+        }
+        self.tasks.insert(task_name, task);
+    }
+
+    pub fn add_player_task<F>(
+        &mut self,
+        ctx: &mut <Self as Actor>::Context,
+        task_name: String,
+        duration: Duration,
+        owner: Option<PlayerID>,
+        closure: F
+    )
+        where F: 'static + FnOnce(&mut Self, & <Self as Actor>::Context) -> Result<()>,
+    {
+        self.add_task(ctx, task_name.clone(), duration, closure);
+        if let Some(task) = self.tasks.get_mut(&task_name) {
+            task.owner = owner;
+        }
+    }
+
+    /// The tasks of a player leaving the game go to the heir of its assets. Without heir, its
+    /// assets are gone and so are its tasks
+    pub fn hand_over_player_tasks(&mut self, pid: PlayerID, heir: Option<PlayerID>, ctx: &mut <Self as Actor>::Context) {
+        for task in take_player_tasks(&mut self.tasks, pid, heir) {
+            if let Some(handle) = task.handle {
+                ctx.cancel_future(handle);
             }
-        ));
+        }
+    }
+
+    fn perform_task(&mut self, task_name: String, ctx: &<Self as Actor>::Context) {
+        let callback = self.tasks.remove(&task_name).and_then(|task| task.callback);
+        if let Some(callback) = callback {
+            let result = callback(self, ctx).map_err(ServerError::from);
+            if result.is_err() {
+                log(
+                    gelf::Level::Error,
+                    &format!("Task {} failed", task_name),
+                    &format!("{:?}", result.err()),
+                    vec![],
+                    &self.state.logger
+                );
+            }
+        }
     }
 
     pub fn cancel_task(&mut self, task_name: &str, context: &mut actix::Context<GameServer>) {
         if let Some(task) = self.tasks.get(task_name) {
-            context.cancel_future(*task);
+            if let Some(handle) = task.handle {
+                context.cancel_future(handle);
+            }
 
             self.remove_task(task_name);
         }
     }
 
+    /// Stops the timers of the game, keeping the remaining time of each task
+    pub fn pause(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let now = Instant::now();
+        for task in self.tasks.values_mut() {
+            if let Some(handle) = task.handle.take() {
+                ctx.cancel_future(handle);
+                task.remaining_time = Some(task.end_time.saturating_duration_since(now));
+            }
+        }
+        for handle in self.intervals.drain(..) {
+            ctx.cancel_future(handle);
+        }
+        self.pause_votes.clear();
+        self.paused_at = Some(Time::now());
+    }
+
+    /// Reschedules every task with the time it had left when the game was paused
+    pub fn resume(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let now = Instant::now();
+        for (task_name, task) in self.tasks.iter_mut() {
+            if let Some(remaining_time) = task.remaining_time.take() {
+                task.end_time = now + remaining_time;
+                task.handle = Some(schedule_task(ctx, task_name.clone(), remaining_time));
+            }
+        }
+        self.start_intervals(ctx);
+        self.pause_votes.clear();
+        self.paused_at = None;
+    }
+
+    /// Registers the vote of a player to pause or resume the game. Returns whether the majority was reached
+    pub fn vote_pause(&mut self, pid: PlayerID, pause: bool, ctx: &mut <Self as Actor>::Context) -> Result<bool> {
+        if self.paused_at.is_some() == pause {
+            return Err(InternalError::Conflict.into());
+        }
+        let now = Instant::now();
+        expire_pause_votes(&mut self.pause_votes, now);
+        self.pause_votes.insert(pid, now);
+        let nb_players = block_on(self.player_cache.find_ids_by_game(self.id, &self.state.db_pool))?.len();
+        if !has_majority(self.pause_votes.len(), nb_players) {
+            return Ok(false);
+        }
+
+        if pause {
+            self.pause(ctx);
+            block_on(self.ws_broadcast(&protocol::Message::new(
+                protocol::Action::GamePaused,
+                self.paused_at,
+                None,
+            )))?;
+            return Ok(true);
+        }
+        let paused_at = self.paused_at.unwrap();
+        let resumed_at = Time::now();
+        let pause_duration = DateTime::<Utc>::from(resumed_at).signed_duration_since(DateTime::<Utc>::from(paused_at));
+        block_on(self.postpone_deadlines(paused_at, resumed_at))?;
+        self.resume(ctx);
+
+        #[derive(Serialize)]
+        struct GameResumedData{
+            pause_duration: i64
+        }
+        block_on(self.ws_broadcast(&protocol::Message::new(
+            protocol::Action::GameResumed,
+            GameResumedData{ pause_duration: pause_duration.num_milliseconds() },
+            None,
+        )))?;
+        Ok(true)
+    }
+
+    /// Extends the deadlines stored in database by the time the game was paused, like the tasks are rescheduled
+    async fn postpone_deadlines(&self, paused_at: Time, resumed_at: Time) -> Result<()> {
+        let mut tx = self.state.db_pool.begin().await?;
+        Fleet::postpone_deadlines(self.id, paused_at, resumed_at, &mut tx).await?;
+        Building::postpone_deadlines(self.id, paused_at, resumed_at, &mut tx).await?;
+        ShipQueue::postpone_deadlines(self.id, paused_at, resumed_at, &mut tx).await?;
+        Technology::postpone_deadlines(self.id, paused_at, resumed_at, &mut tx).await?;
+        Conquest::postpone_deadlines(self.id, paused_at, resumed_at, &mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
     pub fn remove_task(&mut self, task_name: &str) {
         self.tasks.remove(task_name);
     }
@@ -369,7 +709,7 @@ macro_rules! task {
     ($data:ident -> $exp:expr) => {
         {
             use crate::game::game::server::{GameScheduleTaskMessage, GameServerTask};
-            GameScheduleTaskMessage::new($data.get_task_id(), $data.get_task_duration(), $exp)
+            GameScheduleTaskMessage::new($data.get_task_id(), $data.get_task_duration(), $exp).with_owner($data.get_task_owner())
         }
     };
 }
@@ -392,6 +732,9 @@ pub struct GameScheduleTaskMessage
 {
     task_id: String,
     task_duration: Option<Duration>,
+    /// Id of the request which scheduled the task, kept for the logs of the task
+    request_id: Option<String>,
+    owner: Option<PlayerID>,
     callback: Box<dyn FnOnce(&GameServer) -> Result<()> + Send + 'static>,
 }
 
@@ -408,9 +751,16 @@ impl GameScheduleTaskMessage
         Self {
             task_id,
             task_duration,
+            request_id: current_request_id(),
+            owner: None,
             callback : Box::new(callback),
         }
     }
+
+    pub fn with_owner(mut self, owner: Option<PlayerID>) -> Self {
+        self.owner = owner;
+        self
+    }
 }
 
 impl GameCancelTaskMessage
@@ -426,6 +776,72 @@ impl GameCancelTaskMessage
 #[rtype(result="()")]
 pub struct GameEndMessage{}
 
+#[derive(actix::Message)]
+#[rtype(result="()")]
+pub struct GamePlayerTasksMessage{
+    pub pid: PlayerID,
+    pub heir: Option<PlayerID>,
+}
+
+#[derive(actix::Message)]
+#[rtype(result="()")]
+pub struct GameMapGeneratedMessage{
+    pub game: Game,
+    pub systems: std::result::Result<(Vec<System>, u32), String>,
+}
+
+/// Draws the galaxy of the game on the blocking thread pool, it is sent back to the server once done
+pub fn generate_map<A>(addr: Addr<A>, game: Game)
+    where
+        A: Handler<GameMapGeneratedMessage>,
+        A::Context: actix::dev::ToEnvelope<A, GameMapGeneratedMessage>,
+{
+    run_blocking(addr, move || GameMapGeneratedMessage{
+        systems: generate_systems_with_rng(game.id, game.map_size, game.victory_mode, &mut rand::thread_rng())
+            .map_err(|e| e.to_string()),
+        game,
+    });
+}
+
+/// Runs a CPU-bound job on the blocking thread pool, the actor receives its result as a message
+/// and keeps handling the other ones in the meantime
+pub fn run_blocking<A, M, F>(addr: Addr<A>, job: F)
+    where
+        A: Handler<M>,
+        A::Context: actix::dev::ToEnvelope<A, M>,
+        M: actix::Message + Send + 'static,
+        M::Result: Send,
+        F: FnOnce() -> M + Send + 'static,
+{
+    actix_rt::spawn(async move {
+        // The job is only canceled if it panicked, there is no result to deliver then
+        if let Ok(message) = web::block(move || Ok::<M, ()>(job())).await {
+            addr.do_send(message);
+        }
+    });
+}
+
+#[derive(actix::Message)]
+#[rtype(result="Result<()>")]
+pub struct GameMoneyTransferMessage{
+    pub pid: PlayerID,
+    pub amount: usize,
+}
+
+#[derive(actix::Message)]
+#[rtype(result="()")]
+pub struct GameMoneyTransferFailureMessage{
+    pub pid: PlayerID,
+    pub amount: usize,
+}
+
+#[derive(actix::Message)]
+#[rtype(result="Result<bool>")]
+pub struct GamePauseVoteMessage{
+    pub pid: PlayerID,
+    pub pause: bool,
+}
+
 impl Handler<GameAddClientMessage> for GameServer {
     type Result = ();
 
@@ -480,12 +896,20 @@ impl Handler<GameFleetTravelMessage> for GameServer {
             block_on(conquest.remove_fleet(&msg.system, &msg.fleet, &self)).map_err(ServerError::from).ok().unwrap();
         }
         let datetime: DateTime<Utc> = msg.fleet.destination_arrival_date.unwrap().into();
-        ctx.run_later(datetime.signed_duration_since(Utc::now()).to_std().unwrap(), move |this, _| {
-            let res = block_on(process_fleet_arrival(&this, msg.fleet.id));
-            if res.is_err() {
-                println!("Fleet arrival fail : {:?}", res.err());
-            }
-        });
+        let fid = msg.fleet.id;
+        // The arrival is a task like the others, to be delayed when the game is paused.
+        // Overdue arrivals are spread like the other overdue tasks
+        let duration = match datetime.signed_duration_since(Utc::now()).to_std() {
+            Ok(duration) => duration,
+            Err(_) => self.task_catch_up.get_delay(Instant::now()),
+        };
+        self.add_player_task(
+            ctx,
+            format!("{}.arrival", fid.0),
+            duration,
+            Some(msg.fleet.player),
+            move |this, _| block_on(process_fleet_arrival(&this, fid))
+        );
     }
 }
 
@@ -494,11 +918,17 @@ impl Handler<GameScheduleTaskMessage> for GameServer
     type Result = ();
 
     fn handle(&mut self, msg: GameScheduleTaskMessage, mut ctx: &mut Self::Context) -> Self::Result {
-        self.add_task(
+        // The deadline of the task has passed already
+        let duration = match msg.task_duration {
+            Some(duration) => duration,
+            None => self.task_catch_up.get_delay(Instant::now()),
+        };
+        self.add_player_task(
             &mut ctx,
             msg.task_id.clone(),
-            msg.task_duration.unwrap_or(Duration::new(0, 0)),
-            move |this, _| (msg.callback)(&this)
+            duration,
+            msg.owner,
+            move |this, _| in_request_scope(msg.request_id, || (msg.callback)(&this))
         )
     }
 }
@@ -512,10 +942,77 @@ impl Handler<GameCancelTaskMessage> for GameServer
     }
 }
 
+impl Handler<GamePauseVoteMessage> for GameServer {
+    type Result = Result<bool>;
+
+    fn handle(&mut self, msg: GamePauseVoteMessage, ctx: &mut Self::Context) -> Self::Result {
+        self.vote_pause(msg.pid, msg.pause, ctx)
+    }
+}
+
+impl Handler<GameMapGeneratedMessage> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: GameMapGeneratedMessage, ctx: &mut Self::Context) -> Self::Result {
+        let game = msg.game.clone();
+        let result = msg.systems
+            .map_err(|e| {
+                log(gelf::Level::Error, "Galaxy generation failed", &e, vec![], &self.state.logger);
+                ServerError::from(InternalError::Conflict)
+            })
+            .and_then(|(systems, nb_victory_systems)| block_on(self.populate_map(msg.game, systems, nb_victory_systems)));
+        if let Err(e) = result {
+            log(
+                gelf::Level::Error,
+                "Map initialization failed",
+                &format!("{:?}", e),
+                vec![("game_id", game.id.0.to_string())],
+                &self.state.logger
+            );
+            if let Err(e) = block_on(self.abort_map_generation(&game)) {
+                log(
+                    gelf::Level::Error,
+                    "Game removal failed",
+                    &format!("{:?}", e),
+                    vec![("game_id", game.id.0.to_string())],
+                    &self.state.logger
+                );
+            }
+            return;
+        }
+        self.add_task(ctx, "begin".to_string(), Duration::new(3, 0), |this, _| block_on(this.begin()));
+    }
+}
+
+impl Handler<GamePlayerTasksMessage> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: GamePlayerTasksMessage, ctx: &mut Self::Context) -> Self::Result {
+        self.hand_over_player_tasks(msg.pid, msg.heir, ctx);
+    }
+}
+
+impl Handler<GameMoneyTransferMessage> for GameServer {
+    type Result = Result<()>;
+
+    fn handle(&mut self, msg: GameMoneyTransferMessage, _ctx: &mut Self::Context) -> Self::Result {
+        self.money_transfers.register(msg.pid, msg.amount)
+    }
+}
+
+impl Handler<GameMoneyTransferFailureMessage> for GameServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: GameMoneyTransferFailureMessage, _ctx: &mut Self::Context) -> Self::Result {
+        self.money_transfers.release(msg.pid, msg.amount)
+    }
+}
+
 impl Handler<GameEndMessage> for GameServer {
     type Result = ();
 
     fn handle(&mut self, _msg: GameEndMessage, ctx: &mut Self::Context) -> Self::Result {
+        self.flush_pending_messages();
         let clients = self.clients.read().expect("Poisoned lock on game clients");
         for (pid, c) in clients.iter() {
             self.state.add_client(&pid, c.clone());
@@ -525,11 +1022,87 @@ impl Handler<GameEndMessage> for GameServer {
     }
 }
 
+fn batch_messages(mut messages: Vec<protocol::Message>) -> protocol::Message {
+    if messages.len() == 1 {
+        return messages.remove(0);
+    }
+    protocol::Message::new(protocol::Action::BatchedMessages, messages, None)
+}
+
+/// Gives the tasks of the player to its heir. Without heir, they are removed and returned to be cancelled
+fn take_player_tasks(tasks: &mut HashMap<String, ScheduledTask>, pid: PlayerID, heir: Option<PlayerID>) -> Vec<ScheduledTask> {
+    let task_names: Vec<String> = tasks.iter()
+        .filter(|(_, task)| task.owner == Some(pid))
+        .map(|(name, _)| name.clone())
+        .collect();
+    match heir {
+        Some(hid) => {
+            for task_name in task_names.iter() {
+                if let Some(task) = tasks.get_mut(task_name) {
+                    task.owner = Some(hid);
+                }
+            }
+            vec![]
+        },
+        None => task_names.iter().filter_map(|task_name| tasks.remove(task_name)).collect(),
+    }
+}
+
+/// Each victory system scores for the faction of its owner, if any
+fn score_victory_systems(factions: &mut HashMap<FactionID, GameFaction>, owner_factions: impl Iterator<Item = Option<FactionID>>, points: i32) {
+    for fid in owner_factions.flatten() {
+        if let Some(faction) = factions.get_mut(&fid) {
+            faction.victory_points += points;
+        }
+    }
+}
+
+/// The victory points are earned per minute, whatever the victory interval. Each tick gives the points earned
+/// since the previous one, so that the fractions of points are not lost with short intervals
+fn get_victory_points_per_tick(points_per_minute: i32, interval: Duration, tick: u32) -> i32 {
+    let get_points_at = |tick: u32| (points_per_minute as i64 * interval.as_millis() as i64 * tick as i64 / 60_000) as i32;
+
+    get_points_at(tick) - get_points_at(tick.saturating_sub(1))
+}
+
+/// In elimination mode, the game is won by the only faction which still has players in the game.
+/// Players who left the game forfeit, their faction is eliminated once all of them are gone.
+fn get_last_faction_standing<'a>(starting_factions: &HashSet<FactionID>, players: impl Iterator<Item = &'a Player>) -> Option<FactionID> {
+    // A game begun by a single faction would be won right away
+    if starting_factions.len() < 2 {
+        return None;
+    }
+    let remaining_factions: HashSet<FactionID> = players
+        .filter(|p| !p.is_eliminated)
+        .filter_map(|p| p.faction)
+        .collect();
+
+    if remaining_factions.len() == 1 {
+        return remaining_factions.into_iter().next();
+    }
+    None
+}
+
+const PAUSE_VOTE_DURATION: Duration = Duration::from_secs(300);
+
+/// Old votes are dropped, so that they cannot add up with much later ones
+fn expire_pause_votes(votes: &mut HashMap<PlayerID, Instant>, now: Instant) {
+    votes.retain(|_, voted_at| now.saturating_duration_since(*voted_at) < PAUSE_VOTE_DURATION);
+}
+
+const fn has_majority(nb_votes: usize, nb_players: usize) -> bool {
+    nb_votes * 2 > nb_players
+}
+
+fn schedule_task(ctx: &mut <GameServer as Actor>::Context, task_name: String, duration: Duration) -> actix::SpawnHandle {
+    ctx.run_later(duration, move |this, ctx| this.perform_task(task_name, ctx))
+}
+
 fn run_interval<F>(
     ctx: &mut <GameServer as Actor>::Context,
     duration: Duration,
     mut closure: F
-)
+) -> actix::SpawnHandle
     where F: FnMut(&mut GameServer, & <GameServer as Actor>::Context) -> Result<()> + 'static,
 {
     ctx.run_interval(duration, move |this, ctx| {
@@ -537,5 +1110,385 @@ fn run_interval<F>(
         if result.is_err() {
             println!("{:?}", result.err());
         }
-    });
-}
\ No newline at end of file
+    })
+}
+
+const SYSTEM_INCOME: usize = 10;
+const MINE_SYSTEM_INCOME: usize = 40;
+
+/// Income of a player, detailed by system
+#[derive(Serialize, Clone, Default, Debug, PartialEq)]
+struct PlayerIncome {
+    income: usize,
+    systems: HashMap<SystemID, usize>,
+}
+
+/// The conquests halted by a victorious defender no longer contest their system
+fn get_contested_systems(conquests: Vec<Conquest>) -> HashSet<SystemID> {
+    conquests.into_iter()
+        .filter(Conquest::is_ongoing)
+        .map(|c| c.system)
+        .collect()
+}
+
+/// Systems being conquered produce nothing until the conquest is over
+fn get_players_income(systems: &[System], mines: &HashSet<SystemID>, contested_systems: &HashSet<SystemID>) -> HashMap<PlayerID, PlayerIncome> {
+    let mut players_income: HashMap<PlayerID, PlayerIncome> = HashMap::new();
+
+    for system in systems {
+        if let Some(pid) = system.player {
+            let income = if contested_systems.contains(&system.id) {
+                0
+            } else if mines.contains(&system.id) {
+                MINE_SYSTEM_INCOME
+            } else {
+                SYSTEM_INCOME
+            };
+            let player_income = players_income.entry(pid).or_default();
+            player_income.income += income;
+            player_income.systems.insert(system.id, income);
+        }
+    }
+    players_income
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+    use crate::game::{
+        fleet::combat::conquest::ConquestID,
+        game::{
+            game::GameConfig,
+            option::{GameOptionMapSize, GameOptionSpeed, GameOptionVictoryMode},
+        },
+        system::system::{Coordinates, SystemKind},
+    };
+    use std::sync::Mutex;
+
+    struct ProbeActor {
+        events: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Actor for ProbeActor {
+        type Context = Context<Self>;
+    }
+
+    #[derive(actix::Message)]
+    #[rtype(result="()")]
+    struct Ping;
+
+    #[derive(actix::Message)]
+    #[rtype(result="()")]
+    struct JobDone;
+
+    impl Handler<Ping> for ProbeActor {
+        type Result = ();
+
+        fn handle(&mut self, _msg: Ping, _ctx: &mut Self::Context) -> Self::Result {
+            self.events.lock().unwrap().push("ping");
+        }
+    }
+
+    impl Handler<JobDone> for ProbeActor {
+        type Result = ();
+
+        fn handle(&mut self, _msg: JobDone, _ctx: &mut Self::Context) -> Self::Result {
+            self.events.lock().unwrap().push("done");
+        }
+    }
+
+    /// Starts the generation of a map like `GameServer::init_async`
+    #[derive(actix::Message)]
+    #[rtype(result="()")]
+    struct StartMap(Game);
+
+    impl Handler<StartMap> for ProbeActor {
+        type Result = ();
+
+        fn handle(&mut self, msg: StartMap, ctx: &mut Self::Context) -> Self::Result {
+            self.events.lock().unwrap().push("MapGenerating");
+            generate_map(ctx.address(), msg.0);
+        }
+    }
+
+    impl Handler<GameMapGeneratedMessage> for ProbeActor {
+        type Result = ();
+
+        fn handle(&mut self, msg: GameMapGeneratedMessage, _ctx: &mut Self::Context) -> Self::Result {
+            self.events.lock().unwrap().push(match msg.systems {
+                Ok((systems, _)) if !systems.is_empty() => "SystemsCreated",
+                _ => "MapGenerationFailed",
+            });
+        }
+    }
+
+    /// A game server can only be started along with a database, the probe receives the map in its place.
+    /// Both the generation job and the message carrying the galaxy are the ones the server uses
+    #[actix_rt::test]
+    async fn test_generate_map() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let addr = ProbeActor{ events: events.clone() }.start();
+        let game = Game{
+            id: GameID(Uuid::new_v4()),
+            victory_points: 0,
+            game_speed: GameOptionSpeed::Medium,
+            map_size: GameOptionMapSize::Mini,
+            victory_mode: GameOptionVictoryMode::VictoryPoints,
+            nb_factions: None,
+            config: GameConfig::default(),
+        };
+
+        // Both messages are queued before the generation starts, the second one can not wait for the galaxy
+        addr.do_send(StartMap(game));
+        addr.send(Ping).await.unwrap();
+        for _ in 0..500 {
+            if events.lock().unwrap().len() > 2 {
+                break;
+            }
+            actix_rt::time::delay_for(Duration::from_millis(10)).await;
+        }
+        assert_eq!(vec!["MapGenerating", "ping", "SystemsCreated"], *events.lock().unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn test_run_blocking() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let addr = ProbeActor{ events: events.clone() }.start();
+        let (release, wait) = std::sync::mpsc::channel::<()>();
+
+        run_blocking(addr.clone(), move || {
+            wait.recv().unwrap();
+            JobDone
+        });
+        // The actor answers while the job is still running
+        addr.send(Ping).await.unwrap();
+        assert_eq!(vec!["ping"], *events.lock().unwrap());
+
+        release.send(()).unwrap();
+        for _ in 0..100 {
+            if events.lock().unwrap().len() > 1 {
+                break;
+            }
+            actix_rt::time::delay_for(Duration::from_millis(10)).await;
+        }
+        assert_eq!(vec!["ping", "done"], *events.lock().unwrap());
+    }
+
+    #[test]
+    fn test_task_catch_up() {
+        let interval = Duration::from_millis(50);
+        let mut catch_up = TaskCatchUp::new(interval);
+        let now = Instant::now();
+
+        let delays: Vec<Duration> = (0..10).map(|_| catch_up.get_delay(now)).collect();
+        // Ten overdue tasks are spread over the catch-up window instead of firing in the same tick
+        let distinct_delays: HashSet<Duration> = delays.iter().copied().collect();
+        assert_eq!(10, distinct_delays.len());
+        assert_eq!(Duration::new(0, 0), delays[0]);
+        assert_eq!(interval * 9, delays[9]);
+
+        // Once the backlog is processed, the next overdue task is performed right away
+        assert_eq!(Duration::new(0, 0), catch_up.get_delay(now + interval * 20));
+
+        let mut disabled_catch_up = TaskCatchUp::new(Duration::new(0, 0));
+        assert!((0..10).all(|_| disabled_catch_up.get_delay(now) == Duration::new(0, 0)));
+    }
+
+    #[test]
+    fn test_take_player_tasks() {
+        let (pid, heir, other_pid) = (PlayerID(Uuid::new_v4()), PlayerID(Uuid::new_v4()), PlayerID(Uuid::new_v4()));
+        let get_task = |owner| ScheduledTask{
+            handle: None,
+            end_time: Instant::now(),
+            remaining_time: None,
+            owner,
+            callback: Some(Box::new(|_: &mut GameServer, _: &Context<GameServer>| -> Result<()> { panic!("The task should be cancelled") })),
+        };
+        let mut tasks = HashMap::new();
+        tasks.insert("building".to_string(), get_task(Some(pid)));
+        tasks.insert("conquest".to_string(), get_task(Some(pid)));
+        tasks.insert("fleet.arrival".to_string(), get_task(Some(other_pid)));
+        tasks.insert("round".to_string(), get_task(None));
+
+        // The heir takes over the tasks of the leaving player
+        assert!(take_player_tasks(&mut tasks, pid, Some(heir)).is_empty());
+        assert_eq!(4, tasks.len());
+        assert_eq!(Some(heir), tasks["building"].owner);
+        assert_eq!(Some(heir), tasks["conquest"].owner);
+
+        // Without heir, the tasks are removed from the server, so their timers find nothing to perform
+        let cancelled_tasks = take_player_tasks(&mut tasks, heir, None);
+        assert_eq!(2, cancelled_tasks.len());
+        assert!(cancelled_tasks.iter().all(|task| task.owner == Some(heir)));
+        assert!(!tasks.contains_key("building"));
+        assert!(!tasks.contains_key("conquest"));
+        assert_eq!(Some(other_pid), tasks["fleet.arrival"].owner);
+        assert!(tasks["round"].owner.is_none());
+    }
+
+    #[test]
+    fn test_score_victory_systems_with_game_config() {
+        let gid = GameID(Uuid::new_v4());
+        let config = crate::game::game::game::GameConfig::new(60000.0, 5000.0, 100.0, 200, 25, 50).unwrap();
+        // Three victory systems on the map, two owned by the first faction and one left by a player who quit
+        let threshold = config.get_victory_points(3);
+        let mut factions: HashMap<FactionID, GameFaction> = vec![FactionID(1), FactionID(2)].into_iter()
+            .map(|fid| (fid, GameFaction{ faction: fid, game: gid, victory_points: 0 }))
+            .collect();
+        let owners = vec![Some(FactionID(1)), Some(FactionID(1)), None];
+
+        assert_eq!(150, threshold);
+        let mut nb_intervals = 0;
+        while factions[&FactionID(1)].victory_points < threshold {
+            score_victory_systems(&mut factions, owners.clone().into_iter(), config.victory_points_per_minute);
+            nb_intervals += 1;
+        }
+        assert_eq!(3, nb_intervals);
+        assert_eq!(0, factions[&FactionID(2)].victory_points);
+    }
+
+    #[test]
+    fn test_get_victory_points_per_tick() {
+        assert_eq!(10, get_victory_points_per_tick(10, Duration::from_secs(60), 1));
+        assert_eq!(20, get_victory_points_per_tick(10, Duration::from_secs(120), 3));
+        assert_eq!(1, get_victory_points_per_tick(10, Duration::from_secs(6), 7));
+
+        // A short interval gives the same points per minute, spread over the ticks
+        let total: i32 = (1..=60).map(|tick| get_victory_points_per_tick(10, Duration::from_secs(1), tick)).sum();
+        assert_eq!(10, total);
+        let total: i32 = (1..=40).map(|tick| get_victory_points_per_tick(25, Duration::from_millis(1500), tick)).sum();
+        assert_eq!(25, total);
+    }
+
+    #[test]
+    fn test_get_last_faction_standing() {
+        let factions: HashSet<FactionID> = vec![FactionID(1), FactionID(2)].into_iter().collect();
+        let players = vec![
+            get_player_mock(1, false),
+            get_player_mock(1, true),
+            get_player_mock(2, false),
+        ];
+        assert_eq!(None, get_last_faction_standing(&factions, players.iter()));
+
+        let players = vec![
+            get_player_mock(1, false),
+            get_player_mock(2, true),
+            get_player_mock(2, true),
+        ];
+        assert_eq!(Some(FactionID(1)), get_last_faction_standing(&factions, players.iter()));
+
+        // The other faction forfeited, its players left the game
+        let players = vec![get_player_mock(1, false)];
+        assert_eq!(Some(FactionID(1)), get_last_faction_standing(&factions, players.iter()));
+        assert_eq!(None, get_last_faction_standing(&factions, vec![].iter()));
+
+        let single_faction: HashSet<FactionID> = vec![FactionID(1)].into_iter().collect();
+        assert_eq!(None, get_last_faction_standing(&single_faction, players.iter()));
+    }
+
+    #[test]
+    fn test_get_players_income() {
+        let pid = PlayerID(Uuid::new_v4());
+        let (base_system, mine_system, contested_system) = (get_system_mock(Some(pid)), get_system_mock(Some(pid)), get_system_mock(Some(pid)));
+        let mines: HashSet<SystemID> = vec![mine_system.id, contested_system.id].into_iter().collect();
+        let contested_systems: HashSet<SystemID> = vec![contested_system.id].into_iter().collect();
+
+        let players_income = get_players_income(
+            &[base_system.clone(), mine_system.clone(), contested_system.clone(), get_system_mock(None)],
+            &mines,
+            &contested_systems,
+        );
+
+        assert_eq!(1, players_income.len());
+        let income = &players_income[&pid];
+        assert_eq!(SYSTEM_INCOME + MINE_SYSTEM_INCOME, income.income);
+        assert_eq!(Some(&SYSTEM_INCOME), income.systems.get(&base_system.id));
+        assert_eq!(Some(&MINE_SYSTEM_INCOME), income.systems.get(&mine_system.id));
+        // The operational mine of a system under conquest yields nothing
+        assert_eq!(Some(&0), income.systems.get(&contested_system.id));
+    }
+
+    #[test]
+    fn test_get_contested_systems() {
+        let get_conquest_mock = |is_stopped, is_over| Conquest{
+            id: ConquestID(Uuid::new_v4()),
+            player: PlayerID(Uuid::new_v4()),
+            system: SystemID(Uuid::new_v4()),
+            fleet: None,
+            fleets: None,
+            is_successful: false,
+            is_stopped,
+            is_over,
+            percent: 0.4,
+            started_at: Time::now(),
+            ended_at: Time::now(),
+        };
+        let ongoing_conquest = get_conquest_mock(false, false);
+        let halted_conquest = get_conquest_mock(true, false);
+        let pid = PlayerID(Uuid::new_v4());
+        let halted_system = System{ id: halted_conquest.system, ..get_system_mock(Some(pid)) };
+
+        let contested_systems = get_contested_systems(vec![
+            ongoing_conquest.clone(),
+            halted_conquest,
+            get_conquest_mock(false, true),
+        ]);
+        assert_eq!(vec![&ongoing_conquest.system], contested_systems.iter().collect::<Vec<&SystemID>>());
+
+        // The defender won the battle, so the system produces again
+        let players_income = get_players_income(&[halted_system.clone()], &HashSet::new(), &contested_systems);
+        assert_eq!(Some(&SYSTEM_INCOME), players_income[&pid].systems.get(&halted_system.id));
+    }
+
+    fn get_system_mock(player: Option<PlayerID>) -> System {
+        System{
+            id: SystemID(Uuid::new_v4()),
+            game: GameID(Uuid::new_v4()),
+            player,
+            kind: SystemKind::BaseSystem,
+            coordinates: Coordinates{ x: 0.0, y: 0.0 },
+            unreachable: false,
+        }
+    }
+
+    fn get_player_mock(faction: u8, is_eliminated: bool) -> Player {
+        Player{
+            id: PlayerID(Uuid::new_v4()),
+            username: String::from("Galadruin"),
+            game: None,
+            lobby: None,
+            faction: Some(FactionID(faction)),
+            ready: true,
+            wallet: 0,
+            is_connected: true,
+            home_system: None,
+            is_eliminated,
+        }
+    }
+
+    #[test]
+    fn test_has_majority() {
+        assert!(has_majority(1, 1));
+        assert!(has_majority(2, 3));
+        assert!(!has_majority(1, 2));
+        assert!(!has_majority(2, 4));
+        assert!(has_majority(3, 4));
+    }
+
+    #[test]
+    fn test_expire_pause_votes() {
+        let now = Instant::now();
+        let old_voter = PlayerID(Uuid::new_v4());
+        let voter = PlayerID(Uuid::new_v4());
+        let mut votes = HashMap::new();
+        votes.insert(old_voter, now);
+        votes.insert(voter, now + PAUSE_VOTE_DURATION - Duration::from_secs(1));
+
+        expire_pause_votes(&mut votes, now + PAUSE_VOTE_DURATION - Duration::from_secs(1));
+        assert_eq!(2, votes.len());
+
+        expire_pause_votes(&mut votes, now + PAUSE_VOTE_DURATION);
+        assert_eq!(vec![&voter], votes.keys().collect::<Vec<&PlayerID>>());
+    }
+}
@@ -0,0 +1,197 @@
+use actix_web::{get, web, HttpResponse};
+use serde::Serialize;
+use uuid::Uuid;
+use std::collections::HashMap;
+use crate::{
+    lib::{
+        Result,
+        error::ServerError,
+        pagination::{Paginator, new_paginated_response},
+    },
+    game::{
+        faction::FactionID,
+        fleet::combat::battle::Battle,
+        game::game::GameID,
+        player::PlayerID,
+    },
+    AppState,
+};
+use sqlx::{PgPool, postgres::{PgRow, PgQueryAs}, FromRow, Executor, Error, Postgres};
+use sqlx_core::row::Row;
+
+/// Achievements of a player, accumulated over all the games he completed
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct GlobalStats {
+    pub player: PlayerID,
+    pub username: String,
+    pub wins: u32,
+    pub destroyed_ships: u32,
+    pub conquests: u32,
+}
+
+impl<'a> FromRow<'a, PgRow<'a>> for GlobalStats {
+    fn from_row(row: &PgRow) -> std::result::Result<Self, Error> {
+        Ok(GlobalStats {
+            player: row.try_get("player_id").map(PlayerID)?,
+            username: row.try_get("username")?,
+            wins: row.try_get::<i32, _>("wins")? as u32,
+            destroyed_ships: row.try_get::<i32, _>("destroyed_ships")? as u32,
+            conquests: row.try_get::<i32, _>("conquests")? as u32,
+        })
+    }
+}
+
+impl GlobalStats {
+    fn new(player: PlayerID) -> Self {
+        GlobalStats{
+            player,
+            username: String::new(),
+            wins: 0,
+            destroyed_ships: 0,
+            conquests: 0,
+        }
+    }
+
+    pub async fn find_top(limit: i64, offset: i64, db_pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as("SELECT gs.*, p.username FROM player__global_stats gs
+            INNER JOIN player__players p ON p.id = gs.player_id
+            ORDER BY gs.wins DESC, gs.conquests DESC, gs.destroyed_ships DESC LIMIT $1 OFFSET $2")
+            .bind(limit)
+            .bind(offset)
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub async fn count(db_pool: &PgPool) -> Result<i64> {
+        sqlx::query_as("SELECT COUNT(*) FROM player__global_stats")
+            .fetch_one(db_pool).await
+            .map(|count: (i64,)| count.0)
+            .map_err(ServerError::from)
+    }
+
+    /// Adds the achievements of a game to the existing ones
+    pub async fn accumulate<E>(&self, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("INSERT INTO player__global_stats(player_id, wins, destroyed_ships, conquests) VALUES($1, $2, $3, $4)
+            ON CONFLICT (player_id) DO UPDATE SET
+            wins = player__global_stats.wins + EXCLUDED.wins,
+            destroyed_ships = player__global_stats.destroyed_ships + EXCLUDED.destroyed_ships,
+            conquests = player__global_stats.conquests + EXCLUDED.conquests")
+            .bind(Uuid::from(self.player))
+            .bind(self.wins as i32)
+            .bind(self.destroyed_ships as i32)
+            .bind(self.conquests as i32)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+}
+
+/// Records the achievements of the players of a completed game, before it is removed
+pub async fn record_game_stats(gid: GameID, victorious_faction: FactionID, db_pool: &PgPool) -> Result<()> {
+    let (p, b, c) = futures::join!(
+        sqlx::query_as("SELECT id, faction_id FROM player__players WHERE game_id = $1")
+            .bind(Uuid::from(gid))
+            .fetch_all(db_pool),
+        sqlx::query_as("SELECT b.* FROM fleet__combat__battles b
+            INNER JOIN map__systems s ON s.id = b.system_id
+            WHERE s.game_id = $1")
+            .bind(Uuid::from(gid))
+            .fetch_all(db_pool),
+        sqlx::query_as("SELECT c.player_id, COUNT(*) FROM fleet__combat__conquests c
+            INNER JOIN map__systems s ON s.id = c.system_id
+            WHERE s.game_id = $1 AND c.is_successful = TRUE
+            GROUP BY c.player_id")
+            .bind(Uuid::from(gid))
+            .fetch_all(db_pool),
+    );
+    let players: Vec<(Uuid, Option<i32>)> = p.map_err(ServerError::from)?;
+    let battles: Vec<Battle> = b.map_err(ServerError::from)?;
+    let conquests: Vec<(Uuid, i64)> = c.map_err(ServerError::from)?;
+
+    let stats = get_game_stats(
+        players.into_iter().map(|(pid, fid)| (PlayerID(pid), fid.map(|id| FactionID(id as u8)))).collect(),
+        victorious_faction,
+        &battles,
+        conquests.into_iter().map(|(pid, count)| (PlayerID(pid), count as u32)).collect(),
+    );
+
+    let mut tx = db_pool.begin().await?;
+    for s in stats {
+        s.accumulate(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+fn get_game_stats(
+    players: Vec<(PlayerID, Option<FactionID>)>,
+    victorious_faction: FactionID,
+    battles: &[Battle],
+    conquests: HashMap<PlayerID, u32>
+) -> Vec<GlobalStats> {
+    let mut stats: HashMap<PlayerID, GlobalStats> = players.iter()
+        .map(|(pid, fid)| {
+            let mut s = GlobalStats::new(*pid);
+            if *fid == Some(victorious_faction) {
+                s.wins = 1;
+            }
+            (*pid, s)
+        })
+        .collect();
+
+    for (pid, quantity) in battles.iter()
+        .flat_map(|b| b.rounds.iter())
+        .flat_map(|r| r.squadron_actions.iter())
+        .filter_map(|a| a.get_destroyed_ships()) {
+        if let Some(s) = stats.get_mut(&pid) {
+            s.destroyed_ships += quantity as u32;
+        }
+    }
+    for (pid, count) in conquests {
+        if let Some(s) = stats.get_mut(&pid) {
+            s.conquests += count;
+        }
+    }
+    stats.into_iter().map(|(_, s)| s).collect()
+}
+
+#[get("/")]
+pub async fn get_leaderboard(state: web::Data<AppState>, pagination: web::Query<Paginator>)
+    -> Result<HttpResponse>
+{
+    let (count, stats) = futures::join!(
+        GlobalStats::count(&state.db_pool),
+        GlobalStats::find_top(pagination.limit, (pagination.page - 1) * pagination.limit, &state.db_pool),
+    );
+    Ok(new_paginated_response(
+        pagination.limit,
+        pagination.page,
+        count?,
+        stats?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_game_stats() {
+        let (winner, loser) = (PlayerID(Uuid::new_v4()), PlayerID(Uuid::new_v4()));
+        let mut conquests = HashMap::new();
+        conquests.insert(winner, 3);
+        conquests.insert(PlayerID(Uuid::new_v4()), 2);
+
+        let stats = get_game_stats(
+            vec![(winner, Some(FactionID(1))), (loser, Some(FactionID(2)))],
+            FactionID(1),
+            &[],
+            conquests,
+        );
+        let get_stats = |pid| stats.iter().find(|s| s.player == pid).unwrap();
+
+        assert_eq!(2, stats.len());
+        assert_eq!(1, get_stats(winner).wins);
+        assert_eq!(3, get_stats(winner).conquests);
+        assert_eq!(0, get_stats(loser).wins);
+        assert_eq!(0, get_stats(loser).conquests);
+    }
+}
@@ -1,18 +1,22 @@
 use actix_web::{delete, get, patch, post, web, HttpResponse};
 use actix::prelude::*;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use uuid::Uuid;
 use crate::{
     lib::{
         Result,
         error::{ServerError, InternalError},
-        auth::Claims
+        auth::Claims,
+        log::log,
+        time::Time,
     },
     game::game::{
-        game::create_game,
-        option::{GameOptionMapSize, GameOptionSpeed},
+        game::{create_game, GameID},
+        option::{GameOptionMapSize, GameOptionSpeed, GameOptionVictoryMode},
     },
+    game::faction::{Faction, FactionID, MIN_NB_FACTIONS},
     game::player::{PlayerID, Player},
+    game::system::system::{SystemPreview, generate_systems},
     ws::{ client::ClientSession, protocol},
     AppState,
 };
@@ -21,6 +25,7 @@ use std::collections::{HashMap};
 use sqlx::{PgPool, postgres::{PgRow, PgQueryAs}, FromRow, Executor, Error, Postgres};
 use sqlx_core::row::Row;
 use futures::join;
+use chrono::{DateTime, Duration, Utc};
 
 #[derive(Serialize, Deserialize, Hash, PartialEq, Eq, Clone, Copy, Debug)]
 pub struct LobbyID(pub Uuid);
@@ -38,14 +43,37 @@ pub struct LobbyServer {
 pub struct Lobby {
     pub id: LobbyID,
     pub owner: PlayerID,
+    /// The owner role is lent to another player while its owner is disconnected, and given back when
+    /// the owner reconnects
+    pub disconnected_owner: Option<PlayerID>,
     pub game_speed: GameOptionSpeed,
-    pub map_size: GameOptionMapSize
+    pub map_size: GameOptionMapSize,
+    pub victory_mode: GameOptionVictoryMode,
+    /// All factions take part in the game when their number is not restricted
+    pub nb_factions: Option<u8>,
+    pub created_at: Time,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Only the given options are updated, the other ones are left unchanged
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct LobbyOptionsPatch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub map_size: Option<GameOptionMapSize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub game_speed: Option<GameOptionSpeed>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub victory_mode: Option<GameOptionVictoryMode>,
+    /// A null value lifts the restriction on the number of factions
+    #[serde(default, deserialize_with = "deserialize_some", skip_serializing_if = "Option::is_none")]
+    pub nb_factions: Option<Option<u8>>,
+}
+
+/// Tells an explicit null value apart from a missing field
+fn deserialize_some<'de, T, D>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+    where T: Deserialize<'de>, D: Deserializer<'de>
+{
+    T::deserialize(deserializer).map(Some)
 }
 
 impl<'a> FromRow<'a, PgRow<'a>> for Lobby {
@@ -56,8 +84,12 @@ impl<'a> FromRow<'a, PgRow<'a>> for Lobby {
         Ok(Lobby {
             id: LobbyID(id),
             owner: PlayerID(owner_id),
+            disconnected_owner: row.try_get("disconnected_owner_id").map(PlayerID).ok(),
             game_speed: row.try_get("game_speed")?,
-            map_size: row.try_get("map_size")?
+            map_size: row.try_get("map_size")?,
+            victory_mode: row.try_get("victory_mode")?,
+            nb_factions: row.try_get::<Option<i32>, _>("nb_factions")?.map(|n| n as u8),
+            created_at: row.try_get("created_at")?,
         })
     }
 }
@@ -98,15 +130,101 @@ impl LobbyServer {
 }
 
 impl Lobby {
+    /// Returns the options which actually changed
+    pub fn apply_options(&mut self, patch: &LobbyOptionsPatch) -> LobbyOptionsPatch {
+        let mut changes = LobbyOptionsPatch::default();
+        if let Some(map_size) = patch.map_size.filter(|ms| *ms != self.map_size) {
+            self.map_size = map_size;
+            changes.map_size = Some(map_size);
+        }
+        if let Some(game_speed) = patch.game_speed.filter(|gs| *gs != self.game_speed) {
+            self.game_speed = game_speed;
+            changes.game_speed = Some(game_speed);
+        }
+        if let Some(victory_mode) = patch.victory_mode.filter(|vm| *vm != self.victory_mode) {
+            self.victory_mode = victory_mode;
+            changes.victory_mode = Some(victory_mode);
+        }
+        if let Some(nb_factions) = patch.nb_factions.filter(|nb| *nb != self.nb_factions) {
+            self.nb_factions = nb_factions;
+            changes.nb_factions = Some(nb_factions);
+        }
+        changes
+    }
+
     pub async fn update_owner(&mut self, db_pool: &PgPool) -> Result<()> {
         let players = Player::find_by_lobby(self.id, db_pool).await?;
-        self.owner = players.first().unwrap().id.clone();
+        if let Some(owner) = self.pick_new_owner(&players) {
+            self.owner = owner;
+        }
         let mut tx = db_pool.begin().await?;
         self.update(&mut tx).await?;
         tx.commit().await?;
         Ok(())
     }
 
+    /// The disconnected owner stays in the lobby, and gets its role back when it reconnects.
+    /// Returns whether the owner role was transferred.
+    pub async fn lend_ownership(&mut self, db_pool: &PgPool) -> Result<bool> {
+        let players = Player::find_by_lobby(self.id, db_pool).await?;
+        let new_owner = match self.pick_new_owner(&players) {
+            Some(pid) => pid,
+            None => return Ok(false),
+        };
+        self.lend_ownership_to(new_owner);
+        let mut tx = db_pool.begin().await?;
+        self.update(&mut tx).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Returns whether the reconnected player got its owner role back
+    pub async fn restore_owner(&mut self, pid: PlayerID, db_pool: &PgPool) -> Result<bool> {
+        if !self.give_back_ownership(pid) {
+            return Ok(false);
+        }
+        let mut tx = db_pool.begin().await?;
+        self.update(&mut tx).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Connected players are preferred to take over the owner role
+    fn pick_new_owner(&self, players: &[Player]) -> Option<PlayerID> {
+        let candidates = players.iter().filter(|p| p.id != self.owner);
+
+        candidates.clone().find(|p| p.is_connected)
+            .or_else(|| candidates.clone().next())
+            .map(|p| p.id)
+    }
+
+    /// When the lent owner disconnects in turn, the original owner still gets its role back
+    fn lend_ownership_to(&mut self, new_owner: PlayerID) {
+        if self.disconnected_owner.is_none() {
+            self.disconnected_owner = Some(self.owner);
+        }
+        self.owner = new_owner;
+    }
+
+    fn give_back_ownership(&mut self, pid: PlayerID) -> bool {
+        if self.disconnected_owner != Some(pid) {
+            return false;
+        }
+        self.owner = pid;
+        self.disconnected_owner = None;
+        true
+    }
+
+    /// Lobbies become abandoned when their players are gone without the lobby being removed
+    pub fn is_expired(&self, ttl: Duration, now: DateTime<Utc>) -> bool {
+        DateTime::<Utc>::from(self.created_at) + ttl < now
+    }
+
+    pub async fn find_without_players(db_pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as("SELECT * FROM lobby__lobbies l WHERE NOT EXISTS (SELECT 1 FROM player__players p WHERE p.lobby_id = l.id)")
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
     pub async fn find_all(db_pool: &PgPool) -> Result<Vec<Self>> {
         sqlx::query_as("SELECT * FROM lobby__lobbies")
             .fetch_all(db_pool).await.map_err(ServerError::from)
@@ -120,36 +238,64 @@ impl Lobby {
 
     pub async fn insert<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres> {
-        sqlx::query("INSERT INTO lobby__lobbies(id, owner_id, game_speed, map_size) VALUES($1, $2, $3, $4)")
+        sqlx::query("INSERT INTO lobby__lobbies(id, owner_id, game_speed, map_size, victory_mode, nb_factions, created_at) VALUES($1, $2, $3, $4, $5, $6, $7)")
             .bind(Uuid::from(self.id))
             .bind(Uuid::from(self.owner))
             .bind(self.game_speed)
             .bind(self.map_size)
+            .bind(self.victory_mode)
+            .bind(self.nb_factions.map(i32::from))
+            .bind(self.created_at)
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
     pub async fn update<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres> {
-        sqlx::query("UPDATE lobby__lobbies SET owner_id = $2, game_speed = $3, map_size = $4 WHERE id = $1")
+        sqlx::query("UPDATE lobby__lobbies SET owner_id = $2, game_speed = $3, map_size = $4, disconnected_owner_id = $5, victory_mode = $6, nb_factions = $7 WHERE id = $1")
             .bind(Uuid::from(self.id))
             .bind(Uuid::from(self.owner))
             .bind(self.game_speed)
             .bind(self.map_size)
+            .bind(self.disconnected_owner.map(Uuid::from))
+            .bind(self.victory_mode)
+            .bind(self.nb_factions.map(i32::from))
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
+    /// Inserts the lobby and puts its owner in it, within the same transaction
+    pub async fn create(&self, owner: &mut Player, db_pool: &PgPool) -> Result<()> {
+        let mut tx = db_pool.begin().await?;
+        self.insert(&mut tx).await?;
+        owner.lobby = Some(self.id);
+        owner.update(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
     pub async fn remove<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres>{
         sqlx::query("DELETE FROM lobby__lobbies WHERE id = $1")
             .bind(Uuid::from(self.id))
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
+
+    /// Returns zero when a player joined the lobby meanwhile, it is then kept
+    pub async fn remove_if_empty<E>(&self, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres>{
+        sqlx::query("DELETE FROM lobby__lobbies l WHERE l.id = $1 AND NOT EXISTS (SELECT 1 FROM player__players p WHERE p.lobby_id = l.id)")
+            .bind(Uuid::from(self.id))
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
 }
 
 impl Actor for LobbyServer {
     type Context = Context<Self>;
 }
 
+#[derive(actix::Message, Clone)]
+#[rtype(result="()")]
+pub struct LobbyCloseMessage;
+
 #[derive(actix::Message, Clone)]
 #[rtype(result="()")]
 pub struct LobbyAddClientMessage(pub PlayerID, pub actix::Addr<ClientSession>);
@@ -184,6 +330,14 @@ impl Handler<LobbyRemoveClientMessage> for LobbyServer {
     }
 }
 
+impl Handler<LobbyCloseMessage> for LobbyServer {
+    type Result = ();
+
+    fn handle(&mut self, _msg: LobbyCloseMessage, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
+    }
+}
+
 impl Handler<LobbyGetClientsMessage> for LobbyServer {
     type Result = Arc<HashMap<PlayerID, actix::Addr<ClientSession>>>;
 
@@ -246,7 +400,9 @@ pub async fn get_lobby(state: web::Data<AppState>, info: web::Path<(LobbyID,)>)
         owner: Player,
         players: Vec<Player>,
         game_speed: GameOptionSpeed,
-        map_size: GameOptionMapSize
+        map_size: GameOptionMapSize,
+        victory_mode: GameOptionVictoryMode,
+        nb_factions: Option<u8>,
     }
 
     Ok(HttpResponse::Ok().json(LobbyData{
@@ -254,7 +410,9 @@ pub async fn get_lobby(state: web::Data<AppState>, info: web::Path<(LobbyID,)>)
         owner: Player::find(lobby.owner, &state.db_pool).await?,
         players: Player::find_by_lobby(lobby.id, &state.db_pool).await?,
         game_speed: lobby.game_speed,
-        map_size: lobby.map_size
+        map_size: lobby.map_size,
+        victory_mode: lobby.victory_mode,
+        nb_factions: lobby.nb_factions,
     }))
 }
 
@@ -262,34 +420,28 @@ pub async fn get_lobby(state: web::Data<AppState>, info: web::Path<(LobbyID,)>)
 pub async fn create_lobby(state: web::Data<AppState>, claims: Claims) -> Result<HttpResponse> {
     // Get the requesting player identity
     let mut player = Player::find(claims.pid, &state.db_pool).await?;
-    let mut lobby_servers = state.lobbies_mut();
 
-    // If already in lobby, then error
-    if player.lobby.is_some() {
-        return Err(InternalError::AlreadyInLobby.into());
-    }
+    check_lobby_access(&player)?;
 
     // Else, create a lobby
     let new_lobby = Lobby {
         id: LobbyID(Uuid::new_v4()),
         owner: player.id.clone(),
+        disconnected_owner: None,
         game_speed: GameOptionSpeed::Medium,
         map_size: GameOptionMapSize::Medium,
+        victory_mode: GameOptionVictoryMode::default(),
+        nb_factions: None,
+        created_at: Time::now(),
     };
-    let lobby_server = LobbyServer{
-        id: new_lobby.id.clone(),
-        clients: RwLock::new(HashMap::new()),
-    }.start();
     let client = state.retrieve_client(&claims.pid)?;
-    lobby_server.do_send(LobbyAddClientMessage(player.id.clone(), client));
-    lobby_servers.insert(new_lobby.id.clone(), lobby_server);
-    // Insert the lobby into the list
-    let mut tx = state.db_pool.begin().await?;
-    new_lobby.insert(&mut tx).await?;
-    // Put the player in the lobby
-    player.lobby = Some(new_lobby.id.clone());
-    player.update(&mut tx).await?;
-    tx.commit().await?;
+    let lobby_server = start_lobby_server(new_lobby.id, new_lobby.create(&mut player, &state.db_pool), &state.lobbies).await
+        .map_err(|error| {
+            // The player stays in the global clients list when the lobby could not be created
+            state.add_client(&claims.pid, client.clone());
+            error
+        })?;
+    lobby_server.do_send(LobbyAddClientMessage(claims.pid, client));
     // Notify players for lobby creation
     state.ws_broadcast(&protocol::Message::new(
         protocol::Action::LobbyCreated,
@@ -313,8 +465,16 @@ pub async fn update_lobby_options(
     if lobby.owner != claims.pid.clone() {
         return Err(InternalError::AccessDenied.into());
     }
-    lobby.game_speed = data.game_speed.clone().map_or(GameOptionSpeed::Medium, |gs| gs);
-    lobby.map_size = data.map_size.clone().map_or(GameOptionMapSize::Medium, |ms| ms);
+    let changes = lobby.apply_options(&data);
+    if changes == LobbyOptionsPatch::default() {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+
+    let (factions, nb_players) = join!(
+        Faction::find_all(&state.db_pool),
+        Player::count_by_lobby(lobby.id, &state.db_pool),
+    );
+    check_nb_factions(lobby.nb_factions, factions?.len(), nb_players? as usize)?;
 
     let mut tx = state.db_pool.begin().await?;
     lobby.update(&mut tx).await?;
@@ -324,12 +484,42 @@ pub async fn update_lobby_options(
     let lobby_server = lobbies.get(&lobby.id).ok_or(InternalError::LobbyUnknown)?;
     lobby_server.do_send(protocol::Message::new(
         protocol::Action::LobbyOptionsUpdated,
-        data.clone(),
+        changes,
         Some(claims.pid),
     ));
     Ok(HttpResponse::NoContent().finish())
 }
 
+#[post("/{id}/preview-map/")]
+pub async fn preview_map(
+    state: web::Data<AppState>,
+    info: web::Path<(LobbyID,)>,
+    claims: Claims
+) -> Result<HttpResponse>
+{
+    #[derive(Serialize)]
+    struct MapPreview {
+        map_size: GameOptionMapSize,
+        systems: Vec<SystemPreview>,
+    }
+    let lobby = Lobby::find(info.0, &state.db_pool).await?;
+
+    if lobby.owner != claims.pid {
+        return Err(InternalError::AccessDenied.into());
+    }
+    // The biggest galaxies are too expensive to be generated on demand
+    if lobby.map_size == GameOptionMapSize::VeryLarge {
+        return Err(InternalError::Conflict.into());
+    }
+    // The galaxy shape is drawn by galaxy-rs, which takes no seed: each preview gives a new galaxy
+    let (systems, _) = generate_systems(GameID(Uuid::nil()), lobby.map_size, lobby.victory_mode).await?;
+
+    Ok(HttpResponse::Ok().json(MapPreview{
+        map_size: lobby.map_size,
+        systems: systems.into_iter().map(|s| SystemPreview{ kind: s.kind, coordinates: s.coordinates }).collect(),
+    }))
+}
+
 #[post("/{id}/launch/")]
 pub async fn launch_game(state: web::Data<AppState>, claims:Claims, info: web::Path<(LobbyID,)>)
     -> Result<HttpResponse>
@@ -341,10 +531,20 @@ pub async fn launch_game(state: web::Data<AppState>, claims:Claims, info: web::P
     if lobby.owner != claims.pid.clone() {
         return Err(InternalError::AccessDenied.into());
     }
+    if let Some(nb_factions) = lobby.nb_factions {
+        let (factions, players) = join!(
+            Faction::find_all(&state.db_pool),
+            Player::find_by_lobby(lobby.id, &state.db_pool),
+        );
+        let (factions, players) = (factions?, players?);
+        check_nb_factions(Some(nb_factions), factions.len(), players.len())?;
+        let factions: Vec<FactionID> = factions.into_iter().map(|f| f.id).take(nb_factions as usize).collect();
+        check_teams(&factions, &players)?;
+    }
     let clients = Arc::try_unwrap({
         let lobbies = state.lobbies();
         let lobby_server = lobbies.get(&lobby.id).ok_or(InternalError::LobbyUnknown)?;
-        lobby_server.send(LobbyGetClientsMessage{})
+        lobby_server.send(LobbyGetClientsMessage())
     }.await?).ok().unwrap();
     let (game_id, game) = create_game(&lobby, state.clone(), clients).await?;
     games.insert(game_id, game);
@@ -387,6 +587,9 @@ pub async fn leave_lobby(state:web::Data<AppState>, claims:Claims, info:web::Pat
             lobby.owner.clone(),
             None,
         ));
+    } else if lobby.disconnected_owner == Some(player.id) {
+        lobby.disconnected_owner = None;
+        lobby.update(&mut &state.db_pool).await?;
     }
     Ok(HttpResponse::NoContent().finish())
 }
@@ -397,9 +600,7 @@ pub async fn join_lobby(info: web::Path<(LobbyID,)>, state: web::Data<AppState>,
 {
     let lobby = Lobby::find(info.0, &state.db_pool).await?;
     let mut player = Player::find(claims.pid, &state.db_pool).await?;
-    if player.lobby.is_some() {
-        return Err(InternalError::AlreadyInLobby.into());
-    }
+    check_lobby_access(&player)?;
     player.lobby = Some(lobby.id);
     let mut tx = state.db_pool.begin().await?;
     player.update(&mut tx).await?;
@@ -420,3 +621,284 @@ pub async fn join_lobby(info: web::Path<(LobbyID,)>, state: web::Data<AppState>,
 
     Ok(HttpResponse::NoContent().finish())
 }
+
+/// Removes the lobbies left without any player nor client once their TTL is over
+pub async fn remove_abandoned_lobbies(state: &web::Data<AppState>, ttl: Duration) -> Result<()> {
+    let now = Utc::now();
+
+    for lobby in Lobby::find_without_players(&state.db_pool).await?.into_iter().filter(|l| l.is_expired(ttl, now)) {
+        let lobby_server = state.lobbies().get(&lobby.id).cloned();
+        if let Some(server) = lobby_server.as_ref() {
+            if !server.send(LobbyGetClientsMessage{}).await?.is_empty() {
+                continue;
+            }
+        }
+        if lobby.remove_if_empty(&mut &state.db_pool).await? == 0 {
+            continue;
+        }
+        if let Some(server) = state.lobbies_mut().remove(&lobby.id) {
+            server.do_send(LobbyCloseMessage);
+        }
+
+        log(
+            gelf::Level::Informational,
+            "Lobby closed",
+            "An abandoned lobby has been removed",
+            vec![
+                ("lobby_id", lobby.id.0.to_string()),
+            ],
+            &state.logger
+        );
+        state.ws_broadcast(&protocol::Message::new(
+            protocol::Action::LobbyClosed,
+            lobby,
+            None,
+        ));
+    }
+    Ok(())
+}
+
+/// Number of sweeps during a TTL. An abandoned lobby is removed at most a sweep interval after its expiry
+const LOBBY_SWEEPS_PER_TTL: u32 = 10;
+
+fn get_sweep_interval(ttl: Duration) -> std::time::Duration {
+    (ttl.to_std().expect("Invalid lobby TTL") / LOBBY_SWEEPS_PER_TTL).max(std::time::Duration::from_secs(1))
+}
+
+/// Runs the abandoned lobbies removal forever, several times per TTL
+pub async fn sweep_abandoned_lobbies(state: web::Data<AppState>, ttl: Duration) {
+    let mut interval = actix_rt::time::interval(get_sweep_interval(ttl));
+    loop {
+        interval.tick().await;
+        if let Err(error) = remove_abandoned_lobbies(&state, ttl).await {
+            log(
+                gelf::Level::Error,
+                "Lobby sweep failed",
+                &format!("{:?}", error),
+                vec![],
+                &state.logger
+            );
+        }
+    }
+}
+
+/// The lobby server is only started and registered once the lobby is persisted, so that a failed commit
+/// leaves no ghost lobby behind
+async fn start_lobby_server(
+    lid: LobbyID,
+    persist: impl std::future::Future<Output = Result<()>>,
+    lobby_servers: &RwLock<HashMap<LobbyID, actix::Addr<LobbyServer>>>,
+) -> Result<actix::Addr<LobbyServer>> {
+    persist.await?;
+    let lobby_server = LobbyServer{
+        id: lid,
+        clients: RwLock::new(HashMap::new()),
+    }.start();
+    lobby_servers.write().expect("Poisoned lock on lobbies").insert(lid, lobby_server.clone());
+    Ok(lobby_server)
+}
+
+/// A player can only be in one lobby or game at once
+fn check_lobby_access(player: &Player) -> Result<()> {
+    if player.lobby.is_some() {
+        return Err(InternalError::AlreadyInLobby.into());
+    }
+    if player.game.is_some() {
+        return Err(InternalError::Conflict.into());
+    }
+    Ok(())
+}
+
+/// The number of factions must be available and leave each faction at least one player
+fn check_nb_factions(nb_factions: Option<u8>, nb_available_factions: usize, nb_players: usize) -> Result<()> {
+    if let Some(nb) = nb_factions.map(usize::from) {
+        if nb < MIN_NB_FACTIONS || nb > nb_available_factions || nb > nb_players {
+            return Err(InternalError::Conflict.into());
+        }
+    }
+    Ok(())
+}
+
+/// Every player must have picked one of the game factions, and each of them must have a player
+fn check_teams(factions: &[FactionID], players: &[Player]) -> Result<()> {
+    let is_valid_player = |p: &Player| p.faction.map_or(false, |f| factions.contains(&f));
+    let is_filled_faction = |f: &FactionID| players.iter().any(|p| p.faction == Some(*f));
+    if !players.iter().all(is_valid_player) || !factions.iter().all(is_filled_faction) {
+        return Err(InternalError::Conflict.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owner_disconnect_and_reconnect() {
+        let owner = get_player_mock(false);
+        let disconnected_player = get_player_mock(false);
+        let connected_player = get_player_mock(true);
+        let mut lobby = get_lobby_mock(owner.id);
+
+        let new_owner = lobby.pick_new_owner(&[owner.clone(), disconnected_player.clone(), connected_player.clone()]);
+        assert_eq!(Some(connected_player.id), new_owner);
+
+        lobby.lend_ownership_to(new_owner.unwrap());
+        assert_eq!(Some(owner.id), lobby.disconnected_owner);
+
+        // Only the disconnected owner gets its role back
+        assert!(!lobby.give_back_ownership(disconnected_player.id));
+        assert_eq!(connected_player.id, lobby.owner);
+
+        assert!(lobby.give_back_ownership(owner.id));
+        assert_eq!(owner.id, lobby.owner);
+        assert_eq!(None, lobby.disconnected_owner);
+    }
+
+    #[test]
+    fn test_lent_owner_disconnect() {
+        let owner = get_player_mock(false);
+        let lent_owner = get_player_mock(false);
+        let last_player = get_player_mock(true);
+        let mut lobby = get_lobby_mock(owner.id);
+
+        lobby.lend_ownership_to(lent_owner.id);
+        lobby.lend_ownership_to(last_player.id);
+        assert_eq!(last_player.id, lobby.owner);
+        assert_eq!(Some(owner.id), lobby.disconnected_owner);
+
+        assert!(!lobby.give_back_ownership(lent_owner.id));
+        assert!(lobby.give_back_ownership(owner.id));
+        assert_eq!(owner.id, lobby.owner);
+    }
+
+    #[test]
+    fn test_pick_new_owner_without_other_player() {
+        let owner = get_player_mock(false);
+        let lobby = get_lobby_mock(owner.id);
+
+        assert_eq!(None, lobby.pick_new_owner(&[owner]));
+    }
+
+    #[test]
+    fn test_is_expired() {
+        let now = Utc::now();
+        let mut lobby = get_lobby_mock(PlayerID(Uuid::new_v4()));
+        lobby.created_at = Time::from(now - Duration::minutes(90));
+
+        assert!(lobby.is_expired(Duration::hours(1), now));
+        assert!(!lobby.is_expired(Duration::hours(2), now));
+    }
+
+    #[test]
+    fn test_get_sweep_interval() {
+        assert_eq!(std::time::Duration::from_secs(360), get_sweep_interval(Duration::hours(1)));
+        assert_eq!(std::time::Duration::from_secs(1), get_sweep_interval(Duration::seconds(5)));
+    }
+
+    #[test]
+    fn test_check_lobby_access() {
+        let player = get_player_mock(true);
+        assert!(check_lobby_access(&player).is_ok());
+
+        let lobby_player = Player{ lobby: Some(LobbyID(Uuid::new_v4())), ..get_player_mock(true) };
+        assert!(check_lobby_access(&lobby_player).is_err());
+
+        let game_player = Player{ game: Some(GameID(Uuid::new_v4())), ..get_player_mock(true) };
+        assert!(check_lobby_access(&game_player).is_err());
+    }
+
+    #[test]
+    fn test_check_nb_factions() {
+        assert!(check_nb_factions(None, 3, 1).is_ok());
+        assert!(check_nb_factions(Some(2), 3, 2).is_ok());
+        assert!(check_nb_factions(Some(3), 3, 5).is_ok());
+        assert!(check_nb_factions(Some(1), 3, 5).is_err());
+        assert!(check_nb_factions(Some(4), 3, 5).is_err());
+        assert!(check_nb_factions(Some(3), 3, 2).is_err());
+    }
+
+    #[test]
+    fn test_check_teams() {
+        let factions = [FactionID(1), FactionID(2)];
+        let player = |faction| Player{ faction, ..get_player_mock(true) };
+
+        assert!(check_teams(&factions, &[player(Some(FactionID(1))), player(Some(FactionID(2))), player(Some(FactionID(2)))]).is_ok());
+        // A faction without any player
+        assert!(check_teams(&factions, &[player(Some(FactionID(1))), player(Some(FactionID(1)))]).is_err());
+        // A player in a faction excluded from the game
+        assert!(check_teams(&factions, &[player(Some(FactionID(1))), player(Some(FactionID(2))), player(Some(FactionID(3)))]).is_err());
+        // A player without faction
+        assert!(check_teams(&factions, &[player(Some(FactionID(1))), player(Some(FactionID(2))), player(None)]).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_start_lobby_server_after_failed_commit() {
+        let lobby_servers = RwLock::new(HashMap::new());
+        let lid = LobbyID(Uuid::new_v4());
+
+        let failed_commit = async { Err::<(), _>(ServerError::from(InternalError::Conflict)) };
+        assert!(start_lobby_server(lid, failed_commit, &lobby_servers).await.is_err());
+        // No ghost lobby is left for the players to join
+        assert!(lobby_servers.read().unwrap().is_empty());
+
+        let lobby_server = start_lobby_server(lid, async { Ok(()) }, &lobby_servers).await.unwrap();
+        assert!(lobby_servers.read().unwrap().contains_key(&lid));
+        // The registered lobby is served by a running actor
+        assert!(lobby_server.send(LobbyGetClientsMessage()).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_apply_options() {
+        let mut lobby = get_lobby_mock(PlayerID(Uuid::new_v4()));
+        lobby.map_size = GameOptionMapSize::Large;
+        lobby.nb_factions = Some(2);
+
+        let patch: LobbyOptionsPatch = serde_json::from_str(r#"{"game_speed": "fast"}"#).unwrap();
+        let changes = lobby.apply_options(&patch);
+
+        assert_eq!(GameOptionSpeed::Fast, lobby.game_speed);
+        assert_eq!(GameOptionMapSize::Large, lobby.map_size);
+        assert_eq!(Some(2), lobby.nb_factions);
+        assert_eq!(patch, changes);
+        assert_eq!(r#"{"game_speed":"fast"}"#, serde_json::to_string(&changes).unwrap());
+
+        // Options set to their current value are not reported as changed
+        let patch: LobbyOptionsPatch = serde_json::from_str(r#"{"game_speed": "fast", "nb_factions": null}"#).unwrap();
+        let changes = lobby.apply_options(&patch);
+
+        assert_eq!(None, lobby.nb_factions);
+        assert_eq!(LobbyOptionsPatch{ nb_factions: Some(None), ..LobbyOptionsPatch::default() }, changes);
+
+        assert!(serde_json::from_str::<LobbyOptionsPatch>(r#"{"game_speed": "warp"}"#).is_err());
+        assert!(serde_json::from_str::<LobbyOptionsPatch>(r#"{"speed": "fast"}"#).is_err());
+    }
+
+    fn get_lobby_mock(owner: PlayerID) -> Lobby {
+        Lobby{
+            id: LobbyID(Uuid::new_v4()),
+            owner,
+            disconnected_owner: None,
+            game_speed: GameOptionSpeed::Medium,
+            map_size: GameOptionMapSize::Medium,
+            victory_mode: GameOptionVictoryMode::default(),
+            nb_factions: None,
+            created_at: Time::now(),
+        }
+    }
+
+    fn get_player_mock(is_connected: bool) -> Player {
+        Player{
+            id: PlayerID(Uuid::new_v4()),
+            username: String::from("Galadruin"),
+            game: None,
+            lobby: None,
+            faction: None,
+            ready: false,
+            wallet: 0,
+            is_connected,
+            home_system: None,
+            is_eliminated: false,
+        }
+    }
+}
@@ -1,8 +1,11 @@
 pub mod lobby;
 pub mod player;
+#[cfg(feature = "leaderboard")]
+pub mod leaderboard;
 pub mod faction;
 pub mod game;
 pub mod fleet;
 pub mod ship;
 pub mod system;
+pub mod technology;
 pub mod communication;
\ No newline at end of file
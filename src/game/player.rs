@@ -1,20 +1,30 @@
-use actix_web::{web, get, patch, post, HttpResponse};
+use actix_web::{web, get, patch, post, HttpRequest, HttpResponse};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use sqlx::{PgPool, postgres::{PgRow, PgQueryAs}, Executor, FromRow, Error, Postgres};
+use sqlx::{PgPool, PgConnection, pool::PoolConnection, postgres::{PgRow, PgQueryAs}, Executor, FromRow, Error, Postgres, Transaction};
 use sqlx_core::row::Row;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::RwLock;
 use crate::{
     AppState,
     game::game::{
-        game::{GameID, GAME_START_WALLET},
-        server::GameNotifyPlayerMessage,
+        game::GameID,
+        server::{GameServer, GameMoneyTransferMessage, GameMoneyTransferFailureMessage, GameNotifyPlayerMessage, GamePlayerTasksMessage},
     },
     game::lobby::{LobbyID, Lobby},
-    game::faction::FactionID,
-    game::system::system::SystemID,
+    game::communication::notification::{Notification, NotificationKind},
+    game::faction::{FactionID, GameFaction},
+    game::fleet::{
+        combat::{battle::Battle, conquest::Conquest},
+        fleet::{Fleet, FleetID},
+        squadron::FleetSquadron,
+    },
+    game::ship::queue::ShipQueue,
+    game::system::{building::Building, system::{System, SystemID}},
     lib::{
         Result,
-        log::Loggable,
+        log::{log, Loggable},
         error::{InternalError, ServerError},
         auth
     },
@@ -31,11 +41,69 @@ pub struct Player {
     pub ready: bool,
     pub wallet: usize,
     pub is_connected: bool,
+    pub home_system: Option<SystemID>,
+    /// Set once the player owns neither a system nor a fleet anymore
+    pub is_eliminated: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct PlayerID(pub Uuid);
 
+/// Short-lived cache of the players of a game, used by the game server to avoid querying the same
+/// players several times during a single tick.
+///
+/// It must be cleared at the beginning of each tick, and every player persisted during the tick
+/// must be stored back so the cache never holds outdated data.
+#[derive(Default)]
+pub struct PlayerCache {
+    players: RwLock<HashMap<PlayerID, Player>>,
+    is_complete: RwLock<bool>,
+    /// Recipients of the faction broadcasts, resolved once for a burst of messages
+    faction_members: RwLock<HashMap<FactionID, Vec<PlayerID>>>,
+}
+
+/// Amount of money each player sent to its faction-mates since the last income tick.
+/// Without cap, players can transfer their whole wallet at once.
+#[derive(Default)]
+pub struct MoneyTransferLimit {
+    cap: Option<usize>,
+    transferred: HashMap<PlayerID, usize>,
+}
+
+/// Data of a player shown to the other players of its game.
+/// The wallet is only disclosed to the player itself and its faction-mates.
+#[derive(Serialize)]
+pub struct PlayerProfile {
+    pub id: PlayerID,
+    pub username: String,
+    pub faction: Option<FactionID>,
+    pub is_connected: bool,
+    pub is_eliminated: bool,
+    pub nb_systems: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wallet: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct PlayerAssetsTransfer {
+    pub player: PlayerID,
+    pub heir: Option<PlayerID>,
+    pub systems: Vec<SystemID>,
+    pub fleets: Vec<FleetID>,
+}
+
+#[derive(Deserialize)]
+pub struct LoginOptions{
+    /// Players asking for it get a recovery token along with their JWT
+    #[serde(default)]
+    pub recoverable: bool,
+}
+
+#[derive(Deserialize)]
+pub struct PlayerRecoveryRequest{
+    pub recovery_token: String,
+}
+
 #[derive(Deserialize)]
 pub struct PlayerUpdateData{
     pub username: String,
@@ -43,6 +111,11 @@ pub struct PlayerUpdateData{
     pub is_ready: bool,
 }
 
+#[derive(Deserialize)]
+pub struct PlayerReadyData{
+    pub is_ready: bool,
+}
+
 #[derive(Deserialize)]
 pub struct PlayerMoneyTransferRequest{
     pub amount: usize
@@ -69,6 +142,8 @@ impl<'a> FromRow<'a, PgRow<'a>> for Player {
             wallet: row.try_get("wallet").map(|w: i32| w as usize)?,
             ready: row.try_get("is_ready")?,
             is_connected: row.try_get("is_connected")?,
+            home_system: row.try_get("home_system_id").map(SystemID).ok(),
+            is_eliminated: row.try_get("is_eliminated")?,
         })
     }
 }
@@ -88,6 +163,8 @@ impl Player {
         self.ready = false;
         self.lobby = None;
         self.game = None;
+        self.home_system = None;
+        self.is_eliminated = false;
         let mut tx = db_pool.begin().await?;
         self.update(&mut tx).await?;
         tx.commit().await?;
@@ -100,6 +177,28 @@ impl Player {
             .fetch_one(db_pool).await.map_err(ServerError::if_row_not_found(InternalError::PlayerUnknown))
     }
 
+    /// Locks the player row until the end of the transaction, returning its current state
+    pub async fn lock<E>(pid: PlayerID, exec: &mut E) -> Result<Self>
+        where E: Executor<Database = Postgres> {
+        sqlx::query_as("SELECT * FROM player__players WHERE id = $1 FOR UPDATE")
+            .bind(Uuid::from(pid))
+            .fetch_one(&mut *exec).await.map_err(ServerError::if_row_not_found(InternalError::PlayerUnknown))
+    }
+
+    pub async fn find_by_recovery_token(token: &str, db_pool: &PgPool) -> Result<Self> {
+        sqlx::query_as("SELECT * FROM player__players WHERE recovery_token_hash = $1")
+            .bind(auth::hash_recovery_token(token))
+            .fetch_one(db_pool).await.map_err(ServerError::if_row_not_found(InternalError::AccessDenied))
+    }
+
+    pub async fn update_recovery_token<E>(&self, token: &str, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE player__players SET recovery_token_hash = $2 WHERE id = $1")
+            .bind(Uuid::from(self.id))
+            .bind(auth::hash_recovery_token(token))
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
     pub async fn find_system_owner(sid: SystemID, db_pool: &PgPool) -> Result<Self> {
         sqlx::query_as("SELECT p.* FROM map__systems s INNER JOIN player__players p ON p.id = s.player_id WHERE s.id = $1")
             .bind(Uuid::from(sid))
@@ -174,6 +273,36 @@ impl Player {
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
+    /// The wallet is debited in a single statement, so concurrent spendings can not overdraw it
+    pub async fn withdraw<E>(pid: PlayerID, amount: usize, exec: &mut E) -> Result<()>
+        where E: Executor<Database = Postgres> {
+        let nb_rows = sqlx::query("UPDATE player__players SET wallet = wallet - $2 WHERE id = $1 AND wallet >= $2")
+            .bind(Uuid::from(pid))
+            .bind(amount as i32)
+            .execute(&mut *exec).await.map_err(ServerError::from)?;
+        if nb_rows == 0 {
+            return Err(InternalError::NotEnoughMoney.into());
+        }
+        Ok(())
+    }
+
+    pub async fn deposit<E>(pid: PlayerID, amount: usize, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE player__players SET wallet = wallet + $2 WHERE id = $1")
+            .bind(Uuid::from(pid))
+            .bind(amount as i32)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
+    /// Only the flag is written, the wallet or the faction may be changed meanwhile
+    pub async fn update_connection<E>(pid: PlayerID, is_connected: bool, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE player__players SET is_connected = $2 WHERE id = $1")
+            .bind(Uuid::from(pid))
+            .bind(is_connected)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
     pub async fn update<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres> {
         sqlx::query("UPDATE player__players SET username = $1,
@@ -182,8 +311,10 @@ impl Player {
             faction_id = $4,
             wallet = $5,
             is_ready = $6,
-            is_connected = $7
-            WHERE id = $8")
+            is_connected = $7,
+            home_system_id = $8,
+            is_eliminated = $9
+            WHERE id = $10")
             .bind(self.username.clone())
             .bind(self.game.map(Uuid::from))
             .bind(self.lobby.map(Uuid::from))
@@ -191,25 +322,314 @@ impl Player {
             .bind(self.wallet as i32)
             .bind(self.ready)
             .bind(self.is_connected)
+            .bind(self.home_system.map(Uuid::from))
+            .bind(self.is_eliminated)
             .bind(Uuid::from(self.id))
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 }
 
-pub async fn init_player_wallets(players: &mut Vec<Player>, db_pool: &PgPool) -> Result<()> {
+impl PlayerProfile {
+    pub fn new(player: Player, viewer: &Player, nb_systems: u32) -> Self {
+        PlayerProfile{
+            wallet: if can_see_private_data(viewer, &player) { Some(player.wallet) } else { None },
+            id: player.id,
+            username: player.username,
+            faction: player.faction,
+            is_connected: player.is_connected,
+            is_eliminated: player.is_eliminated,
+            nb_systems,
+        }
+    }
+}
+
+impl MoneyTransferLimit {
+    pub fn new(cap: Option<usize>) -> Self {
+        MoneyTransferLimit{ cap, transferred: HashMap::new() }
+    }
+
+    /// Transfers exceeding the sender's remaining allowance are rejected without being counted
+    pub fn register(&mut self, pid: PlayerID, amount: usize) -> Result<()> {
+        let transferred = self.transferred.get(&pid).copied().unwrap_or(0);
+        if let Some(cap) = self.cap {
+            if transferred.saturating_add(amount) > cap {
+                return Err(InternalError::RateLimited.into());
+            }
+        }
+        self.transferred.insert(pid, transferred + amount);
+        Ok(())
+    }
+
+    /// Gives back the allowance of a transfer which failed after being registered
+    pub fn release(&mut self, pid: PlayerID, amount: usize) {
+        if let Some(transferred) = self.transferred.get_mut(&pid) {
+            *transferred = transferred.saturating_sub(amount);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.transferred.clear();
+    }
+}
+
+impl PlayerCache {
+    pub fn clear(&self) {
+        self.players.write().expect("Poisoned lock on player cache").clear();
+        self.faction_members.write().expect("Poisoned lock on player cache").clear();
+        *self.is_complete.write().expect("Poisoned lock on player cache") = false;
+    }
+
+    pub fn store(&self, player: Player) {
+        // Only the factions whose membership no longer matches the stored player are dropped
+        self.faction_members.write().expect("Poisoned lock on player cache").retain(|fid, ids| {
+            ids.contains(&player.id) == (player.faction == Some(*fid))
+        });
+        self.players.write().expect("Poisoned lock on player cache").insert(player.id, player);
+    }
+
+    pub fn invalidate(&self, pid: &PlayerID) {
+        self.players.write().expect("Poisoned lock on player cache").remove(pid);
+        // The new faction of the player is unknown, so any membership may be outdated
+        self.faction_members.write().expect("Poisoned lock on player cache").clear();
+        *self.is_complete.write().expect("Poisoned lock on player cache") = false;
+    }
+
+    pub fn get(&self, pid: &PlayerID) -> Option<Player> {
+        self.players.read().expect("Poisoned lock on player cache").get(pid).cloned()
+    }
+
+    fn all(&self) -> Option<Vec<Player>> {
+        if !*self.is_complete.read().expect("Poisoned lock on player cache") {
+            return None;
+        }
+        Some(self.players.read().expect("Poisoned lock on player cache").values().cloned().collect())
+    }
+
+    pub async fn find_by_game(&self, gid: GameID, db_pool: &PgPool) -> Result<Vec<Player>> {
+        self.load_game(Player::find_by_game(gid, db_pool)).await
+    }
+
+    /// The query is only awaited, and thus sent, when the game players are not cached yet
+    async fn load_game<F>(&self, query: F) -> Result<Vec<Player>>
+        where F: Future<Output = Result<Vec<Player>>> {
+        if let Some(players) = self.all() {
+            return Ok(players);
+        }
+        let players = query.await?;
+        {
+            let mut cache = self.players.write().expect("Poisoned lock on player cache");
+            cache.clear();
+            cache.extend(players.iter().map(|p| (p.id, p.clone())));
+        }
+        *self.is_complete.write().expect("Poisoned lock on player cache") = true;
+        Ok(players)
+    }
+
+    pub async fn find_ids_by_game(&self, gid: GameID, db_pool: &PgPool) -> Result<Vec<PlayerID>> {
+        self.find_by_game(gid, db_pool).await.map(|vec| vec.iter().map(|p| p.id).collect())
+    }
+
+    pub async fn find_ids_by_game_and_faction(&self, gid: GameID, fid: FactionID, db_pool: &PgPool) -> Result<Vec<PlayerID>> {
+        if let Some(ids) = self.get_faction_members(fid) {
+            return Ok(ids);
+        }
+        let players = self.find_by_game(gid, db_pool).await?;
+        Ok(self.store_faction_members(fid, &players))
+    }
+
+    fn get_faction_members(&self, fid: FactionID) -> Option<Vec<PlayerID>> {
+        if let Some(ids) = self.faction_members.read().expect("Poisoned lock on player cache").get(&fid) {
+            return Some(ids.clone());
+        }
+        self.all().map(|players| self.store_faction_members(fid, &players))
+    }
+
+    fn store_faction_members(&self, fid: FactionID, players: &[Player]) -> Vec<PlayerID> {
+        let ids: Vec<PlayerID> = players.iter().filter(|p| p.faction == Some(fid)).map(|p| p.id).collect();
+        self.faction_members.write().expect("Poisoned lock on player cache").insert(fid, ids.clone());
+        ids
+    }
+
+    pub async fn find(&self, pid: PlayerID, db_pool: &PgPool) -> Result<Player> {
+        if let Some(player) = self.get(&pid) {
+            return Ok(player);
+        }
+        let player = Player::find(pid, db_pool).await?;
+        self.store(player.clone());
+        Ok(player)
+    }
+}
+
+pub async fn init_player_wallets(players: &mut Vec<Player>, start_wallet: usize, db_pool: &PgPool) -> Result<()> {
     let mut tx = db_pool.begin().await?;
     for player in players.iter_mut() {
-        player.wallet = GAME_START_WALLET;
+        player.wallet = start_wallet;
         player.update(&mut tx).await?;
     }
     tx.commit().await?;
     Ok(())
 }
 
+/// Flags the player as eliminated once they own neither a system nor a fleet.
+/// Their money goes to a faction mate still in the game, otherwise their faction is knocked out
+pub async fn check_player_elimination(pid: PlayerID, server: &GameServer) -> Result<()> {
+    let mut player = Player::find(pid, &server.state.db_pool).await?;
+    if player.is_eliminated || player.game != Some(server.id) {
+        return Ok(());
+    }
+    let (s, f) = futures::join!(
+        System::find_ids_by_player(server.id, pid, &server.state.db_pool),
+        Fleet::count_by_player(pid, &server.state.db_pool),
+    );
+    if !s?.is_empty() || f? > 0 {
+        return Ok(());
+    }
+    let faction_members = match player.faction {
+        Some(fid) => Player::find_by_game_and_faction(server.id, fid, &server.state.db_pool).await?,
+        None => vec![],
+    };
+    let heir = pick_heir(&player, faction_members);
+
+    // The player is read again with its row locked, so the income or a transfer received meanwhile
+    // is part of the inheritance. The heir is credited without overwriting its own wallet
+    let mut tx = server.state.db_pool.begin().await?;
+    player = Player::lock(pid, &mut tx).await?;
+    if player.is_eliminated {
+        tx.rollback().await?;
+        return Ok(());
+    }
+    let inheritance = player.wallet;
+    player.is_eliminated = true;
+    if let Some(h) = heir.as_ref() {
+        Player::deposit(h.id, inheritance, &mut tx).await?;
+        player.wallet = 0;
+    }
+    player.update(&mut tx).await?;
+    tx.commit().await?;
+    if let Some(h) = heir.as_ref() {
+        server.player_cache.invalidate(&h.id);
+    }
+    server.player_cache.invalidate(&player.id);
+
+    if let Some(game_server) = server.state.games().get(&server.id) {
+        game_server.do_send(GamePlayerTasksMessage{
+            pid: player.id,
+            heir: heir.as_ref().map(|h| h.id),
+        });
+    }
+
+    log(
+        gelf::Level::Informational,
+        "Player eliminated",
+        &format!("{} has lost all their systems and fleets", player.to_log_message()),
+        vec![
+            ("player_id", player.id.0.to_string()),
+            ("heir_id", heir.as_ref().map_or(String::new(), |h| h.id.0.to_string())),
+        ],
+        &server.state.logger
+    );
+
+    #[derive(Serialize)]
+    struct PlayerEliminatedData {
+        player: PlayerID,
+        heir: Option<PlayerID>,
+        inheritance: usize,
+    }
+    server.ws_broadcast(&protocol::Message::new(
+        protocol::Action::PlayerEliminated,
+        PlayerEliminatedData{
+            player: player.id,
+            inheritance: if heir.is_some() { inheritance } else { 0 },
+            heir: heir.as_ref().map(|h| h.id),
+        },
+        None,
+    )).await?;
+
+    if let (None, Some(fid)) = (heir, player.faction) {
+        server.ws_broadcast(&protocol::Message::new(
+            protocol::Action::FactionEliminated,
+            fid,
+            None,
+        )).await?;
+    }
+    Ok(())
+}
+
+/// A player quitting the game leaves its assets to the same heir as an eliminated player.
+/// Without any, its systems become neutral and its fleets are destroyed, so nothing belongs to a player out of the game
+pub async fn hand_over_assets(player: &mut Player, db_pool: &PgPool) -> Result<PlayerAssetsTransfer> {
+    let gid = player.game.ok_or(InternalError::NotInLobby)?;
+    let (s, f) = futures::join!(
+        System::find_by_player(gid, player.id, db_pool),
+        Fleet::find_by_player(player.id, db_pool),
+    );
+    let mut systems = s?;
+    let mut fleets = f?;
+    let faction_members = match player.faction {
+        Some(fid) => Player::find_by_game_and_faction(gid, fid, db_pool).await?,
+        None => vec![],
+    };
+    let heir = pick_heir(player, faction_members);
+
+    hand_over(&mut systems, &mut fleets, heir.as_ref().map(|h| h.id));
+
+    let mut tx = db_pool.begin().await?;
+    for system in systems.iter() {
+        system.update(&mut tx).await?;
+    }
+    for fleet in fleets.iter() {
+        fleet.update(&mut tx).await?;
+    }
+    if let Some(h) = heir.as_ref() {
+        // The wallet is moved in place, so the money earned or received meanwhile is neither lost nor duplicated
+        player.wallet = Player::lock(player.id, &mut tx).await?.wallet;
+        Player::withdraw(player.id, player.wallet, &mut tx).await?;
+        Player::deposit(h.id, player.wallet, &mut tx).await?;
+        player.wallet = 0;
+    } else {
+        // The ships being built would have nowhere to go
+        ShipQueue::remove_by_player(player.id, &mut tx).await?;
+    }
+    Conquest::hand_over(player.id, heir.as_ref().map(|h| h.id), &mut tx).await?;
+    tx.commit().await?;
+
+    Ok(PlayerAssetsTransfer{
+        player: player.id,
+        heir: heir.map(|h| h.id),
+        systems: systems.into_iter().map(|s| s.id).collect(),
+        fleets: fleets.into_iter().map(|f| f.id).collect(),
+    })
+}
+
+fn hand_over(systems: &mut [System], fleets: &mut [Fleet], heir: Option<PlayerID>) {
+    for system in systems.iter_mut() {
+        system.player = heir;
+    }
+    for fleet in fleets.iter_mut() {
+        match heir {
+            Some(hid) => fleet.player = hid,
+            None => fleet.is_destroyed = true,
+        }
+    }
+}
+
+/// The richest faction mate still in the game inherits the assets of an eliminated player
+fn pick_heir(player: &Player, faction_members: Vec<Player>) -> Option<Player> {
+    faction_members.into_iter()
+        .filter(|p| p.id != player.id && !p.is_eliminated)
+        .max_by_key(|p| p.wallet)
+}
+
 #[post("/login")]
-pub async fn login(state:web::Data<AppState>)
-    -> Result<auth::Claims>
+pub async fn login(state:web::Data<AppState>, options: web::Query<LoginOptions>)
+    -> Result<HttpResponse>
 {
+    #[derive(Serialize)]
+    struct LoginResponse {
+        token: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        recovery_token: Option<String>,
+    }
     let player = Player {
         id: PlayerID(Uuid::new_v4()),
         username: String::from(""),
@@ -219,24 +639,49 @@ pub async fn login(state:web::Data<AppState>)
         ready: false,
         wallet: 0,
         is_connected: true,
+        home_system: None,
+        is_eliminated: false,
     };
+    let recovery_token = if options.recoverable { Some(auth::generate_recovery_token()) } else { None };
     let mut tx = state.db_pool.begin().await?;
     player.insert(&mut tx).await?;
+    if let Some(token) = recovery_token.as_ref() {
+        player.update_recovery_token(token, &mut tx).await?;
+    }
     tx.commit().await?;
-    
+
+    Ok(HttpResponse::Ok().json(LoginResponse{
+        token: auth::create_jwt(auth::Claims { pid: player.id })?,
+        recovery_token,
+    }))
+}
+
+/// Exchanges a recovery token for a new JWT of the same player
+#[post("/recover/")]
+pub async fn recover(state: web::Data<AppState>, req: HttpRequest, data: web::Json<PlayerRecoveryRequest>)
+    -> Result<auth::Claims>
+{
+    // The forwarding headers are set by the client, only the address of the peer can be trusted
+    let client_address = req.peer_addr().map(|addr| addr.ip().to_string()).unwrap_or_default();
+    if !state.recovery_limiter.hit(client_address) {
+        return Err(InternalError::RateLimited.into());
+    }
+    let player = Player::find_by_recovery_token(&data.recovery_token, &state.db_pool).await?;
+
     Ok(auth::Claims { pid: player.id })
 }
 
 #[get("/count/")]
 pub async fn get_nb_players(state:web::Data<AppState>)
-    -> Option<HttpResponse>
+    -> Result<HttpResponse>
 {
     #[derive(Serialize)]
     struct PlayersCount {
         nb_players: usize
     }
-    Some(HttpResponse::Ok().json(PlayersCount{
-        nb_players: (*state.clients()).len()
+    // The clients map only holds the players who are neither in a lobby nor in a game
+    Ok(HttpResponse::Ok().json(PlayersCount{
+        nb_players: state.sessions.count()
     }))
 }
 
@@ -247,6 +692,120 @@ pub async fn get_current_player(state:web::Data<AppState>, claims: auth::Claims)
     Ok(HttpResponse::Ok().json(Player::find(claims.pid, &state.db_pool).await?))
 }
 
+#[derive(Serialize)]
+pub struct SystemOverview {
+    #[serde(flatten)]
+    pub system: System,
+    pub buildings: Vec<Building>,
+}
+
+/// Everything a player needs to manage its empire
+#[derive(Serialize)]
+pub struct PlayerOverview {
+    pub wallet: usize,
+    pub systems: Vec<SystemOverview>,
+    pub ship_queues: Vec<ShipQueue>,
+    pub fleets: Vec<Fleet>,
+    pub factions: Vec<GameFaction>,
+    pub rank: u32,
+}
+
+/// The overview completed with the ongoing fights involving the player's systems and fleets,
+/// sent to a client which asks to resynchronize its state
+#[derive(Serialize)]
+pub struct StateSnapshot {
+    #[serde(flatten)]
+    pub overview: PlayerOverview,
+    pub conquests: Vec<Conquest>,
+    pub battles: Vec<Battle>,
+}
+
+impl PlayerOverview {
+    pub async fn build(player: &Player, gid: GameID, db_pool: &PgPool) -> Result<Self> {
+        let (s, sq, f, gf, c) = futures::join!(
+            System::find_by_player(gid, player.id, db_pool),
+            ShipQueue::find_by_player(player.id, db_pool),
+            Fleet::find_by_player(player.id, db_pool),
+            GameFaction::find_all(gid, db_pool),
+            System::count_by_players(gid, db_pool),
+        );
+        let systems = s?;
+        let mut fleets: HashMap<FleetID, Fleet> = f?.into_iter().map(|fleet| (fleet.id, fleet)).collect();
+        let (b, fs) = futures::join!(
+            Building::find_by_systems(systems.iter().map(|system| system.id).collect(), db_pool),
+            FleetSquadron::find_by_fleets(fleets.keys().copied().collect(), db_pool),
+        );
+        let mut buildings: HashMap<SystemID, Vec<Building>> = HashMap::new();
+        for building in b? {
+            buildings.entry(building.system).or_default().push(building);
+        }
+        for squadron in fs? {
+            if let Some(fleet) = fleets.get_mut(&squadron.fleet) {
+                fleet.squadrons.push(squadron);
+            }
+        }
+        let mut factions = gf?;
+        factions.sort_by(|a, b| b.victory_points.cmp(&a.victory_points));
+
+        Ok(PlayerOverview{
+            wallet: player.wallet,
+            systems: systems.into_iter().map(|system| SystemOverview{
+                buildings: buildings.remove(&system.id).unwrap_or_default(),
+                system,
+            }).collect(),
+            ship_queues: sq?,
+            fleets: fleets.into_iter().map(|(_, fleet)| fleet).collect(),
+            factions,
+            rank: get_player_rank(player.id, &c?),
+        })
+    }
+}
+
+impl StateSnapshot {
+    pub async fn build(player: &Player, gid: GameID, db_pool: &PgPool) -> Result<Self> {
+        let overview = PlayerOverview::build(player, gid, db_pool).await?;
+        let system_ids = get_overview_system_ids(&overview);
+        let (c, b) = futures::join!(
+            Conquest::find_current_by_systems(system_ids.clone(), db_pool),
+            Battle::find_current_by_systems(system_ids, db_pool),
+        );
+
+        Ok(StateSnapshot{
+            overview,
+            conquests: c?,
+            battles: b?,
+        })
+    }
+}
+
+#[get("/overview/")]
+pub async fn get_current_player_overview(state: web::Data<AppState>, info: web::Path<(GameID,)>, claims: auth::Claims)
+    -> Result<HttpResponse>
+{
+    let player = Player::find(claims.pid, &state.db_pool).await?;
+    if player.game != Some(info.0) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    Ok(HttpResponse::Ok().json(PlayerOverview::build(&player, info.0, &state.db_pool).await?))
+}
+
+/// The player is concerned by what happens in its own systems and wherever its fleets are
+fn get_overview_system_ids(overview: &PlayerOverview) -> Vec<SystemID> {
+    let mut ids: Vec<SystemID> = overview.systems.iter().map(|s| s.system.id)
+        .chain(overview.fleets.iter().map(|f| f.system))
+        .collect();
+    ids.sort_by_key(|id| id.0);
+    ids.dedup();
+    ids
+}
+
+/// Ranks the players by the number of systems they own, ties sharing the same rank
+fn get_player_rank(pid: PlayerID, nb_systems: &HashMap<PlayerID, u32>) -> u32 {
+    let player_nb_systems = nb_systems.get(&pid).copied().unwrap_or(0);
+
+    1 + nb_systems.values().filter(|nb| **nb > player_nb_systems).count() as u32
+}
+
 #[patch("/me/")]
 pub async fn update_current_player(state: web::Data<AppState>, json_data: web::Json<PlayerUpdateData>, claims: auth::Claims)
     -> Result<HttpResponse>
@@ -290,6 +849,31 @@ pub async fn update_current_player(state: web::Data<AppState>, json_data: web::J
     Ok(HttpResponse::NoContent().finish())
 }
 
+/// Toggles readiness without the validations of a full player update
+#[patch("/me/ready/")]
+pub async fn update_current_player_readiness(state: web::Data<AppState>, json_data: web::Json<PlayerReadyData>, claims: auth::Claims)
+    -> Result<HttpResponse>
+{
+    let mut player = Player::find(claims.pid, &state.db_pool).await?;
+    let lobby_id = player.lobby.ok_or(InternalError::LobbyUnknown)?;
+
+    if player.ready == json_data.is_ready {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    player.ready = json_data.is_ready;
+    player.update(&mut &state.db_pool).await?;
+
+    let lobbies = state.lobbies();
+    let lobby_server = lobbies.get(&lobby_id).ok_or(InternalError::LobbyUnknown)?;
+    lobby_server.do_send(protocol::Message::new(
+        protocol::Action::PlayerUpdate,
+        player.clone(),
+        Some(player.id),
+    ));
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
 #[get("/players/")]
 pub async fn get_faction_members(state: web::Data<AppState>, info: web::Path<(GameID, FactionID)>)
     -> Result<HttpResponse>
@@ -297,13 +881,40 @@ pub async fn get_faction_members(state: web::Data<AppState>, info: web::Path<(Ga
     Ok(HttpResponse::Ok().json(Player::find_by_game_and_faction(info.0, info.1, &state.db_pool).await?))
 }
 
+#[get("/{player_id}/")]
+pub async fn get_game_player(state: web::Data<AppState>, info: web::Path<(GameID, PlayerID)>, claims: auth::Claims)
+    -> Result<HttpResponse>
+{
+    let (v, p) = futures::join!(
+        Player::find(claims.pid, &state.db_pool),
+        Player::find(info.1, &state.db_pool),
+    );
+    let viewer = v?;
+    let player = p?;
+
+    if viewer.game != Some(info.0) || player.game != Some(info.0) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let nb_systems = System::count_by_player(info.0, player.id, &state.db_pool).await?;
+
+    Ok(HttpResponse::Ok().json(PlayerProfile::new(player, &viewer, nb_systems)))
+}
+
+fn can_see_private_data(viewer: &Player, player: &Player) -> bool {
+    viewer.id == player.id || (viewer.faction.is_some() && viewer.faction == player.faction)
+}
+
 #[patch("/players/{player_id}/money/")]
 pub async fn transfer_money(state: web::Data<AppState>, info: web::Path<(GameID, FactionID, PlayerID)>, data: web::Json<PlayerMoneyTransferRequest>, claims: auth::Claims)
     -> Result<HttpResponse>
 {
-    let mut current_player = Player::find(claims.pid, &state.db_pool).await?;
-    let mut other_player = Player::find(info.2, &state.db_pool).await?;
+    let current_player = Player::find(claims.pid, &state.db_pool).await?;
+    let other_player = Player::find(info.2, &state.db_pool).await?;
 
+    // The transfer is counted by the game server of the sender
+    if current_player.game != Some(info.0) || other_player.game != Some(info.0) {
+        return Err(InternalError::AccessDenied.into());
+    }
     if current_player.faction != other_player.faction {
         return Err(InternalError::Conflict.into());
     }
@@ -311,31 +922,288 @@ pub async fn transfer_money(state: web::Data<AppState>, info: web::Path<(GameID,
     if current_player.wallet < data.amount {
         return Err(InternalError::Conflict.into());
     }
+    send_money(info.0, &current_player, &other_player, data.amount, state.db_pool.begin().await?, &state).await?;
 
-    other_player.wallet += data.amount;
-    current_player.wallet -= data.amount;
+    Ok(HttpResponse::NoContent().finish())
+}
 
-    let mut tx = state.db_pool.begin().await?;
-    current_player.update(&mut tx).await?;
-    other_player.update(&mut tx).await?;
-    tx.commit().await?;
+/// Moves money between two faction-mates within the given transaction, the receiver is notified of the transfer
+pub async fn send_money(gid: GameID, sender: &Player, receiver: &Player, amount: usize, mut tx: Transaction<PoolConnection<PgConnection>>, state: &AppState) -> Result<()> {
+    let game_server = state.games().get(&gid).cloned().ok_or(InternalError::GameUnknown)?;
+    // The allowance is reserved first, so that concurrent transfers cannot exceed it
+    game_server.send(GameMoneyTransferMessage{ pid: sender.id, amount }).await??;
 
     #[derive(Serialize)]
     pub struct PlayerMoneyTransferData{
         pub amount: usize,
         pub player_id: PlayerID,
     }
-    
-    let games = state.games();
-    let game_server = games.get(&other_player.game.clone().unwrap()).expect("Game exists in DB but not in HashMap");
+
+    let result: Result<()> = async {
+        Player::withdraw(sender.id, amount, &mut tx).await?;
+        Player::deposit(receiver.id, amount, &mut tx).await?;
+        Notification::new(
+            receiver.id,
+            gid,
+            NotificationKind::MoneyReceived,
+            PlayerMoneyTransferData{ player_id: sender.id, amount },
+        ).insert(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }.await;
+    if result.is_err() {
+        game_server.do_send(GameMoneyTransferFailureMessage{ pid: sender.id, amount });
+    }
+    result?;
+
     game_server.do_send(GameNotifyPlayerMessage(
-        other_player.id.clone(),
+        receiver.id,
         protocol::Message::new(
             protocol::Action::PlayerMoneyTransfer,
-            PlayerMoneyTransferData{ player_id: current_player.id.clone(), amount: data.amount },
+            PlayerMoneyTransferData{ player_id: sender.id, amount },
             None,
         )
     ));
+    Ok(())
+}
 
-    Ok(HttpResponse::NoContent().finish())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::system::system::{Coordinates, SystemKind};
+
+    #[test]
+    fn test_money_transfer_limit() {
+        let (sender, other_sender) = (PlayerID(Uuid::new_v4()), PlayerID(Uuid::new_v4()));
+        let mut limit = MoneyTransferLimit::new(Some(500));
+
+        assert!(limit.register(sender, 300).is_ok());
+        assert!(limit.register(sender, 300).is_err());
+        assert!(limit.register(sender, 200).is_ok());
+        assert!(limit.register(sender, 1).is_err());
+        // The allowance is tracked per sender
+        assert!(limit.register(other_sender, 500).is_ok());
+
+        limit.reset();
+        assert!(limit.register(sender, 500).is_ok());
+
+        // A failed transfer does not consume the allowance
+        limit.release(sender, 200);
+        assert!(limit.register(sender, 200).is_ok());
+        assert!(limit.register(sender, 1).is_err());
+    }
+
+    #[test]
+    fn test_money_transfer_without_limit() {
+        let mut limit = MoneyTransferLimit::new(None);
+
+        assert!(limit.register(PlayerID(Uuid::new_v4()), usize::MAX).is_ok());
+    }
+
+    #[test]
+    fn test_get_player_rank() {
+        let (first, second, third) = (PlayerID(Uuid::new_v4()), PlayerID(Uuid::new_v4()), PlayerID(Uuid::new_v4()));
+        let mut nb_systems = HashMap::new();
+        nb_systems.insert(first, 12);
+        nb_systems.insert(second, 5);
+        nb_systems.insert(third, 5);
+
+        assert_eq!(1, get_player_rank(first, &nb_systems));
+        assert_eq!(2, get_player_rank(second, &nb_systems));
+        assert_eq!(2, get_player_rank(third, &nb_systems));
+        // Players without any system come last
+        assert_eq!(4, get_player_rank(PlayerID(Uuid::new_v4()), &nb_systems));
+    }
+
+    #[test]
+    fn test_player_cache() {
+        let cache = PlayerCache::default();
+        let player = get_player_mock();
+
+        assert!(cache.get(&player.id).is_none());
+        assert!(cache.all().is_none());
+
+        cache.store(player.clone());
+
+        assert!(cache.get(&player.id) == Some(player.clone()));
+        // Storing a single player does not mean the whole game is cached
+        assert!(cache.all().is_none());
+
+        *cache.is_complete.write().unwrap() = true;
+        assert_eq!(1, cache.all().unwrap().len());
+
+        cache.invalidate(&player.id);
+
+        assert!(cache.get(&player.id).is_none());
+        assert!(cache.all().is_none());
+
+        cache.store(player.clone());
+        cache.clear();
+
+        assert!(cache.get(&player.id).is_none());
+    }
+
+    #[test]
+    fn test_player_cache_faction_members() {
+        let cache = PlayerCache::default();
+        let player = get_player_mock();
+        let mate = Player{ id: PlayerID(Uuid::new_v4()), ..get_player_mock() };
+        let enemy = Player{ id: PlayerID(Uuid::new_v4()), faction: Some(FactionID(2)), ..get_player_mock() };
+        let fid = player.faction.unwrap();
+
+        for p in vec![player.clone(), mate.clone(), enemy] {
+            cache.store(p);
+        }
+        assert!(cache.get_faction_members(fid).is_none());
+        *cache.is_complete.write().unwrap() = true;
+
+        let ids = cache.get_faction_members(fid).unwrap();
+        assert_eq!(2, ids.len());
+        assert!(ids.contains(&player.id) && ids.contains(&mate.id));
+        // A burst of broadcasts shares the same lookup, even once the players are no longer cached
+        cache.players.write().unwrap().clear();
+        assert_eq!(Some(ids), cache.get_faction_members(fid));
+
+        // Storing a player without membership change keeps the resolved ids
+        cache.store(Player{ wallet: 1000, ..player.clone() });
+        assert!(cache.faction_members.read().unwrap().contains_key(&fid));
+
+        // A player switching faction drops the outdated membership
+        cache.store(Player{ faction: Some(FactionID(2)), ..mate.clone() });
+        assert!(cache.faction_members.read().unwrap().get(&fid).is_none());
+
+        assert_eq!(vec![player.id], cache.store_faction_members(fid, &[player.clone(), Player{ faction: Some(FactionID(2)), ..mate }]));
+
+        // A leaving player invalidates every resolved faction
+        cache.invalidate(&player.id);
+        assert!(cache.faction_members.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_player_cache_queries_per_tick() {
+        let cache = PlayerCache::default();
+        let nb_queries = std::cell::Cell::new(0);
+        // A large game, with 1000 players spread in 4 factions
+        let players: Vec<Player> = (0..1000).map(|i: u16| Player{
+            id: PlayerID(Uuid::new_v4()),
+            faction: Some(FactionID((i % 4 + 1) as u8)),
+            ..get_player_mock()
+        }).collect();
+        // Only the queries actually awaited are sent to the database, and thus counted
+        let query = || {
+            let nb_queries = &nb_queries;
+            let players = players.clone();
+            async move {
+                nb_queries.set(nb_queries.get() + 1);
+                Ok::<Vec<Player>, ServerError>(players)
+            }
+        };
+        let find_faction_members = |fid: FactionID| {
+            let members = futures::executor::block_on(cache.load_game(query())).unwrap();
+            cache.get_faction_members(fid).unwrap_or_else(|| cache.store_faction_members(fid, &members))
+        };
+
+        // A tick produces the income, then sends 10 game broadcasts and 10 broadcasts per faction,
+        // each of them being a query of the game players without cache
+        cache.clear();
+        assert_eq!(1000, futures::executor::block_on(cache.load_game(query())).unwrap().len());
+        for _ in 0..10 {
+            assert_eq!(1000, futures::executor::block_on(cache.load_game(query())).unwrap().len());
+            for fid in 1..=4 {
+                assert_eq!(250, find_faction_members(FactionID(fid)).len());
+            }
+        }
+        // The 51 queries of the tick are reduced to a single one
+        assert_eq!(1, nb_queries.get());
+
+        // The next tick reloads the players once
+        cache.clear();
+        futures::executor::block_on(cache.load_game(query())).unwrap();
+        assert_eq!(2, nb_queries.get());
+    }
+
+    #[test]
+    fn test_pick_heir() {
+        let player = get_player_mock();
+        let eliminated_mate = Player{ id: PlayerID(Uuid::new_v4()), wallet: 1000, is_eliminated: true, ..player.clone() };
+        let poor_mate = Player{ id: PlayerID(Uuid::new_v4()), wallet: 50, ..player.clone() };
+        let rich_mate = Player{ id: PlayerID(Uuid::new_v4()), wallet: 500, ..player.clone() };
+
+        assert_eq!(
+            Some(rich_mate.id),
+            pick_heir(&player, vec![player.clone(), eliminated_mate.clone(), poor_mate, rich_mate.clone()]).map(|p| p.id),
+        );
+        // Without any faction mate left, the faction is knocked out
+        assert!(pick_heir(&player, vec![player.clone(), eliminated_mate]).is_none());
+    }
+
+    #[test]
+    fn test_hand_over() {
+        let player = get_player_mock();
+        let heir = Player{ id: PlayerID(Uuid::new_v4()), ..get_player_mock() };
+        let get_assets = || {
+            let system = System{
+                id: SystemID(Uuid::new_v4()),
+                game: player.game.unwrap(),
+                player: Some(player.id),
+                kind: SystemKind::BaseSystem,
+                coordinates: Coordinates{ x: 0.0, y: 0.0 },
+                unreachable: false,
+            };
+            let fleet = Fleet{
+                id: FleetID(Uuid::new_v4()),
+                name: None,
+                player: player.id,
+                system: system.id,
+                destination_system: None,
+                destination_arrival_date: None,
+                departure_date: None,
+                squadrons: vec![],
+                is_destroyed: false,
+            };
+            (vec![system], vec![fleet])
+        };
+
+        let (mut systems, mut fleets) = get_assets();
+        hand_over(&mut systems, &mut fleets, Some(heir.id));
+        assert_eq!(Some(heir.id), systems[0].player);
+        assert_eq!(heir.id, fleets[0].player);
+        assert!(!fleets[0].is_destroyed);
+
+        // Without heir, nothing is left to the player out of the game
+        let (mut systems, mut fleets) = get_assets();
+        hand_over(&mut systems, &mut fleets, None);
+        assert_eq!(None, systems[0].player);
+        assert!(fleets[0].is_destroyed);
+    }
+
+    #[test]
+    fn test_player_profile() {
+        let player = get_player_mock();
+        let ally = Player{ id: PlayerID(Uuid::new_v4()), ..get_player_mock() };
+        let enemy = Player{ id: PlayerID(Uuid::new_v4()), faction: Some(FactionID(2)), ..get_player_mock() };
+
+        assert_eq!(Some(200), PlayerProfile::new(player.clone(), &player, 3).wallet);
+        assert_eq!(Some(200), PlayerProfile::new(player.clone(), &ally, 3).wallet);
+
+        let public_profile = PlayerProfile::new(player.clone(), &enemy, 3);
+        assert_eq!(None, public_profile.wallet);
+        assert_eq!(player.username, public_profile.username);
+        assert_eq!(3, public_profile.nb_systems);
+    }
+
+    fn get_player_mock() -> Player {
+        Player {
+            id: PlayerID(Uuid::new_v4()),
+            username: String::from("Galadruin"),
+            game: Some(GameID(Uuid::new_v4())),
+            lobby: None,
+            faction: Some(FactionID(1)),
+            ready: true,
+            wallet: 200,
+            is_connected: true,
+            home_system: None,
+            is_eliminated: false,
+        }
+    }
 }
@@ -1,12 +1,24 @@
-use actix_web::{get, HttpResponse};
+use actix_web::{get, web, HttpResponse};
 use chrono::{DateTime, Duration, Utc};
 use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
 use crate::{
     lib::{
         Result,
+        auth::Claims,
+        error::InternalError,
         time::Time,
     },
-    game::game::option::GameOptionSpeed,
+    game::{
+        game::{
+            game::GameID,
+            option::GameOptionSpeed,
+        },
+        player::Player,
+        technology::{Technology, TechnologyKind},
+    },
+    AppState,
+    get_env,
 };
 
 #[derive(Serialize, Copy, Clone)]
@@ -21,7 +33,7 @@ pub struct ShipModel {
     pub precision: u16,
 }
 
-#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, sqlx::Type)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, Hash, Eq, PartialEq, sqlx::Type)]
 #[sqlx(rename = "VARCHAR")]
 #[sqlx(rename_all = "snake_case")]
 #[serde(rename_all(serialize = "snake_case", deserialize = "snake_case"))]
@@ -33,6 +45,13 @@ pub enum ShipModelCategory {
 }
 
 impl ShipModelCategory {
+    pub const ALL: [ShipModelCategory; 4] = [
+        ShipModelCategory::Fighter,
+        ShipModelCategory::Corvette,
+        ShipModelCategory::Frigate,
+        ShipModelCategory::Cruiser,
+    ];
+
     pub const fn to_data(self) -> ShipModel {
         match self {
             ShipModelCategory::Fighter => ShipModel{
@@ -79,7 +98,46 @@ impl ShipModelCategory {
     }
 }
 
+/// Technologies required to build some ship categories, such as `{"cruiser": "reinforced_hulls"}`.
+/// Every category can be built when none is configured.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+pub struct ShipPrerequisites(HashMap<ShipModelCategory, TechnologyKind>);
+
+impl ShipPrerequisites {
+    /// An invalid configuration disables the prerequisites.
+    pub fn from_config(config: &str) -> Self {
+        serde_json::from_str(config).unwrap_or_default()
+    }
+
+    pub fn from_env() -> Self {
+        Self::from_config(&get_env("SHIP_PREREQUISITES", "{}"))
+    }
+
+    pub fn is_buildable(&self, category: ShipModelCategory, technologies: &[TechnologyKind]) -> bool {
+        self.0.get(&category).map_or(true, |technology| technologies.contains(technology))
+    }
+
+    pub fn get_buildable_categories(&self, technologies: &[TechnologyKind]) -> Vec<ShipModelCategory> {
+        ShipModelCategory::ALL.iter()
+            .copied()
+            .filter(|category| self.is_buildable(*category, technologies))
+            .collect()
+    }
+}
+
 impl ShipModel {
+    /// Applies the effects of the given unlocked technologies on the base ship statistics.
+    pub fn with_technologies(mut self, technologies: &[TechnologyKind]) -> Self {
+        for technology in technologies {
+            match technology {
+                TechnologyKind::ReinforcedHulls => self.hit_points += self.hit_points / 10,
+                TechnologyKind::ImprovedTargeting => self.precision = (self.precision + self.precision / 10).min(100),
+                TechnologyKind::StreamlinedProduction => self.cost -= self.cost / 10,
+            }
+        }
+        self
+    }
+
     pub fn compute_construction_deadline(self, quantity: u16, from: Time, game_speed: GameOptionSpeed) -> Time {
         let datetime: DateTime<Utc> = from.into();
 
@@ -94,15 +152,29 @@ impl ShipModel {
 }
 
 
+fn get_ship_models_data(technologies: &[TechnologyKind]) -> Vec<ShipModel> {
+    ShipModelCategory::ALL.iter()
+        .map(|category| category.to_data().with_technologies(technologies))
+        .collect()
+}
 
 #[get("/ship-models/")]
 pub async fn get_ship_models() -> Result<HttpResponse> {
-    Ok(HttpResponse::Ok().json(vec![
-        ShipModelCategory::Fighter.to_data(),
-        ShipModelCategory::Corvette.to_data(),
-        ShipModelCategory::Frigate.to_data(),
-        ShipModelCategory::Cruiser.to_data(),
-    ]))
+    Ok(HttpResponse::Ok().json(get_ship_models_data(&[])))
+}
+
+/// Returns the ship models with the modifiers of the technologies unlocked by the player's faction
+#[get("/{id}/ship-models/")]
+pub async fn get_game_ship_models(state: web::Data<AppState>, info: web::Path<(GameID,)>, claims: Claims) -> Result<HttpResponse> {
+    let player = Player::find(claims.pid, &state.db_pool).await?;
+    if player.game != Some(info.0) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let technologies = match player.faction {
+        Some(fid) => Technology::find_unlocked_kinds(info.0, fid, &state.db_pool).await?,
+        None => vec![],
+    };
+    Ok(HttpResponse::Ok().json(get_ship_models_data(&technologies)))
 }
 
 #[cfg(test)]
@@ -122,6 +194,52 @@ mod tests {
         assert_ne!(fighter.cost, cruiser.cost);
     }
 
+    #[test]
+    fn test_ship_prerequisites() {
+        let prerequisites = ShipPrerequisites::from_config(r#"{"cruiser": "reinforced_hulls"}"#);
+
+        assert!(prerequisites.is_buildable(ShipModelCategory::Frigate, &[]));
+        assert!(!prerequisites.is_buildable(ShipModelCategory::Cruiser, &[TechnologyKind::ImprovedTargeting]));
+        assert!(prerequisites.is_buildable(ShipModelCategory::Cruiser, &[TechnologyKind::ReinforcedHulls]));
+        assert_eq!(3, prerequisites.get_buildable_categories(&[]).len());
+        assert_eq!(ShipModelCategory::ALL.to_vec(), prerequisites.get_buildable_categories(&[TechnologyKind::ReinforcedHulls]));
+    }
+
+    #[test]
+    fn test_ship_prerequisites_default() {
+        assert_eq!(ShipPrerequisites::default(), ShipPrerequisites::from_config("{}"));
+        assert_eq!(ShipPrerequisites::default(), ShipPrerequisites::from_config("invalid"));
+        assert_eq!(ShipModelCategory::ALL.to_vec(), ShipPrerequisites::default().get_buildable_categories(&[]));
+    }
+
+    #[test]
+    fn test_ship_model_with_technologies() {
+        let corvette = ShipModelCategory::Corvette.to_data();
+        let upgraded = corvette.with_technologies(&[
+            TechnologyKind::ReinforcedHulls,
+            TechnologyKind::StreamlinedProduction,
+        ]);
+
+        assert_eq!(66, upgraded.hit_points);
+        assert_eq!(126, upgraded.cost);
+        assert_eq!(corvette.precision, upgraded.precision);
+        assert_eq!(corvette.cost, corvette.with_technologies(&[]).cost);
+    }
+
+    #[test]
+    fn test_get_ship_models_data() {
+        let base_models = get_ship_models_data(&[]);
+        let upgraded_models = get_ship_models_data(&[TechnologyKind::ImprovedTargeting]);
+
+        assert_eq!(ShipModelCategory::ALL.len(), upgraded_models.len());
+        for (base, upgraded) in base_models.iter().zip(upgraded_models.iter()) {
+            assert_eq!(base.category, upgraded.category);
+            assert_eq!(base.category.to_data().precision, base.precision);
+            assert!(upgraded.precision > base.precision);
+            assert_eq!(base.hit_points, upgraded.hit_points);
+        }
+    }
+
     #[test]
     fn test_ship_model_construction_milliseconds() {
         let fighter_model = ShipModelCategory::Fighter.to_data();
@@ -129,5 +247,7 @@ mod tests {
         assert_eq!(960, fighter_model.into_duration(2, GameOptionSpeed::Slow).num_milliseconds());
         assert_eq!(800, fighter_model.into_duration(2, GameOptionSpeed::Medium).num_milliseconds());
         assert_eq!(640, fighter_model.into_duration(2, GameOptionSpeed::Fast).num_milliseconds());
+        assert_eq!(1200, fighter_model.into_duration(2, GameOptionSpeed::VerySlow).num_milliseconds());
+        assert_eq!(480, fighter_model.into_duration(2, GameOptionSpeed::VeryFast).num_milliseconds());
     }
 }
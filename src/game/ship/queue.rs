@@ -12,7 +12,7 @@ use crate::{
         time::Time,
     },
     game::{
-        player::{Player},
+        player::{Player, PlayerID},
         fleet::{
             fleet::FleetID,
             formation::FleetFormation,
@@ -24,16 +24,18 @@ use crate::{
             server::{GameServer, GameServerTask},
         },
         ship::{
-            model::ShipModelCategory,
+            model::{ShipModel, ShipModelCategory},
             squadron::{Squadron},
         },
         system::{
             building::{Building, BuildingKind},
             system::{SystemID, System},
         },
+        technology::{Technology, TechnologyKind},
     },
     ws::protocol,
     AppState,
+    get_env,
 };
 use futures::join;
 use futures::executor::block_on;
@@ -45,6 +47,7 @@ pub struct ShipQueueID(pub Uuid);
 pub struct ShipQueue {
     pub id: ShipQueueID,
     pub system: SystemID,
+    pub player: PlayerID,
     pub category: ShipModelCategory,
     pub quantity: u16,
     pub assigned_fleet: Option<String>,
@@ -59,6 +62,23 @@ pub struct ShipQuantityData {
     pub quantity: usize
 }
 
+#[derive(Debug, Serialize, Clone)]
+pub struct ShipQueueEstimate {
+    pub cost: usize,
+    pub started_at: Time,
+    pub finished_at: Time,
+}
+
+impl ShipQueueEstimate {
+    pub fn new(ship_model: ShipModel, quantity: u16, started_at: Time, game_speed: GameOptionSpeed) -> Self {
+        ShipQueueEstimate{
+            cost: ship_model.cost as usize * quantity as usize,
+            started_at,
+            finished_at: ship_model.compute_construction_deadline(quantity, started_at, game_speed),
+        }
+    }
+}
+
 impl From<ShipQueueID> for Uuid {
     fn from(sqid: ShipQueueID) -> Self { sqid.0 }
 }
@@ -68,6 +88,7 @@ impl<'a> FromRow<'a, PgRow<'a>> for ShipQueue {
         Ok(ShipQueue {
             id: row.try_get("id").map(ShipQueueID)?,
             system: row.try_get("system_id").map(SystemID)?,
+            player: row.try_get("player_id").map(PlayerID)?,
             category: row.try_get("category")?,
             quantity: row.try_get::<i32, _>("quantity")? as u16,
             assigned_fleet: row.try_get("assigned_fleet")?,
@@ -86,6 +107,10 @@ impl GameServerTask for ShipQueue {
     fn get_task_end_time(&self) -> Time {
         self.finished_at
     }
+
+    fn get_task_owner(&self) -> Option<PlayerID> {
+        Some(self.player)
+    }
 }
 
 impl ShipQueue {
@@ -101,12 +126,26 @@ impl ShipQueue {
             .fetch_all(db_pool).await.map_err(ServerError::from)
     }
 
+    pub async fn find_by_player(pid: PlayerID, db_pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as("SELECT * FROM system__ship_queues WHERE player_id = $1 ORDER BY finished_at")
+            .bind(Uuid::from(pid))
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
     pub async fn find_last(sid: SystemID, db_pool: &PgPool) -> Result<Self> {
         sqlx::query_as("SELECT * FROM system__ship_queues WHERE system_id = $1 ORDER BY finished_at DESC LIMIT 1")
             .bind(Uuid::from(sid))
             .fetch_one(db_pool).await.map_err(ServerError::from)
     }
 
+    pub async fn count_by_system<E>(sid: SystemID, exec: &mut E) -> Result<usize>
+        where E: Executor<Database = Postgres> {
+        let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM system__ship_queues WHERE system_id = $1")
+            .bind(Uuid::from(sid))
+            .fetch_one(&mut *exec).await.map_err(ServerError::from)?;
+        Ok(count.0 as usize)
+    }
+
     pub async fn count_assigned_ships(assigned_fleet: &str, category: ShipModelCategory, db_pool: &PgPool) -> Result<u32> {
         let count: (i64,) = sqlx::query_as("SELECT COALESCE(SUM(quantity), 0) FROM system__ship_queues WHERE assigned_fleet = $1 AND category = $2")
             .bind(assigned_fleet)
@@ -115,11 +154,26 @@ impl ShipQueue {
         Ok(count.0 as u32)
     }
 
+    /// Delays the ship queues pending during the pause. The ones ordered meanwhile only start once it is resumed.
+    /// Their start moves along, so the production progress is unchanged
+    pub async fn postpone_deadlines<E>(gid: GameID, paused_at: Time, resumed_at: Time, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE system__ship_queues SET
+            started_at = started_at + ($3 - GREATEST(created_at, $2)),
+            finished_at = finished_at + ($3 - GREATEST(created_at, $2))
+            WHERE finished_at > $2 AND system_id IN (SELECT id FROM map__systems WHERE game_id = $1)")
+            .bind(Uuid::from(gid))
+            .bind(paused_at)
+            .bind(resumed_at)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
     pub async fn insert<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres> {
-        sqlx::query("INSERT INTO system__ship_queues (id, system_id, category, quantity, assigned_fleet, created_at, started_at, finished_at) VALUES($1, $2, $3, $4, $5, $6, $7, $8)")
+        sqlx::query("INSERT INTO system__ship_queues (id, system_id, player_id, category, quantity, assigned_fleet, created_at, started_at, finished_at) VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9)")
             .bind(Uuid::from(self.id))
             .bind(Uuid::from(self.system))
+            .bind(Uuid::from(self.player))
             .bind(self.category)
             .bind(self.quantity as i32)
             .bind(self.assigned_fleet.as_ref())
@@ -129,6 +183,21 @@ impl ShipQueue {
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
+    /// The ships produced for the fleet are stored in their system once built
+    pub async fn unassign_fleet<E>(fid: FleetID, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE system__ship_queues SET assigned_fleet = NULL WHERE assigned_fleet LIKE $1")
+            .bind(format!("{}:%", fid))
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
+    pub async fn remove_by_player<E>(pid: PlayerID, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("DELETE FROM system__ship_queues WHERE player_id = $1")
+            .bind(Uuid::from(pid))
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
     pub async fn remove<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres> {
         sqlx::query("DELETE FROM system__ship_queues WHERE id = $1")
@@ -136,8 +205,19 @@ impl ShipQueue {
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
+    /// The system may have been conquered, abandoned or lost its shipyard since the ships were ordered
+    pub fn can_be_produced(&self, system_owner: Option<PlayerID>, has_shipyard: bool) -> bool {
+        has_shipyard && system_owner == Some(self.player)
+    }
+
     pub async fn produce(&self, server: &GameServer) -> Result<()> {
-        let player = Player::find_system_owner(self.system.clone(), &server.state.db_pool).await?;
+        let (s, nb_shipyards) = join!(
+            System::find(self.system, &server.state.db_pool),
+            Building::count_by_kind_and_system(BuildingKind::Shipyard, self.system, &server.state.db_pool),
+        );
+        if !self.can_be_produced(s?.player, nb_shipyards? > 0) {
+            return self.void(server).await;
+        }
         let mut tx = server.state.db_pool.begin().await?;
 
         if let Some(assigned_fleet) = self.assigned_fleet.clone() {
@@ -163,7 +243,7 @@ impl ShipQueue {
 
         tx.commit().await?;
 
-        server.player_broadcast(&player.id, &protocol::Message::new(
+        server.player_broadcast(&self.player, &protocol::Message::new(
             protocol::Action::ShipQueueFinished,
             self.clone(),
             None,
@@ -172,22 +252,118 @@ impl ShipQueue {
         Ok(())
     }
 
+    async fn void(&self, server: &GameServer) -> Result<()> {
+        self.remove(&mut &server.state.db_pool).await?;
+
+        server.player_broadcast(&self.player, &protocol::Message::new(
+            protocol::Action::ShipQueueVoided,
+            self.clone(),
+            None,
+        ));
+
+        Ok(())
+    }
+
+    async fn check_shipyard(sid: SystemID, db_pool: &PgPool) -> Result<()> {
+        let has_shipyard = Building::count_by_kind_and_system(BuildingKind::Shipyard, sid, &db_pool).await? > 0;
+        if !has_shipyard {
+            return Err(InternalError::Conflict.into());
+        }
+        Ok(())
+    }
+
+    async fn get_technologies(player: &Player, db_pool: &PgPool) -> Result<Vec<TechnologyKind>> {
+        match (player.game, player.faction) {
+            (Some(gid), Some(fid)) => Technology::find_unlocked_kinds(gid, fid, &db_pool).await,
+            _ => Ok(vec![]),
+        }
+    }
+
+    async fn get_ship_model(player: &Player, category: ShipModelCategory, state: &AppState) -> Result<ShipModel> {
+        let technologies = ShipQueue::get_technologies(player, &state.db_pool).await?;
+        if !state.ship_prerequisites.is_buildable(category, &technologies) {
+            return Err(InternalError::ShipCategoryLocked.into());
+        }
+        Ok(category.to_data().with_technologies(&technologies))
+    }
+
+    /// Without a shipyard, no ship can be built in the system
+    pub async fn get_buildable_categories(player: &Player, sid: SystemID, state: &AppState) -> Result<Vec<ShipModelCategory>> {
+        if ShipQueue::check_shipyard(sid, &state.db_pool).await.is_err() {
+            return Ok(vec![]);
+        }
+        let technologies = ShipQueue::get_technologies(player, &state.db_pool).await?;
+        Ok(state.ship_prerequisites.get_buildable_categories(&technologies))
+    }
+
+    /// New queues start once the last queued ships of the system are built
+    async fn find_next_start(sid: SystemID, db_pool: &PgPool) -> Time {
+        ShipQueue::find_last(sid, &db_pool).await.ok().map_or(Time::now(), |sq| sq.finished_at)
+    }
+
+    pub async fn estimate(
+        player: &Player,
+        sid: SystemID,
+        category: ShipModelCategory,
+        quantity: u16,
+        game_speed: GameOptionSpeed,
+        state: &AppState
+    ) -> Result<ShipQueueEstimate> {
+        ShipQueue::check_shipyard(sid, &state.db_pool).await?;
+
+        let ship_model = ShipQueue::get_ship_model(player, category, state).await?;
+        let starts_at = ShipQueue::find_next_start(sid, &state.db_pool).await;
+
+        Ok(ShipQueueEstimate::new(ship_model, quantity, starts_at, game_speed))
+    }
+
     pub async fn schedule(
         player: &mut Player,
         sid: SystemID,
         category: ShipModelCategory,
-        mut quantity: u16,
+        quantity: u16,
         only_affordable: bool,
         assigned_fleet: Option<String>,
         game_speed: GameOptionSpeed,
-        db_pool: &PgPool
+        state: &AppState
     ) -> Result<Option<ShipQueue>> {
-        let has_shipyard = Building::count_by_kind_and_system(BuildingKind::Shipyard, sid, &db_pool).await? > 0;
-        if !has_shipyard {
-            return Err(InternalError::Conflict.into());
-        }
+        let mut tx = state.db_pool.begin().await?;
+        let ship_queue = ShipQueue::schedule_in(
+            player,
+            sid,
+            category,
+            quantity,
+            only_affordable,
+            assigned_fleet,
+            game_speed,
+            state,
+            &mut tx
+        ).await?;
+        tx.commit().await?;
+
+        Ok(ship_queue)
+    }
+
+    /// Schedules the ships within the transaction of the caller, so they are only paid if it commits
+    pub async fn schedule_in<E>(
+        player: &mut Player,
+        sid: SystemID,
+        category: ShipModelCategory,
+        mut quantity: u16,
+        only_affordable: bool,
+        assigned_fleet: Option<String>,
+        game_speed: GameOptionSpeed,
+        state: &AppState,
+        exec: &mut E
+    ) -> Result<Option<ShipQueue>>
+        where E: Executor<Database = Postgres> {
+        ShipQueue::check_shipyard(sid, &state.db_pool).await?;
 
-        let ship_model = category.to_data();
+        // The orders of the player are serialized, so that concurrent ones cannot exceed the cap or the wallet
+        player.wallet = Player::lock(player.id, &mut *exec).await?.wallet;
+        check_pending_queues(ShipQueue::count_by_system(sid, &mut *exec).await?, get_max_pending_queues())?;
+
+        let ship_model = ShipQueue::get_ship_model(player, category, state).await?;
         if only_affordable {
             let affordable_quantity = (player.wallet / ship_model.cost as usize) as u16;
             if affordable_quantity < 1 {
@@ -198,11 +374,12 @@ impl ShipQueue {
         }
         player.spend(ship_model.cost as usize * quantity.clone() as usize)?;
         
-        let starts_at = ShipQueue::find_last(sid.clone(), &db_pool).await.ok().map_or(Time::now(), |sq| sq.finished_at);
+        let starts_at = ShipQueue::find_next_start(sid, &state.db_pool).await;
 
         let ship_queue = ShipQueue{
             id: ShipQueueID(Uuid::new_v4()),
             system: sid,
+            player: player.id,
             category: category.clone(),
             quantity: quantity.clone(),
             assigned_fleet,
@@ -210,15 +387,28 @@ impl ShipQueue {
             started_at: starts_at.clone(),
             finished_at: ship_model.compute_construction_deadline(quantity, starts_at, game_speed),
         };
-        let mut tx = db_pool.begin().await?;
-        ship_queue.insert(&mut tx).await?;
-        player.update(&mut tx).await?;
-        tx.commit().await?;
+        ship_queue.insert(&mut *exec).await?;
+        player.update(&mut *exec).await?;
 
         Ok(Some(ship_queue))
     }
 }
 
+pub const PENDING_COUNT_HEADER: &str = "X-Pending-Count";
+pub const MAX_PENDING_HEADER: &str = "X-Max-Pending";
+
+/// The queues of a system are built one after another, so their number is capped to avoid endless productions.
+/// Zero means the queues are unlimited.
+pub fn get_max_pending_queues() -> Option<usize> {
+    get_env("SHIP_QUEUE_MAX_PENDING", "0").parse().ok().filter(|n| *n > 0)
+}
+
+fn check_pending_queues(nb_pending: usize, max_pending: Option<usize>) -> Result<()> {
+    match max_pending {
+        Some(max) if nb_pending >= max => Err(InternalError::Conflict.into()),
+        _ => Ok(()),
+    }
+}
 
 #[post("/")]
 pub async fn add_ship_queue(
@@ -239,6 +429,10 @@ pub async fn add_ship_queue(
     if system.player.clone() != Some(player.id.clone()) {
         return Err(InternalError::AccessDenied.into());
     }
+    // Queueing no ships at all makes no sense, and bigger quantities would overflow
+    if json_data.quantity == 0 || json_data.quantity > u16::MAX as usize {
+        return Err(InternalError::ValidationFailed{ field: "quantity" }.into());
+    }
     let ship_queue = ShipQueue::schedule(
         &mut player,
         system.id,
@@ -247,7 +441,7 @@ pub async fn add_ship_queue(
         false,
         None,
         game.game_speed,
-        &state.db_pool
+        &state
     ).await?.unwrap();
 
     let sq = ship_queue.clone();
@@ -270,5 +464,104 @@ pub async fn get_ship_queues(state: web::Data<AppState>, info: web::Path<(GameID
     if system.player.clone() != Some(player.id.clone()) {
         return Err(InternalError::AccessDenied.into());
     }
-    Ok(HttpResponse::Ok().json(ShipQueue::find_by_system(system.id, &state.db_pool).await?))
+    let queues = ShipQueue::find_by_system(system.id, &state.db_pool).await?;
+    // The pending count is sent aside the list, so the response remains a bare array of queues
+    let mut response = HttpResponse::Ok();
+    response.header(PENDING_COUNT_HEADER, queues.len().to_string());
+    if let Some(max_pending) = get_max_pending_queues() {
+        response.header(MAX_PENDING_HEADER, max_pending.to_string());
+    }
+    Ok(response.json(queues))
+}
+
+#[get("/estimate/")]
+pub async fn estimate_ship_queue(
+    state: web::Data<AppState>,
+    info: web::Path<(GameID, SystemID)>,
+    query: web::Query<ShipQuantityData>,
+    claims: Claims
+) -> Result<HttpResponse> {
+    let (g, s, p) = join!(
+        Game::find(info.0, &state.db_pool),
+        System::find(info.1, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    let game = g?;
+    let system = s?;
+    let player = p?;
+
+    if system.player.clone() != Some(player.id.clone()) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    if query.quantity == 0 || query.quantity > u16::MAX as usize {
+        return Err(InternalError::ValidationFailed{ field: "quantity" }.into());
+    }
+    Ok(HttpResponse::Ok().json(ShipQueue::estimate(
+        &player,
+        system.id,
+        query.category,
+        query.quantity as u16,
+        game.game_speed,
+        &state
+    ).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_can_be_produced() {
+        let ship_queue = get_ship_queue_mock();
+
+        assert!(ship_queue.can_be_produced(Some(ship_queue.player), true));
+        assert!(!ship_queue.can_be_produced(Some(ship_queue.player), false));
+        assert!(!ship_queue.can_be_produced(None, true));
+    }
+
+    #[test]
+    fn test_cannot_be_produced_after_conquest() {
+        let ship_queue = get_ship_queue_mock();
+        let conqueror = PlayerID(Uuid::new_v4());
+
+        assert!(!ship_queue.can_be_produced(Some(conqueror), true));
+    }
+
+    #[test]
+    fn test_estimate() {
+        let ship_model = ShipModelCategory::Corvette.to_data();
+        let started_at = Time::from(1_600_000_000);
+        let estimate = ShipQueueEstimate::new(ship_model, 3, started_at, GameOptionSpeed::Medium);
+
+        // Three corvettes cost 140 each and take 1.5 seconds each to build
+        assert_eq!(420, estimate.cost);
+        assert_eq!(started_at, estimate.started_at);
+        assert_eq!(1_600_000_004_500, i64::from(estimate.finished_at));
+
+        let fast_estimate = ShipQueueEstimate::new(ship_model, 3, started_at, GameOptionSpeed::Fast);
+        assert_eq!(420, fast_estimate.cost);
+        assert_eq!(1_600_000_003_600, i64::from(fast_estimate.finished_at));
+    }
+
+    #[test]
+    fn test_check_pending_queues() {
+        assert!(check_pending_queues(1000, None).is_ok());
+        assert!(check_pending_queues(4, Some(5)).is_ok());
+        assert!(check_pending_queues(5, Some(5)).is_err());
+        assert!(check_pending_queues(6, Some(5)).is_err());
+    }
+
+    fn get_ship_queue_mock() -> ShipQueue {
+        ShipQueue{
+            id: ShipQueueID(Uuid::new_v4()),
+            system: SystemID(Uuid::new_v4()),
+            player: PlayerID(Uuid::new_v4()),
+            category: ShipModelCategory::Fighter,
+            quantity: 10,
+            assigned_fleet: None,
+            created_at: Time::now(),
+            started_at: Time::now(),
+            finished_at: Time::now(),
+        }
+    }
 }
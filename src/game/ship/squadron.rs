@@ -51,13 +51,30 @@ impl Squadron {
             .fetch_all(db_pool).await.map_err(ServerError::from)
     }
 
+    /// Locks all the stationed ships of the system until the end of the transaction
+    pub async fn find_by_system_for_update<E>(sid: SystemID, exec: &mut E) -> Result<Vec<Self>>
+        where E: Executor<Database = Postgres> {
+        sqlx::query_as("SELECT * FROM map__system_squadrons WHERE system_id = $1 FOR UPDATE")
+            .bind(Uuid::from(sid))
+            .fetch_all(&mut *exec).await.map_err(ServerError::from)
+    }
+
     pub async fn find_by_system_and_category(sid: SystemID, category: ShipModelCategory, db_pool: &PgPool) -> Result<Option<Self>> {
         sqlx::query_as("SELECT * FROM map__system_squadrons WHERE system_id = $1 AND category = $2")
             .bind(Uuid::from(sid))
             .bind(category)
             .fetch_optional(db_pool).await.map_err(ServerError::from)
     }
-    
+
+    /// Locks the stationed ships until the end of the transaction, for them to be assigned
+    pub async fn find_by_system_and_category_for_update<E>(sid: SystemID, category: ShipModelCategory, exec: &mut E) -> Result<Option<Self>>
+        where E: Executor<Database = Postgres> {
+        sqlx::query_as("SELECT * FROM map__system_squadrons WHERE system_id = $1 AND category = $2 FOR UPDATE")
+            .bind(Uuid::from(sid))
+            .bind(category)
+            .fetch_optional(&mut *exec).await.map_err(ServerError::from)
+    }
+
     pub async fn insert<E>(&self, exec: &mut E) -> Result<u64>
     where
         E: Executor<Database = Postgres> {
@@ -1,4 +1,4 @@
-use actix_web::{get, post, web, HttpResponse};
+use actix_web::{delete, get, post, web, HttpResponse};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 use chrono::{DateTime, Duration, Utc};
@@ -7,6 +7,7 @@ use sqlx_core::row::Row;
 use futures::executor::block_on;
 use crate::{
     task,
+    cancel_task,
     AppState,
     lib::{
         Result,
@@ -18,7 +19,7 @@ use crate::{
     game::{
         game::{
             game::{Game, GameID},
-            server::{GameServer, GameServerTask},
+            server::{GameServer, GameServerTask, GameNotifyFactionMessage},
             option::GameOptionSpeed
         },
         system::system::{System, SystemID},
@@ -53,6 +54,7 @@ pub enum BuildingStatus {
 pub enum BuildingKind {
     Mine,
     Portal,
+    Research,
     Shipyard
 }
 
@@ -66,6 +68,34 @@ pub struct BuildingData {
     pub construction_time: u16,
 }
 
+/// The building data with its construction time adjusted to the speed of a game
+#[derive(Serialize, Clone)]
+pub struct GameBuildingData {
+    #[serde(flatten)]
+    pub data: BuildingData,
+    pub construction_seconds: i64,
+}
+
+/// Systems only have room for a single building
+const MAX_BUILDINGS_PER_SYSTEM: usize = 1;
+
+/// Reason preventing the construction of a building in a system
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BuildingUnavailability {
+    NoSlotLeft,
+    NotEnoughMoney,
+}
+
+/// Whether a building can be constructed in a given system by its owner
+#[derive(Serialize, Clone)]
+pub struct AvailableBuilding {
+    #[serde(flatten)]
+    pub data: GameBuildingData,
+    pub can_build: bool,
+    pub reason: Option<BuildingUnavailability>,
+}
+
 #[derive(Deserialize, Clone)]
 pub struct BuildingRequest {
     pub kind: BuildingKind,
@@ -75,6 +105,15 @@ impl From<BuildingID> for Uuid {
     fn from(bid: BuildingID) -> Self { bid.0 }
 }
 
+impl From<BuildingUnavailability> for InternalError {
+    fn from(reason: BuildingUnavailability) -> Self {
+        match reason {
+            BuildingUnavailability::NoSlotLeft => InternalError::Conflict,
+            BuildingUnavailability::NotEnoughMoney => InternalError::NotEnoughMoney,
+        }
+    }
+}
+
 impl GameServerTask for Building {
     fn get_task_id(&self) -> String {
         self.id.0.to_string()
@@ -99,6 +138,13 @@ impl<'a> FromRow<'a, PgRow<'a>> for Building {
 }
 
 impl BuildingKind {
+    pub const ALL: [BuildingKind; 4] = [
+        BuildingKind::Mine,
+        BuildingKind::Portal,
+        BuildingKind::Research,
+        BuildingKind::Shipyard,
+    ];
+
     pub const fn to_data(self) -> BuildingData {
         match self {
             BuildingKind::Mine => BuildingData{
@@ -111,6 +157,11 @@ impl BuildingKind {
                 construction_time: 60,
                 kind: BuildingKind::Portal,
             },
+            BuildingKind::Research => BuildingData {
+                cost: 1500,
+                construction_time: 40,
+                kind: BuildingKind::Research,
+            },
             BuildingKind::Shipyard => BuildingData {
                 cost: 500,
                 construction_time: 20,
@@ -160,6 +211,12 @@ impl Building {
             .fetch_all(db_pool).await.map_err(ServerError::from)
     }
 
+    pub async fn find_by_systems(ids: Vec<SystemID>, db_pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as("SELECT * FROM map__system_buildings WHERE system_id = ANY($1)")
+            .bind(ids.into_iter().map(Uuid::from).collect::<Vec<Uuid>>())
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
     pub async fn find_by_kind(kind: BuildingKind, db_pool: &PgPool) -> Result<Vec<Building>> {
         sqlx::query_as("SELECT * FROM map__system_buildings WHERE kind = $1")
             .bind(kind)
@@ -187,6 +244,20 @@ impl Building {
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
+    /// Delays the buildings under construction during the pause. The ones ordered meanwhile only start once it is resumed.
+    /// The construction start moves along with the deadline
+    pub async fn postpone_deadlines<E>(gid: GameID, paused_at: Time, resumed_at: Time, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE map__system_buildings SET
+            created_at = created_at + ($3 - GREATEST(created_at, $2)),
+            built_at = built_at + ($3 - GREATEST(created_at, $2))
+            WHERE built_at > $2 AND system_id IN (SELECT id FROM map__systems WHERE game_id = $1)")
+            .bind(Uuid::from(gid))
+            .bind(paused_at)
+            .bind(resumed_at)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
     pub async fn update<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres> {
         sqlx::query("UPDATE map__system_buildings SET status = $2 WHERE id = $1")
@@ -195,13 +266,27 @@ impl Building {
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
+    /// Returns zero when the construction ended meanwhile
+    pub async fn remove_constructing<E>(&self, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("DELETE FROM map__system_buildings WHERE id = $1 AND system_id = $2 AND status = $3")
+            .bind(Uuid::from(self.id))
+            .bind(Uuid::from(self.system))
+            .bind(BuildingStatus::Constructing)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
     async fn construct(&mut self, server: &GameServer) -> Result<()> {
         let player = Player::find_system_owner(self.system.clone(), &server.state.db_pool).await?;
 
         self.status = BuildingStatus::Operational;
 
+        // The building is gone if its construction was cancelled before its task could be
         let mut tx = server.state.db_pool.begin().await?;
-        self.update(&mut tx).await?;
+        if self.update(&mut tx).await? == 0 {
+            tx.rollback().await?;
+            return Ok(());
+        }
         tx.commit().await?;
 
         log(
@@ -250,11 +335,9 @@ pub async fn create_building(
     }
 
     let buildings = Building::find_by_system(system.id.clone(), &state.db_pool).await?;
-    if ! buildings.is_empty() {
-        return Err(InternalError::Conflict.into());
-    }
-
     let building_data = data.kind.to_data();
+    check_construction(building_data, &buildings, player.wallet).map_err(InternalError::from)?;
+
     player.spend(building_data.cost as usize)?;
 
     let building = Building::new(info.1.clone(), data.kind, building_data, game.game_speed);
@@ -265,18 +348,141 @@ pub async fn create_building(
     tx.commit().await?;
 
     let mut b = building.clone();
-    state.games().get(&info.0).unwrap().do_send(task!(building -> move |gs: &GameServer| block_on(b.construct(gs))));
+    state.games().get(&info.0).unwrap().do_send(task!(building -> move |gs: &GameServer| block_on(b.construct(gs))).with_owner(Some(player.id)));
 
     Ok(HttpResponse::Created().json(building))
 }
 
+/// Stops the construction of a building and refunds its whole cost
+#[delete("/{building_id}/cancel/")]
+pub async fn cancel_building(
+    state: web::Data<AppState>,
+    info: web::Path<(GameID, SystemID, BuildingID)>,
+    claims: Claims
+)
+    -> Result<HttpResponse>
+{
+    let (s, b, p) = futures::join!(
+        System::find(info.1, &state.db_pool),
+        Building::find(info.2, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    let system = s?;
+    let building = b?;
+    let player = p?;
+
+    if system.game != info.0 || system.player != Some(player.id) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    check_cancellation(&building, system.id)?;
+
+    let game = state.games().get(&info.0).cloned().ok_or(InternalError::GameUnknown)?;
+
+    // The building is only refunded if it was still under construction when deleted.
+    // Its task is cancelled once the deletion is committed, a construction ending meanwhile finds no building
+    let mut tx = state.db_pool.begin().await?;
+    if building.remove_constructing(&mut tx).await? == 0 {
+        tx.rollback().await?;
+        return Err(InternalError::Conflict.into());
+    }
+    Player::deposit(player.id, building.kind.to_data().cost as usize, &mut tx).await?;
+    tx.commit().await?;
+    game.do_send(cancel_task!(building));
+
+    log(
+        gelf::Level::Informational,
+        "Building cancelled",
+        &format!("The construction of a {:?} belonging to {} has been cancelled", building.kind, player.username),
+        vec![
+            ("player_id", player.id.0.to_string()),
+            ("system_id", system.id.0.to_string())
+        ],
+        &state.logger
+    );
+
+    if let Some(faction) = player.faction {
+        game.do_send(GameNotifyFactionMessage(faction, protocol::Message::new(
+            protocol::Action::BuildingCancelled,
+            building,
+            None,
+        )));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The building must belong to the system of the path, which the player owns
+fn check_cancellation(building: &Building, sid: SystemID) -> Result<()> {
+    if building.system != sid {
+        return Err(InternalError::AccessDenied.into());
+    }
+    if building.status != BuildingStatus::Constructing {
+        return Err(InternalError::Conflict.into());
+    }
+    Ok(())
+}
+
+fn check_construction(data: BuildingData, buildings: &[Building], wallet: usize) -> std::result::Result<(), BuildingUnavailability> {
+    if buildings.len() >= MAX_BUILDINGS_PER_SYSTEM {
+        return Err(BuildingUnavailability::NoSlotLeft);
+    }
+    if wallet < data.cost as usize {
+        return Err(BuildingUnavailability::NotEnoughMoney);
+    }
+    Ok(())
+}
+
+fn get_available_buildings(buildings: &[Building], wallet: usize, game_speed: GameOptionSpeed) -> Vec<AvailableBuilding> {
+    get_buildings_data_for_speed(game_speed).into_iter()
+        .map(|data| {
+            let reason = check_construction(data.data, buildings, wallet).err();
+            AvailableBuilding{ data, can_build: reason.is_none(), reason }
+        })
+        .collect()
+}
+
+fn get_buildings_data_for_speed(game_speed: GameOptionSpeed) -> Vec<GameBuildingData> {
+    BuildingKind::ALL.iter()
+        .map(|kind| kind.to_data())
+        .map(|data| GameBuildingData{ data, construction_seconds: data.into_duration(game_speed).num_seconds() })
+        .collect()
+}
+
 #[get("/buildings/")]
 pub async fn get_buildings_data() -> Result<HttpResponse> {
-    Ok(HttpResponse::Ok().json(vec![
-        BuildingKind::Mine.to_data(),
-        BuildingKind::Portal.to_data(),
-        BuildingKind::Shipyard.to_data(),
-    ]))
+    Ok(HttpResponse::Ok().json(BuildingKind::ALL.iter().map(|kind| kind.to_data()).collect::<Vec<BuildingData>>()))
+}
+
+#[get("/{id}/buildings-data/")]
+pub async fn get_game_buildings_data(state: web::Data<AppState>, info: web::Path<(GameID,)>, claims: Claims) -> Result<HttpResponse> {
+    let (g, p) = futures::join!(
+        Game::find(info.0, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    let game = g?;
+    if p?.game != Some(game.id) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    Ok(HttpResponse::Ok().json(get_buildings_data_for_speed(game.game_speed)))
+}
+
+#[get("/{system_id}/available-buildings/")]
+pub async fn get_system_available_buildings(state: web::Data<AppState>, info: web::Path<(GameID, SystemID)>, claims: Claims) -> Result<HttpResponse> {
+    let (g, s, p) = futures::join!(
+        Game::find(info.0, &state.db_pool),
+        System::find(info.1, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    let game = g?;
+    let system = s?;
+    let player = p?;
+
+    if system.player != Some(player.id) || system.game != game.id {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let buildings = Building::find_by_system(system.id, &state.db_pool).await?;
+
+    Ok(HttpResponse::Ok().json(get_available_buildings(&buildings, player.wallet, game.game_speed)))
 }
 
 #[cfg(test)]
@@ -291,4 +497,49 @@ mod tests {
         assert_eq!(20, shipyard_data.into_duration(GameOptionSpeed::Medium).num_seconds());
         assert_eq!(16, shipyard_data.into_duration(GameOptionSpeed::Fast).num_seconds());
     }
+
+    #[test]
+    fn test_get_buildings_data_for_speed() {
+        let buildings_data = get_buildings_data_for_speed(GameOptionSpeed::Slow);
+
+        assert_eq!(BuildingKind::ALL.len(), buildings_data.len());
+
+        let shipyard_data = buildings_data.iter().find(|b| b.data.kind == BuildingKind::Shipyard).unwrap();
+        assert_eq!(20, shipyard_data.data.construction_time);
+        assert_eq!(24, shipyard_data.construction_seconds);
+    }
+
+    #[test]
+    fn test_get_available_buildings() {
+        let sid = SystemID(Uuid::new_v4());
+        let available_buildings = get_available_buildings(&[], 600, GameOptionSpeed::Medium);
+
+        assert_eq!(BuildingKind::ALL.len(), available_buildings.len());
+        let shipyard = available_buildings.iter().find(|b| b.data.data.kind == BuildingKind::Shipyard).unwrap();
+        assert!(shipyard.can_build);
+        assert_eq!(None, shipyard.reason);
+        assert_eq!(20, shipyard.data.construction_seconds);
+        let portal = available_buildings.iter().find(|b| b.data.data.kind == BuildingKind::Portal).unwrap();
+        assert!(!portal.can_build);
+        assert_eq!(Some(BuildingUnavailability::NotEnoughMoney), portal.reason);
+
+        let mine = Building::new(sid, BuildingKind::Mine, BuildingKind::Mine.to_data(), GameOptionSpeed::Medium);
+        assert!(get_available_buildings(&[mine], 10000, GameOptionSpeed::Medium).iter()
+            .all(|b| !b.can_build && b.reason == Some(BuildingUnavailability::NoSlotLeft)));
+    }
+
+    #[test]
+    fn test_check_cancellation() {
+        let sid = SystemID(Uuid::new_v4());
+        let mut building = Building::new(sid, BuildingKind::Portal, BuildingKind::Portal.to_data(), GameOptionSpeed::Medium);
+
+        assert!(check_cancellation(&building, sid).is_ok());
+        assert!(matches!(
+            check_cancellation(&building, SystemID(Uuid::new_v4())),
+            Err(ServerError::InternalError(InternalError::AccessDenied))
+        ));
+
+        building.status = BuildingStatus::Operational;
+        assert!(check_cancellation(&building, sid).is_err());
+    }
 }
@@ -0,0 +1,289 @@
+use actix_web::{get, post, web, HttpResponse};
+use sqlx::{PgPool, postgres::{PgRow, PgQueryAs}, FromRow, Executor, Error, Postgres};
+use sqlx_core::row::Row;
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use crate::{
+    lib::{
+        Result,
+        auth::Claims,
+        error::{ServerError, InternalError},
+        log::{log, Loggable},
+    },
+    game::{
+        faction::FactionID,
+        fleet::{
+            combat::battle::update_fleet,
+            fleet::Fleet,
+            squadron::FleetSquadron,
+        },
+        game::{
+            game::GameID,
+            server::GameServer,
+        },
+        player::Player,
+        system::system::{System, SystemID},
+        technology::{Technology, TechnologyKind},
+    },
+    ws::protocol,
+    AppState,
+};
+
+pub const MINE_COST: usize = 30;
+pub const MINEFIELD_CAPACITY: u16 = 20;
+/// Flat damage dealt by each mine to every squadron of the triggering fleet
+pub const MINE_DAMAGE: u32 = 20;
+
+#[derive(Serialize, Clone)]
+pub struct Minefield {
+    pub system: SystemID,
+    pub quantity: u16,
+}
+
+#[derive(Deserialize)]
+pub struct MinefieldRequest {
+    pub quantity: u16,
+}
+
+#[derive(Serialize, Clone)]
+pub struct MinesTriggeredData {
+    pub system: SystemID,
+    pub fleet: Fleet,
+    pub damage: u32,
+    pub destroyed_ships: u16,
+}
+
+impl<'a> FromRow<'a, PgRow<'a>> for Minefield {
+    fn from_row(row: &PgRow) -> std::result::Result<Self, Error> {
+        Ok(Minefield {
+            system: row.try_get("system_id").map(SystemID)?,
+            quantity: row.try_get::<i32, _>("quantity")? as u16,
+        })
+    }
+}
+
+impl Minefield {
+    pub async fn find(sid: SystemID, db_pool: &PgPool) -> Result<Self> {
+        let minefield: Option<Self> = sqlx::query_as("SELECT * FROM map__system_minefields WHERE system_id = $1")
+            .bind(Uuid::from(sid))
+            .fetch_optional(db_pool).await.map_err(ServerError::from)?;
+
+        Ok(minefield.unwrap_or(Minefield{ system: sid, quantity: 0 }))
+    }
+
+    /// Locks the mines of the system until the end of the transaction, for more to be deployed
+    pub async fn find_for_update<E>(sid: SystemID, exec: &mut E) -> Result<Self>
+        where E: Executor<Database = Postgres> {
+        let minefield: Option<Self> = sqlx::query_as("SELECT * FROM map__system_minefields WHERE system_id = $1 FOR UPDATE")
+            .bind(Uuid::from(sid))
+            .fetch_optional(&mut *exec).await.map_err(ServerError::from)?;
+
+        Ok(minefield.unwrap_or(Minefield{ system: sid, quantity: 0 }))
+    }
+
+    pub async fn save<E>(&self, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("INSERT INTO map__system_minefields (system_id, quantity) VALUES($1, $2)
+            ON CONFLICT (system_id) DO UPDATE SET quantity = $2")
+            .bind(Uuid::from(self.system))
+            .bind(self.quantity as i32)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
+    pub async fn remove<E>(&self, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("DELETE FROM map__system_minefields WHERE system_id = $1")
+            .bind(Uuid::from(self.system))
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
+    pub const fn get_damage(&self) -> u32 {
+        self.quantity as u32 * MINE_DAMAGE
+    }
+
+    /// Applies the mines damage to each squadron of the fleet and returns the number of destroyed ships
+    pub fn detonate(&self, fleet: &mut Fleet, technologies: &[TechnologyKind]) -> u16 {
+        let damage = self.get_damage();
+        let mut destroyed_ships = 0;
+
+        for squadron in fleet.squadrons.iter_mut() {
+            let hit_points = squadron.category.to_data().with_technologies(technologies).hit_points as u32;
+            let casualties = std::cmp::min(squadron.quantity as u32, damage / hit_points) as u16;
+
+            squadron.quantity -= casualties;
+            destroyed_ships += casualties;
+        }
+        destroyed_ships
+    }
+
+    /// Called when a hostile fleet arrives in a mined system, before any battle can start.
+    /// All the mines are consumed.
+    pub async fn trigger(system: &System, mut fleet: Fleet, player: &Player, owner_faction: Option<FactionID>, server: &GameServer) -> Result<Fleet> {
+        let minefield = Minefield::find(system.id, &server.state.db_pool).await?;
+        if minefield.quantity == 0 {
+            return Ok(fleet);
+        }
+        let technologies = match (player.game, player.faction) {
+            (Some(gid), Some(fid)) => Technology::find_unlocked_kinds(gid, fid, &server.state.db_pool).await?,
+            _ => vec![],
+        };
+        let destroyed_ships = minefield.detonate(&mut fleet, &technologies);
+
+        let mut tx = server.state.db_pool.begin().await?;
+        fleet.is_destroyed = update_fleet(fleet.clone(), &mut tx).await?;
+        minefield.remove(&mut tx).await?;
+        tx.commit().await?;
+
+        fleet.squadrons.retain(FleetSquadron::can_fight);
+
+        log(
+            gelf::Level::Informational,
+            "Mines triggered",
+            &format!("Fleet {} has triggered {} mines on system {}", fleet.to_log_message(), minefield.quantity, system.to_log_message()),
+            vec![
+                ("fleet_id", fleet.id.to_string()),
+                ("system_id", system.id.0.to_string()),
+                ("destroyed_ships", destroyed_ships.to_string()),
+            ],
+            &server.state.logger
+        );
+
+        let message = protocol::Message::new(
+            protocol::Action::MinesTriggered,
+            MinesTriggeredData{
+                system: system.id,
+                fleet: fleet.clone(),
+                damage: minefield.get_damage(),
+                destroyed_ships,
+            },
+            None,
+        );
+        if let Some(fid) = owner_faction {
+            server.faction_broadcast(fid, message.clone()).await?;
+        }
+        server.player_broadcast(&fleet.player, &message);
+
+        Ok(fleet)
+    }
+}
+
+#[get("/")]
+pub async fn get_minefield(state: web::Data<AppState>, info: web::Path<(GameID, SystemID)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    let (s, p) = futures::join!(
+        System::find(info.1, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    let system = s?;
+    let player = p?;
+
+    // Mines are only known by the faction which owns the system
+    let owner = Player::find(system.player.ok_or(InternalError::AccessDenied)?, &state.db_pool).await?;
+    if owner.faction != player.faction {
+        return Err(InternalError::AccessDenied.into());
+    }
+    Ok(HttpResponse::Ok().json(Minefield::find(system.id, &state.db_pool).await?))
+}
+
+#[post("/")]
+pub async fn deploy_mines(
+    state: web::Data<AppState>,
+    info: web::Path<(GameID, SystemID)>,
+    json_data: web::Json<MinefieldRequest>,
+    claims: Claims
+) -> Result<HttpResponse> {
+    let system = System::find(info.1, &state.db_pool).await?;
+
+    if system.game != info.0 || system.player != Some(claims.pid) {
+        return Err(InternalError::AccessDenied.into());
+    }
+
+    // The system is locked first, so that concurrent deployments add up instead of overwriting each other.
+    // An empty system has no minefield row to lock yet
+    let mut tx = state.db_pool.begin().await?;
+    if System::lock(system.id, &mut tx).await? != Some(claims.pid) {
+        tx.rollback().await?;
+        return Err(InternalError::AccessDenied.into());
+    }
+    let mut minefield = Minefield::find_for_update(system.id, &mut tx).await?;
+    if let Err(error) = check_deployment(minefield.quantity, json_data.quantity) {
+        tx.rollback().await?;
+        return Err(error);
+    }
+    Player::withdraw(claims.pid, MINE_COST * json_data.quantity as usize, &mut tx).await?;
+    minefield.quantity += json_data.quantity;
+    minefield.save(&mut tx).await?;
+    tx.commit().await?;
+
+    Ok(HttpResponse::Created().json(minefield))
+}
+
+fn check_deployment(deployed_quantity: u16, quantity: u16) -> Result<()> {
+    if quantity == 0 || deployed_quantity as u32 + quantity as u32 > MINEFIELD_CAPACITY as u32 {
+        return Err(InternalError::Conflict.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{
+        fleet::{
+            fleet::FleetID,
+            formation::FleetFormation,
+            squadron::{FleetSquadron, FleetSquadronID},
+        },
+        player::PlayerID,
+        ship::model::ShipModelCategory,
+    };
+
+    #[test]
+    fn test_check_deployment() {
+        assert!(check_deployment(0, MINEFIELD_CAPACITY).is_ok());
+        assert!(check_deployment(5, 0).is_err());
+        assert!(check_deployment(MINEFIELD_CAPACITY - 1, 2).is_err());
+        assert!(check_deployment(MINEFIELD_CAPACITY, u16::MAX).is_err());
+    }
+
+    #[test]
+    fn test_detonate() {
+        let minefield = Minefield{ system: SystemID(Uuid::new_v4()), quantity: 10 };
+        let mut fleet = get_fleet_mock(vec![
+            (ShipModelCategory::Fighter, 15),
+            (ShipModelCategory::Cruiser, 5),
+        ]);
+
+        let destroyed_ships = minefield.detonate(&mut fleet, &[]);
+
+        // 200 damage points: 20 fighters of 10 HP, or 1 cruiser of 200 HP
+        assert_eq!(0, fleet.squadrons[0].quantity);
+        assert_eq!(4, fleet.squadrons[1].quantity);
+        assert_eq!(16, destroyed_ships);
+    }
+
+    fn get_fleet_mock(squadrons: Vec<(ShipModelCategory, u16)>) -> Fleet {
+        let id = FleetID(Uuid::new_v4());
+
+        Fleet{
+            id,
+            name: None,
+            player: PlayerID(Uuid::new_v4()),
+            system: SystemID(Uuid::new_v4()),
+            destination_system: None,
+            destination_arrival_date: None,
+            departure_date: None,
+            squadrons: squadrons.into_iter().map(|(category, quantity)| FleetSquadron{
+                id: FleetSquadronID(Uuid::new_v4()),
+                fleet: id,
+                formation: FleetFormation::Center,
+                category,
+                quantity,
+                max_quantity: quantity,
+                auto_reinforce: false,
+            }).collect(),
+            is_destroyed: false,
+        }
+    }
+}
@@ -1,2 +1,3 @@
 pub mod building;
+pub mod minefield;
 pub mod system;
\ No newline at end of file
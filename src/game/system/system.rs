@@ -1,12 +1,13 @@
-use actix_web::{get, web, HttpResponse};
+use actix_web::{get, post, web, HttpResponse};
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use crate::{
     AppState,
     lib::{
         Result,
-        log::Loggable,
+        auth::Claims,
+        log::{log, Loggable},
         pagination::{Paginator, new_paginated_response},
         error::{ServerError, InternalError}
     },
@@ -18,13 +19,15 @@ use crate::{
         },
         game::{
             game::GameID,
-            option::{GameOptionMapSize, GameOptionSpeed},
+            option::{GameOptionMapSize, GameOptionSpeed, GameOptionVictoryMode},
         },
         player::{PlayerID, Player},
+        ship::{model::ShipModelCategory, queue::ShipQueue},
         system::{
             building::{Building, BuildingStatus, BuildingKind},
         },
     },
+    ws::protocol,
 };
 use galaxy_rs::{Point, DataPoint};
 use sqlx::{PgPool, postgres::{PgRow, PgQueryAs}, FromRow, Executor, Error, Postgres};
@@ -61,6 +64,13 @@ pub enum SystemKind {
     VictorySystem,
 }
 
+/// A system generated for a map preview, which is never persisted
+#[derive(Serialize, Clone)]
+pub struct SystemPreview {
+    pub kind: SystemKind,
+    pub coordinates: Coordinates,
+}
+
 #[derive(Serialize, Clone)]
 pub struct SystemDominion {
     pub faction_id: FactionID,
@@ -73,6 +83,13 @@ pub struct Coordinates {
     pub y: f64
 }
 
+#[derive(Debug, Serialize, Clone)]
+pub struct MapBounds {
+    pub min: Coordinates,
+    pub max: Coordinates,
+    pub center: Coordinates,
+}
+
 impl Coordinates {
     pub fn polar(r:f64, theta:f64) -> Coordinates {
         Coordinates {
@@ -118,6 +135,20 @@ impl<'a> FromRow<'a, PgRow<'a>> for Coordinates {
     }
 }
 
+impl<'a> FromRow<'a, PgRow<'a>> for MapBounds {
+    fn from_row(row: &PgRow) -> std::result::Result<Self, Error> {
+        let coord = |column: &str| -> std::result::Result<f64, Error> {
+            Ok(row.try_get::<Option<f64>, _>(column)?.unwrap_or(0.0))
+        };
+
+        Ok(MapBounds{
+            min: Coordinates::new(coord("min_x")?, coord("min_y")?),
+            max: Coordinates::new(coord("max_x")?, coord("max_y")?),
+            center: Coordinates::new(coord("center_x")?, coord("center_y")?),
+        })
+    }
+}
+
 impl<'a> FromRow<'a, PgRow<'a>> for System {
     fn from_row(row: &PgRow) -> std::result::Result<Self, Error> {
         let id : Uuid = row.try_get("id")?;
@@ -179,11 +210,13 @@ impl System {
             .fetch_all(db_pool).await.map_err(ServerError::from)
     }
 
-    pub async fn find_all(gid: &GameID, limit: i64, offset: i64, db_pool: &PgPool) -> Result<Vec<System>> {
-        sqlx::query_as("SELECT * FROM map__systems WHERE game_id = $1 LIMIT $2 OFFSET $3")
+    /// All kinds of systems are returned when none is given
+    pub async fn find_all(gid: &GameID, kind: Option<SystemKind>, limit: i64, offset: i64, db_pool: &PgPool) -> Result<Vec<System>> {
+        sqlx::query_as("SELECT * FROM map__systems WHERE game_id = $1 AND ($4::SMALLINT IS NULL OR kind = $4) LIMIT $2 OFFSET $3")
             .bind(Uuid::from(gid.clone()))
             .bind(limit)
             .bind(offset)
+            .bind(kind.map(i16::from))
             .fetch_all(db_pool).await.map_err(ServerError::from)
     }
 
@@ -198,6 +231,49 @@ impl System {
         .fetch_all(db_pool).await.map_err(ServerError::from)
     }
 
+    pub async fn find_by_faction(gid: GameID, fid: FactionID, db_pool: &PgPool) -> Result<Vec<System>> {
+        sqlx::query_as(
+            "SELECT s.* FROM map__systems s
+            INNER JOIN player__players p ON s.player_id = p.id
+            WHERE s.game_id = $1 AND p.faction_id = $2")
+        .bind(Uuid::from(gid))
+        .bind(i32::from(fid))
+        .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    /// Aggregates the extents of the galaxy. When the map has not been generated yet, every
+    /// coordinate defaults to the origin.
+    pub async fn find_bounds(gid: GameID, db_pool: &PgPool) -> Result<MapBounds> {
+        sqlx::query_as(
+            "SELECT MIN(coord_x) as min_x, MIN(coord_y) as min_y,
+            MAX(coord_x) as max_x, MAX(coord_y) as max_y,
+            AVG(coord_x) as center_x, AVG(coord_y) as center_y
+            FROM map__systems WHERE game_id = $1")
+        .bind(Uuid::from(gid))
+        .fetch_one(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub async fn find_by_ids(ids: Vec<SystemID>, db_pool: &PgPool) -> Result<Vec<System>> {
+        sqlx::query_as("SELECT * FROM map__systems WHERE id = ANY($1)")
+            .bind(ids.into_iter().map(Uuid::from).collect::<Vec<Uuid>>())
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub async fn find_by_player(gid: GameID, pid: PlayerID, db_pool: &PgPool) -> Result<Vec<System>> {
+        sqlx::query_as("SELECT * FROM map__systems WHERE game_id = $1 AND player_id = $2")
+            .bind(Uuid::from(gid))
+            .bind(Uuid::from(pid))
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub async fn find_ids_by_player(gid: GameID, pid: PlayerID, db_pool: &PgPool) -> Result<Vec<SystemID>> {
+        let ids: Vec<(Uuid,)> = sqlx::query_as("SELECT id FROM map__systems WHERE game_id = $1 AND player_id = $2")
+            .bind(Uuid::from(gid))
+            .bind(Uuid::from(pid))
+            .fetch_all(db_pool).await.map_err(ServerError::from)?;
+        Ok(ids.into_iter().map(|(id,)| SystemID(id)).collect())
+    }
+
     pub async fn count(gid: GameID, db_pool: &PgPool) -> u32 {
         let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM map__systems WHERE game_id = $1")
             .bind(Uuid::from(gid))
@@ -205,6 +281,30 @@ impl System {
         count.0 as u32
     }
 
+    pub async fn count_by_kind(gid: GameID, kind: SystemKind, db_pool: &PgPool) -> Result<u32> {
+        let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM map__systems WHERE game_id = $1 AND kind = $2")
+            .bind(Uuid::from(gid))
+            .bind(i16::from(kind))
+            .fetch_one(db_pool).await.map_err(ServerError::from)?;
+        Ok(count.0 as u32)
+    }
+
+    pub async fn count_by_player(gid: GameID, pid: PlayerID, db_pool: &PgPool) -> Result<u32> {
+        let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM map__systems WHERE game_id = $1 AND player_id = $2")
+            .bind(Uuid::from(gid))
+            .bind(Uuid::from(pid))
+            .fetch_one(db_pool).await.map_err(ServerError::from)?;
+        Ok(count.0 as u32)
+    }
+
+    pub async fn count_by_players(gid: GameID, db_pool: &PgPool) -> Result<HashMap<PlayerID, u32>> {
+        let counts: Vec<(Uuid, i64)> = sqlx::query_as("SELECT player_id, COUNT(*) FROM map__systems WHERE game_id = $1 AND player_id IS NOT NULL GROUP BY player_id")
+            .bind(Uuid::from(gid))
+            .fetch_all(db_pool).await.map_err(ServerError::from)?;
+
+        Ok(counts.into_iter().map(|(pid, count)| (PlayerID(pid), count as u32)).collect())
+    }
+
     pub async fn insert<E>(&self, exec: &mut E) -> Result<u64>
         where E: Executor<Database = Postgres> {
         sqlx::query("INSERT INTO map__systems (id, game_id, player_id, kind, coord_x, coord_y, is_unreachable) VALUES($1, $2, $3, $4, $5, $6, $7)")
@@ -227,6 +327,39 @@ impl System {
             .execute(&mut *exec).await.map_err(ServerError::from)
     }
 
+    /// Locks the system until the end of the transaction, returning its current owner.
+    /// The rows referencing the system, such as ship queues, can still be inserted meanwhile
+    pub async fn lock<E>(sid: SystemID, exec: &mut E) -> Result<Option<PlayerID>>
+        where E: Executor<Database = Postgres> {
+        sqlx::query_as("SELECT player_id FROM map__systems WHERE id = $1 FOR NO KEY UPDATE")
+            .bind(Uuid::from(sid))
+            .fetch_one(&mut *exec).await
+            .map(|(pid,): (Option<Uuid>,)| pid.map(PlayerID))
+            .map_err(ServerError::from)
+    }
+
+    /// A system is contested while a battle or a conquest is ongoing on it
+    pub async fn is_contested<E>(&self, exec: &mut E) -> Result<bool>
+        where E: Executor<Database = Postgres> {
+        sqlx::query_as("SELECT EXISTS(SELECT 1 FROM fleet__combat__battles WHERE system_id = $1 AND ended_at IS NULL)
+            OR EXISTS(SELECT 1 FROM fleet__combat__conquests WHERE system_id = $1 AND is_over = false AND is_stopped = false)")
+            .bind(Uuid::from(self.id))
+            .fetch_one(&mut *exec).await
+            .map(|(is_contested,): (bool,)| is_contested)
+            .map_err(ServerError::from)
+    }
+
+    /// Changes the owner only if the system still belongs to the expected player.
+    /// Returns the number of updated rows, zero meaning the owner was changed meanwhile
+    pub async fn update_owner<E>(&self, expected_owner: Option<PlayerID>, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE map__systems SET player_id = $1 WHERE id = $2 AND player_id IS NOT DISTINCT FROM $3")
+            .bind(self.player.map(Uuid::from))
+            .bind(Uuid::from(self.id))
+            .bind(expected_owner.map(Uuid::from))
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
     pub async fn insert_all<'a, I>(systems_iter: I, pool:&PgPool) -> Result<u64>
         where I : Iterator<Item=&'a System>
     {
@@ -261,15 +394,19 @@ impl System {
     }
 }
 
-pub async fn generate_systems(gid: GameID, map_size: GameOptionMapSize) -> Result<(Vec<System>, u32)> {
+pub async fn generate_systems(gid: GameID, map_size: GameOptionMapSize, victory_mode: GameOptionVictoryMode) -> Result<(Vec<System>, u32)> {
+    generate_systems_with_rng(gid, map_size, victory_mode, &mut rand::thread_rng())
+}
+
+/// The shape of the galaxy is drawn by galaxy-rs, only the kind of the systems depends on the given generator
+pub fn generate_systems_with_rng(gid: GameID, map_size: GameOptionMapSize, victory_mode: GameOptionVictoryMode, rng: &mut impl rand::Rng) -> Result<(Vec<System>, u32)> {
     let graph = map_size.to_galaxy_builder().build(Point { x: 0_f64, y: 0_f64 }).expect("Failed to generate the galaxy map");
 
     let mut probability: f64 = 0.5;
     let mut nb_victory_systems: u32 = 0;
-    let mut rng = rand::thread_rng();
     
     let mut system_list = graph.into_points().map(|DataPoint { point:Point { x, y }, .. }| {
-        let (system, prob) = generate_system(&gid, x, y, probability, &mut rng);
+        let (system, prob) = generate_system(&gid, x, y, probability, rng);
         probability = prob;
         if system.kind == SystemKind::VictorySystem {
             nb_victory_systems += 1;
@@ -293,10 +430,21 @@ pub async fn generate_systems(gid: GameID, map_size: GameOptionMapSize) -> Resul
             .kind = SystemKind::VictorySystem;
         nb_victory_systems += 1;
     }
+    if !victory_mode.has_victory_systems() {
+        remove_victory_systems(&mut system_list);
+        nb_victory_systems = 0;
+    }
     
     Ok((system_list, nb_victory_systems))
 }
 
+/// The systems are drawn regardless of the victory mode, so a given generator always draws the same kinds
+fn remove_victory_systems(systems: &mut [System]) {
+    systems.iter_mut()
+        .filter(|s| s.kind == SystemKind::VictorySystem)
+        .for_each(|s| s.kind = SystemKind::BaseSystem);
+}
+
 fn generate_system(gid: &GameID, x: f64, y: f64, probability: f64, rng: &mut impl rand::Rng) -> (System, f64) {
     let (kind, prob) = generate_system_kind(x, y, probability, rng);
     (System{
@@ -318,21 +466,33 @@ fn generate_system_kind(x: f64, y: f64, probability: f64, rng: &mut impl rand::R
     (SystemKind::BaseSystem, probability + 0.1)
 }
 
-#[allow(clippy::ptr_arg)]
-#[allow(clippy::needless_range_loop)]
-pub async fn assign_systems(players: &Vec<Player>, galaxy:&mut Vec<System>) -> Result<()> {
+const GRID_SIZE : usize = 16;
+const EXCLUSION : usize = 1;
+/// Random picks of a free cell before falling back to a scan of the whole grid
+const MAX_CELL_ATTEMPTS : usize = GRID_SIZE * GRID_SIZE;
+
+/// Grid splitting the galaxy in zones, each faction starting in one of its cells
+#[derive(Default)]
+struct PlacementGrid {
+    /// Number of faction zones whose exclusion area covers each cell
+    exclusions: [[u8;GRID_SIZE];GRID_SIZE],
+    /// Cells already given to a faction
+    cells: HashSet<(usize, usize)>,
+}
 
-    const GRID_SIZE : usize = 16;
-    const EXCLUSION : usize = 1;
+#[allow(clippy::ptr_arg)]
+pub async fn assign_systems(players: &mut Vec<Player>, galaxy:&mut Vec<System>) -> Result<()> {
+    assign_systems_with_rng(players, galaxy, &mut thread_rng())
+}
 
-    let mut rng = thread_rng();
+/// The same players, galaxy and seeded generator always give the same home systems
+#[allow(clippy::ptr_arg)]
+pub fn assign_systems_with_rng(players: &mut Vec<Player>, galaxy:&mut Vec<System>, rng: &mut impl rand::Rng) -> Result<()> {
     let mut faction_cell = HashMap::new();
-    let mut taken : [[bool;GRID_SIZE];GRID_SIZE] = [[false;GRID_SIZE];GRID_SIZE];
+    let mut grid = PlacementGrid::default();
     let mut min : Coordinates = Coordinates { x: std::f64::MAX, y: std::f64::MAX };
     let mut max : Coordinates = Coordinates { x: std::f64::MIN, y: std::f64::MIN };
 
-    let grid_range = Uniform::from(0..GRID_SIZE);
-
     for sys in galaxy.iter() {
         min.x = min.x.min(sys.coordinates.x);
         min.y = min.y.min(sys.coordinates.y);
@@ -343,54 +503,80 @@ pub async fn assign_systems(players: &Vec<Player>, galaxy:&mut Vec<System>) -> R
     let cell_w = (max.x - min.x) / GRID_SIZE as f64;
     let cell_h = (max.y - min.y) / GRID_SIZE as f64;
 
-    for player in players {
+    for player in players.iter_mut() {
         // Take the zone assigned to the player's faction
         // Assigning a new zone when encountering a new faction
-        let (cell_min, cell_max) = faction_cell
-            .entry(player.faction.unwrap())
-            .or_insert_with(|| {
-                let mut cell_x = grid_range.sample(&mut rng);
-                let mut cell_y = grid_range.sample(&mut rng);
-                while taken[cell_x][cell_y] {
-                    cell_x = grid_range.sample(&mut rng);
-                    cell_y = grid_range.sample(&mut rng);
-                }
-
-                // make the place AND its neighbours in a zone which width is defined by the
-                // EXCLUSION constant not usable anymore
-                for i in cell_x.saturating_sub(EXCLUSION)..=(cell_x+EXCLUSION).min(GRID_SIZE-1) {
-                    for j in cell_y.saturating_sub(EXCLUSION)..=(cell_y+EXCLUSION).min(GRID_SIZE-1) {
-                        taken[i][j] = true;
-                    }
-                }
-
-                // the (x, y) coordinates of the topleft corner of the chosen cell
-                //
-                // mul_add : compute min.x + cell_x as f64 * cell_w more efficiently,
-                // see https://doc.rust-lang.org/std/primitive.f64.html#method.mul_add
-                let x = (cell_x as f64).mul_add(cell_w, min.x);
-                let y = (cell_y as f64).mul_add(cell_h, min.y);
-
-                (Coordinates { x, y }, Coordinates { x: x + cell_w, y: y + cell_h })
-            });
+        let faction = player.faction.ok_or(InternalError::FactionUnknown)?;
+        if !faction_cell.contains_key(&faction) {
+            // Only happens when there are more factions than cells in the grid
+            let (cell_x, cell_y) = grid.take_cell(rng).ok_or(InternalError::Conflict)?;
+
+            // the (x, y) coordinates of the topleft corner of the chosen cell
+            //
+            // mul_add : compute min.x + cell_x as f64 * cell_w more efficiently,
+            // see https://doc.rust-lang.org/std/primitive.f64.html#method.mul_add
+            let x = (cell_x as f64).mul_add(cell_w, min.x);
+            let y = (cell_y as f64).mul_add(cell_h, min.y);
+
+            faction_cell.insert(faction, (Coordinates { x, y }, Coordinates { x: x + cell_w, y: y + cell_h }));
+        }
+        let (cell_min, cell_max) = &faction_cell[&faction];
 
         // find a place for the player in its faction zone
-        let place = find_place(cell_min, cell_max, galaxy).await.ok_or(InternalError::SystemUnknown)?;
+        let place = find_place(cell_min, cell_max, galaxy, rng).ok_or(InternalError::SystemUnknown)?;
         place.player = Some(player.id);
+        player.home_system = Some(place.id);
     }
 
     Ok(())
 }
 
+impl PlacementGrid {
+    /// Picks a free cell of the grid, then makes the cell AND its neighbours, in a zone which width
+    /// is defined by the EXCLUSION constant, not usable anymore.
+    /// When the grid is crowded, the cell overlapped by the fewest zones is picked instead.
+    /// Returns None when every cell is already given to a faction.
+    #[allow(clippy::needless_range_loop)]
+    fn take_cell(&mut self, rng: &mut impl rand::Rng) -> Option<(usize, usize)> {
+        let grid_range = Uniform::from(0..GRID_SIZE);
+        let (cell_x, cell_y) = (0..MAX_CELL_ATTEMPTS)
+            .map(|_| (grid_range.sample(rng), grid_range.sample(rng)))
+            .find(|&(x, y)| self.exclusions[x][y] == 0)
+            .or_else(|| self.find_least_contested_cell(rng))?;
+
+        for i in cell_x.saturating_sub(EXCLUSION)..=(cell_x+EXCLUSION).min(GRID_SIZE-1) {
+            for j in cell_y.saturating_sub(EXCLUSION)..=(cell_y+EXCLUSION).min(GRID_SIZE-1) {
+                self.exclusions[i][j] = self.exclusions[i][j].saturating_add(1);
+            }
+        }
+        self.cells.insert((cell_x, cell_y));
+        Some((cell_x, cell_y))
+    }
+
+    fn find_least_contested_cell(&self, rng: &mut impl rand::Rng) -> Option<(usize, usize)> {
+        let candidates: Vec<(usize, usize)> = (0..GRID_SIZE)
+            .flat_map(|x| (0..GRID_SIZE).map(move |y| (x, y)))
+            .filter(|cell| !self.cells.contains(cell))
+            .collect();
+        let min_exclusions = candidates.iter().map(|&(x, y)| self.exclusions[x][y]).min()?;
+
+        candidates.into_iter()
+            .filter(|&(x, y)| self.exclusions[x][y] == min_exclusions)
+            .collect::<Vec<(usize, usize)>>()
+            .choose(rng)
+            .copied()
+    }
+}
+
 #[allow(clippy::needless_lifetimes)] // false positive
-async fn find_place<'a>(
+fn find_place<'a>(
     Coordinates { x:xmin, y:ymin }: &Coordinates,
     Coordinates { x:xmax, y:ymax }: &Coordinates,
-    galaxy: & 'a mut Vec<System>
+    galaxy: & 'a mut Vec<System>,
+    rng: &mut impl rand::Rng,
 )
     -> Option<& 'a mut System>
 {
-    let mut rng = thread_rng();
     let final_x: f64 = rng.gen_range(xmin, xmax);
     let final_y: f64 = rng.gen_range(ymin, ymax);
     let final_coord = Coordinates { x:final_x, y:final_y };
@@ -408,19 +594,253 @@ async fn find_place<'a>(
     idx.map(move |id| &mut galaxy[id])
 }
 
+#[derive(Deserialize)]
+pub struct SystemFilter {
+    pub kind: Option<SystemKind>,
+}
+
 #[allow(clippy::eval_order_dependence)] // false positive ?
 #[get("/")]
-pub async fn get_systems(state: web::Data<AppState>, info: web::Path<(GameID,)>, pagination: web::Query<Paginator>)
+pub async fn get_systems(state: web::Data<AppState>, info: web::Path<(GameID,)>, pagination: web::Query<Paginator>, filter: web::Query<SystemFilter>)
     -> Result<HttpResponse>
 {
+    let count = match filter.kind {
+        Some(kind) => System::count_by_kind(info.0, kind, &state.db_pool).await?,
+        None => System::count(info.0, &state.db_pool).await,
+    };
     Ok(new_paginated_response(
         pagination.limit,
         pagination.page,
-        System::count(info.0.clone(), &state.db_pool).await.into(),
-        System::find_all(&info.0, pagination.limit, (pagination.page - 1) * pagination.limit, &state.db_pool).await?,
+        count.into(),
+        System::find_all(&info.0, filter.kind, pagination.limit, (pagination.page - 1) * pagination.limit, &state.db_pool).await?,
     ))
 }
 
+/// The ship categories are only detailed to the system owner
+#[get("/{system_id}/")]
+pub async fn get_system(state: web::Data<AppState>, info: web::Path<(GameID, SystemID)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    #[derive(Serialize)]
+    struct SystemDetails {
+        #[serde(flatten)]
+        system: System,
+        buildable_categories: Vec<ShipModelCategory>,
+    }
+    let (s, p) = futures::join!(
+        System::find(info.1, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    let system = s?;
+    let player = p?;
+
+    if system.game != info.0 {
+        return Err(InternalError::SystemUnknown.into());
+    }
+    let buildable_categories = match system.player {
+        Some(pid) if pid == player.id => ShipQueue::get_buildable_categories(&player, system.id, &state).await?,
+        _ => vec![],
+    };
+    Ok(HttpResponse::Ok().json(SystemDetails{
+        system,
+        buildable_categories,
+    }))
+}
+
+#[get("/territory/")]
+pub async fn get_territory(state: web::Data<AppState>, info: web::Path<(GameID,)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    #[derive(Serialize)]
+    pub struct Territory {
+        nb_systems: u32,
+        nb_total_systems: u32,
+        percent: f64,
+        home_system: Option<SystemID>,
+        systems: Vec<SystemID>,
+    }
+    let player = Player::find(claims.pid, &state.db_pool).await?;
+    if player.game != Some(info.0) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    let (ids, nb_total_systems) = futures::join!(
+        System::find_ids_by_player(info.0, player.id, &state.db_pool),
+        System::count(info.0, &state.db_pool),
+    );
+    let systems = ids?;
+    let nb_systems = systems.len() as u32;
+
+    Ok(HttpResponse::Ok().json(Territory{
+        nb_systems,
+        nb_total_systems,
+        percent: get_territory_percent(nb_systems, nb_total_systems),
+        home_system: player.home_system,
+        systems,
+    }))
+}
+
+/// The systems of every member of the faction, each one with its owner
+#[get("/systems/")]
+pub async fn get_faction_systems(state: web::Data<AppState>, info: web::Path<(GameID, FactionID)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    let player = Player::find(claims.pid, &state.db_pool).await?;
+    if player.game != Some(info.0) || player.faction != Some(info.1) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    Ok(HttpResponse::Ok().json(System::find_by_faction(info.0, info.1, &state.db_pool).await?))
+}
+
+fn get_territory_percent(nb_systems: u32, nb_total_systems: u32) -> f64 {
+    if nb_total_systems == 0 {
+        return 0.0;
+    }
+    f64::from(nb_systems) * 100.0 / f64::from(nb_total_systems)
+}
+
+#[get("/bounds/")]
+pub async fn get_map_bounds(state: web::Data<AppState>, info: web::Path<(GameID,)>)
+    -> Result<HttpResponse>
+{
+    Ok(HttpResponse::Ok().json(System::find_bounds(info.0, &state.db_pool).await?))
+}
+
+#[post("/{system_id}/abandon/")]
+pub async fn abandon_system(state: web::Data<AppState>, info: web::Path<(GameID, SystemID)>, claims: Claims)
+    -> Result<HttpResponse>
+{
+    let (s, p) = futures::join!(
+        System::find(info.1, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+    );
+    let mut system = s?;
+    let player = p?;
+
+    if system.game != info.0 || system.player != Some(player.id) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    if player.home_system == Some(system.id) {
+        return Err(InternalError::Conflict.into());
+    }
+
+    // The system is locked while checked, and only released if the player still owns it
+    let mut tx = state.db_pool.begin().await?;
+    System::lock(system.id, &mut tx).await?;
+    if system.is_contested(&mut tx).await? || Fleet::count_stationed_by_system(&system.id, &mut tx).await? > 0 {
+        tx.rollback().await?;
+        return Err(InternalError::Conflict.into());
+    }
+    system.player = None;
+    if system.update_owner(Some(player.id), &mut tx).await? == 0 {
+        tx.rollback().await?;
+        return Err(InternalError::Conflict.into());
+    }
+    tx.commit().await?;
+
+    log(
+        gelf::Level::Informational,
+        "System abandoned",
+        &format!("{} has abandoned system {}", player.to_log_message(), system.to_log_message()),
+        vec![
+            ("player_id", player.id.0.to_string()),
+            ("system_id", system.id.0.to_string()),
+        ],
+        &state.logger
+    );
+
+    let games = state.games();
+    let game = games.get(&info.0).cloned().ok_or(InternalError::GameUnknown)?;
+    game.do_send(protocol::Message::new(
+        protocol::Action::SystemAbandoned,
+        system,
+        None,
+    ));
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize)]
+pub struct SystemTransferData {
+    pub receiver_id: PlayerID,
+}
+
+/// Gives a system to an ally. The buildings belong to the system, so they follow it
+#[post("/{system_id}/transfer/")]
+pub async fn transfer_system(
+    state: web::Data<AppState>,
+    info: web::Path<(GameID, SystemID)>,
+    json_data: web::Json<SystemTransferData>,
+    claims: Claims
+) -> Result<HttpResponse> {
+    let (s, p, r) = futures::join!(
+        System::find(info.1, &state.db_pool),
+        Player::find(claims.pid, &state.db_pool),
+        Player::find(json_data.receiver_id, &state.db_pool),
+    );
+    let mut system = s?;
+    let player = p?;
+    let receiver = r?;
+
+    check_system_transfer(&system, &player, &receiver)?;
+
+    // The system is locked while checked, and only changes hands if the donator still owns it
+    let mut tx = state.db_pool.begin().await?;
+    System::lock(system.id, &mut tx).await?;
+    if system.is_contested(&mut tx).await? {
+        tx.rollback().await?;
+        return Err(InternalError::Conflict.into());
+    }
+    system.player = Some(receiver.id);
+    if system.update_owner(Some(player.id), &mut tx).await? == 0 {
+        tx.rollback().await?;
+        return Err(InternalError::Conflict.into());
+    }
+    tx.commit().await?;
+
+    log(
+        gelf::Level::Informational,
+        "System transfer",
+        &format!("{} has given system {} to {}", player.to_log_message(), system.to_log_message(), receiver.to_log_message()),
+        vec![
+            ("donator_id", player.id.0.to_string()),
+            ("receiver_id", receiver.id.0.to_string()),
+            ("system_id", system.id.0.to_string()),
+        ],
+        &state.logger
+    );
+
+    #[derive(Serialize)]
+    pub struct SystemTransferredData{
+        pub system: System,
+        pub donator_id: PlayerID,
+        pub receiver_id: PlayerID,
+    }
+
+    let games = state.games();
+    let game = games.get(&info.0).cloned().ok_or(InternalError::GameUnknown)?;
+    game.do_send(protocol::Message::new(
+        protocol::Action::SystemTransferred,
+        SystemTransferredData{ donator_id: player.id, receiver_id: receiver.id, system },
+        None,
+    ));
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn check_system_transfer(system: &System, player: &Player, receiver: &Player) -> Result<()> {
+    if system.player != Some(player.id) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    if player.home_system == Some(system.id)
+        || receiver.id == player.id
+        || receiver.game != player.game
+        || receiver.faction.is_none()
+        || receiver.faction != player.faction {
+        return Err(InternalError::Conflict.into());
+    }
+    Ok(())
+}
+
 #[allow(clippy::ptr_arg)]
 pub async fn init_player_systems(systems: &Vec<System>, game_speed: GameOptionSpeed, db_pool: &PgPool) -> Result<()> {
     let building_data = BuildingKind::Shipyard.to_data();
@@ -445,6 +865,34 @@ pub async fn init_player_systems(systems: &Vec<System>, game_speed: GameOptionSp
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_generate_system_kind_is_reproducible() {
+        let generate_kinds = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..20).map(|i| generate_system_kind(f64::from(i) / 10.0, 0.0, 0.5, &mut rng).0).collect::<Vec<SystemKind>>()
+        };
+
+        assert!(generate_kinds(42) == generate_kinds(42));
+    }
+
+    #[test]
+    fn test_remove_victory_systems() {
+        let gid = GameID(Uuid::new_v4());
+        let mut systems: Vec<System> = [SystemKind::VictorySystem, SystemKind::BaseSystem, SystemKind::VictorySystem].iter().map(|&kind| System{
+            id: SystemID(Uuid::new_v4()),
+            game: gid,
+            player: None,
+            kind,
+            coordinates: Coordinates{ x: 0.0, y: 0.0 },
+            unreachable: false,
+        }).collect();
+
+        remove_victory_systems(&mut systems);
+
+        assert!(systems.iter().all(|s| s.kind == SystemKind::BaseSystem));
+    }
 
     #[test]
     fn test_as_distance_to() {
@@ -456,4 +904,110 @@ mod tests {
             y: 4.0
         }));
     }
+
+    #[test]
+    fn test_get_territory_percent() {
+        assert_eq!(25.0, get_territory_percent(5, 20));
+        assert_eq!(0.0, get_territory_percent(0, 20));
+        assert_eq!(0.0, get_territory_percent(0, 0));
+    }
+
+    #[test]
+    fn test_check_system_transfer() {
+        let gid = GameID(Uuid::new_v4());
+        let player = get_player_mock(gid, 1);
+        let ally = get_player_mock(gid, 1);
+        let enemy = get_player_mock(gid, 2);
+        let system = System{
+            id: SystemID(Uuid::new_v4()),
+            game: gid,
+            player: Some(player.id),
+            kind: SystemKind::BaseSystem,
+            coordinates: Coordinates{ x: 0.0, y: 0.0 },
+            unreachable: false,
+        };
+
+        assert!(check_system_transfer(&system, &player, &ally).is_ok());
+        assert!(check_system_transfer(&system, &player, &enemy).is_err());
+        assert!(check_system_transfer(&system, &player, &player).is_err());
+        assert!(check_system_transfer(&system, &ally, &player).is_err());
+
+        let home_player = Player{ home_system: Some(system.id), ..player };
+        assert!(check_system_transfer(&system, &home_player, &ally).is_err());
+    }
+
+    #[test]
+    fn test_assign_systems_is_reproducible() {
+        let gid = GameID(Uuid::new_v4());
+        let players: Vec<Player> = [1, 2, 3, 1, 2, 3].iter().map(|&faction| get_player_mock(gid, faction)).collect();
+        let galaxy = get_galaxy_mock(gid);
+
+        let assign = |seed| {
+            let (mut players, mut galaxy) = (players.clone(), galaxy.clone());
+            assign_systems_with_rng(&mut players, &mut galaxy, &mut StdRng::seed_from_u64(seed)).unwrap();
+            (
+                players.into_iter().map(|p| p.home_system).collect::<Vec<Option<SystemID>>>(),
+                galaxy.into_iter().map(|s| s.player).collect::<Vec<Option<PlayerID>>>(),
+            )
+        };
+        let (home_systems, owners) = assign(42);
+
+        assert!((home_systems.clone(), owners.clone()) == assign(42));
+        assert!(home_systems.iter().all(Option::is_some));
+        assert_eq!(players.len(), owners.iter().filter(|owner| owner.is_some()).count());
+    }
+
+    #[test]
+    fn test_assign_systems_with_many_factions() {
+        let gid = GameID(Uuid::new_v4());
+        let mut players: Vec<Player> = (1..=200).map(|faction| get_player_mock(gid, faction)).collect();
+        let mut galaxy = get_galaxy_mock(gid);
+
+        assign_systems_with_rng(&mut players, &mut galaxy, &mut StdRng::seed_from_u64(42)).unwrap();
+
+        assert!(players.iter().all(|p| p.home_system.is_some()));
+        assert_eq!(players.len(), galaxy.iter().filter(|s| s.player.is_some()).count());
+    }
+
+    #[test]
+    fn test_placement_grid_take_cell() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut grid = PlacementGrid::default();
+
+        for _ in 0..GRID_SIZE * GRID_SIZE {
+            let has_free_cell = grid.exclusions.iter().flatten().any(|nb| *nb == 0);
+            let (x, y) = grid.take_cell(&mut rng).unwrap();
+            // Exclusion zones only overlap once there is no free cell left
+            assert!(!has_free_cell || grid.exclusions[x][y] == 1);
+        }
+        // No cell is given twice
+        assert_eq!(GRID_SIZE * GRID_SIZE, grid.cells.len());
+        assert_eq!(None, grid.take_cell(&mut rng));
+    }
+
+    fn get_galaxy_mock(gid: GameID) -> Vec<System> {
+        (0..400).map(|i| System{
+            id: SystemID(Uuid::new_v4()),
+            game: gid,
+            player: None,
+            kind: SystemKind::BaseSystem,
+            coordinates: Coordinates{ x: f64::from(i % 20), y: f64::from(i / 20) },
+            unreachable: false,
+        }).collect()
+    }
+
+    fn get_player_mock(gid: GameID, faction: u8) -> Player {
+        Player{
+            id: PlayerID(Uuid::new_v4()),
+            username: String::from("Galadruin"),
+            game: Some(gid),
+            lobby: None,
+            faction: Some(FactionID(faction)),
+            ready: true,
+            wallet: 0,
+            is_connected: true,
+            home_system: None,
+            is_eliminated: false,
+        }
+    }
 }
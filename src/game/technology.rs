@@ -0,0 +1,328 @@
+use actix_web::{get, post, web, HttpResponse};
+use serde::{Serialize, Deserialize};
+use uuid::Uuid;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{PgPool, postgres::{PgRow, PgQueryAs}, FromRow, Executor, Error, Postgres};
+use sqlx_core::row::Row;
+use futures::executor::block_on;
+use std::collections::HashMap;
+use crate::{
+    task,
+    AppState,
+    lib::{
+        Result,
+        auth::Claims,
+        log::log,
+        error::{ServerError, InternalError},
+        time::Time
+    },
+    game::{
+        faction::FactionID,
+        game::{
+            game::{Game, GameID},
+            server::{GameServer, GameServerTask},
+            option::GameOptionSpeed
+        },
+        system::{
+            building::{Building, BuildingKind},
+            system::{System, SystemID},
+        },
+        player::{Player, PlayerID},
+    },
+    ws::protocol,
+};
+
+#[derive(Serialize, Clone)]
+pub struct Technology {
+    pub id: TechnologyID,
+    pub game: GameID,
+    pub faction: FactionID,
+    pub player: PlayerID,
+    pub kind: TechnologyKind,
+    pub status: TechnologyStatus,
+    pub started_at: Time,
+    pub unlocked_at: Time,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy)]
+pub struct TechnologyID(pub Uuid);
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, sqlx::Type)]
+#[sqlx(rename = "VARCHAR")]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all(serialize = "snake_case", deserialize = "snake_case"))]
+pub enum TechnologyStatus {
+    Researching,
+    Unlocked
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, sqlx::Type)]
+#[sqlx(rename = "VARCHAR")]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all(serialize = "snake_case", deserialize = "snake_case"))]
+pub enum TechnologyKind {
+    /// Increases the hit points of every ship
+    ReinforcedHulls,
+    /// Increases the precision of every ship
+    ImprovedTargeting,
+    /// Decreases the cost of every ship
+    StreamlinedProduction,
+}
+
+#[derive(Serialize, Copy, Clone)]
+pub struct TechnologyData {
+    pub kind: TechnologyKind,
+    pub cost: u16,
+    pub research_time: u16,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TechnologyRequest {
+    pub kind: TechnologyKind,
+}
+
+impl From<TechnologyID> for Uuid {
+    fn from(tid: TechnologyID) -> Self { tid.0 }
+}
+
+impl GameServerTask for Technology {
+    fn get_task_id(&self) -> String {
+        self.id.0.to_string()
+    }
+
+    fn get_task_end_time(&self) -> Time {
+        self.unlocked_at
+    }
+}
+
+impl<'a> FromRow<'a, PgRow<'a>> for Technology {
+    fn from_row(row: &PgRow) -> std::result::Result<Self, Error> {
+        Ok(Technology {
+            id: row.try_get("id").map(TechnologyID)?,
+            game: row.try_get("game_id").map(GameID)?,
+            faction: row.try_get::<i32, _>("faction_id").map(|id| FactionID(id as u8))?,
+            player: row.try_get("player_id").map(PlayerID)?,
+            kind: row.try_get("kind")?,
+            status: row.try_get("status")?,
+            started_at: row.try_get("started_at")?,
+            unlocked_at: row.try_get("unlocked_at")?,
+        })
+    }
+}
+
+impl TechnologyKind {
+    pub const fn to_data(self) -> TechnologyData {
+        match self {
+            TechnologyKind::ReinforcedHulls => TechnologyData{
+                kind: TechnologyKind::ReinforcedHulls,
+                cost: 1500,
+                research_time: 90,
+            },
+            TechnologyKind::ImprovedTargeting => TechnologyData{
+                kind: TechnologyKind::ImprovedTargeting,
+                cost: 1500,
+                research_time: 90,
+            },
+            TechnologyKind::StreamlinedProduction => TechnologyData{
+                kind: TechnologyKind::StreamlinedProduction,
+                cost: 1000,
+                research_time: 60,
+            },
+        }
+    }
+}
+
+impl TechnologyData {
+    fn into_research_time(self, from: Time, game_speed: GameOptionSpeed) -> Time {
+        let time: DateTime<Utc> = from.into();
+        Time(time
+            .checked_add_signed(self.into_duration(game_speed))
+            .expect("Could not add research time")
+        )
+    }
+
+    fn into_duration(self, game_speed: GameOptionSpeed) -> Duration {
+        Duration::seconds((self.research_time as f64 * game_speed.into_coeff()).ceil() as i64)
+    }
+}
+
+impl Technology {
+    pub fn new(gid: GameID, fid: FactionID, player: &Player, kind: TechnologyKind, game_speed: GameOptionSpeed) -> Technology {
+        let now = Time::now();
+
+        Technology{
+            id: TechnologyID(Uuid::new_v4()),
+            game: gid,
+            faction: fid,
+            player: player.id,
+            kind,
+            status: TechnologyStatus::Researching,
+            started_at: now.clone(),
+            unlocked_at: kind.to_data().into_research_time(now, game_speed),
+        }
+    }
+
+    pub async fn find_by_faction(gid: GameID, fid: FactionID, db_pool: &PgPool) -> Result<Vec<Self>> {
+        sqlx::query_as("SELECT * FROM faction__technologies WHERE game_id = $1 AND faction_id = $2")
+            .bind(Uuid::from(gid))
+            .bind(i32::from(fid))
+            .fetch_all(db_pool).await.map_err(ServerError::from)
+    }
+
+    pub async fn find_unlocked_kinds(gid: GameID, fid: FactionID, db_pool: &PgPool) -> Result<Vec<TechnologyKind>> {
+        Ok(Self::find_by_faction(gid, fid, db_pool).await?
+            .into_iter()
+            .filter(|t| t.status == TechnologyStatus::Unlocked)
+            .map(|t| t.kind)
+            .collect())
+    }
+
+    /// Retrieves the unlocked technologies of every faction in the game, so that combat can apply
+    /// their effects without querying each faction separately.
+    pub async fn find_unlocked_by_game(gid: GameID, db_pool: &PgPool) -> Result<HashMap<FactionID, Vec<TechnologyKind>>> {
+        let technologies: Vec<Self> = sqlx::query_as("SELECT * FROM faction__technologies WHERE game_id = $1 AND status = $2")
+            .bind(Uuid::from(gid))
+            .bind(TechnologyStatus::Unlocked)
+            .fetch_all(db_pool).await.map_err(ServerError::from)?;
+
+        let mut result: HashMap<FactionID, Vec<TechnologyKind>> = HashMap::new();
+        for t in technologies {
+            result.entry(t.faction).or_default().push(t.kind);
+        }
+        Ok(result)
+    }
+
+    pub async fn insert<E>(&self, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("INSERT INTO faction__technologies (id, game_id, faction_id, player_id, kind, status, started_at, unlocked_at) VALUES($1, $2, $3, $4, $5, $6, $7, $8)")
+            .bind(Uuid::from(self.id))
+            .bind(Uuid::from(self.game))
+            .bind(i32::from(self.faction))
+            .bind(Uuid::from(self.player))
+            .bind(self.kind)
+            .bind(self.status)
+            .bind(self.started_at)
+            .bind(self.unlocked_at)
+            .execute(&mut *exec).await.map_err(ServerError::if_unique_violation(InternalError::Conflict))
+    }
+
+    /// Delays the technologies researched during the pause. The ones started meanwhile only progress once it is resumed.
+    /// The research start moves by as much, keeping its progress
+    pub async fn postpone_deadlines<E>(gid: GameID, paused_at: Time, resumed_at: Time, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE faction__technologies SET
+            started_at = started_at + ($3 - GREATEST(started_at, $2)),
+            unlocked_at = unlocked_at + ($3 - GREATEST(started_at, $2))
+            WHERE game_id = $1 AND unlocked_at > $2")
+            .bind(Uuid::from(gid))
+            .bind(paused_at)
+            .bind(resumed_at)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
+    pub async fn update<E>(&self, exec: &mut E) -> Result<u64>
+        where E: Executor<Database = Postgres> {
+        sqlx::query("UPDATE faction__technologies SET status = $2 WHERE id = $1")
+            .bind(Uuid::from(self.id))
+            .bind(self.status)
+            .execute(&mut *exec).await.map_err(ServerError::from)
+    }
+
+    async fn unlock(&mut self, server: &GameServer) -> Result<()> {
+        self.status = TechnologyStatus::Unlocked;
+
+        let mut tx = server.state.db_pool.begin().await?;
+        self.update(&mut tx).await?;
+        tx.commit().await?;
+
+        log(
+            gelf::Level::Informational,
+            "Technology unlocked",
+            &format!("The faction {} has unlocked {:?}", self.faction.0, self.kind),
+            vec![
+                ("faction_id", self.faction.0.to_string()),
+                ("player_id", self.player.0.to_string()),
+            ],
+            &server.state.logger
+        );
+
+        server.faction_broadcast(self.faction, protocol::Message::new(
+            protocol::Action::TechUnlocked,
+            self.clone(),
+            None,
+        )).await?;
+
+        Ok(())
+    }
+}
+
+#[get("/technologies/")]
+pub async fn get_faction_technologies(state: web::Data<AppState>, info: web::Path<(GameID, FactionID)>)
+    -> Result<HttpResponse>
+{
+    Ok(HttpResponse::Ok().json(Technology::find_by_faction(info.0, info.1, &state.db_pool).await?))
+}
+
+#[post("/")]
+pub async fn research_technology(
+    state: web::Data<AppState>,
+    info: web::Path<(GameID, SystemID)>,
+    data: web::Json<TechnologyRequest>,
+    claims: Claims
+)
+    -> Result<HttpResponse>
+{
+    let game = Game::find(info.0, &state.db_pool).await?;
+    let system = System::find(info.1, &state.db_pool).await?;
+    let player = Player::find(claims.pid, &state.db_pool).await?;
+    let faction = player.faction.ok_or(InternalError::FactionUnknown)?;
+
+    if system.game != game.id || system.player != Some(player.id) {
+        return Err(InternalError::AccessDenied.into());
+    }
+    if Building::count_by_kind_and_system(BuildingKind::Research, system.id, &state.db_pool).await? == 0 {
+        return Err(InternalError::Conflict.into());
+    }
+    // A technology is shared by the whole faction, it can only be researched once
+    let technologies = Technology::find_by_faction(game.id, faction, &state.db_pool).await?;
+    if technologies.iter().any(|t| t.kind == data.kind) {
+        return Err(InternalError::Conflict.into());
+    }
+
+    let technology = Technology::new(game.id, faction, &player, data.kind, game.game_speed);
+
+    // Another faction member may research the same technology meanwhile, only one of them pays for it
+    let mut tx = state.db_pool.begin().await?;
+    Player::withdraw(player.id, data.kind.to_data().cost as usize, &mut tx).await?;
+    technology.insert(&mut tx).await?;
+    tx.commit().await?;
+
+    let mut t = technology.clone();
+    state.games().get(&info.0).ok_or(InternalError::GameUnknown)?
+        .do_send(task!(technology -> move |gs: &GameServer| block_on(t.unlock(gs))));
+
+    Ok(HttpResponse::Created().json(technology))
+}
+
+#[get("/technologies/")]
+pub async fn get_technologies_data() -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok().json(vec![
+        TechnologyKind::ReinforcedHulls.to_data(),
+        TechnologyKind::ImprovedTargeting.to_data(),
+        TechnologyKind::StreamlinedProduction.to_data(),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_research_seconds() {
+        let data = TechnologyKind::StreamlinedProduction.to_data();
+
+        assert_eq!(72, data.into_duration(GameOptionSpeed::Slow).num_seconds());
+        assert_eq!(60, data.into_duration(GameOptionSpeed::Medium).num_seconds());
+        assert_eq!(48, data.into_duration(GameOptionSpeed::Fast).num_seconds());
+    }
+}
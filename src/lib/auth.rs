@@ -4,9 +4,12 @@ use actix_web::dev::Payload;
 use actix_web::{FromRequest, HttpResponse, HttpRequest, Responder};
 use crate::{lib::error::{ServerError, InternalError}, game::player::PlayerID};
 use futures::future::{ready, Ready};
+use rand::{Rng, distributions::Alphanumeric};
+use sha2::{Digest, Sha256};
 use std::default::Default;
 
 const JWT_SECRET: &[u8] = b"secret";
+const RECOVERY_TOKEN_LENGTH: usize = 32;
 
 /// This structure represent an HTTP authentication token.
 /// Every route with a `Claim` in its parameters will only allow authentified requests.
@@ -65,3 +68,32 @@ pub fn decode_jwt(token: &str) -> Result<Claims, JwtError> {
         &Validation { validate_exp: false, ..Default::default() }
     ).map(|data| data.claims)
 }
+
+/// A durable token given to the player, letting it get a new JWT if it loses the current one.
+/// Only its hash is persisted.
+pub fn generate_recovery_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(RECOVERY_TOKEN_LENGTH)
+        .collect()
+}
+
+pub fn hash_recovery_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovery_token() {
+        let token = generate_recovery_token();
+
+        assert_eq!(RECOVERY_TOKEN_LENGTH, token.len());
+        assert_ne!(token, generate_recovery_token());
+        assert_eq!(64, hash_recovery_token(&token).len());
+        assert_eq!(hash_recovery_token(&token), hash_recovery_token(&token));
+        assert_ne!(hash_recovery_token(&token), hash_recovery_token(&generate_recovery_token()));
+    }
+}
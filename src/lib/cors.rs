@@ -0,0 +1,93 @@
+use actix_cors::{Cors, CorsFactory};
+use crate::{
+    game::ship::queue::{PENDING_COUNT_HEADER, MAX_PENDING_HEADER},
+    get_env,
+};
+
+/// Response headers the browsers let the clients read, besides the default ones
+const EXPOSED_HEADERS: [&str; 3] = ["Content-Range", PENDING_COUNT_HEADER, MAX_PENDING_HEADER];
+
+/// Cross-origin policy of the API. Without any allowed origin, no CORS header is sent
+/// and browsers only call the API from pages served on its own origin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    pub supports_credentials: bool,
+    pub max_age: usize,
+}
+
+impl CorsConfig {
+    pub fn from_env() -> Self {
+        Self::new(
+            &get_env("CORS_ALLOWED_ORIGINS", ""),
+            &get_env("CORS_ALLOWED_METHODS", "GET,POST,PUT,PATCH,DELETE"),
+            &get_env("CORS_ALLOWED_HEADERS", "Authorization,Content-Type,Accept"),
+            &get_env("CORS_SUPPORTS_CREDENTIALS", "false"),
+            &get_env("CORS_MAX_AGE_SEC", "3600"),
+        )
+    }
+
+    fn new(origins: &str, methods: &str, headers: &str, supports_credentials: &str, max_age: &str) -> Self {
+        CorsConfig{
+            allowed_origins: split_list(origins),
+            allowed_methods: split_list(methods),
+            allowed_headers: split_list(headers),
+            supports_credentials: supports_credentials.trim() == "true",
+            max_age: max_age.trim().parse().unwrap_or(3600),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed_origins.is_empty()
+    }
+
+    pub fn build(&self) -> CorsFactory {
+        let mut cors = Cors::new()
+            .allowed_methods(self.allowed_methods.iter().map(String::as_str))
+            .allowed_headers(self.allowed_headers.iter().map(String::as_str))
+            .expose_headers(EXPOSED_HEADERS.iter().copied())
+            .max_age(self.max_age);
+        for origin in &self.allowed_origins {
+            cors = cors.allowed_origin(origin);
+        }
+        if self.supports_credentials {
+            cors = cors.supports_credentials();
+        }
+        cors.finish()
+    }
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cors_config() {
+        let config = CorsConfig::new("http://localhost:8080, https://kalaxia.org", "GET,POST", "Authorization", "true", "600");
+
+        assert!(config.is_enabled());
+        assert_eq!(vec!["http://localhost:8080", "https://kalaxia.org"], config.allowed_origins);
+        assert_eq!(vec!["GET", "POST"], config.allowed_methods);
+        assert!(config.supports_credentials);
+        assert_eq!(600, config.max_age);
+    }
+
+    #[test]
+    fn test_cors_config_is_restrictive_by_default() {
+        let config = CorsConfig::new("", "GET", "Authorization", "", "invalid");
+
+        assert!(!config.is_enabled());
+        assert!(!config.supports_credentials);
+        assert_eq!(3600, config.max_age);
+    }
+}
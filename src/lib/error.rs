@@ -60,8 +60,23 @@ impl ServerError {
             }
         }
     }
+
+    /// Concurrent requests may insert the same row, the one which loses the race gets the given error
+    pub fn if_unique_violation<E>(internal_error:E) -> impl FnOnce(SqlxError) -> Self
+        where E : Into<Self>,
+    {
+        |e| {
+            match e {
+                SqlxError::Database(ref error) if error.code() == Some(UNIQUE_VIOLATION) => internal_error.into(),
+                _ => e.into()
+            }
+        }
+    }
 }
 
+/// Code of the Postgres errors raised by unique constraints
+const UNIQUE_VIOLATION: &str = "23505";
+
 impl From<ActixWebError> for ServerError {
     fn from(error:ActixWebError) -> Self { Self::ActixWebError(error) }
 }
@@ -109,8 +124,10 @@ impl ResponseError for ServerError {
             ServerError::JwtError(_) => (StatusCode::UNAUTHORIZED, Level::Warning),
             ServerError::InternalError(e) => match e {
                 NoAuthorizationGiven => (StatusCode::UNAUTHORIZED, Level::Warning),
+                RateLimited => (StatusCode::TOO_MANY_REQUESTS, Level::Warning),
+                ValidationFailed{ .. } => (StatusCode::UNPROCESSABLE_ENTITY, Level::Warning),
                 AccessDenied => (StatusCode::FORBIDDEN, Level::Warning),
-                Conflict | AlreadyInLobby | NotInLobby | NotEnoughMoney | FleetInvalidDestination | FleetAlreadyTravelling | FleetEmpty | PlayerUsernameAlreadyTaken => (StatusCode::CONFLICT, Level::Warning),
+                Conflict | AlreadyInLobby | NotInLobby | NotEnoughMoney | ShipCategoryLocked | FleetInvalidDestination | FleetAlreadyTravelling | FleetEmpty | FleetInBattle | PlayerUsernameAlreadyTaken => (StatusCode::CONFLICT, Level::Warning),
                 NotFound | FactionUnknown | PlayerUnknown | LobbyUnknown | FleetUnknown | GameUnknown | SystemUnknown => (StatusCode::NOT_FOUND, Level::Warning),
                 _ => (StatusCode::INTERNAL_SERVER_ERROR, Level::Error),
             },
@@ -166,10 +183,34 @@ pub enum InternalError {
     FleetAlreadyTravelling,
     /// A player tried to move an empty fleet
     FleetEmpty,
+    /// A player tried to move a fleet engaged in a battle
+    FleetInBattle,
     /// A player tried to take a username already taken by another in the same lobby
     PlayerUsernameAlreadyTaken,
     /// A Claims was requested by the route but none were given
     NoAuthorizationGiven,
     /// A player tried to spend an unauthorized amount of money
     NotEnoughMoney,
+    /// A player tried to build ships without the technology their category requires
+    ShipCategoryLocked,
+    /// A player performed an operation too many times in a short period
+    RateLimited,
+    /// A field of the request data has an invalid value
+    ValidationFailed{ field: &'static str },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_internal_error_status_codes() {
+        let get_status = |error: InternalError| ServerError::from(error).status_code();
+
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, get_status(InternalError::RateLimited));
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, get_status(InternalError::ValidationFailed{ field: "name" }));
+        assert_eq!(StatusCode::CONFLICT, get_status(InternalError::Conflict));
+        assert_eq!(StatusCode::FORBIDDEN, get_status(InternalError::AccessDenied));
+        assert_eq!(StatusCode::NOT_FOUND, get_status(InternalError::FleetUnknown));
+    }
 }
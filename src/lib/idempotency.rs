@@ -0,0 +1,140 @@
+use actix_web::HttpRequest;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use crate::game::{
+    fleet::fleet::FleetID,
+    player::PlayerID,
+};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(300);
+
+/// The keys are only unique for a given player and fleet
+type IdempotencyKey = (PlayerID, FleetID, String);
+
+enum IdempotencyEntry {
+    Pending(Instant),
+    Done(Instant, serde_json::Value),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IdempotencyStatus {
+    /// The request is processed for the first time. Its response must then be stored, or its key released
+    New,
+    /// A request with the same key is still being processed
+    Pending,
+    Done(serde_json::Value),
+}
+
+/// Keeps the responses of recent requests sent with an `Idempotency-Key` header, so that a retried
+/// request gets the original response instead of being processed twice.
+pub struct IdempotencyCache {
+    ttl: Duration,
+    responses: RwLock<HashMap<IdempotencyKey, IdempotencyEntry>>,
+}
+
+impl IdempotencyEntry {
+    fn get_stored_at(&self) -> Instant {
+        match self {
+            IdempotencyEntry::Pending(stored_at) | IdempotencyEntry::Done(stored_at, _) => *stored_at,
+        }
+    }
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        Self::new(IDEMPOTENCY_KEY_TTL)
+    }
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration) -> Self {
+        IdempotencyCache {
+            ttl,
+            responses: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// The key is checked and reserved at once, so concurrent requests with the same key are processed only once
+    pub fn reserve(&self, pid: PlayerID, fid: FleetID, key: &str) -> IdempotencyStatus {
+        let mut responses = self.responses.write().expect("IdempotencyCache RwLock poisoned");
+        let ttl = self.ttl;
+
+        responses.retain(|_, entry| entry.get_stored_at().elapsed() < ttl);
+        match responses.get(&(pid, fid, key.to_string())) {
+            Some(IdempotencyEntry::Done(_, response)) => IdempotencyStatus::Done(response.clone()),
+            Some(IdempotencyEntry::Pending(_)) => IdempotencyStatus::Pending,
+            None => {
+                responses.insert((pid, fid, key.to_string()), IdempotencyEntry::Pending(Instant::now()));
+                IdempotencyStatus::New
+            }
+        }
+    }
+
+    pub fn store<T: Serialize>(&self, pid: PlayerID, fid: FleetID, key: String, response: &T) {
+        self.responses.write().expect("IdempotencyCache RwLock poisoned")
+            .insert((pid, fid, key), IdempotencyEntry::Done(Instant::now(), serde_json::value::to_value(response).unwrap()));
+    }
+
+    /// A failed request can be retried with the same key
+    pub fn release(&self, pid: PlayerID, fid: FleetID, key: &str) {
+        self.responses.write().expect("IdempotencyCache RwLock poisoned")
+            .remove(&(pid, fid, key.to_string()));
+    }
+}
+
+pub fn get_idempotency_key(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|header| header.to_str().ok())
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_idempotency_cache() {
+        let cache = IdempotencyCache::default();
+        let pid = PlayerID(Uuid::new_v4());
+        let fid = FleetID(Uuid::new_v4());
+
+        assert_eq!(IdempotencyStatus::New, cache.reserve(pid, fid, "key"));
+        // A concurrent request with the same key is not processed while the first one is
+        assert_eq!(IdempotencyStatus::Pending, cache.reserve(pid, fid, "key"));
+
+        cache.store(pid, fid, String::from("key"), &"response");
+
+        assert_eq!(IdempotencyStatus::Done(serde_json::Value::from("response")), cache.reserve(pid, fid, "key"));
+        assert_eq!(IdempotencyStatus::New, cache.reserve(pid, fid, "other_key"));
+        // The same key sent by another player or for another fleet is another request
+        assert_eq!(IdempotencyStatus::New, cache.reserve(PlayerID(Uuid::new_v4()), fid, "key"));
+        assert_eq!(IdempotencyStatus::New, cache.reserve(pid, FleetID(Uuid::new_v4()), "key"));
+    }
+
+    #[test]
+    fn test_idempotency_cache_release() {
+        let cache = IdempotencyCache::default();
+        let pid = PlayerID(Uuid::new_v4());
+        let fid = FleetID(Uuid::new_v4());
+
+        assert_eq!(IdempotencyStatus::New, cache.reserve(pid, fid, "key"));
+        cache.release(pid, fid, "key");
+        assert_eq!(IdempotencyStatus::New, cache.reserve(pid, fid, "key"));
+    }
+
+    #[test]
+    fn test_idempotency_cache_expiration() {
+        let cache = IdempotencyCache::new(Duration::from_secs(0));
+        let pid = PlayerID(Uuid::new_v4());
+        let fid = FleetID(Uuid::new_v4());
+
+        cache.store(pid, fid, String::from("key"), &"response");
+
+        assert_eq!(IdempotencyStatus::New, cache.reserve(pid, fid, "key"));
+    }
+}
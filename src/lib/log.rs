@@ -1,45 +1,297 @@
-use gelf::{Logger, Message, Level};
+use gelf::{Logger, Level, TcpBackend};
+#[cfg(feature="graylog")]
+use gelf::Message;
 use chrono::Utc;
-use std::io::Write;
+use log::LevelFilter;
+use serde_json::{Map, Value};
+use std::{
+    collections::VecDeque,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use crate::lib::request_id::current_request_id;
+
+/// Number of logs kept while Graylog is unreachable. The oldest ones are dropped first.
+const GRAYLOG_BUFFER_CAPACITY: usize = 1000;
+const GRAYLOG_RETRY_DELAY_MIN: Duration = Duration::from_secs(1);
+const GRAYLOG_RETRY_DELAY_MAX: Duration = Duration::from_secs(60);
+/// gelf does not report the failed sends, so the connection is renewed regularly to recover from outages
+const GRAYLOG_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
 
 pub trait Loggable {
     fn to_log_message(&self) -> String;
 }
 
+/// Graylog client connected by a background thread, which keeps trying with an increasing delay while
+/// the server is unreachable. The logs written meanwhile are buffered and sent once the connection is established.
+#[cfg_attr(not(feature="graylog"), allow(dead_code))]
+pub struct GraylogLogger {
+    connection: Arc<Mutex<GraylogConnection>>,
+}
+
+#[cfg_attr(not(feature="graylog"), allow(dead_code))]
+struct GraylogConnection {
+    logger: Option<Logger>,
+    buffer: VecDeque<BufferedLog>,
+    retry: Backoff,
+}
+
+#[cfg_attr(not(feature="graylog"), allow(dead_code))]
+struct BufferedLog {
+    level: Level,
+    message: String,
+    full_message: String,
+    metadata: Vec<(String, String)>,
+}
+
+struct Backoff {
+    delay: Duration,
+    next_attempt: Instant,
+}
+
+#[cfg_attr(not(feature="graylog"), allow(dead_code))]
+impl GraylogLogger {
+    pub fn new(address: String) -> Self {
+        let connection = Arc::new(Mutex::new(GraylogConnection{
+            logger: None,
+            buffer: VecDeque::new(),
+            retry: Backoff::new(Instant::now()),
+        }));
+        reconnect(&connection, &address, Instant::now());
+        if connection.lock().expect("GraylogLogger Mutex poisoned").logger.is_none() {
+            println!("Could not connect to Graylog. Logging to the default output until it is available");
+        }
+        let background_connection = connection.clone();
+        std::thread::spawn(move || loop {
+            let delay = background_connection.lock().expect("GraylogLogger Mutex poisoned").retry.get_delay(Instant::now());
+            std::thread::sleep(delay);
+            reconnect(&background_connection, &address, Instant::now());
+        });
+        GraylogLogger{ connection }
+    }
+
+    /// Returns false when the log could not be sent yet. The connection is never waited for,
+    /// the log is written on the standard output instead
+    fn log(&self, log: BufferedLog) -> bool {
+        let mut guard = match self.connection.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => return false,
+        };
+        let connection = &mut *guard;
+        match &connection.logger {
+            Some(logger) => {
+                for buffered_log in connection.buffer.drain(..) {
+                    send_log(logger, buffered_log);
+                }
+                send_log(logger, log);
+                true
+            },
+            None => {
+                push_bounded(&mut connection.buffer, log, GRAYLOG_BUFFER_CAPACITY);
+                false
+            }
+        }
+    }
+}
+
+/// The connection is opened without holding the lock, so that the logs are not blocked meanwhile
+#[cfg_attr(not(feature="graylog"), allow(dead_code))]
+fn reconnect(connection: &Mutex<GraylogConnection>, address: &str, now: Instant) {
+    let logger = TcpBackend::new(address).ok().and_then(|backend| Logger::new(Box::new(backend)).ok());
+    let mut connection = connection.lock().expect("GraylogLogger Mutex poisoned");
+    match logger {
+        Some(logger) => {
+            connection.logger = Some(logger);
+            connection.retry.reset(now + GRAYLOG_REFRESH_INTERVAL);
+        },
+        None => {
+            connection.logger = None;
+            connection.retry.fail(now);
+        },
+    }
+}
+
+#[cfg_attr(not(feature="graylog"), allow(dead_code))]
+impl Backoff {
+    fn new(now: Instant) -> Self {
+        Backoff{ delay: GRAYLOG_RETRY_DELAY_MIN, next_attempt: now }
+    }
+
+    fn get_delay(&self, now: Instant) -> Duration {
+        self.next_attempt.saturating_duration_since(now)
+    }
+
+    fn fail(&mut self, now: Instant) {
+        self.next_attempt = now + self.delay;
+        self.delay = (self.delay * 2).min(GRAYLOG_RETRY_DELAY_MAX);
+    }
+
+    fn reset(&mut self, next_attempt: Instant) {
+        *self = Backoff::new(next_attempt);
+    }
+}
+
+#[cfg_attr(not(feature="graylog"), allow(dead_code))]
+fn push_bounded<T>(buffer: &mut VecDeque<T>, item: T, capacity: usize) {
+    if buffer.len() >= capacity {
+        buffer.pop_front();
+    }
+    buffer.push_back(item);
+}
+
+#[cfg(feature="graylog")]
+fn send_log(logger: &Logger, log: BufferedLog) {
+    let mut message = Message::new(log.message);
+    message.set_full_message(log.full_message);
+    message.set_level(log.level);
+
+    for (key, value) in log.metadata {
+        message.set_metadata(key, value).ok();
+    }
+    logger.log_message(message);
+}
+
+#[cfg(not(feature="graylog"))]
+#[allow(dead_code)]
+fn send_log(_logger: &Logger, _log: BufferedLog) {}
+
 #[cfg(feature="graylog")]
-pub fn log(level: Level, message: &str, full_message: &str, metadata: Vec<(&str, String)>, logger: &Option<Logger>) {
-    if let Some(log) = logger {
-        let mut message = Message::new(String::from(message));
-        message.set_full_message(String::from(full_message));
-        message.set_level(level);
-    
-        for (key, value) in metadata {
-            message.set_metadata(String::from(key), value).ok();
+pub fn log(level: Level, message: &str, full_message: &str, metadata: Vec<(&str, String)>, logger: &Option<GraylogLogger>) {
+    let metadata = add_request_id(metadata);
+    if let Some(graylog) = logger {
+        let sent = graylog.log(BufferedLog{
+            level,
+            message: String::from(message),
+            full_message: String::from(full_message),
+            metadata: metadata.iter().map(|(key, value)| (String::from(*key), value.clone())).collect(),
+        });
+        if sent {
+            return;
         }
-    
-        log.log_message(message);
+    }
+    print_log(level, message, full_message, metadata);
+}
 
+#[cfg(not(feature="graylog"))]
+pub fn log(level: Level, message: &str, full_message: &str, metadata: Vec<(&str, String)>, _logger: &Option<GraylogLogger>) {
+    print_log(level, message, full_message, add_request_id(metadata));
+}
+
+/// Logs written while processing a request, or a task scheduled by one, carry its id
+fn add_request_id(mut metadata: Vec<(&str, String)>) -> Vec<(&str, String)> {
+    if let Some(request_id) = current_request_id() {
+        metadata.push(("request_id", request_id));
+    }
+    metadata
+}
+
+/// Fallback used when no Graylog server is available: each log is written on the standard output
+/// as a single JSON line. The minimum level can be set with the `LOG_LEVEL` environment variable
+/// (`error`, `warn`, `info`, `debug` or `trace`), and defaults to `info`.
+fn print_log(level: Level, message: &str, full_message: &str, metadata: Vec<(&str, String)>) {
+    if level.to_rust() > get_level_filter() {
         return;
     }
-    print_log(level, full_message);
+    println!("{}", format_log(level, message, full_message, metadata));
 }
 
-#[cfg(not(feature="graylog"))]
-pub fn log(level: Level, message: &str, full_message: &str, metadata: Vec<(&str, String)>, logger: &Option<Logger>) {
-    print_log(level, full_message);
+fn get_level_filter() -> LevelFilter {
+    std::env::var("LOG_LEVEL")
+        .ok()
+        .and_then(|level| LevelFilter::from_str(&level).ok())
+        .unwrap_or(LevelFilter::Info)
+}
+
+fn format_log(level: Level, message: &str, full_message: &str, metadata: Vec<(&str, String)>) -> String {
+    let mut fields = Map::new();
+    fields.insert(String::from("timestamp"), Value::String(Utc::now().to_rfc3339()));
+    fields.insert(String::from("level"), Value::String(format!("app.{}", level.to_rust().to_string().to_uppercase())));
+    fields.insert(String::from("message"), Value::String(String::from(message)));
+    fields.insert(String::from("full_message"), Value::String(String::from(full_message)));
+
+    for (key, value) in metadata {
+        fields.insert(String::from(key), Value::String(value));
+    }
+    Value::Object(fields).to_string()
 }
 
-fn print_log(level: Level, full_message: &str) {
-    let writer = std::fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open("logs/current.log");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_log() {
+        let log = format_log(
+            Level::Warning,
+            "Player disconnected",
+            "Galadruin has lost its websocket connection",
+            vec![("player_id", String::from("uuid"))],
+        );
+        let value: Value = serde_json::from_str(&log).unwrap();
+
+        assert_eq!("app.WARN", value["level"]);
+        assert_eq!("Player disconnected", value["message"]);
+        assert_eq!("Galadruin has lost its websocket connection", value["full_message"]);
+        assert_eq!("uuid", value["player_id"]);
+        assert!(!log.contains('\n'));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let now = Instant::now();
+        let mut backoff = Backoff::new(now);
 
-    let date = Utc::now().to_string();
+        assert_eq!(Duration::new(0, 0), backoff.get_delay(now));
 
-    match writer {
-        Ok(mut file) => { write!(file, "[{}] app.{}: {}\n", date, level.to_rust().to_string().to_uppercase(), full_message); },
-        Err(_err) => { println!("[{}] app.{}: {}", date, level.to_rust().to_string().to_uppercase(), full_message); }
-    };
-}
\ No newline at end of file
+        backoff.fail(now);
+        assert_eq!(GRAYLOG_RETRY_DELAY_MIN, backoff.get_delay(now));
+        assert_eq!(Duration::new(0, 0), backoff.get_delay(now + GRAYLOG_RETRY_DELAY_MIN));
+
+        for _ in 0..10 {
+            backoff.fail(now);
+        }
+        assert_eq!(GRAYLOG_RETRY_DELAY_MAX, backoff.delay);
+
+        // A working connection is renewed after the refresh interval
+        backoff.reset(now + GRAYLOG_REFRESH_INTERVAL);
+        assert_eq!(GRAYLOG_REFRESH_INTERVAL, backoff.get_delay(now));
+        assert_eq!(GRAYLOG_RETRY_DELAY_MIN, backoff.delay);
+    }
+
+    #[test]
+    fn test_graylog_log_without_waiting() {
+        let graylog = GraylogLogger{
+            connection: Arc::new(Mutex::new(GraylogConnection{
+                logger: None,
+                buffer: VecDeque::new(),
+                retry: Backoff::new(Instant::now()),
+            })),
+        };
+        let get_log = || BufferedLog{
+            level: Level::Informational,
+            message: String::from("Conquest succeeded"),
+            full_message: String::new(),
+            metadata: vec![],
+        };
+        // The log is not buffered while the connection is busy, such as during a reconnection
+        let guard = graylog.connection.lock().unwrap();
+        assert!(!graylog.log(get_log()));
+        drop(guard);
+        assert!(graylog.connection.lock().unwrap().buffer.is_empty());
+
+        // Without connection, the log is kept to be sent later
+        assert!(!graylog.log(get_log()));
+        assert_eq!(1, graylog.connection.lock().unwrap().buffer.len());
+    }
+
+    #[test]
+    fn test_push_bounded() {
+        let mut buffer = VecDeque::new();
+        for i in 0..5 {
+            push_bounded(&mut buffer, i, 3);
+        }
+
+        assert_eq!(vec![2, 3, 4], buffer.into_iter().collect::<Vec<i32>>());
+    }
+}
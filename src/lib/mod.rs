@@ -1,7 +1,11 @@
 pub mod auth;
+pub mod cors;
 pub mod error;
+pub mod idempotency;
 pub mod log;
 pub mod pagination;
+pub mod rate_limit;
+pub mod request_id;
 pub mod time;
 
 /// Helper type used as a return type for HTTP handler.
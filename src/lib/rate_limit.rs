@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// Counts the requests of each client over a sliding window, rejecting them past a maximum
+pub struct RateLimiter<K: Hash + Eq> {
+    max_hits: usize,
+    window: Duration,
+    hits: RwLock<HashMap<K, Vec<Instant>>>,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    pub fn new(max_hits: usize, window: Duration) -> Self {
+        RateLimiter {
+            max_hits,
+            window,
+            hits: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Returns false when the client already reached its maximum number of requests
+    pub fn hit(&self, key: K) -> bool {
+        let mut hits = self.hits.write().expect("RateLimiter RwLock poisoned");
+        let window = self.window;
+
+        hits.retain(|_, instants| {
+            instants.retain(|instant| instant.elapsed() < window);
+            !instants.is_empty()
+        });
+        let client_hits = hits.entry(key).or_default();
+        if client_hits.len() >= self.max_hits {
+            return false;
+        }
+        client_hits.push(Instant::now());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+
+        assert!(limiter.hit("127.0.0.1"));
+        assert!(limiter.hit("127.0.0.1"));
+        assert!(!limiter.hit("127.0.0.1"));
+        assert!(limiter.hit("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_rate_limiter_window() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(0));
+
+        assert!(limiter.hit("127.0.0.1"));
+        assert!(limiter.hit("127.0.0.1"));
+    }
+}
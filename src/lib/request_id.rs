@@ -0,0 +1,121 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const REQUEST_ID_MAX_LENGTH: usize = 64;
+
+thread_local! {
+    static CURRENT_REQUEST_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Returns the id of the request being processed on this thread, if any
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+/// Runs the closure with the given request id, restoring the previous one afterwards
+pub fn in_request_scope<T>(request_id: Option<String>, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT_REQUEST_ID.with(|id| id.replace(request_id));
+    let result = f();
+    CURRENT_REQUEST_ID.with(|id| id.replace(previous));
+    result
+}
+
+/// Several requests are interleaved on the same worker thread, so the request id is set each time
+/// the wrapped future is polled instead of once for the whole request.
+pub struct RequestScope<F> {
+    request_id: Option<String>,
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> RequestScope<F> {
+    pub fn new(request_id: Option<String>, future: F) -> Self {
+        RequestScope{ request_id, inner: Box::pin(future) }
+    }
+}
+
+impl<F: Future> Future for RequestScope<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        in_request_scope(this.request_id.clone(), || inner.as_mut().poll(cx))
+    }
+}
+
+/// Middleware giving an id to each request, so that its log lines can be correlated.
+/// The id sent by the client or a proxy in the `X-Request-Id` header is kept when there is one.
+pub fn request_id_middleware<S, B>(req: ServiceRequest, srv: &mut S) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+    where S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>
+{
+    let request_id = get_request_id(req.headers().get(REQUEST_ID_HEADER));
+    let future = RequestScope::new(
+        Some(request_id.clone()),
+        in_request_scope(Some(request_id.clone()), || srv.call(req)),
+    );
+
+    async move {
+        let mut response = future.await?;
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            response.headers_mut().insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+        }
+        Ok(response)
+    }
+}
+
+fn get_request_id(header: Option<&HeaderValue>) -> String {
+    header
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= REQUEST_ID_MAX_LENGTH)
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_request_scope() {
+        assert_eq!(None, current_request_id());
+
+        in_request_scope(Some(String::from("first")), || {
+            in_request_scope(Some(String::from("second")), || {
+                assert_eq!(Some(String::from("second")), current_request_id());
+            });
+            assert_eq!(Some(String::from("first")), current_request_id());
+        });
+        assert_eq!(None, current_request_id());
+    }
+
+    #[test]
+    fn test_request_scope() {
+        let request_id = futures::executor::block_on(RequestScope::new(
+            Some(String::from("uuid")),
+            async { current_request_id() },
+        ));
+
+        assert_eq!(Some(String::from("uuid")), request_id);
+        assert_eq!(None, current_request_id());
+    }
+
+    #[test]
+    fn test_get_request_id() {
+        let header = HeaderValue::from_static("client-id");
+
+        assert_eq!("client-id", get_request_id(Some(&header)));
+        assert_ne!("", get_request_id(Some(&HeaderValue::from_static(""))));
+        assert_eq!(36, get_request_id(None).len());
+    }
+}
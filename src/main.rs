@@ -22,7 +22,7 @@
 
 
 use actix_web::{web, App, HttpServer};
-use actix_web::middleware::Logger;
+use actix_web::middleware::{Condition, Logger};
 use std::collections::HashMap;
 use std::sync::RwLock;
 use std::env;
@@ -32,7 +32,7 @@ use sqlx::PgPool;
 
 extern crate gelf;
 
-use gelf::{Logger as GelfLogger, TcpBackend, NullBackend, Message, Level};
+use gelf::{NullBackend, Message, Level};
 
 mod ws;
 mod game;
@@ -40,21 +40,28 @@ mod lib;
 
 use game::{
     communication::chat,
+    communication::money_request,
+    communication::notification,
+    fleet::combat::battle,
+    fleet::combat::conquest,
     fleet::fleet,
     fleet::travel,
     fleet::squadron as fleet_squadron,
     game::{
         game as g,
+        option,
         server::{GameEndMessage, GameServer},
     },
     faction,
     player,
     lobby,
     system::building,
+    system::minefield,
     system::system,
     ship::model,
     ship::queue,
-    ship::squadron
+    ship::squadron,
+    technology,
 };
 use lib::Result;
 use ws::protocol;
@@ -63,11 +70,15 @@ use ws::protocol;
 /// Each attribute is between a [`RwLock`](https://doc.rust-lang.org/std/sync/struct.RwLock.html)
 pub struct AppState {
     db_pool: PgPool,
-    logger: Option<GelfLogger>,
+    logger: Option<lib::log::GraylogLogger>,
     clients: RwLock<HashMap<player::PlayerID, actix::Addr<ws::client::ClientSession>>>,
     lobbies: RwLock<HashMap<lobby::LobbyID, actix::Addr<lobby::LobbyServer>>>,
     games: RwLock<HashMap<g::GameID, actix::Addr<GameServer>>>,
     missing_messages: RwLock<HashMap<player::PlayerID, Vec<protocol::Message>>>,
+    sessions: ws::client::SessionRegistry,
+    idempotency_cache: lib::idempotency::IdempotencyCache,
+    recovery_limiter: lib::rate_limit::RateLimiter<String>,
+    ship_prerequisites: model::ShipPrerequisites,
 }
 
 macro_rules! res_access {
@@ -140,11 +151,24 @@ async fn generate_state() -> AppState {
         lobbies: RwLock::new(HashMap::new()),
         clients: RwLock::new(HashMap::new()),
         missing_messages: RwLock::new(HashMap::new()),
+        sessions: ws::client::SessionRegistry::default(),
+        idempotency_cache: lib::idempotency::IdempotencyCache::default(),
+        recovery_limiter: lib::rate_limit::RateLimiter::new(
+            get_env("PLAYER_RECOVERY_MAX_ATTEMPTS", "5").parse().ok().filter(|n| *n > 0).unwrap_or(5),
+            std::time::Duration::from_secs(60),
+        ),
+        ship_prerequisites: model::ShipPrerequisites::from_env(),
     }
 }
 
 // this function could be located in different module
 fn config(cfg: &mut web::ServiceConfig) {
+    // Registered before the main scope, which would otherwise catch its requests
+    #[cfg(feature="leaderboard")]
+    cfg.service(
+        web::scope("/api/leaderboard")
+        .service(game::leaderboard::get_leaderboard)
+    );
     cfg.service(
         web::scope("/api")
         .service(
@@ -153,33 +177,84 @@ fn config(cfg: &mut web::ServiceConfig) {
         )
         .service(
             web::scope("/games")
+            .service(g::get_games)
+            .service(g::get_game)
+            .service(g::get_game_effective_constants)
             .service(g::get_players)
+            .service(g::get_players_status)
+            .service(model::get_game_ship_models)
+            .service(building::get_game_buildings_data)
             .service(g::leave_game)
+            .service(g::pause_game)
+            .service(g::resume_game)
+            .service(
+                web::scope("/{game_id}/battles")
+                .service(battle::get_ongoing_battles)
+                .service(battle::get_battle)
+                .service(battle::get_battle_replay)
+            )
             .service(
                 web::scope("/{game_id}/communications")
                 .service(chat::send_message)
             )
             .service(
                 web::scope("/{game_id}/factions")
+                .service(faction::get_game_factions)
                 .service(
                     web::scope("/{faction_id}")
                     .service(player::get_faction_members)
                     .service(player::transfer_money)
+                    .service(money_request::request_money)
+                    .service(money_request::get_money_requests)
+                    .service(money_request::accept_money_request)
+                    .service(money_request::decline_money_request)
+                    .service(technology::get_faction_technologies)
+                    .service(system::get_faction_systems)
                 )
             )
+            .service(
+                web::scope("/{game_id}/me")
+                .service(system::get_territory)
+                .service(player::get_current_player_overview)
+                .service(travel::get_incoming_fleets)
+                .service(
+                    web::scope("/notifications")
+                    .service(notification::get_notifications)
+                    .service(notification::read_notification)
+                )
+            )
+            .service(
+                web::scope("/{game_id}/players")
+                .service(player::get_game_player)
+            )
+            .service(
+                web::scope("/{game_id}/map")
+                .service(system::get_map_bounds)
+            )
             .service(
                 web::scope("/{game_id}/systems")
                 .service(system::get_systems)
+                .service(system::abandon_system)
+                .service(system::transfer_system)
+                .service(system::get_system)
+                .service(building::get_system_available_buildings)
                 .service(
                     web::scope("/{system_id}/fleets")
                     .service(fleet::create_fleet)
+                    .service(fleet::get_system_fleets_strength)
                     .service(
                         web::scope("/{fleet_id}")
+                        .service(fleet::get_fleet)
+                        .service(fleet::get_fleet_strength)
+                        .service(fleet::rename)
+                        .service(fleet::disband)
+                        .service(fleet::scuttle)
                         .service(fleet::donate)
                         .service(travel::travel)
                         .service(
                             web::scope("/squadrons")
                             .service(fleet_squadron::assign_ships)
+                            .service(fleet_squadron::update_squadron)
                         )
                     )
                 )
@@ -190,12 +265,31 @@ fn config(cfg: &mut web::ServiceConfig) {
                 .service(
                     web::scope("/{system_id}/ship-queues")
                     .service(queue::add_ship_queue)
+                    .service(queue::estimate_ship_queue)
                     .service(queue::get_ship_queues)
                 )
                 .service(
                     web::scope("/{system_id}/buildings")
                     .service(building::get_system_buildings)
                     .service(building::create_building)
+                    .service(building::cancel_building)
+                )
+                .service(
+                    web::scope("/{system_id}/battles")
+                    .service(battle::get_system_battles)
+                )
+                .service(
+                    web::scope("/{system_id}/conquest")
+                    .service(conquest::get_system_conquest)
+                )
+                .service(
+                    web::scope("/{system_id}/minefield")
+                    .service(minefield::get_minefield)
+                    .service(minefield::deploy_mines)
+                )
+                .service(
+                    web::scope("/{system_id}/technologies")
+                    .service(technology::research_technology)
                 )
             )
         )
@@ -206,6 +300,7 @@ fn config(cfg: &mut web::ServiceConfig) {
             .service(lobby::get_lobby)
             .service(lobby::join_lobby)
             .service(lobby::update_lobby_options)
+            .service(lobby::preview_map)
             .service(lobby::leave_lobby)
             .service(lobby::launch_game)
         )
@@ -214,12 +309,17 @@ fn config(cfg: &mut web::ServiceConfig) {
             .service(player::get_nb_players)
             .service(player::get_current_player)
             .service(player::update_current_player)
+            .service(player::update_current_player_readiness)
         )
         .service(building::get_buildings_data)
         .service(g::get_game_constants)
+        .service(option::get_game_speeds)
+        .service(option::get_victory_modes)
         .service(model::get_ship_models)
+        .service(technology::get_technologies_data)
     )
     .service(player::login)
+    .service(player::recover)
     .service(web::resource("/ws/").to(ws::client::entrypoint));
 }
 
@@ -244,22 +344,16 @@ async fn create_pool() -> Result<PgPool> {
     Ok(result?)
 }
 
-fn create_logger() -> Option<GelfLogger> {
+fn create_logger() -> Option<lib::log::GraylogLogger> {
     #[cfg(feature="graylog")]
     {
         println!("Graylog feature enabled");
 
-        let tcp_backend = TcpBackend::new(&format!(
+        Some(lib::log::GraylogLogger::new(format!(
             "{}:{}",
             &get_env("GRAYLOG_HOST", "kalaxia_v2_graylog"),
             &get_env("GRAYLOG_PORT", "1514")
-        ));
-        if let Some(backend) = tcp_backend.ok() {
-            return GelfLogger::new(Box::new(backend)).ok();
-        }
-        println!("Could not connect to Graylog. Logging to the default output instead");
-
-        None
+        )))
     }
     #[cfg(not(feature="graylog"))]
     None
@@ -271,9 +365,15 @@ async fn main() -> std::io::Result<()> {
     env_logger::init();
 
     let state = web::Data::new(generate_state().await);
+    let cors_config = lib::cors::CorsConfig::from_env();
+    let lobby_ttl = chrono::Duration::seconds(get_env("LOBBY_TTL_SEC", "3600").parse().ok().filter(|s| *s > 0).unwrap_or(3600));
+    actix_rt::spawn(lobby::sweep_abandoned_lobbies(state.clone(), lobby_ttl));
 
     let mut server = HttpServer::new(move || App::new()
         .wrap(Logger::default())
+        .wrap_fn(lib::request_id::request_id_middleware)
+        // Registered last to answer the preflight requests before any other middleware
+        .wrap(Condition::new(cors_config.is_enabled(), cors_config.build()))
         .app_data(state.clone()).configure(config));
 
     #[cfg(feature="ssl-secure")]
@@ -1,9 +1,16 @@
 use std::time::{Duration, Instant};
+use std::sync::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use actix::*;
 use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
+use flate2::{Compression, write::GzEncoder};
 use futures::executor::block_on;
+use serde::Deserialize;
+use uuid::Uuid;
 use crate::{
+    get_env,
     lib::{
         Result,
         log::log,
@@ -15,7 +22,8 @@ use crate::{
             game::Game,
             server::{GameAddClientMessage, GameRemovePlayerMessage},
         },
-        player::{Player, PlayerID},
+        player::{Player, PlayerID, StateSnapshot},
+        system::system::{System, SystemID},
     },
     ws::protocol,
     AppState,
@@ -24,6 +32,93 @@ use crate::{
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 /// How long before lack of client response causes a timeout
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Minimum delay between two state resynchronizations requested by the same session
+const RESYNC_COOLDOWN: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionID(pub Uuid);
+
+#[derive(Deserialize)]
+pub struct SessionOptions {
+    /// Set by the clients able to inflate gzipped binary frames
+    #[serde(default)]
+    compression: bool,
+    /// Comma-separated actions the client does not want to receive
+    #[serde(default)]
+    excluded_events: String,
+}
+
+/// Events a client opted out of. The critical ones are always sent.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct EventFilter {
+    excluded: HashSet<protocol::Action>,
+}
+
+impl EventFilter {
+    pub fn new(excluded: impl IntoIterator<Item = protocol::Action>) -> Self {
+        EventFilter{
+            excluded: excluded.into_iter().filter(|action| !action.is_critical()).collect(),
+        }
+    }
+
+    /// Unknown actions are ignored
+    pub fn parse(excluded: &str) -> Self {
+        Self::new(excluded.split(',')
+            .filter_map(|name| serde_json::from_value(serde_json::Value::String(name.trim().to_string())).ok()))
+    }
+
+    pub fn accepts(&self, action: &protocol::Action) -> bool {
+        !self.excluded.contains(action)
+    }
+
+    /// The batched messages are filtered one by one
+    pub fn filter(&self, mut msg: protocol::Message) -> Option<protocol::Message> {
+        if !self.accepts(&msg.action) {
+            return None;
+        }
+        if let (protocol::Action::BatchedMessages, serde_json::Value::Array(messages)) = (&msg.action, &mut msg.data) {
+            messages.retain(|m| serde_json::from_value(m["action"].clone()).map_or(true, |action| self.accepts(&action)));
+            if messages.is_empty() {
+                return None;
+            }
+        }
+        Some(msg)
+    }
+}
+
+/// A player can only have one active websocket session. Opening a new one (in another tab for
+/// example) supersedes the previous session, which is closed at its next heartbeat without
+/// running the logout cleanup, as the player is still connected.
+#[derive(Default)]
+pub struct SessionRegistry {
+    sessions: RwLock<HashMap<PlayerID, SessionID>>,
+}
+
+impl SessionRegistry {
+    /// Returns whether the new session replaces a still opened one
+    pub fn open(&self, pid: PlayerID, sid: SessionID) -> bool {
+        self.sessions.write().expect("SessionRegistry RwLock poisoned").insert(pid, sid).is_some()
+    }
+
+    pub fn is_active(&self, pid: &PlayerID, sid: SessionID) -> bool {
+        self.sessions.read().expect("SessionRegistry RwLock poisoned").get(pid) == Some(&sid)
+    }
+
+    /// Returns true if the closed session was the active one, meaning the player is now offline
+    pub fn close(&self, pid: &PlayerID, sid: SessionID) -> bool {
+        let mut sessions = self.sessions.write().expect("SessionRegistry RwLock poisoned");
+        if sessions.get(pid) != Some(&sid) {
+            return false;
+        }
+        sessions.remove(pid);
+        true
+    }
+
+    /// Players are counted once, whether they are idle, in a lobby or in a game
+    pub fn count(&self) -> usize {
+        self.sessions.read().expect("SessionRegistry RwLock poisoned").len()
+    }
+}
 
 /// Entry point for our the WebSocket handshake
 pub async fn entrypoint(
@@ -31,14 +126,26 @@ pub async fn entrypoint(
     stream: web::Payload,
     state: web::Data<AppState>,
     claims: Claims,
+    options: web::Query<SessionOptions>,
 ) -> Result<HttpResponse> {
-    let player = Player::find(claims.pid, &state.db_pool).await?;
+    let mut player = Player::find(claims.pid, &state.db_pool).await?;
+    if !player.is_connected {
+        player.is_connected = true;
+        Player::update_connection(player.id, true, &mut &state.db_pool).await?;
+    }
+    let session = SessionID(Uuid::new_v4());
     // Creates the websocket client for the current player
     let (client, resp) = ws::start_with_addr(ClientSession{
         hb: Instant::now(),
         state: state.clone(),
-        pid: player.id.clone()
+        pid: player.id.clone(),
+        session,
+        last_resync: None,
+        compression_threshold: get_compression_threshold(options.compression),
+        event_filter: EventFilter::parse(&options.excluded_events),
+        focused_system: None,
     }, &req, stream)?;
+    let is_already_connected = state.sessions.open(player.id, session);
 
     let mut missing_messages = state.missing_messages_mut();
     if let Some(player_messages) = missing_messages.get_mut(&player.id) {
@@ -65,6 +172,15 @@ pub async fn entrypoint(
         let lobby_server = lobbies.get(&lobby_id).expect("Lobby server not found");
 
         lobby_server.send(LobbyAddClientMessage(player.id.clone(), client)).await?;
+
+        let mut lobby = Lobby::find(lobby_id, &state.db_pool).await?;
+        if lobby.restore_owner(player.id, &state.db_pool).await? {
+            lobby_server.do_send(protocol::Message::new(
+                protocol::Action::LobbyOwnerUpdated,
+                lobby.owner,
+                None,
+            ));
+        }
     } else if let Some(game_id) = player.game {
         let games = state.games();
         let game_server = games.get(&game_id).expect("Game server not found");
@@ -74,11 +190,13 @@ pub async fn entrypoint(
         state.add_client(&player.id, client);
     }
 
-    state.ws_broadcast(&protocol::Message::new(
-        protocol::Action::PlayerConnected,
-        player.clone(),
-        Some(player.id.clone()),
-    ));
+    if !is_already_connected {
+        state.ws_broadcast(&protocol::Message::new(
+            protocol::Action::PlayerConnected,
+            player.clone(),
+            Some(player.id.clone()),
+        ));
+    }
 
     Ok(resp)
 }
@@ -87,12 +205,26 @@ pub async fn entrypoint(
 pub struct ClientSession {
     hb: Instant,
     state: web::Data<AppState>,
-    pid: PlayerID
+    pid: PlayerID,
+    session: SessionID,
+    last_resync: Option<Instant>,
+    /// Size in bytes above which the messages are sent gzipped, if the client supports it
+    compression_threshold: Option<usize>,
+    event_filter: EventFilter,
+    /// System currently displayed by the client, updates about it may be detailed or sent first
+    #[allow(dead_code)]
+    focused_system: Option<SystemID>,
 }
 
 impl ClientSession {
     async fn logout(&self) -> Result<()> {
-        let player = Player::find(self.pid, &self.state.db_pool).await.unwrap();
+        // A newer session of the same player now owns the client entries
+        if !self.state.sessions.close(&self.pid, self.session) {
+            return Ok(());
+        }
+        let mut player = Player::find(self.pid, &self.state.db_pool).await.unwrap();
+        player.is_connected = false;
+        Player::update_connection(player.id, false, &mut &self.state.db_pool).await?;
         {
             let mut clients = self.state.clients_mut();
             clients.remove(&self.pid);
@@ -113,8 +245,7 @@ impl ClientSession {
             let (_, is_empty) = std::sync::Arc::try_unwrap(lobby_server.send(LobbyRemoveClientMessage(player.id.clone())).await?).ok().unwrap();
             if is_empty {
                 self.state.clear_lobby(lobby, player.id).await?;
-            } else if player.id == lobby.owner {
-                lobby.update_owner(&self.state.db_pool).await?;
+            } else if player.id == lobby.owner && lobby.lend_ownership(&self.state.db_pool).await? {
                 lobby_server.do_send(protocol::Message::new(
                     protocol::Action::LobbyOwnerUpdated,
                     lobby.owner.clone(),
@@ -168,7 +299,15 @@ impl Handler<protocol::Message> for ClientSession {
         if msg.skip_id == Some(self.pid) {
             return;
         }
-        ctx.text(serde_json::to_string(&msg).expect("Couldnt serialize WsMessage data"))
+        let msg = match self.event_filter.filter(msg) {
+            Some(msg) => msg,
+            None => return,
+        };
+        let payload = serde_json::to_string(&msg).expect("Couldnt serialize WsMessage data");
+        match compress_payload(&payload, self.compression_threshold) {
+            Some(compressed) => ctx.binary(compressed),
+            None => ctx.text(payload),
+        }
     }
 }
 
@@ -195,9 +334,12 @@ impl StreamHandler<std::result::Result<ws::Message, ws::ProtocolError>> for Clie
             ws::Message::Pong(_) => {
                 self.hb = Instant::now();
             }
-            ws::Message::Text(_text) => {
-                
-            }
+            ws::Message::Text(text) => match serde_json::from_str(&text) {
+                Ok(protocol::ClientAction::Resync) => self.resync(ctx),
+                Ok(protocol::ClientAction::FilterEvents{ excluded }) => self.event_filter = EventFilter::new(excluded),
+                Ok(protocol::ClientAction::Focus{ system }) => self.focus(system),
+                Err(_) => println!("Unexpected websocket message : {}", text),
+            },
             ws::Message::Binary(_) => println!("Unexpected binary"),
             ws::Message::Close(_) => {
                 ctx.stop();
@@ -211,6 +353,56 @@ impl StreamHandler<std::result::Result<ws::Message, ws::ProtocolError>> for Clie
 }
 
 impl ClientSession {
+    /// Sends the current state of the player's game, unless the session asked for it too recently
+    fn resync(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        let now = Instant::now();
+        if !can_resync(self.last_resync, now) {
+            return;
+        }
+        self.last_resync = Some(now);
+
+        match block_on(self.get_state_snapshot()) {
+            Ok(Some(snapshot)) => ctx.notify(protocol::Message::new(
+                protocol::Action::StateSnapshot,
+                snapshot,
+                None,
+            )),
+            Ok(None) => (),
+            Err(error) => println!("Resync error : {:?}", error),
+        }
+    }
+
+    fn focus(&mut self, system: Option<SystemID>) {
+        let sid = match system {
+            Some(sid) => sid,
+            None => {
+                self.focused_system = None;
+                return;
+            },
+        };
+        match block_on(self.get_focus_target(sid)) {
+            Ok(true) => self.focused_system = Some(sid),
+            Ok(false) => println!("Invalid focus target : {:?}", sid),
+            Err(error) => println!("Focus error : {:?}", error),
+        }
+    }
+
+    async fn get_focus_target(&self, sid: SystemID) -> Result<bool> {
+        let (player, system) = futures::join!(
+            Player::find(self.pid, &self.state.db_pool),
+            System::find(sid, &self.state.db_pool),
+        );
+        Ok(is_valid_focus(&player?, &system?))
+    }
+
+    async fn get_state_snapshot(&self) -> Result<Option<StateSnapshot>> {
+        let player = Player::find(self.pid, &self.state.db_pool).await?;
+        match player.game {
+            Some(gid) => Ok(Some(StateSnapshot::build(&player, gid, &self.state.db_pool).await?)),
+            None => Ok(None),
+        }
+    }
+
     /// helper method that sends ping to client every second.
     ///
     /// also this method checks heartbeats from client
@@ -228,8 +420,199 @@ impl ClientSession {
                 // don't try to send a ping
                 return;
             }
+            if !act.state.sessions.is_active(&act.pid, act.session) {
+                // the player opened another session
+                ctx.stop();
+                return;
+            }
 
             ctx.ping(b"");
         });
     }
 }
+
+/// A zero WS_COMPRESSION_THRESHOLD disables the compression for every client
+fn get_compression_threshold(is_supported: bool) -> Option<usize> {
+    let threshold = get_env("WS_COMPRESSION_THRESHOLD", "0").parse().unwrap_or(0);
+    if !is_supported || threshold == 0 {
+        return None;
+    }
+    Some(threshold)
+}
+
+/// Large payloads, such as battles or the galaxy map, are gzipped. The small ones are not worth it
+fn compress_payload(payload: &str, threshold: Option<usize>) -> Option<Vec<u8>> {
+    let threshold = threshold?;
+    if payload.len() < threshold {
+        return None;
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(payload.as_bytes()).ok()?;
+    encoder.finish().ok()
+}
+
+/// Players can only focus on the systems of the game they are playing
+fn is_valid_focus(player: &Player, system: &System) -> bool {
+    player.game == Some(system.game)
+}
+
+fn can_resync(last_resync: Option<Instant>, now: Instant) -> bool {
+    last_resync.map_or(true, |last| now.duration_since(last) >= RESYNC_COOLDOWN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{
+        game::game::GameID,
+        system::system::{Coordinates, SystemKind},
+    };
+
+    #[test]
+    fn test_session_registry() {
+        let registry = SessionRegistry::default();
+        let pid = PlayerID(Uuid::new_v4());
+
+        let first_session = SessionID(Uuid::new_v4());
+        let second_session = SessionID(Uuid::new_v4());
+
+        assert!(!registry.open(pid, first_session));
+        assert!(registry.open(pid, second_session));
+        assert!(!registry.is_active(&pid, first_session));
+        assert!(registry.is_active(&pid, second_session));
+
+        // Closing the superseded session must not log the player out
+        assert!(!registry.close(&pid, first_session));
+        assert!(registry.is_active(&pid, second_session));
+
+        assert!(registry.close(&pid, second_session));
+        assert!(!registry.is_active(&pid, second_session));
+    }
+
+    #[test]
+    fn test_session_registry_count() {
+        let registry = SessionRegistry::default();
+        let (first_pid, second_pid) = (PlayerID(Uuid::new_v4()), PlayerID(Uuid::new_v4()));
+        let reconnection_session = SessionID(Uuid::new_v4());
+
+        assert_eq!(0, registry.count());
+
+        registry.open(first_pid, SessionID(Uuid::new_v4()));
+        registry.open(second_pid, SessionID(Uuid::new_v4()));
+        registry.open(first_pid, reconnection_session);
+        assert_eq!(2, registry.count());
+
+        registry.close(&first_pid, reconnection_session);
+        assert_eq!(1, registry.count());
+    }
+
+    #[test]
+    fn test_is_valid_focus() {
+        let gid = GameID(Uuid::new_v4());
+        let mut player = Player{
+            id: PlayerID(Uuid::new_v4()),
+            username: String::from("Galadruin"),
+            game: Some(gid),
+            lobby: None,
+            faction: None,
+            ready: true,
+            wallet: 0,
+            is_connected: true,
+            home_system: None,
+            is_eliminated: false,
+        };
+        let mut system = System{
+            id: SystemID(Uuid::new_v4()),
+            game: gid,
+            player: None,
+            kind: SystemKind::BaseSystem,
+            coordinates: Coordinates{ x: 1.0, y: 2.0 },
+            unreachable: false,
+        };
+        assert!(is_valid_focus(&player, &system));
+
+        system.game = GameID(Uuid::new_v4());
+        assert!(!is_valid_focus(&player, &system));
+
+        player.game = None;
+        assert!(!is_valid_focus(&player, &system));
+    }
+
+    #[test]
+    fn test_can_resync() {
+        let now = Instant::now();
+
+        assert!(can_resync(None, now));
+        assert!(!can_resync(Some(now), now + Duration::from_secs(1)));
+        assert!(can_resync(Some(now), now + RESYNC_COOLDOWN));
+    }
+
+    #[test]
+    fn test_compress_payload() {
+        use std::io::Read;
+        use flate2::read::GzDecoder;
+
+        // A battle involves a few fleets over many rounds
+        let fleets: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+        let rounds: Vec<serde_json::Value> = (0..200).map(|i| serde_json::json!({
+            "fleet": fleets[i % fleets.len()],
+            "squadron": { "category": "fighter", "quantity": 50, "position": i % 9 },
+            "damage": 1000 - i,
+            "nb_destroyed": i % 7,
+        })).collect();
+        let payload = serde_json::to_string(&protocol::Message::new(
+            protocol::Action::BattleEnded,
+            rounds,
+            None,
+        )).unwrap();
+
+        assert_eq!(None, compress_payload(&payload, None));
+        assert_eq!(None, compress_payload("{}", Some(1024)));
+
+        let compressed = compress_payload(&payload, Some(1024)).unwrap();
+        // A big battle is several times lighter once compressed
+        assert!(compressed.len() * 3 < payload.len());
+
+        let mut inflated = String::new();
+        GzDecoder::new(compressed.as_slice()).read_to_string(&mut inflated).unwrap();
+        assert_eq!(payload, inflated);
+    }
+
+    #[test]
+    fn test_event_filter() {
+        let filter = EventFilter::parse("PlayerIncome, ConquestUpdated,Victory,Unknown");
+        let income = protocol::Message::new(protocol::Action::PlayerIncome, 40, None);
+        let fleet = protocol::Message::new(protocol::Action::FleetArrived, (), None);
+        let victory = protocol::Message::new(protocol::Action::Victory, (), None);
+
+        assert_eq!(EventFilter::new(vec![protocol::Action::PlayerIncome, protocol::Action::ConquestUpdated]), filter);
+        // Filtered actions are not forwarded, while critical ones are always delivered
+        assert!(filter.filter(income.clone()).is_none());
+        assert!(filter.filter(fleet.clone()).is_some());
+        assert!(filter.filter(victory).is_some());
+
+        let batch = protocol::Message::new(protocol::Action::BatchedMessages, vec![income.clone(), fleet], None);
+        let filtered_batch = filter.filter(batch).unwrap();
+        assert_eq!(1, filtered_batch.data.as_array().unwrap().len());
+        assert_eq!("FleetArrived", filtered_batch.data[0]["action"]);
+
+        let income_batch = protocol::Message::new(protocol::Action::BatchedMessages, vec![income.clone(), income.clone()], None);
+        assert!(filter.filter(income_batch).is_none());
+
+        assert!(EventFilter::default().filter(income).is_some());
+    }
+
+    #[test]
+    fn test_client_action_deserialization() {
+        assert_eq!(protocol::ClientAction::Resync, serde_json::from_str(r#"{ "action": "resync" }"#).unwrap());
+        assert_eq!(
+            protocol::ClientAction::FilterEvents{ excluded: vec![protocol::Action::PlayerIncome] },
+            serde_json::from_str(r#"{ "action": "filter_events", "excluded": ["PlayerIncome"] }"#).unwrap()
+        );
+        assert_eq!(
+            protocol::ClientAction::Focus{ system: None },
+            serde_json::from_str(r#"{ "action": "focus", "system": null }"#).unwrap()
+        );
+        assert!(serde_json::from_str::<protocol::ClientAction>(r#"{ "action": "unknown" }"#).is_err());
+    }
+}
@@ -1,28 +1,46 @@
-use crate::game::player::PlayerID;
+use crate::game::{
+    player::PlayerID,
+    system::system::SystemID,
+};
 
 /// Tokens representing the type of WS message sent to notify a player.
-#[derive(serde::Serialize, Clone, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[non_exhaustive]
 pub enum Action {
+    BatchedMessages,
+    BuildingCancelled,
     BuildingConstructed,
     BattleStarted,
     BattleEnded,
+    BattleSummary,
     ConquestCancelled,
     ConquestStarted,
     ConquestUpdated,
+    FactionEliminated,
     FactionPointsUpdated,
     FleetCreated,
+    FleetDisbanded,
     FleetArrived,
     FleetSailed,
+    FleetScuttled,
     FleetTransfer,
     FleetJoinedBattle,
+    FleetSquadronReinforced,
+    GamePaused,
+    GameResumed,
     GameStarted,
+    HomeSystemLost,
+    LobbyClosed,
     LobbyCreated,
     LobbyOptionsUpdated,
     LobbyOwnerUpdated,
     LobbyNameUpdated,
     LobbyRemoved,
     LobbyLaunched,
+    MapGenerating,
+    MapGenerationFailed,
+    MinesTriggered,
+    MoneyRequested,
     NewChatMessage,
     PlayerConnected,
     PlayerJoined,
@@ -30,13 +48,58 @@ pub enum Action {
     PlayerMoneyTransfer,
     PlayerLeft,
     PlayerDisconnected,
+    PlayerEliminated,
+    PlayerAssetsTransferred,
     PlayerIncome,
     ShipQueueFinished,
+    ShipQueueVoided,
+    StateSnapshot,
+    SystemAbandoned,
     SystemConquerred,
+    SystemTransferred,
     SystemsCreated,
+    TechUnlocked,
     Victory,
 }
 
+impl Action {
+    /// Progress and income updates are frequent and not time-sensitive, so they can be coalesced
+    /// with other messages before being sent to the client.
+    pub const fn is_batchable(&self) -> bool {
+        matches!(self, Action::ConquestUpdated | Action::FactionPointsUpdated | Action::PlayerIncome)
+    }
+
+    /// Messages every client must receive, whatever the events it subscribed to
+    pub const fn is_critical(&self) -> bool {
+        matches!(self,
+            Action::BatchedMessages |
+            Action::BattleStarted |
+            Action::BattleEnded |
+            Action::BattleSummary |
+            Action::FactionEliminated |
+            Action::GamePaused |
+            Action::GameResumed |
+            Action::GameStarted |
+            Action::MapGenerationFailed |
+            Action::PlayerEliminated |
+            Action::StateSnapshot |
+            Action::Victory
+        )
+    }
+}
+
+/// Requests sent by the clients through their websocket
+#[derive(serde::Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ClientAction {
+    /// The client suspects it missed messages and asks for a snapshot of its current state
+    Resync,
+    /// The client opts out of the given events, replacing its previous choice
+    FilterEvents{ excluded: Vec<Action> },
+    /// The client tells which system the player is looking at, a null system clears the focus
+    Focus{ #[serde(default)] system: Option<SystemID> },
+}
+
 #[derive(actix::Message, serde::Serialize, Clone, Debug)]
 #[rtype(result = "()")]
 pub struct Message {